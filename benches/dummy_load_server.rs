@@ -1,14 +1,20 @@
 use std::env;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use axum::{routing::get, Router};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use rand::Rng;
 use reqwest;
+use tokio::sync::Semaphore;
 use tokio::time::sleep;
 
+#[path = "workloads/stats.rs"]
+mod stats;
+
+use stats::LatencyStats;
+
 #[derive(Parser)]
 struct Args {
     /// Target KVStore++ URL
@@ -18,12 +24,80 @@ struct Args {
     /// API Key for authentication
     #[arg(short, long)]
     api_key: Option<String>,
+
+    /// closed: send, wait for the response, then pace the next op.
+    /// open: send on a fixed schedule whatever is still in flight.
+    #[arg(short, long, value_enum, default_value_t = LoadMode::Closed)]
+    mode: LoadMode,
+
+    /// Most requests in flight at once in open mode; the schedule waits for
+    /// a free slot, and that wait counts toward the corrected latency.
+    #[arg(long, default_value_t = 256)]
+    max_in_flight: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LoadMode {
+    /// One request at a time. A slow server lowers the achieved rate, and
+    /// the time it spends stalled is never measured (coordinated omission).
+    Closed,
+    /// Requests start on schedule, so the achieved rate only drops once
+    /// `--max-in-flight` is reached. Latency is also measured from each
+    /// request's scheduled start, which corrects for coordinated omission.
+    Open,
 }
 
 // Global counters for metrics
 static TOTAL_OPS: AtomicU64 = AtomicU64::new(0);
 static TOTAL_ERRORS: AtomicU64 = AtomicU64::new(0);
 
+// How often the achieved rate and latency summaries are refreshed
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Latency samples (ms) of successful ops since the last report, and the
+/// summaries of the last complete window that `/metrics` serves.
+#[derive(Default)]
+struct Recorder {
+    window: Mutex<Samples>,
+    last: Mutex<Report>,
+}
+
+#[derive(Default)]
+struct Samples {
+    service: Vec<f64>,
+    corrected: Vec<f64>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Report {
+    achieved_ops_per_sec: f64,
+    service: LatencyStats,
+    corrected: Option<LatencyStats>,
+}
+
+impl Recorder {
+    /// Records one completed op. `scheduled` is when it should have started
+    /// (open mode only) and `sent` when it actually did.
+    fn record(&self, scheduled: Option<Instant>, sent: Instant) {
+        let mut window = self.window.lock().unwrap();
+        window.service.push(sent.elapsed().as_secs_f64() * 1000.0);
+        if let Some(scheduled) = scheduled {
+            window.corrected.push(scheduled.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    fn roll(&self, mode: LoadMode, elapsed: Duration) -> Report {
+        let window = std::mem::take(&mut *self.window.lock().unwrap());
+        let report = Report {
+            achieved_ops_per_sec: window.service.len() as f64 / elapsed.as_secs_f64(),
+            service: LatencyStats::from_samples(window.service),
+            corrected: (mode == LoadMode::Open).then(|| LatencyStats::from_samples(window.corrected)),
+        };
+        *self.last.lock().unwrap() = report;
+        report
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
@@ -59,9 +133,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("Warning: operation ratios don't sum to 1.0 (got {})", total_ratio);
     }
 
+    let mode = args.mode;
+    let mode_name = match mode {
+        LoadMode::Closed => "closed",
+        LoadMode::Open => "open",
+    };
+
     println!("🚀 Starting dummy load server...");
     println!("Target: {}", args.target_url);
-    println!("Ops/sec: {}", ops_per_sec);
+    println!("Ops/sec: {} ({}-loop)", ops_per_sec, mode_name);
     println!("Ratios - GET: {}, SET: {}, DEL: {}, INCR: {}", get_ratio, set_ratio, del_ratio, incr_ratio);
 
     // Start metrics server
@@ -71,28 +151,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .expect("METRICS_PORT must be a number");
 
     let metrics_addr = format!("0.0.0.0:{}", metrics_port);
-    let metrics_total_ops = TOTAL_OPS.clone();
-    let metrics_total_errors = TOTAL_ERRORS.clone();
+    let recorder = Arc::new(Recorder::default());
+    let metrics_recorder = recorder.clone();
 
     tokio::spawn(async move {
         let app = Router::new().route("/metrics", get(move || async move {
-            format!(
+            let report = *metrics_recorder.last.lock().unwrap();
+            let mut body = format!(
                 "# HELP dummy_load_total_ops Total operations performed\n\
                  # TYPE dummy_load_total_ops counter\n\
                  dummy_load_total_ops {}\n\
                  # HELP dummy_load_total_errors Total errors encountered\n\
                  # TYPE dummy_load_total_errors counter\n\
-                 dummy_load_total_errors {}\n",
-                metrics_total_ops.load(Ordering::Relaxed),
-                metrics_total_errors.load(Ordering::Relaxed)
-            )
+                 dummy_load_total_errors {}\n\
+                 # HELP dummy_load_target_ops_per_sec Configured operation rate\n\
+                 # TYPE dummy_load_target_ops_per_sec gauge\n\
+                 dummy_load_target_ops_per_sec{{mode=\"{}\"}} {}\n\
+                 # HELP dummy_load_achieved_ops_per_sec Completed operations per second over the last window\n\
+                 # TYPE dummy_load_achieved_ops_per_sec gauge\n\
+                 dummy_load_achieved_ops_per_sec{{mode=\"{}\"}} {}\n",
+                TOTAL_OPS.load(Ordering::Relaxed),
+                TOTAL_ERRORS.load(Ordering::Relaxed),
+                mode_name,
+                ops_per_sec,
+                mode_name,
+                report.achieved_ops_per_sec
+            );
+            body.push_str(&latency_metric(
+                "dummy_load_latency_ms",
+                "Request latency from send to response over the last window",
+                &report.service,
+            ));
+            if let Some(corrected) = &report.corrected {
+                body.push_str(&latency_metric(
+                    "dummy_load_corrected_latency_ms",
+                    "Request latency from scheduled start to response over the last window",
+                    corrected,
+                ));
+            }
+            body
         }));
 
         println!("📈 Metrics server running on http://{}", metrics_addr);
-        axum::Server::bind(&metrics_addr.parse().unwrap())
-            .serve(app.into_make_service())
-            .await
-            .unwrap();
+        let listener = tokio::net::TcpListener::bind(&metrics_addr).await.unwrap();
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let report_recorder = recorder.clone();
+    tokio::spawn(async move {
+        let mut last = Instant::now();
+        loop {
+            sleep(REPORT_INTERVAL).await;
+            let report = report_recorder.roll(mode, last.elapsed());
+            last = Instant::now();
+            print!(
+                "📊 {:.1}/{} ops/sec ({:.1}%), p50 {:.2}ms, p99 {:.2}ms",
+                report.achieved_ops_per_sec,
+                ops_per_sec,
+                report.achieved_ops_per_sec / ops_per_sec as f64 * 100.0,
+                report.service.p50,
+                report.service.p99
+            );
+            match report.corrected {
+                Some(corrected) => println!(
+                    ", corrected p50 {:.2}ms, p99 {:.2}ms",
+                    corrected.p50, corrected.p99
+                ),
+                None => println!(),
+            }
+        }
     });
 
     // Start load generation
@@ -100,89 +227,121 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let target_url = args.target_url.clone();
     let api_key = args.api_key.clone();
 
-    let delay_per_op = Duration::from_secs(1) / ops_per_sec;
+    let delay_per_op = Duration::from_secs(1) / ops_per_sec as u32;
 
     println!("⏱️  Generating load with delay: {:?} per op", delay_per_op);
 
-    loop {
-        let start = Instant::now();
-
-        // Choose operation based on ratios
-        let op_type = {
-            let r = rand::thread_rng().gen_range(0.0..1.0);
-            if r < get_ratio {
-                "GET"
-            } else if r < get_ratio + set_ratio {
-                "SET"
-            } else if r < get_ratio + set_ratio + del_ratio {
-                "DEL"
-            } else {
-                "INCR"
-            }
-        };
-
-        let key_id = rand::thread_rng().gen_range(0..1_000_000);
-        let key = format!("load_test:{}", key_id);
+    let choose_op = move || {
+        let r = rand::thread_rng().gen_range(0.0..1.0);
+        if r < get_ratio {
+            "GET"
+        } else if r < get_ratio + set_ratio {
+            "SET"
+        } else if r < get_ratio + set_ratio + del_ratio {
+            "DEL"
+        } else {
+            "INCR"
+        }
+    };
 
-        let result = match op_type {
-            "GET" => {
-                let mut req = client.get(format!("{}/v1/get?key={}", target_url, key));
-                if let Some(ref key) = api_key {
-                    req = req.header("X-API-Key", key);
-                }
-                req.send().await
-            }
-            "SET" => {
-                let value: String = (0..64).map(|_| 'A').collect();
-                let mut req = client.post(format!("{}/v1/set", target_url))
-                    .json(&serde_json::json!({
-                        "key": key,
-                        "value": base64::encode(&value),
-                        "ttl": 3600
-                    }));
-                if let Some(ref key) = api_key {
-                    req = req.header("X-API-Key", key);
-                }
-                req.send().await
-            }
-            "DEL" => {
-                let mut req = client.post(format!("{}/v1/del", target_url))
-                    .json(&serde_json::json!({
-                        "key": key
-                    }));
-                if let Some(ref key) = api_key {
-                    req = req.header("X-API-Key", key);
-                }
-                req.send().await
-            }
-            "INCR" => {
-                let mut req = client.post(format!("{}/v1/set", target_url)) // Placeholder - use INCR when implemented
-                    .json(&serde_json::json!({
-                        "key": key,
-                        "value": base64::encode(&format!("{}", rand::thread_rng().gen_range(1..100))),
-                        "ttl": 3600
-                    }));
-                if let Some(ref key) = api_key {
-                    req = req.header("X-API-Key", key);
-                }
-                req.send().await
+    match mode {
+        LoadMode::Closed => loop {
+            let start = Instant::now();
+            if send_op(&client, &target_url, api_key.as_deref(), choose_op()).await {
+                recorder.record(None, start);
             }
-            _ => unreachable!(),
-        };
 
-        match result {
-            Ok(_) => {
-                TOTAL_OPS.fetch_add(1, Ordering::Relaxed);
+            // Sleep to maintain target ops/sec
+            let elapsed = start.elapsed();
+            if elapsed < delay_per_op {
+                sleep(delay_per_op - elapsed).await;
             }
-            Err(_) => {
-                TOTAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+        },
+        LoadMode::Open => {
+            let in_flight = Arc::new(Semaphore::new(args.max_in_flight));
+            let mut scheduled = tokio::time::Instant::now();
+            loop {
+                // Each op is due one interval after the last was due, however
+                // long earlier ops took; a backlog is sent as fast as slots free up.
+                tokio::time::sleep_until(scheduled).await;
+                let permit = in_flight.clone().acquire_owned().await.unwrap();
+
+                let client = client.clone();
+                let target_url = target_url.clone();
+                let api_key = api_key.clone();
+                let recorder = recorder.clone();
+                let op_type = choose_op();
+                tokio::spawn(async move {
+                    let sent = Instant::now();
+                    if send_op(&client, &target_url, api_key.as_deref(), op_type).await {
+                        recorder.record(Some(scheduled.into_std()), sent);
+                    }
+                    drop(permit);
+                });
+                scheduled += delay_per_op;
             }
         }
+    }
+}
+
+/// Sends one `op_type` request against a random key and counts the outcome.
+/// Returns whether it got a response.
+async fn send_op(client: &reqwest::Client, target_url: &str, api_key: Option<&str>, op_type: &str) -> bool {
+    let key_id = rand::thread_rng().gen_range(0..1_000_000);
+    let key = format!("load_test:{}", key_id);
 
-        // Sleep to maintain target ops/sec
-        let elapsed = start.elapsed();
-        if elapsed < delay_per_op {
-            sleep(delay_per_op - elapsed).await;
+    let req = match op_type {
+        "GET" => client.get(format!("{}/v1/get?key={}", target_url, key)),
+        "SET" => {
+            let value: String = (0..64).map(|_| 'A').collect();
+            client.post(format!("{}/v1/set", target_url))
+                .json(&serde_json::json!({
+                    "key": key,
+                    "value": base64::encode(&value),
+                    "ttl": 3600
+                }))
+        }
+        "DEL" => client.post(format!("{}/v1/del", target_url))
+            .json(&serde_json::json!({
+                "key": key
+            })),
+        "INCR" => client.post(format!("{}/v1/set", target_url)) // Placeholder - use INCR when implemented
+            .json(&serde_json::json!({
+                "key": key,
+                "value": base64::encode(&format!("{}", rand::thread_rng().gen_range(1..100))),
+                "ttl": 3600
+            })),
+        _ => unreachable!(),
+    };
+    let req = match api_key {
+        Some(key) => req.header("X-API-Key", key),
+        None => req,
+    };
+
+    match req.send().await {
+        Ok(_) => {
+            TOTAL_OPS.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(_) => {
+            TOTAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+            false
         }
     }
-}
\ No newline at end of file
+}
+
+/// `stats` as a Prometheus summary named `name`.
+fn latency_metric(name: &str, help: &str, stats: &LatencyStats) -> String {
+    format!(
+        "# HELP {name} {help}\n\
+         # TYPE {name} summary\n\
+         {name}{{quantile=\"0.5\"}} {}\n\
+         {name}{{quantile=\"0.95\"}} {}\n\
+         {name}{{quantile=\"0.99\"}} {}\n\
+         {name}_max {}\n",
+        stats.p50,
+        stats.p95,
+        stats.p99,
+        stats.max,
+    )
+}
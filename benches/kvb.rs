@@ -1,14 +1,53 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::time::Duration;
 
 mod reporter;
 mod workloads;
 
+use workloads::Workload;
+
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    protocols: ProtocolArgs,
+}
+
+#[derive(clap::Args)]
+struct ProtocolArgs {
+    /// Protocol to run each workload over; "both" runs it twice, REST then
+    /// gRPC, for a side-by-side comparison
+    #[arg(long, global = true, value_enum, default_value_t = Protocols::Rest)]
+    protocol: Protocols,
+    /// gRPC server address, for --protocol grpc or both
+    #[arg(long, global = true, default_value = "http://127.0.0.1:9090")]
+    grpc_url: String,
+    /// HTTP/2 connections that gRPC calls are spread across
+    #[arg(long, global = true, default_value_t = 4)]
+    grpc_connections: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Protocols {
+    Rest,
+    Grpc,
+    Both,
+}
+
+impl ProtocolArgs {
+    /// A client per selected protocol, both sending `api_key`; REST talks to `url`.
+    async fn clients(&self, url: &str, api_key: Option<String>) -> Result<Vec<workloads::Client>, Box<dyn std::error::Error>> {
+        let mut clients = Vec::new();
+        if self.protocol != Protocols::Grpc {
+            clients.push(workloads::Client::new(url.to_string(), api_key.clone()));
+        }
+        if self.protocol != Protocols::Rest {
+            clients.push(workloads::Client::grpc(&self.grpc_url, self.grpc_connections, api_key).await?);
+        }
+        Ok(clients)
+    }
 }
 
 #[derive(Subcommand)]
@@ -21,8 +60,14 @@ enum Commands {
         api_key: Option<String>,
         #[arg(short, long, default_value = "test:")]
         key_prefix: String,
-        #[arg(short, long, default_value_t = 10000)]
+        #[arg(long, default_value_t = 10000)]
         key_count: usize,
+        /// Key distribution: uniform or zipf
+        #[arg(long, default_value = "uniform")]
+        distribution: String,
+        /// Zipfian skew; 0.99 is YCSB's default
+        #[arg(long, default_value_t = 0.99)]
+        theta: f64,
         #[arg(short, long, default_value_t = 10)]
         concurrency: usize,
         #[arg(short, long, default_value_t = 60)]
@@ -38,6 +83,9 @@ enum Commands {
         key_prefix: String,
         #[arg(short, long, default_value_t = 64)]
         value_size: usize,
+        /// Per-SET compression: auto, none, lz4 or zstd
+        #[arg(long, default_value = "auto")]
+        compression: String,
         #[arg(short, long, default_value_t = 10)]
         concurrency: usize,
         #[arg(short, long, default_value_t = 60)]
@@ -55,6 +103,35 @@ enum Commands {
         value_size: usize,
         #[arg(short, long, default_value_t = 0.7)]
         read_ratio: f64,
+        /// Key distribution: uniform or zipf
+        #[arg(long, default_value = "uniform")]
+        distribution: String,
+        /// Zipfian skew; 0.99 is YCSB's default
+        #[arg(long, default_value_t = 0.99)]
+        theta: f64,
+        #[arg(short, long, default_value_t = 10)]
+        concurrency: usize,
+        #[arg(short, long, default_value_t = 60)]
+        duration: u64,
+    },
+    /// Run a custom weighted mix of operations
+    Custom {
+        #[arg(short, long, default_value = "http://localhost:8080")]
+        url: String,
+        #[arg(short, long)]
+        api_key: Option<String>,
+        /// Op weights, e.g. "get=50,set=20,incr=20,scan=10"
+        #[arg(long, conflicts_with = "spec")]
+        weights: Option<String>,
+        /// Path to a TOML workload spec (weights, key space, value size)
+        #[arg(long)]
+        spec: Option<String>,
+        #[arg(long, default_value = "custom:")]
+        key_prefix: String,
+        #[arg(long, default_value_t = 1_000_000)]
+        key_count: usize,
+        #[arg(long, default_value_t = 64)]
+        value_size: usize,
         #[arg(short, long, default_value_t = 10)]
         concurrency: usize,
         #[arg(short, long, default_value_t = 60)]
@@ -81,59 +158,105 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut results = Vec::new();
 
+    let protocols = cli.protocols;
+
     match cli.command {
-        Commands::GetHeavy { url, api_key, key_prefix, key_count, concurrency, duration } => {
-            let client = workloads::Client::new(url, api_key);
-            let workload = workloads::GetHeavyWorkload { key_prefix, key_count };
-            let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
-            results.push(result);
+        Commands::GetHeavy { url, api_key, key_prefix, key_count, distribution, theta, concurrency, duration } => {
+            let key_distribution = workloads::KeyDistribution::parse(&distribution, theta)?;
+            let workload = workloads::GetHeavyWorkload { key_prefix, key_count, key_distribution };
+            for client in protocols.clients(&url, api_key).await? {
+                let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(result);
+            }
         }
-        Commands::SetHeavy { url, api_key, key_prefix, value_size, concurrency, duration } => {
-            let client = workloads::Client::new(url, api_key);
-            let workload = workloads::SetHeavyWorkload { key_prefix, value_size_bytes: value_size };
-            let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
-            results.push(result);
+        Commands::SetHeavy { url, api_key, key_prefix, value_size, compression, concurrency, duration } => {
+            let workload = workloads::SetHeavyWorkload { key_prefix, value_size_bytes: value_size, compression };
+            for client in protocols.clients(&url, api_key).await? {
+                let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(result);
+            }
         }
-        Commands::Mixed { url, api_key, key_prefix, value_size, read_ratio, concurrency, duration } => {
-            let client = workloads::Client::new(url, api_key);
+        Commands::Mixed { url, api_key, key_prefix, value_size, read_ratio, distribution, theta, concurrency, duration } => {
             let workload = workloads::MixedWorkload { 
                 key_prefix, 
                 value_size_bytes: value_size, 
-                read_write_ratio: read_ratio 
+                read_write_ratio: read_ratio,
+                key_distribution: workloads::KeyDistribution::parse(&distribution, theta)?,
             };
-            let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
-            results.push(result);
+            for client in protocols.clients(&url, api_key).await? {
+                let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(result);
+            }
+        }
+        Commands::Custom { url, api_key, weights, spec, key_prefix, key_count, value_size, concurrency, duration } => {
+            let workload = match (spec, weights) {
+                (Some(path), _) => workloads::CustomWorkload::from_toml(&std::fs::read_to_string(path)?)?,
+                (None, Some(weights)) => workloads::CustomWorkload {
+                    key_prefix,
+                    key_count,
+                    value_size_bytes: value_size,
+                    weights: weights.parse::<workloads::OpWeights>()?,
+                },
+                (None, None) => return Err("custom workload needs --weights or --spec".into()),
+            };
+            for client in protocols.clients(&url, api_key).await? {
+                let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(result);
+            }
         }
         Commands::Suite { url, api_key, concurrency, duration, output_prefix } => {
-            let client = workloads::Client::new(url.clone(), api_key.clone());
+            for client in protocols.clients(&url, api_key).await? {
+                let protocol = client.protocol();
 
-            // Test 1: GET-heavy
-            println!("Running GET-heavy workload...");
-            let get_workload = workloads::GetHeavyWorkload { 
-                key_prefix: "get_test:".to_string(), 
-                key_count: 10000 
-            };
-            let get_result = get_workload.run(&client, concurrency, Duration::from_secs(duration)).await;
-            results.push(get_result);
-
-            // Test 2: SET-heavy
-            println!("Running SET-heavy workload...");
-            let set_workload = workloads::SetHeavyWorkload { 
-                key_prefix: "set_test:".to_string(), 
-                value_size_bytes: 64 
-            };
-            let set_result = set_workload.run(&client, concurrency, Duration::from_secs(duration)).await;
-            results.push(set_result);
-
-            // Test 3: Mixed 70/30
-            println!("Running Mixed 70/30 workload...");
-            let mixed_workload = workloads::MixedWorkload { 
-                key_prefix: "mixed_test:".to_string(), 
-                value_size_bytes: 64, 
-                read_write_ratio: 0.7 
-            };
-            let mixed_result = mixed_workload.run(&client, concurrency, Duration::from_secs(duration)).await;
-            results.push(mixed_result);
+                // Test 1: GET-heavy
+                println!("Running GET-heavy workload over {}...", protocol);
+                let get_workload = workloads::GetHeavyWorkload { 
+                    key_prefix: "get_test:".to_string(), 
+                    key_count: 10000,
+                    key_distribution: workloads::KeyDistribution::Uniform,
+                };
+                let get_result = get_workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(get_result);
+
+                // Test 2: SET-heavy
+                println!("Running SET-heavy workload over {}...", protocol);
+                let set_workload = workloads::SetHeavyWorkload { 
+                    key_prefix: "set_test:".to_string(), 
+                    value_size_bytes: 64,
+                    compression: "auto".to_string(),
+                };
+                let set_result = set_workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(set_result);
+
+                // Test 3: Mixed 70/30
+                println!("Running Mixed 70/30 workload over {}...", protocol);
+                let mixed_workload = workloads::MixedWorkload { 
+                    key_prefix: "mixed_test:".to_string(), 
+                    value_size_bytes: 64, 
+                    read_write_ratio: 0.7,
+                    key_distribution: workloads::KeyDistribution::Uniform,
+                };
+                let mixed_result = mixed_workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                results.push(mixed_result);
+
+                // Test 4: 4KB values, uncompressed vs zstd. Stored sizes are
+                // compared in-process by the ignored
+                // `test_zstd_4kb_throughput_and_size` storage test. Only
+                // REST picks a compression mode per request.
+                if protocol != workloads::Protocol::Rest {
+                    continue;
+                }
+                for compression in ["none", "zstd"] {
+                    println!("Running SET-heavy 4KB {} workload...", compression);
+                    let workload = workloads::SetHeavyWorkload {
+                        key_prefix: format!("{}_test:", compression),
+                        value_size_bytes: 4096,
+                        compression: compression.to_string(),
+                    };
+                    let result = workload.run(&client, concurrency, Duration::from_secs(duration)).await;
+                    results.push(result);
+                }
+            }
 
             // Save reports
             reporter::save_json_report(&results, &format!("{}.json", output_prefix))?;
@@ -3,28 +3,31 @@ use std::fs::File;
 use std::io::Write;
 
 pub fn print_text_report(results: &[WorkloadResult]) {
-    println!("{:-<80}", "");
-    println!("{:^80}", "KVSTORE++ BENCHMARK RESULTS");
-    println!("{:-<80}", "");
+    println!("{:-<125}", "");
+    println!("{:^125}", "KVSTORE++ BENCHMARK RESULTS");
+    println!("{:-<125}", "");
     println!(
-        "{:<15} {:<10} {:<12} {:<12} {:<12} {:<12} {:<10}",
-        "WORKLOAD", "OPS/SEC", "P50(ms)", "P95(ms)", "P99(ms)", "ERROR RATE", "TOTAL OPS"
+        "{:<15} {:<6} {:<10} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12} {:<10}",
+        "WORKLOAD", "PROTO", "OPS/SEC", "MEAN(ms)", "P50(ms)", "P95(ms)", "P99(ms)", "MAX(ms)", "ERROR RATE", "TOTAL OPS"
     );
-    println!("{:-<80}", "");
+    println!("{:-<125}", "");
 
     for result in results {
         println!(
-            "{:<15} {:<10.2} {:<12.2} {:<12.2} {:<12.2} {:<12.2%} {:<10}",
+            "{:<15} {:<6} {:<10.2} {:<12.2} {:<12.2} {:<12.2} {:<12.2} {:<12.2} {:<12} {:<10}",
             result.workload_type,
+            result.protocol,
             result.ops_per_sec,
+            result.latency_mean_ms,
             result.latency_p50_ms,
             result.latency_p95_ms,
             result.latency_p99_ms,
-            result.error_rate,
+            result.latency_max_ms,
+            format!("{:.2}%", result.error_rate * 100.0),
             result.total_ops
         );
     }
-    println!("{:-<80}", "");
+    println!("{:-<125}", "");
 }
 
 pub fn save_json_report(results: &[WorkloadResult], filename: &str) -> std::io::Result<()> {
@@ -0,0 +1,312 @@
+use super::*;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Get,
+    Set,
+    Del,
+    Incr,
+    Scan,
+}
+
+impl std::str::FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "get" => Ok(Op::Get),
+            "set" => Ok(Op::Set),
+            "del" => Ok(Op::Del),
+            "incr" => Ok(Op::Incr),
+            "scan" => Ok(Op::Scan),
+            other => Err(format!("unknown op '{}'", other)),
+        }
+    }
+}
+
+/// Relative op weights, e.g. "get=50,set=20,incr=20,scan=10".
+#[derive(Debug, Clone)]
+pub struct OpWeights {
+    ops: Vec<(Op, u32)>,
+    total: u32,
+}
+
+impl OpWeights {
+    pub fn from_map(weights: &HashMap<String, u32>) -> Result<Self, String> {
+        let mut ops = Vec::new();
+        for (name, weight) in weights {
+            ops.push((name.parse::<Op>()?, *weight));
+        }
+        Self::from_ops(ops)
+    }
+
+    fn from_ops(mut ops: Vec<(Op, u32)>) -> Result<Self, String> {
+        ops.retain(|(_, w)| *w > 0);
+        // Stable order so the same spec always maps rolls to the same ops
+        ops.sort_by_key(|(op, _)| *op as u8);
+        let total = ops.iter().map(|(_, w)| *w).sum::<u32>();
+        if total == 0 {
+            return Err("at least one op needs a positive weight".to_string());
+        }
+        Ok(Self { ops, total })
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Op {
+        let mut roll = rng.gen_range(0..self.total);
+        for (op, weight) in &self.ops {
+            if roll < *weight {
+                return *op;
+            }
+            roll -= weight;
+        }
+        unreachable!("roll is always below the total weight")
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    pub fn weight(&self, op: Op) -> u32 {
+        self.ops
+            .iter()
+            .find(|(o, _)| *o == op)
+            .map_or(0, |(_, w)| *w)
+    }
+}
+
+impl std::str::FromStr for OpWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ops = Vec::new();
+        for part in s.split(',').filter(|p| !p.trim().is_empty()) {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected op=weight, got '{}'", part))?;
+            let weight = weight
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("invalid weight for '{}'", name.trim()))?;
+            ops.push((name.parse::<Op>()?, weight));
+        }
+        Self::from_ops(ops)
+    }
+}
+
+/// TOML workload spec:
+///
+/// ```toml
+/// key_prefix = "custom:"
+/// key_count = 100000
+/// value_size = 64
+///
+/// [weights]
+/// get = 50
+/// set = 20
+/// incr = 20
+/// scan = 10
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct CustomSpec {
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    #[serde(default = "default_key_count")]
+    pub key_count: usize,
+    #[serde(default = "default_value_size")]
+    pub value_size: usize,
+    pub weights: HashMap<String, u32>,
+}
+
+fn default_key_prefix() -> String {
+    "custom:".to_string()
+}
+
+fn default_key_count() -> usize {
+    1_000_000
+}
+
+fn default_value_size() -> usize {
+    64
+}
+
+impl CustomSpec {
+    pub fn into_workload(self) -> Result<CustomWorkload, String> {
+        Ok(CustomWorkload {
+            weights: OpWeights::from_map(&self.weights)?,
+            key_prefix: self.key_prefix,
+            key_count: self.key_count,
+            value_size_bytes: self.value_size,
+        })
+    }
+}
+
+pub struct CustomWorkload {
+    pub key_prefix: String,
+    pub key_count: usize,
+    pub value_size_bytes: usize,
+    pub weights: OpWeights,
+}
+
+impl CustomWorkload {
+    pub fn from_toml(spec: &str) -> Result<Self, String> {
+        let spec: CustomSpec = toml::from_str(spec).map_err(|e| e.to_string())?;
+        spec.into_workload()
+    }
+}
+
+#[async_trait::async_trait]
+impl Workload for CustomWorkload {
+    async fn run(&self, client: &Client, concurrency: usize, duration: std::time::Duration) -> WorkloadResult {
+        let start = Instant::now();
+        let mut latencies = Vec::new();
+        let mut errors = 0;
+        let mut total_ops = 0;
+
+        let handles: Vec<_> = (0..concurrency)
+            .map(|_| {
+                let client = client.clone();
+                let key_prefix = self.key_prefix.clone();
+                let key_count = self.key_count;
+                let value_size = self.value_size_bytes;
+                let weights = self.weights.clone();
+
+                tokio::spawn(async move {
+                    let mut local_latencies = Vec::new();
+                    let mut local_errors = 0;
+                    let mut local_ops = 0;
+
+                    let start = Instant::now();
+                    while start.elapsed() < duration {
+                        let (op, key) = {
+                            let mut rng = rand::thread_rng();
+                            let op = weights.sample(&mut rng);
+                            let key_id = rng.gen_range(0..key_count);
+                            (op, format!("{}{}", key_prefix, key_id))
+                        };
+
+                        let op_start = Instant::now();
+                        let result = match op {
+                            Op::Get => client.get(&key).await,
+                            Op::Set => {
+                                let value: String = (0..value_size).map(|_| 'A').collect();
+                                client.set(&key, &value, None).await
+                            }
+                            Op::Del => client.del(&key).await,
+                            Op::Incr => client.incr(&key, 1).await,
+                            Op::Scan => client.scan(&format!("{}*", key_prefix), 100).await,
+                        };
+
+                        match result {
+                            Ok(_) => {
+                                local_latencies.push(op_start.elapsed().as_millis() as f64);
+                                local_ops += 1;
+                            }
+                            Err(_) => {
+                                local_errors += 1;
+                            }
+                        }
+                    }
+
+                    (local_latencies, local_errors, local_ops)
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let (lats, errs, ops) = handle.await.unwrap();
+            latencies.extend(lats);
+            errors += errs;
+            total_ops += ops;
+        }
+
+        let duration_sec = start.elapsed().as_secs_f64();
+        let ops_per_sec = total_ops as f64 / duration_sec;
+
+        let latency = LatencyStats::from_samples(latencies);
+
+        let error_rate = if total_ops + errors > 0 {
+            errors as f64 / (total_ops + errors) as f64
+        } else {
+            0.0
+        };
+
+        WorkloadResult {
+            workload_type: "Custom".to_string(),
+            protocol: client.protocol().to_string(),
+            key_distribution: KeyDistribution::Uniform.to_string(),
+            total_ops,
+            duration_sec,
+            ops_per_sec,
+            latency_min_ms: latency.min,
+            latency_mean_ms: latency.mean,
+            latency_max_ms: latency.max,
+            latency_p50_ms: latency.p50,
+            latency_p95_ms: latency.p95,
+            latency_p99_ms: latency.p99,
+            error_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_weights_string_distribution() {
+        let weights: OpWeights = "get=50,set=20,incr=20,scan=10".parse().unwrap();
+        assert_eq!(weights.total(), 100);
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let samples = 100_000;
+        let mut counts: HashMap<Op, u32> = HashMap::new();
+        for _ in 0..samples {
+            *counts.entry(weights.sample(&mut rng)).or_default() += 1;
+        }
+
+        assert_eq!(counts.get(&Op::Del), None);
+        for op in [Op::Get, Op::Set, Op::Incr, Op::Scan] {
+            let expected = weights.weight(op) as f64 / weights.total() as f64;
+            let observed = counts[&op] as f64 / samples as f64;
+            assert!(
+                (observed - expected).abs() < 0.01,
+                "{:?}: expected {:.3}, observed {:.3}",
+                op,
+                expected,
+                observed
+            );
+        }
+    }
+
+    #[test]
+    fn test_toml_spec() {
+        let workload = CustomWorkload::from_toml(
+            r#"
+            key_prefix = "bench:"
+            value_size = 128
+
+            [weights]
+            get = 3
+            del = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(workload.key_prefix, "bench:");
+        assert_eq!(workload.key_count, 1_000_000);
+        assert_eq!(workload.value_size_bytes, 128);
+        assert_eq!(workload.weights.weight(Op::Get), 3);
+        assert_eq!(workload.weights.weight(Op::Del), 1);
+    }
+
+    #[test]
+    fn test_invalid_weights() {
+        assert!("get=abc".parse::<OpWeights>().is_err());
+        assert!("fly=10".parse::<OpWeights>().is_err());
+        assert!("get=0".parse::<OpWeights>().is_err());
+    }
+}
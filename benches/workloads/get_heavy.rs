@@ -1,10 +1,10 @@
 use super::*;
-use rand::Rng;
 use std::time::Instant;
 
 pub struct GetHeavyWorkload {
     pub key_prefix: String,
     pub key_count: usize,
+    pub key_distribution: KeyDistribution,
 }
 
 #[async_trait::async_trait]
@@ -14,12 +14,13 @@ impl Workload for GetHeavyWorkload {
         let mut latencies = Vec::new();
         let mut errors = 0;
         let mut total_ops = 0;
+        let keys = KeySampler::new(self.key_distribution, self.key_count);
 
         let handles: Vec<_> = (0..concurrency)
             .map(|_| {
                 let client = client.clone();
                 let key_prefix = self.key_prefix.clone();
-                let key_count = self.key_count;
+                let keys = keys.clone();
 
                 tokio::spawn(async move {
                     let mut local_latencies = Vec::new();
@@ -28,7 +29,7 @@ impl Workload for GetHeavyWorkload {
 
                     let start = Instant::now();
                     while start.elapsed() < duration {
-                        let key_id = rand::thread_rng().gen_range(0..key_count);
+                        let key_id = keys.sample(&mut rand::thread_rng());
                         let key = format!("{}{}", key_prefix, key_id);
 
                         let op_start = Instant::now();
@@ -58,11 +59,7 @@ impl Workload for GetHeavyWorkload {
         let duration_sec = start.elapsed().as_secs_f64();
         let ops_per_sec = total_ops as f64 / duration_sec;
 
-        // Calculate percentiles
-        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let p50 = latencies.get((latencies.len() as f64 * 0.5) as usize).copied().unwrap_or(0.0);
-        let p95 = latencies.get((latencies.len() as f64 * 0.95) as usize).copied().unwrap_or(0.0);
-        let p99 = latencies.get((latencies.len() as f64 * 0.99) as usize).copied().unwrap_or(0.0);
+        let latency = LatencyStats::from_samples(latencies);
 
         let error_rate = if total_ops + errors > 0 {
             errors as f64 / (total_ops + errors) as f64
@@ -72,12 +69,17 @@ impl Workload for GetHeavyWorkload {
 
         WorkloadResult {
             workload_type: "GET-heavy".to_string(),
+            protocol: client.protocol().to_string(),
+            key_distribution: self.key_distribution.to_string(),
             total_ops,
             duration_sec,
             ops_per_sec,
-            latency_p50_ms: p50,
-            latency_p95_ms: p95,
-            latency_p99_ms: p99,
+            latency_min_ms: latency.min,
+            latency_mean_ms: latency.mean,
+            latency_max_ms: latency.max,
+            latency_p50_ms: latency.p50,
+            latency_p95_ms: latency.p95,
+            latency_p99_ms: latency.p99,
             error_rate,
         }
     }
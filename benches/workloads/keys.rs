@@ -0,0 +1,135 @@
+use rand::Rng;
+use std::sync::Arc;
+
+/// How a workload picks key ids out of `0..key_count`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyDistribution {
+    Uniform,
+    /// Key id `k` is drawn with probability proportional to `1 / (k+1)^theta`,
+    /// so id 0 is the hottest. `theta` 0 is uniform; YCSB's default is 0.99.
+    Zipfian { theta: f64 },
+}
+
+impl KeyDistribution {
+    /// `name` is "uniform" or "zipf"/"zipfian"; `theta` only applies to the
+    /// latter.
+    pub fn parse(name: &str, theta: f64) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "uniform" => Ok(Self::Uniform),
+            "zipf" | "zipfian" if theta.is_finite() && theta >= 0.0 => Ok(Self::Zipfian { theta }),
+            "zipf" | "zipfian" => Err(format!("invalid zipfian theta {}", theta)),
+            other => Err(format!("unknown key distribution '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for KeyDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Zipfian { theta } => write!(f, "zipfian(theta={})", theta),
+        }
+    }
+}
+
+/// Draws key ids for one workload run. Cloning is cheap, so every worker
+/// gets its own.
+#[derive(Debug, Clone)]
+pub enum KeySampler {
+    Uniform(usize),
+    /// Cumulative probability of each key id, built once per run so a draw
+    /// is a binary search rather than a walk over the key space.
+    Zipfian(Arc<[f64]>),
+}
+
+impl KeySampler {
+    pub fn new(distribution: KeyDistribution, key_count: usize) -> Self {
+        match distribution {
+            KeyDistribution::Uniform => Self::Uniform(key_count),
+            KeyDistribution::Zipfian { theta } => {
+                let mut total = 0.0;
+                let mut cdf: Vec<f64> = (0..key_count)
+                    .map(|k| {
+                        total += 1.0 / ((k + 1) as f64).powf(theta);
+                        total
+                    })
+                    .collect();
+                cdf.iter_mut().for_each(|p| *p /= total);
+                Self::Zipfian(cdf.into())
+            }
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            Self::Uniform(key_count) => rng.gen_range(0..*key_count),
+            Self::Zipfian(cdf) => {
+                let roll: f64 = rng.gen();
+                // Rounding can leave the last entry a hair under 1.0
+                cdf.partition_point(|&p| p < roll).min(cdf.len() - 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_zipfian_skew() {
+        let key_count = 1000;
+        let sampler = KeySampler::new(KeyDistribution::Zipfian { theta: 0.99 }, key_count);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let samples = 200_000;
+        let mut counts = vec![0u32; key_count];
+        for _ in 0..samples {
+            counts[sampler.sample(&mut rng)] += 1;
+        }
+
+        // P(k) = (1/(k+1)^theta) / H, with H = sum over the key space
+        let h: f64 = (1..=key_count).map(|k| 1.0 / (k as f64).powf(0.99)).sum();
+        for k in [0, 1, 9] {
+            let expected = 1.0 / ((k + 1) as f64).powf(0.99) / h;
+            let observed = counts[k] as f64 / samples as f64;
+            assert!(
+                (observed - expected).abs() < expected * 0.05,
+                "key {}: expected {:.4}, observed {:.4}",
+                k,
+                expected,
+                observed
+            );
+        }
+        // The hottest 1% of keys take well over a third of the draws
+        let hot: u32 = counts[..key_count / 100].iter().sum();
+        assert!(hot as f64 / samples as f64 > 0.35);
+    }
+
+    #[test]
+    fn test_uniform_and_flat_zipfian_cover_the_range() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for distribution in [KeyDistribution::Uniform, KeyDistribution::Zipfian { theta: 0.0 }] {
+            let sampler = KeySampler::new(distribution, 10);
+            let mut counts = [0u32; 10];
+            for _ in 0..100_000 {
+                counts[sampler.sample(&mut rng)] += 1;
+            }
+            for count in counts {
+                assert!((9_000..11_000).contains(&count), "{}: {:?}", distribution, counts);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_distribution() {
+        assert_eq!(KeyDistribution::parse("uniform", 0.99), Ok(KeyDistribution::Uniform));
+        assert_eq!(
+            KeyDistribution::parse("zipf", 0.99),
+            Ok(KeyDistribution::Zipfian { theta: 0.99 })
+        );
+        assert_eq!(KeyDistribution::Zipfian { theta: 0.99 }.to_string(), "zipfian(theta=0.99)");
+        assert!(KeyDistribution::parse("zipfian", -1.0).is_err());
+        assert!(KeyDistribution::parse("gaussian", 0.99).is_err());
+    }
+}
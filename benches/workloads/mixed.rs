@@ -6,8 +6,11 @@ pub struct MixedWorkload {
     pub key_prefix: String,
     pub value_size_bytes: usize,
     pub read_write_ratio: f64, // 0.7 = 70% reads, 30% writes
+    pub key_distribution: KeyDistribution,
 }
 
+const KEY_COUNT: usize = 1_000_000;
+
 #[async_trait::async_trait]
 impl Workload for MixedWorkload {
     async fn run(&self, client: &Client, concurrency: usize, duration: std::time::Duration) -> WorkloadResult {
@@ -15,6 +18,7 @@ impl Workload for MixedWorkload {
         let mut latencies = Vec::new();
         let mut errors = 0;
         let mut total_ops = 0;
+        let keys = KeySampler::new(self.key_distribution, KEY_COUNT);
 
         let handles: Vec<_> = (0..concurrency)
             .map(|_| {
@@ -22,6 +26,7 @@ impl Workload for MixedWorkload {
                 let key_prefix = self.key_prefix.clone();
                 let value_size = self.value_size_bytes;
                 let ratio = self.read_write_ratio;
+                let keys = keys.clone();
 
                 tokio::spawn(async move {
                     let mut local_latencies = Vec::new();
@@ -31,15 +36,12 @@ impl Workload for MixedWorkload {
                     let start = Instant::now();
                     while start.elapsed() < duration {
                         let is_read = rand::thread_rng().gen_bool(ratio);
+                        let key = format!("{}{}", key_prefix, keys.sample(&mut rand::thread_rng()));
 
                         let op_start = Instant::now();
                         let result = if is_read {
-                            let key_id = rand::thread_rng().gen_range(0..1_000_000);
-                            let key = format!("{}{}", key_prefix, key_id);
                             client.get(&key).await
                         } else {
-                            let key_id = rand::thread_rng().gen_range(0..1_000_000);
-                            let key = format!("{}{}", key_prefix, key_id);
                             let value: String = (0..value_size).map(|_| 'A').collect();
                             client.set(&key, &value, None).await
                         };
@@ -70,11 +72,7 @@ impl Workload for MixedWorkload {
         let duration_sec = start.elapsed().as_secs_f64();
         let ops_per_sec = total_ops as f64 / duration_sec;
 
-        // Calculate percentiles
-        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let p50 = latencies.get((latencies.len() as f64 * 0.5) as usize).copied().unwrap_or(0.0);
-        let p95 = latencies.get((latencies.len() as f64 * 0.95) as usize).copied().unwrap_or(0.0);
-        let p99 = latencies.get((latencies.len() as f64 * 0.99) as usize).copied().unwrap_or(0.0);
+        let latency = LatencyStats::from_samples(latencies);
 
         let error_rate = if total_ops + errors > 0 {
             errors as f64 / (total_ops + errors) as f64
@@ -84,12 +82,17 @@ impl Workload for MixedWorkload {
 
         WorkloadResult {
             workload_type: format!("Mixed ({:.0}% read)", self.read_write_ratio * 100.0),
+            protocol: client.protocol().to_string(),
+            key_distribution: self.key_distribution.to_string(),
             total_ops,
             duration_sec,
             ops_per_sec,
-            latency_p50_ms: p50,
-            latency_p95_ms: p95,
-            latency_p99_ms: p99,
+            latency_min_ms: latency.min,
+            latency_mean_ms: latency.mean,
+            latency_max_ms: latency.max,
+            latency_p50_ms: latency.p50,
+            latency_p95_ms: latency.p95,
+            latency_p99_ms: latency.p99,
             error_rate,
         }
     }
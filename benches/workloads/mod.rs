@@ -1,11 +1,37 @@
 use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tonic::transport::Channel;
+
+mod custom;
+mod get_heavy;
+mod keys;
+mod mixed;
+mod set_heavy;
+mod stats;
+
+mod kvstore {
+    tonic::include_proto!("kvstore");
+}
+
+pub use custom::{CustomWorkload, OpWeights};
+pub use get_heavy::GetHeavyWorkload;
+pub use keys::{KeyDistribution, KeySampler};
+pub use mixed::MixedWorkload;
+pub use set_heavy::SetHeavyWorkload;
+pub use stats::LatencyStats;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct WorkloadResult {
     pub workload_type: String,
+    pub protocol: String,
+    pub key_distribution: String,
     pub total_ops: u64,
     pub duration_sec: f64,
     pub ops_per_sec: f64,
+    pub latency_min_ms: f64,
+    pub latency_mean_ms: f64,
+    pub latency_max_ms: f64,
     pub latency_p50_ms: f64,
     pub latency_p95_ms: f64,
     pub latency_p99_ms: f64,
@@ -17,37 +43,224 @@ pub trait Workload {
     async fn run(&self, client: &Client, concurrency: usize, duration: std::time::Duration) -> WorkloadResult;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Rest,
+    Grpc,
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Rest => write!(f, "REST"),
+            Protocol::Grpc => write!(f, "gRPC"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Grpc(tonic::Status),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "REST request failed: {}", e),
+            ClientError::Grpc(status) => write!(f, "gRPC call failed: {}", status),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// The server under test, over REST or gRPC. Clones share the same
+/// connections, so every worker can take its own.
 #[derive(Clone)]
 pub struct Client {
-    base_url: String,
+    transport: Transport,
     api_key: Option<String>,
 }
 
+#[derive(Clone)]
+enum Transport {
+    Rest {
+        base_url: String,
+        // reqwest keeps idle connections per host, so ops reuse them
+        http: reqwest::Client,
+    },
+    Grpc {
+        // Each channel is one HTTP/2 connection multiplexing many calls;
+        // calls rotate across them so one connection's flow control and
+        // framing don't cap a high-concurrency run.
+        channels: Arc<[kvstore::kv_store_client::KvStoreClient<Channel>]>,
+        next: Arc<AtomicUsize>,
+    },
+}
+
 impl Client {
     pub fn new(base_url: String, api_key: Option<String>) -> Self {
-        Self { base_url, api_key }
+        Self {
+            transport: Transport::Rest { base_url, http: reqwest::Client::new() },
+            api_key,
+        }
     }
 
-    pub async fn get(&self, key: &str) -> Result<reqwest::Response, reqwest::Error> {
-        let client = reqwest::Client::new();
-        let mut req = client.get(format!("{}/v1/get?key={}", self.base_url, key));
-        if let Some(api_key) = &self.api_key {
-            req = req.header("X-API-Key", api_key);
+    /// Opens `connections` channels to the gRPC server at `url` up front, so
+    /// connecting is not part of any op's latency. `api_key` goes in each
+    /// call's `x-api-key` metadata.
+    pub async fn grpc(url: &str, connections: usize, api_key: Option<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let endpoint = Channel::from_shared(url.to_string())?;
+        let mut channels = Vec::new();
+        for _ in 0..connections.max(1) {
+            let channel = endpoint.connect().await?;
+            channels.push(kvstore::kv_store_client::KvStoreClient::new(channel));
+        }
+        Ok(Self {
+            transport: Transport::Grpc { channels: channels.into(), next: Arc::new(AtomicUsize::new(0)) },
+            api_key,
+        })
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        match self.transport {
+            Transport::Rest { .. } => Protocol::Rest,
+            Transport::Grpc { .. } => Protocol::Grpc,
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<(), ClientError> {
+        match &self.transport {
+            Transport::Rest { base_url, http } => {
+                self.send(http.get(format!("{}/v1/get?key={}", base_url, key))).await
+            }
+            Transport::Grpc { .. } => {
+                let request = kvstore::GetRequest { key: key.to_string() };
+                grpc_result(self.grpc_client().get(self.grpc_request(request)).await)
+            }
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: &str, ttl: Option<u64>) -> Result<(), ClientError> {
+        self.set_compressed(key, value, ttl, "auto").await
+    }
+
+    /// SET with an explicit `compression` mode ("auto", "none", "lz4", "zstd").
+    /// gRPC has no per-request mode, so it always gets the server's default.
+    pub async fn set_compressed(&self, key: &str, value: &str, ttl: Option<u64>, compression: &str) -> Result<(), ClientError> {
+        match &self.transport {
+            Transport::Rest { base_url, http } => {
+                let req = http.post(format!("{}/v1/set", base_url))
+                    .json(&serde_json::json!({
+                        "key": key,
+                        "value": base64::encode(value),
+                        "ttl": ttl,
+                        "compression": compression
+                    }));
+                self.send(req).await
+            }
+            Transport::Grpc { .. } => {
+                let request = kvstore::SetRequest {
+                    key: key.to_string(),
+                    value: value.as_bytes().to_vec(),
+                    ttl_seconds: ttl.unwrap_or(0),
+                };
+                grpc_result(self.grpc_client().set(self.grpc_request(request)).await)
+            }
         }
-        req.send().await
     }
 
-    pub async fn set(&self, key: &str, value: &str, ttl: Option<u64>) -> Result<reqwest::Response, reqwest::Error> {
-        let client = reqwest::Client::new();
-        let mut req = client.post(format!("{}/v1/set", self.base_url))
-            .json(&serde_json::json!({
-                "key": key,
-                "value": base64::encode(value),
-                "ttl": ttl
-            }));
+    pub async fn del(&self, key: &str) -> Result<(), ClientError> {
+        match &self.transport {
+            Transport::Rest { base_url, http } => {
+                let req = http.post(format!("{}/v1/del", base_url))
+                    .json(&serde_json::json!({
+                        "key": key
+                    }));
+                self.send(req).await
+            }
+            Transport::Grpc { .. } => {
+                let request = kvstore::DeleteRequest { key: key.to_string() };
+                grpc_result(self.grpc_client().delete(self.grpc_request(request)).await)
+            }
+        }
+    }
+
+    pub async fn incr(&self, key: &str, delta: i64) -> Result<(), ClientError> {
+        match &self.transport {
+            Transport::Rest { base_url, http } => {
+                let req = http.post(format!("{}/v1/incr", base_url))
+                    .json(&serde_json::json!({
+                        "key": key,
+                        "delta": delta
+                    }));
+                self.send(req).await
+            }
+            Transport::Grpc { .. } => {
+                let request = kvstore::IncrRequest { key: key.to_string(), delta };
+                grpc_result(self.grpc_client().incr(self.grpc_request(request)).await)
+            }
+        }
+    }
+
+    pub async fn scan(&self, pattern: &str, limit: u64) -> Result<(), ClientError> {
+        match &self.transport {
+            Transport::Rest { base_url, http } => {
+                let req = http.get(format!("{}/v1/scan", base_url))
+                    .query(&[("pattern", pattern.to_string()), ("limit", limit.to_string())]);
+                self.send(req).await
+            }
+            Transport::Grpc { .. } => {
+                let request = kvstore::ScanRequest {
+                    pattern: pattern.to_string(),
+                    limit,
+                    cursor: String::new(),
+                };
+                // The op ends with the last streamed item, like a REST body
+                let result: Result<(), tonic::Status> = async {
+                    let mut stream = self.grpc_client().scan(self.grpc_request(request)).await?.into_inner();
+                    while stream.message().await?.is_some() {}
+                    Ok(())
+                }
+                .await;
+                grpc_result(result)
+            }
+        }
+    }
+
+    async fn send(&self, mut req: reqwest::RequestBuilder) -> Result<(), ClientError> {
         if let Some(api_key) = &self.api_key {
             req = req.header("X-API-Key", api_key);
         }
-        req.send().await
+        req.send().await.map(|_| ()).map_err(ClientError::Http)
     }
-}
\ No newline at end of file
+
+    fn grpc_request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(api_key) = self.api_key.as_deref().and_then(|key| key.parse().ok()) {
+            request.metadata_mut().insert("x-api-key", api_key);
+        }
+        request
+    }
+
+    fn grpc_client(&self) -> kvstore::kv_store_client::KvStoreClient<Channel> {
+        match &self.transport {
+            Transport::Grpc { channels, next } => {
+                channels[next.fetch_add(1, Ordering::Relaxed) % channels.len()].clone()
+            }
+            Transport::Rest { .. } => unreachable!("not a gRPC client"),
+        }
+    }
+}
+
+// Any REST response counts as a completed op whatever its status, so a gRPC
+// call likewise only fails when the server never answered it.
+fn grpc_result<T>(result: Result<T, tonic::Status>) -> Result<(), ClientError> {
+    match result {
+        Err(status) if matches!(status.code(), tonic::Code::Unavailable | tonic::Code::Unknown | tonic::Code::Cancelled) => {
+            Err(ClientError::Grpc(status))
+        }
+        _ => Ok(()),
+    }
+}
@@ -5,6 +5,7 @@ use std::time::Instant;
 pub struct SetHeavyWorkload {
     pub key_prefix: String,
     pub value_size_bytes: usize,
+    pub compression: String, // per-SET compression mode
 }
 
 #[async_trait::async_trait]
@@ -20,6 +21,7 @@ impl Workload for SetHeavyWorkload {
                 let client = client.clone();
                 let key_prefix = self.key_prefix.clone();
                 let value_size = self.value_size_bytes;
+                let compression = self.compression.clone();
 
                 tokio::spawn(async move {
                     let mut local_latencies = Vec::new();
@@ -33,7 +35,7 @@ impl Workload for SetHeavyWorkload {
                         let value: String = (0..value_size).map(|_| 'A').collect();
 
                         let op_start = Instant::now();
-                        match client.set(&key, &value, None).await {
+                        match client.set_compressed(&key, &value, None, &compression).await {
                             Ok(_) => {
                                 local_latencies.push(op_start.elapsed().as_millis() as f64);
                                 local_ops += 1;
@@ -59,11 +61,7 @@ impl Workload for SetHeavyWorkload {
         let duration_sec = start.elapsed().as_secs_f64();
         let ops_per_sec = total_ops as f64 / duration_sec;
 
-        // Calculate percentiles
-        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let p50 = latencies.get((latencies.len() as f64 * 0.5) as usize).copied().unwrap_or(0.0);
-        let p95 = latencies.get((latencies.len() as f64 * 0.95) as usize).copied().unwrap_or(0.0);
-        let p99 = latencies.get((latencies.len() as f64 * 0.99) as usize).copied().unwrap_or(0.0);
+        let latency = LatencyStats::from_samples(latencies);
 
         let error_rate = if total_ops + errors > 0 {
             errors as f64 / (total_ops + errors) as f64
@@ -72,13 +70,21 @@ impl Workload for SetHeavyWorkload {
         };
 
         WorkloadResult {
-            workload_type: "SET-heavy".to_string(),
+            workload_type: match self.compression.as_str() {
+                "auto" => "SET-heavy".to_string(),
+                mode => format!("SET-heavy {}", mode),
+            },
+            protocol: client.protocol().to_string(),
+            key_distribution: KeyDistribution::Uniform.to_string(),
             total_ops,
             duration_sec,
             ops_per_sec,
-            latency_p50_ms: p50,
-            latency_p95_ms: p95,
-            latency_p99_ms: p99,
+            latency_min_ms: latency.min,
+            latency_mean_ms: latency.mean,
+            latency_max_ms: latency.max,
+            latency_p50_ms: latency.p50,
+            latency_p95_ms: latency.p95,
+            latency_p99_ms: latency.p99,
             error_rate,
         }
     }
@@ -0,0 +1,91 @@
+use serde::Serialize;
+
+/// Summary of one workload's latency samples, in milliseconds. Every field is
+/// 0.0 when there were no samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl LatencyStats {
+    pub fn from_samples(mut samples: Vec<f64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_by(f64::total_cmp);
+        Self {
+            min: samples[0],
+            max: samples[samples.len() - 1],
+            mean: samples.iter().sum::<f64>() / samples.len() as f64,
+            p50: percentile(&samples, 50.0),
+            p95: percentile(&samples, 95.0),
+            p99: percentile(&samples, 99.0),
+        }
+    }
+}
+
+/// Nearest-rank percentile of `sorted` (ascending): the smallest sample with
+/// at least `p`% of the samples at or below it, i.e. the `ceil(p/100 * n)`th.
+/// No interpolation, so the result is always a latency that was observed.
+/// 0.0 for no samples.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let samples: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&samples, 50.0), 50.0);
+        assert_eq!(percentile(&samples, 95.0), 95.0);
+        assert_eq!(percentile(&samples, 99.0), 99.0);
+        assert_eq!(percentile(&samples, 100.0), 100.0);
+        assert_eq!(percentile(&samples, 0.0), 1.0);
+
+        // The textbook example: 15, 20, 35, 40, 50
+        let samples = [15.0, 20.0, 35.0, 40.0, 50.0];
+        assert_eq!(percentile(&samples, 5.0), 15.0);
+        assert_eq!(percentile(&samples, 30.0), 20.0);
+        assert_eq!(percentile(&samples, 40.0), 20.0);
+        assert_eq!(percentile(&samples, 50.0), 35.0);
+        assert_eq!(percentile(&samples, 100.0), 50.0);
+
+        // Too few samples for a distinct p99: it is the slowest one
+        let samples = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_eq!(percentile(&samples, 99.0), 10.0);
+        assert_eq!(percentile(&[7.0], 50.0), 7.0);
+        assert_eq!(percentile(&[], 99.0), 0.0);
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let stats = LatencyStats::from_samples(vec![4.0, 1.0, 3.0, 2.0]);
+        assert_eq!(
+            stats,
+            LatencyStats {
+                min: 1.0,
+                max: 4.0,
+                mean: 2.5,
+                p50: 2.0,
+                p95: 4.0,
+                p99: 4.0,
+            }
+        );
+
+        let empty = LatencyStats::from_samples(Vec::new());
+        assert_eq!(empty, LatencyStats::default());
+        assert!(!empty.mean.is_nan());
+    }
+}
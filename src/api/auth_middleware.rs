@@ -1,24 +1,19 @@
 use axum::async_trait;
-use axum::extract::{FromRequestParts, State};
+use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
-use axum::RequestPartsExt;
 use std::net::SocketAddr;
 
+use crate::api::connections::{is_websocket_upgrade, TrackedConnection, RESUME_TOKEN_HEADER};
 use crate::auth::types::AuthContext;
 use crate::auth::AuthManager;
 
+/// Request extension the router installs so [`AuthenticatedUser`] can reach
+/// the auth manager whatever the handler's own state type is.
 #[derive(Clone)]
 pub struct AuthState {
     pub auth_manager: std::sync::Arc<AuthManager>,
 }
 
-// Implement FromRef<()> for AuthState to satisfy axum's extractor requirements
-impl axum::extract::FromRef<()> for AuthState {
-    fn from_ref(_: &()) -> AuthState {
-        panic!("AuthState cannot be extracted from unit type");
-    }
-}
-
 #[derive(Debug)]
 pub struct AuthenticatedUser(pub AuthContext);
 
@@ -29,49 +24,113 @@ where
 {
     type Rejection = crate::api::error::ApiError;
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        // Extract AuthState from the request parts
-        let State(auth_state) = parts.extract::<State<AuthState>>().await.map_err(|_| {
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // The router-level middleware already resolved this request
+        if let Some(ctx) = parts.extensions.get::<AuthContext>() {
+            return Ok(AuthenticatedUser(ctx.clone()));
+        }
+
+        let auth_state = parts.extensions.get::<AuthState>().cloned().ok_or(
             crate::api::error::ApiError::AuthError(
                 crate::auth::types::AuthError::InvalidCredentials,
-            )
-        })?;
+            ),
+        )?;
 
-        let headers = &parts.headers;
-        let source_ip = parts
-            .extensions
-            .get::<axum::extract::ConnectInfo<SocketAddr>>()
-            .map(|ci| ci.0.ip())
-            .unwrap_or("127.0.0.1".parse().unwrap());
+        let resume_token = parts
+            .headers
+            .get(RESUME_TOKEN_HEADER)
+            .filter(|_| is_websocket_upgrade(&parts.headers))
+            .cloned();
+        let ctx = match resume_token {
+            Some(token) => resume(&auth_state, parts, &token).await?,
+            None => authenticate(&auth_state, parts).await?,
+        };
+        if let Some(conn) = parts.extensions.get::<TrackedConnection>() {
+            conn.authenticate(&ctx).await;
+        }
+        parts.extensions.insert(ctx.clone());
+        Ok(AuthenticatedUser(ctx))
+    }
+}
 
-        // Check API Key
-        if let Some(api_key) = headers.get("X-API-Key") {
-            if let Ok(key_str) = api_key.to_str() {
-                let ctx = auth_state
-                    .auth_manager
-                    .authenticate_api_key(key_str, source_ip)
-                    .await
-                    .map_err(crate::api::error::ApiError::AuthError)?;
-                return Ok(AuthenticatedUser(ctx));
-            }
+// A WebSocket client reconnecting within the resume window takes its
+// detached session over, which then stands in for this request's connection. The
+// identity it had is checked again, as a fresh login would be.
+async fn resume(
+    auth_state: &AuthState,
+    parts: &mut Parts,
+    token: &axum::http::HeaderValue,
+) -> Result<AuthContext, crate::api::error::ApiError> {
+    let invalid = || {
+        crate::api::error::ApiError::AuthError(crate::auth::types::AuthError::InvalidCredentials)
+    };
+    let conn = parts
+        .extensions
+        .get::<TrackedConnection>()
+        .cloned()
+        .ok_or_else(invalid)?;
+    let token = token.to_str().map_err(|_| invalid())?;
+    let (resumed, ctx) = conn.resume(token).await.map_err(|e| {
+        tracing::debug!("Session not resumed: {}", e);
+        invalid()
+    })?;
+
+    match auth_state
+        .auth_manager
+        .resume_session(ctx, source_ip(parts))
+        .await
+    {
+        Ok(ctx) => {
+            parts.extensions.insert(resumed);
+            Ok(ctx)
+        }
+        Err(e) => {
+            resumed.reject().await;
+            Err(crate::api::error::ApiError::AuthError(e))
         }
+    }
+}
 
-        // Check JWT
-        if let Some(auth_header) = headers.get("Authorization") {
-            if let Ok(auth_str) = auth_header.to_str() {
-                if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                    let ctx = auth_state
-                        .auth_manager
-                        .authenticate_jwt(token, source_ip)
-                        .await
-                        .map_err(crate::api::error::ApiError::AuthError)?;
-                    return Ok(AuthenticatedUser(ctx));
-                }
-            }
+fn source_ip(parts: &Parts) -> std::net::IpAddr {
+    parts
+        .extensions
+        .get::<axum::extract::ConnectInfo<SocketAddr>>()
+        .map(|ci| ci.0.ip())
+        .unwrap_or("127.0.0.1".parse().unwrap())
+}
+
+async fn authenticate(
+    auth_state: &AuthState,
+    parts: &Parts,
+) -> Result<AuthContext, crate::api::error::ApiError> {
+    let headers = &parts.headers;
+    let source_ip = source_ip(parts);
+
+    // Check API Key
+    if let Some(api_key) = headers.get("X-API-Key") {
+        if let Ok(key_str) = api_key.to_str() {
+            return auth_state
+                .auth_manager
+                .authenticate_api_key(key_str, source_ip)
+                .await
+                .map_err(crate::api::error::ApiError::AuthError);
         }
+    }
 
-        Err(crate::api::error::ApiError::AuthError(
-            crate::auth::types::AuthError::InvalidCredentials,
-        ))
+    // Check JWT
+    if let Some(auth_header) = headers.get("Authorization") {
+        if let Ok(auth_str) = auth_header.to_str() {
+            if let Some(token) = auth_str.strip_prefix("Bearer ") {
+                return auth_state
+                    .auth_manager
+                    .authenticate_jwt(token, source_ip)
+                    .await
+                    .map_err(crate::api::error::ApiError::AuthError);
+            }
+        }
     }
+
+    Err(crate::api::error::ApiError::AuthError(
+        crate::auth::types::AuthError::InvalidCredentials,
+    ))
 }
@@ -0,0 +1,136 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use crate::api::error::ApiError;
+use crate::storage::ValueSlice;
+
+/// A single HTTP `Range: bytes=...` request, converted to the inclusive,
+/// end-relative indexes `StorageEngine::getrange` takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl ByteRange {
+    /// `None` when no `Range` header was sent. Only one `bytes` range is
+    /// supported; anything else is rejected with 400.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, ApiError> {
+        let Some(value) = headers.get(header::RANGE) else {
+            return Ok(None);
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| ApiError::InvalidRequest("Range is not valid ASCII".to_string()))?;
+        Self::parse(value).map(Some)
+    }
+
+    pub fn parse(value: &str) -> Result<Self, ApiError> {
+        let invalid = || ApiError::InvalidRequest(format!("unsupported Range '{}'", value));
+
+        let spec = value.trim().strip_prefix("bytes=").ok_or_else(invalid)?;
+        if spec.contains(',') {
+            return Err(invalid());
+        }
+        let (first, last) = spec.split_once('-').ok_or_else(invalid)?;
+        let number = |s: &str| s.trim().parse::<i64>().map_err(|_| invalid());
+
+        match (first.trim().is_empty(), last.trim().is_empty()) {
+            // bytes=a-b
+            (false, false) => Ok(Self {
+                start: number(first)?,
+                end: number(last)?,
+            }),
+            // bytes=a-
+            (false, true) => Ok(Self {
+                start: number(first)?,
+                end: -1,
+            }),
+            // bytes=-n, the last n bytes
+            (true, false) => match number(last)? {
+                0 => Err(invalid()),
+                n => Ok(Self { start: -n, end: -1 }),
+            },
+            (true, true) => Err(invalid()),
+        }
+    }
+}
+
+/// `206 Partial Content` carrying the raw slice, or `416` when the requested
+/// range starts past the end of the value (HTTP has no empty `Content-Range`).
+pub fn partial_content(slice: ValueSlice) -> Response {
+    if slice.bytes.is_empty() {
+        let mut response = ApiError::RangeNotSatisfiable(slice.total_len).into_response();
+        insert_header(
+            &mut response,
+            header::CONTENT_RANGE,
+            format!("bytes */{}", slice.total_len),
+        );
+        return response;
+    }
+
+    let last = slice.start + slice.bytes.len() - 1;
+    let content_range = format!("bytes {}-{}/{}", slice.start, last, slice.total_len);
+    let mut response = (StatusCode::PARTIAL_CONTENT, slice.bytes).into_response();
+    insert_header(&mut response, header::CONTENT_RANGE, content_range);
+    insert_header(&mut response, header::ACCEPT_RANGES, "bytes".to_string());
+    response
+}
+
+fn insert_header(response: &mut Response, name: header::HeaderName, value: String) {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range_forms() {
+        assert_eq!(
+            ByteRange::parse("bytes=2-5").unwrap(),
+            ByteRange { start: 2, end: 5 }
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=7-").unwrap(),
+            ByteRange { start: 7, end: -1 }
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=-3").unwrap(),
+            ByteRange { start: -3, end: -1 }
+        );
+        assert!(ByteRange::parse("bytes=0-1,4-5").is_err());
+        assert!(ByteRange::parse("items=0-1").is_err());
+        assert!(ByteRange::parse("bytes=-").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_partial_content_response() {
+        let response = partial_content(ValueSlice {
+            bytes: b"2345".to_vec(),
+            start: 2,
+            total_len: 10,
+        });
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"2345");
+
+        let response = partial_content(ValueSlice {
+            bytes: Vec::new(),
+            start: 0,
+            total_len: 10,
+        });
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "RANGE_NOT_SATISFIABLE");
+    }
+}
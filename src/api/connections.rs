@@ -0,0 +1,260 @@
+//! Connection accounting for the API servers.
+//!
+//! REST and gRPC register clients with the [`ConnectionManager`] differently:
+//!
+//! * **REST** tracks each *request*. axum hands us requests, not sockets, so
+//!   [`track_connection`] calls `accept` when a request arrives and holds the
+//!   returned [`ConnectionGuard`] until the response has been produced. The
+//!   guard's `Drop` then closes the entry (`CloseReason::ClientClosed`) from a
+//!   spawned task, so the count can lag a finished request by one scheduler
+//!   tick. A keep-alive socket issuing ten sequential requests is therefore
+//!   ten short-lived entries, and `max_connections` bounds concurrent
+//!   *requests*, not open sockets. Evicting an entry only drops its
+//!   bookkeeping; the request it belongs to still completes, so REST
+//!   deployments that want a hard cap should use `evict_policy = "reject"`.
+//!   A WebSocket upgrade keeps its entry for as long as the socket is open,
+//!   and its client can later resume the session with the token it was
+//!   handed (see [`RESUME_TOKEN_HEADER`]).
+//! * **gRPC** multiplexes every call over one HTTP/2 connection, so
+//!   [`TrackedStream`] ties the guard to the accepted socket instead and
+//!   the entry lives exactly as long as the TCP connection.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::Response;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::api::error::ApiError;
+use crate::auth::types::AuthContext;
+use crate::connection::{CloseReason, ConnectionError, ConnectionGuard, ConnectionManager};
+
+/// Header a WebSocket upgrade's response carries the session's resume
+/// token in, and a reconnecting client presents it in instead of
+/// credentials. Only issued when `resume_window_sec` is set.
+pub const RESUME_TOKEN_HEADER: &str = "x-kv-resume-token";
+
+/// Request extension naming the manager entry that a REST request was
+/// admitted under, so authentication can attach the user and role to it.
+/// The entry stays open while any clone is held.
+#[derive(Clone)]
+pub struct TrackedConnection {
+    guard: Arc<ConnectionGuard>,
+    manager: Arc<ConnectionManager>,
+}
+
+impl TrackedConnection {
+    fn id(&self) -> uuid::Uuid {
+        self.guard.id()
+    }
+
+    /// The token this connection's session can be resumed with, once it
+    /// has authenticated.
+    pub async fn resume_token(&self) -> Option<String> {
+        self.guard.info().await.resume_token
+    }
+
+    /// Takes over the session detached under `token` as a new entry, and
+    /// returns it with the identity the session authenticated as. That
+    /// identity is not rechecked here.
+    pub async fn resume(&self, token: &str) -> Result<(Self, AuthContext), ConnectionError> {
+        let addr = self.guard.info().await.addr;
+        let guard = self.manager.resume(addr, token).await?;
+        let Some(ctx) = guard.info().await.auth else {
+            return Err(ConnectionError::InvalidResumeToken);
+        };
+        let resumed = Self {
+            guard: Arc::new(guard),
+            manager: self.manager.clone(),
+        };
+        Ok((resumed, ctx))
+    }
+
+    /// Closes the entry as refused, so its session is not left to resume.
+    pub async fn reject(&self) {
+        self.manager
+            .close_connection(self.id(), CloseReason::AuthFailed)
+            .await;
+    }
+
+    /// Marks the connection active, holding off its idle timeout.
+    pub async fn touch(&self) {
+        self.guard.touch().await;
+    }
+
+    /// Records who the connection belongs to; superusers (`*`) get the
+    /// highest eviction priority.
+    pub async fn authenticate(&self, ctx: &AuthContext) {
+        let role = ctx
+            .roles
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "default".to_string());
+        let priority = if ctx.permissions.iter().any(|p| p == "*") {
+            u8::MAX
+        } else {
+            0
+        };
+
+        // The entry may already have been evicted; the request still runs
+        if let Err(e) = self
+            .manager
+            .authenticate(self.id(), ctx.clone(), role, priority)
+            .await
+        {
+            debug!(conn_id = %self.id(), "Cannot attach user to connection: {}", e);
+        }
+    }
+}
+
+/// axum middleware admitting each request through the connection manager.
+/// A full manager answers `503 Service Unavailable`.
+pub async fn track_connection(
+    State(connections): State<Arc<ConnectionManager>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    // `oneshot`-driven routers (tests) have no peer address
+    let addr = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ci| ci.0)
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+
+    let guard = connections
+        .accept(addr, is_websocket_upgrade(request.headers()))
+        .await
+        .map_err(|e| ApiError::Unavailable(e.to_string()))?;
+    let guard = Arc::new(guard);
+    request.extensions_mut().insert(TrackedConnection {
+        guard: guard.clone(),
+        manager: connections,
+    });
+
+    // A handler that outlives the response (a WebSocket) holds its own clone
+    let response = next.run(request).await;
+    drop(guard);
+    Ok(response)
+}
+
+/// Whether the request asks to become a WebSocket, the only kind of REST
+/// connection whose session can be resumed.
+pub fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// An accepted gRPC socket that stays registered with the connection
+/// manager until it is dropped.
+pub struct TrackedStream {
+    inner: TcpStream,
+    _guard: ConnectionGuard,
+}
+
+impl TrackedStream {
+    /// Registers `stream`, or hands back the error when the manager is full
+    /// so the caller can drop the socket.
+    pub async fn accept(
+        connections: &ConnectionManager,
+        stream: TcpStream,
+    ) -> Result<Self, ConnectionError> {
+        let addr = stream
+            .peer_addr()
+            .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+        let guard = connections.accept(addr, false).await?;
+        Ok(Self {
+            inner: stream,
+            _guard: guard,
+        })
+    }
+}
+
+impl tonic::transport::server::Connected for TrackedStream {
+    type ConnectInfo = <TcpStream as tonic::transport::server::Connected>::ConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+impl AsyncRead for TrackedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TrackedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::config::ConnectionConfig;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    fn app(connections: Arc<ConnectionManager>) -> axum::Router {
+        axum::Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                connections,
+                track_connection,
+            ))
+    }
+
+    #[tokio::test]
+    async fn test_full_manager_returns_503() {
+        let connections = Arc::new(ConnectionManager::new(ConnectionConfig {
+            max_connections: 1,
+            evict_policy: "reject".to_string(),
+            ..Default::default()
+        }));
+
+        let _held = connections
+            .accept("127.0.0.1:4000".parse().unwrap(), false)
+            .await
+            .unwrap();
+        let response = app(connections)
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = app(Arc::new(ConnectionManager::new(Default::default())))
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
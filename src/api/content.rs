@@ -0,0 +1,210 @@
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::Engine;
+use serde::de::{DeserializeOwned, Error as _, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::api::error::ApiError;
+
+pub const MSGPACK: &str = "application/msgpack";
+
+/// Encoding of a REST request or response body. JSON unless the client asks
+/// for MessagePack (`Accept` / `Content-Type: application/msgpack`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl Format {
+    /// The response format the `Accept` header asks for.
+    pub fn accepted(headers: &HeaderMap) -> Self {
+        Self::from_media_types(headers.get(header::ACCEPT))
+    }
+
+    /// The format a request body is in, going by `Content-Type`.
+    pub fn of_body(headers: &HeaderMap) -> Self {
+        Self::from_media_types(headers.get(header::CONTENT_TYPE))
+    }
+
+    fn from_media_types(value: Option<&HeaderValue>) -> Self {
+        let Some(value) = value.and_then(|v| v.to_str().ok()) else {
+            return Self::Json;
+        };
+        let msgpack = value.split(',').any(|media| {
+            let essence = media.split(';').next().unwrap_or_default().trim();
+            essence.eq_ignore_ascii_case(MSGPACK)
+                || essence.eq_ignore_ascii_case("application/x-msgpack")
+        });
+        if msgpack {
+            Self::Msgpack
+        } else {
+            Self::Json
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::accepted(&parts.headers))
+    }
+}
+
+/// A response body in the negotiated [`Format`]. Errors stay JSON.
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        match self.0 {
+            Format::Json => Json(self.1).into_response(),
+            Format::Msgpack => match rmp_serde::to_vec_named(&self.1) {
+                Ok(body) => ([(header::CONTENT_TYPE, MSGPACK)], body).into_response(),
+                Err(e) => {
+                    tracing::error!("Failed to encode msgpack response: {}", e);
+                    ApiError::InternalServerError.into_response()
+                }
+            },
+        }
+    }
+}
+
+/// A request body decoded as JSON or MessagePack, by `Content-Type`. Either
+/// way a body that does not decode is a 400.
+pub struct NegotiatedBody<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for NegotiatedBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = Format::of_body(req.headers());
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| ApiError::InvalidRequest(e.body_text()))?;
+        let value = match format {
+            Format::Json => serde_json::from_slice(&body).map_err(|e| e.to_string()),
+            Format::Msgpack => rmp_serde::from_slice(&body).map_err(|e| e.to_string()),
+        };
+        value
+            .map(Self)
+            .map_err(|e| ApiError::InvalidRequest(format!("Invalid request body: {}", e)))
+    }
+}
+
+/// A stored value in a REST body: a base64 string in JSON, raw bytes in
+/// MessagePack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueBytes(pub Vec<u8>);
+
+impl Serialize for ValueBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let encoded = String::deserialize(deserializer)?;
+            return base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map(Self)
+                .map_err(|_| D::Error::custom("Invalid base64 value"));
+        }
+        deserializer.deserialize_byte_buf(BytesVisitor).map(Self)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("binary value bytes")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    // Clients without a bin type send an array of small integers
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        key: String,
+        value: ValueBytes,
+    }
+
+    #[test]
+    fn test_value_bytes_are_base64_in_json_and_raw_in_msgpack() {
+        let item = Item {
+            key: "k".to_string(),
+            value: ValueBytes(vec![0, 255, 7]),
+        };
+
+        let json = serde_json::to_string(&item).unwrap();
+        assert_eq!(json, r#"{"key":"k","value":"AP8H"}"#);
+        assert_eq!(serde_json::from_str::<Item>(&json).unwrap(), item);
+        assert!(serde_json::from_str::<Item>(r#"{"key":"k","value":"%%"}"#).is_err());
+
+        let packed = rmp_serde::to_vec_named(&item).unwrap();
+        assert!(packed.windows(5).any(|w| w == [0xc4, 3, 0, 255, 7]));
+        assert_eq!(rmp_serde::from_slice::<Item>(&packed).unwrap(), item);
+    }
+
+    #[test]
+    fn test_format_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Format::accepted(&headers), Format::Json);
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("text/html, application/msgpack;q=0.9"),
+        );
+        assert_eq!(Format::accepted(&headers), Format::Msgpack);
+        assert_eq!(Format::of_body(&headers), Format::Json);
+
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-msgpack"),
+        );
+        assert_eq!(Format::of_body(&headers), Format::Msgpack);
+    }
+}
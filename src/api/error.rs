@@ -1,5 +1,6 @@
-use axum::http::StatusCode;
+use axum::http::{header, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
+use serde::Serialize;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,25 +20,274 @@ pub enum ApiError {
     #[error("Storage error: {0}")]
     StorageError(#[from] crate::storage::error::StorageError),
 
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("Rate limit exceeded, retry in {retry_after_secs}s")]
+    RateLimited { retry_after_secs: u64 },
+
+    #[error("Range not satisfiable: the value is {0} bytes")]
+    RangeNotSatisfiable(usize),
+
     #[error("Internal server error")]
     InternalServerError,
 }
 
+/// Machine-readable cause of a REST error, sent as `error.code`. Clients
+/// branch on these, so a code is never renamed or given a new meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    KeyNotFound,
+    VersionMismatch,
+    Conflict,
+    InvalidRequest,
+    NotAnInteger,
+    KeyTooLarge,
+    ValueTooLarge,
+    QuotaExceeded,
+    OutOfMemory,
+    MaintenanceMode,
+    BulkLoadInProgress,
+    ReplicationTimeout, // applied and logged, but not confirmed by enough replicas
+    WalBacklog,         // the WAL is behind; retry shortly
+    Unauthenticated,
+    AccountLocked,
+    PermissionDenied,
+    RateLimited,
+    RangeNotSatisfiable,
+    Unavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::KeyNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::VersionMismatch | ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::InvalidRequest | ErrorCode::NotAnInteger => StatusCode::BAD_REQUEST,
+            ErrorCode::KeyTooLarge | ErrorCode::ValueTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::QuotaExceeded | ErrorCode::OutOfMemory => StatusCode::INSUFFICIENT_STORAGE,
+            ErrorCode::MaintenanceMode | ErrorCode::BulkLoadInProgress | ErrorCode::Unavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ErrorCode::ReplicationTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorCode::WalBacklog | ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Unauthenticated | ErrorCode::AccountLocked => StatusCode::UNAUTHORIZED,
+            ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+            ErrorCode::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Every REST error is `{"error": {"code", "message", "key"?}}`
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: ErrorCode,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'a str>,
+}
+
+// Seconds a client should wait before retrying a write during maintenance
+const MAINTENANCE_RETRY_AFTER_SECS: u64 = 30;
+
+// ... and when the WAL write queue is full, which clears much faster
+const WAL_BACKLOG_RETRY_AFTER_SECS: u64 = 1;
+
+impl ApiError {
+    pub fn code(&self) -> ErrorCode {
+        use crate::auth::types::AuthError;
+        use crate::storage::error::StorageError;
+        use crate::wal::error::WalError;
+
+        match self {
+            ApiError::KeyNotFound(_) => ErrorCode::KeyNotFound,
+            ApiError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            ApiError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            ApiError::AuthError(e) => match e {
+                AuthError::PermissionDenied(..) => ErrorCode::PermissionDenied,
+                AuthError::AccountLocked { .. } => ErrorCode::AccountLocked,
+                AuthError::CatalogError(_) | AuthError::StorageError(_) => ErrorCode::Internal,
+                AuthError::InvalidCredentials
+                | AuthError::UserNotFound(_)
+                | AuthError::AccountDisabled
+                | AuthError::AccountExpired
+                | AuthError::IpNotAllowed(_)
+                | AuthError::JwtError(_) => ErrorCode::Unauthenticated,
+            },
+            ApiError::StorageError(e) => match e {
+                StorageError::KeyNotFound(_) => ErrorCode::KeyNotFound,
+                StorageError::VersionMismatch { .. } => ErrorCode::VersionMismatch,
+                StorageError::Concurrency(_) => ErrorCode::Conflict,
+                StorageError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+                StorageError::NotAnInteger(_) => ErrorCode::NotAnInteger,
+                StorageError::KeyTooLarge { .. } => ErrorCode::KeyTooLarge,
+                StorageError::ValueTooLarge { .. } => ErrorCode::ValueTooLarge,
+                StorageError::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+                StorageError::OutOfMemory { .. } => ErrorCode::OutOfMemory,
+                StorageError::MaintenanceMode => ErrorCode::MaintenanceMode,
+                StorageError::BulkLoadInProgress => ErrorCode::BulkLoadInProgress,
+                StorageError::Wal(WalError::AckTimeout { .. }) => ErrorCode::ReplicationTimeout,
+                StorageError::Wal(WalError::QueueFull { .. }) => ErrorCode::WalBacklog,
+                _ => ErrorCode::Internal,
+            },
+            ApiError::Unavailable(_) => ErrorCode::Unavailable,
+            ApiError::RateLimited { .. } => ErrorCode::RateLimited,
+            ApiError::RangeNotSatisfiable(_) => ErrorCode::RangeNotSatisfiable,
+            ApiError::InternalServerError => ErrorCode::Internal,
+        }
+    }
+
+    // What the client is told. Failed authentication reads the same however
+    // it failed, so a caller can't probe which users exist or are disabled,
+    // and internal failures are only logged, never described.
+    pub(crate) fn message(&self) -> String {
+        match (self, self.code()) {
+            (_, ErrorCode::Internal) => "Internal server error".to_string(),
+            (ApiError::AuthError(_), ErrorCode::Unauthenticated) => {
+                "Invalid credentials".to_string()
+            }
+            (ApiError::AuthError(e), _) => e.to_string(),
+            (ApiError::StorageError(e), _) => e.to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    // The key the error is about, when it names one
+    fn key(&self) -> Option<&str> {
+        use crate::storage::error::StorageError;
+
+        match self {
+            ApiError::StorageError(
+                StorageError::KeyNotFound(key)
+                | StorageError::NotAnInteger(key)
+                | StorageError::ValueTooLarge { key, .. },
+            ) => Some(key),
+            _ => None,
+        }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let status = match &self {
-            ApiError::KeyNotFound(_) => StatusCode::NOT_FOUND,
-            ApiError::PermissionDenied(_) => StatusCode::FORBIDDEN,
-            ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
-            ApiError::AuthError(_) => StatusCode::UNAUTHORIZED,
-            ApiError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ApiError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+        let code = self.code();
+        if code == ErrorCode::Internal {
+            tracing::error!(error = %self, "Request failed");
+        }
+
+        let retry_after = match (&self, code) {
+            (_, ErrorCode::MaintenanceMode | ErrorCode::BulkLoadInProgress) => {
+                Some(MAINTENANCE_RETRY_AFTER_SECS)
+            }
+            (_, ErrorCode::WalBacklog) => Some(WAL_BACKLOG_RETRY_AFTER_SECS),
+            (ApiError::RateLimited { retry_after_secs }, _) => Some(*retry_after_secs),
+            _ => None,
         };
 
-        let body = serde_json::json!({
-            "error": self.to_string(),
-        });
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code,
+                message: self.message(),
+                key: self.key(),
+            },
+        };
+        let mut response = (code.status(), axum::Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::types::AuthError;
+    use crate::storage::error::StorageError;
 
-        (status, axum::Json(body)).into_response()
+    async fn reply(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_error_body_carries_code_and_key() {
+        let (status, body) = reply(StorageError::KeyNotFound("user:1".to_string()).into()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "code": "KEY_NOT_FOUND",
+                    "message": "Key not found: user:1",
+                    "key": "user:1",
+                }
+            })
+        );
+
+        let (status, body) = reply(
+            StorageError::VersionMismatch {
+                expected: 1,
+                actual: 2,
+            }
+            .into(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "VERSION_MISMATCH");
+        assert!(body["error"].get("key").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_auth_failures_look_alike() {
+        let failures = [
+            AuthError::InvalidCredentials,
+            AuthError::UserNotFound("alice".to_string()),
+            AuthError::AccountDisabled,
+            AuthError::AccountExpired,
+            AuthError::IpNotAllowed("10.0.0.1".parse().unwrap()),
+        ];
+        for failure in failures {
+            let (status, body) = reply(failure.into()).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                body,
+                serde_json::json!({
+                    "error": {"code": "UNAUTHENTICATED", "message": "Invalid credentials"}
+                })
+            );
+        }
+
+        let (status, body) =
+            reply(AuthError::PermissionDenied("GET".to_string(), "bob".to_string()).into()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["code"], "PERMISSION_DENIED");
+    }
+
+    #[tokio::test]
+    async fn test_internal_errors_are_not_described() {
+        let failure =
+            StorageError::Io(std::io::Error::other("/var/lib/kv/snapshots: disk on fire"));
+        let (status, body) = reply(failure.into()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {"code": "INTERNAL", "message": "Internal server error"}
+            })
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use axum::async_trait;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::api::error::ApiError;
+
+/// [`axum::Json`], except that a body it can't decode is rejected with the
+/// usual REST error body rather than axum's plain-text one.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::from_request(req, state)
+            .await
+            .map_err(|e| ApiError::InvalidRequest(e.body_text()))?;
+        Ok(Self(value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// [`axum::extract::Query`], rejecting a query string it can't decode with
+/// the usual REST error body.
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(value) = axum::extract::Query::from_request_parts(parts, state)
+            .await
+            .map_err(|e| ApiError::InvalidRequest(e.body_text()))?;
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use tower::ServiceExt;
+
+    #[derive(serde::Deserialize)]
+    struct Params {
+        #[allow(dead_code)]
+        n: u64,
+    }
+
+    async fn error_of(request: Request) -> (StatusCode, serde_json::Value) {
+        let app = axum::Router::new().route(
+            "/",
+            post(|Query(_): Query<Params>, Json(_): Json<Params>| async {}),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rejections_use_the_error_body() {
+        let (status, body) = error_of(
+            Request::post("/?n=x")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"n": 1}"#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "INVALID_REQUEST");
+
+        let (status, body) = error_of(
+            Request::post("/?n=1")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"n": "#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "INVALID_REQUEST");
+        assert!(body["error"]["message"].as_str().unwrap().len() > 0);
+    }
+}
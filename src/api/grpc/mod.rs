@@ -1,17 +1,72 @@
-use std::net::SocketAddr;
+pub mod auth;
+pub mod packed;
+pub mod service;
+
+pub mod kvstore {
+    tonic::include_proto!("kvstore");
+}
+
 use std::sync::Arc;
+use tokio::net::TcpListener;
+use futures_util::StreamExt;
+use tokio_stream::wrappers::TcpListenerStream;
 use tonic::transport::Server;
 
+use crate::api::connections::TrackedStream;
+use crate::api::rate_limit::RateLimiter;
+use crate::api::request_id::GrpcRequestIdLayer;
+use crate::auth::AuthManager;
+use crate::connection::ConnectionManager;
 use crate::storage::StorageEngine;
 
-pub async fn start_grpc_server(addr: SocketAddr, engine: Arc<StorageEngine>) {
-    let svc = kvstore::kv_store_server::KvStoreServer::new(super::service::KvStoreService::new(engine));
+/// Serves the `KvStore` service. Every call is authenticated through
+/// `auth_manager` and counted against the caller's `limiter` bucket, which
+/// REST requests share (see [`auth`]).
+pub async fn start_grpc_server(
+    listener: TcpListener,
+    engine: Arc<StorageEngine>,
+    auth_manager: Arc<AuthManager>,
+    connections: Arc<ConnectionManager>,
+    limiter: Arc<RateLimiter>,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> Result<(), tonic::transport::Error> {
+    let svc = kvstore::kv_store_server::KvStoreServer::new(service::KvStoreService::new(
+        engine,
+        auth_manager.clone(),
+    ));
 
-    tracing::info!("Starting gRPC server on {}", addr);
+    if let Ok(addr) = listener.local_addr() {
+        tracing::info!("Starting gRPC server on {}", addr);
+    }
 
     Server::builder()
+        // The request ID first, so authentication failures are logged under it
+        .layer(GrpcRequestIdLayer)
+        .layer(auth::GrpcAuthLayer::new(auth_manager, limiter))
         .add_service(svc)
-        .serve(addr)
+        .serve_with_incoming_shutdown(tracked_incoming(listener, connections), shutdown)
         .await
-        .unwrap();
-}
\ No newline at end of file
+}
+
+// Sockets refused by a full connection manager are dropped (closed) here,
+// before tonic ever sees them
+fn tracked_incoming(
+    listener: TcpListener,
+    connections: Arc<ConnectionManager>,
+) -> impl futures_util::Stream<Item = std::io::Result<TrackedStream>> {
+    TcpListenerStream::new(listener).filter_map(move |conn| {
+        let connections = connections.clone();
+        async move {
+            match conn {
+                Ok(stream) => match TrackedStream::accept(&connections, stream).await {
+                    Ok(tracked) => Some(Ok(tracked)),
+                    Err(e) => {
+                        tracing::warn!("Rejecting gRPC connection: {}", e);
+                        None
+                    }
+                },
+                Err(e) => Some(Err(e)),
+            }
+        }
+    })
+}
@@ -0,0 +1,117 @@
+/// Packed form of a Batch response: every value concatenated into one blob
+/// with an end-offset table, so big batches avoid one protobuf message per
+/// value. Missing keys take no space in `data` and have `found[i] == false`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackedValues {
+    pub data: Vec<u8>,
+    pub offsets: Vec<u64>, // end of value i in `data`
+    pub found: Vec<bool>,
+    pub versions: Vec<u64>,
+}
+
+/// One looked-up value as returned in the structured form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchValue {
+    pub key: String,
+    pub value: Option<(Vec<u8>, u64)>, // (value, version)
+}
+
+pub fn pack(values: &[BatchValue]) -> PackedValues {
+    let total = values
+        .iter()
+        .filter_map(|v| v.value.as_ref())
+        .map(|(value, _)| value.len())
+        .sum();
+    let mut packed = PackedValues {
+        data: Vec::with_capacity(total),
+        offsets: Vec::with_capacity(values.len()),
+        found: Vec::with_capacity(values.len()),
+        versions: Vec::with_capacity(values.len()),
+    };
+
+    for entry in values {
+        match &entry.value {
+            Some((value, version)) => {
+                packed.data.extend_from_slice(value);
+                packed.found.push(true);
+                packed.versions.push(*version);
+            }
+            None => {
+                packed.found.push(false);
+                packed.versions.push(0);
+            }
+        }
+        packed.offsets.push(packed.data.len() as u64);
+    }
+    packed
+}
+
+/// Splits a packed response back into per-key values, pairing them with the
+/// request `keys`. Returns `None` if the tables are inconsistent.
+pub fn unpack(keys: &[String], packed: &PackedValues) -> Option<Vec<BatchValue>> {
+    let n = keys.len();
+    if packed.offsets.len() != n || packed.found.len() != n || packed.versions.len() != n {
+        return None;
+    }
+
+    let mut start = 0usize;
+    let mut values = Vec::with_capacity(n);
+    for (i, key) in keys.iter().enumerate() {
+        let end = usize::try_from(packed.offsets[i]).ok()?;
+        let value = packed.data.get(start..end)?;
+        values.push(BatchValue {
+            key: key.clone(),
+            value: packed.found[i].then(|| (value.to_vec(), packed.versions[i])),
+        });
+        start = end;
+    }
+    if start != packed.data.len() {
+        return None;
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_matches_structured() {
+        let structured = vec![
+            BatchValue {
+                key: "a".to_string(),
+                value: Some((b"alpha".to_vec(), 3)),
+            },
+            BatchValue {
+                key: "missing".to_string(),
+                value: None,
+            },
+            BatchValue {
+                key: "empty".to_string(),
+                value: Some((Vec::new(), 1)),
+            },
+            BatchValue {
+                key: "b".to_string(),
+                value: Some((vec![0u8, 255, 7], 9)),
+            },
+        ];
+        let keys: Vec<String> = structured.iter().map(|v| v.key.clone()).collect();
+
+        let packed = pack(&structured);
+        assert_eq!(packed.data.len(), 8);
+        assert_eq!(packed.offsets, vec![5, 5, 5, 8]);
+        assert_eq!(unpack(&keys, &packed), Some(structured));
+    }
+
+    #[test]
+    fn test_unpack_rejects_inconsistent_tables() {
+        let keys = vec!["a".to_string()];
+        let mut packed = pack(&[BatchValue {
+            key: "a".to_string(),
+            value: Some((b"alpha".to_vec(), 1)),
+        }]);
+        packed.offsets[0] = 99;
+        assert_eq!(unpack(&keys, &packed), None);
+        assert_eq!(unpack(&[], &packed), None);
+    }
+}
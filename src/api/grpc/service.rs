@@ -1,45 +1,658 @@
-use axum::{routing::post, Router};
-use std::net::SocketAddr;
+//! `KvStore` gRPC service, backed by the same [`StorageEngine`] calls as the
+//! REST handlers. Writes are logged to the WAL with the configured sync
+//! policy once the engine has applied them.
+//!
+//! Status codes, per RPC:
+//!
+//! | RPC      | `NOT_FOUND`  | `INVALID_ARGUMENT`                 | `UNAVAILABLE`             |
+//! |----------|--------------|------------------------------------|---------------------------|
+//! | `Get`    | missing key  | empty key                          |                           |
+//! | `Set`    |              | empty key                          | maintenance / bulk load   |
+//! | `Delete` | missing key  | empty key                          | maintenance / bulk load   |
+//! | `Incr`   |              | empty key, non-integer, overflow   | maintenance / bulk load   |
+//! | `Batch`  |              | empty key, too many keys           |                           |
+//!
+//! `Scan` has no request-level failures of its own. Every call is
+//! authenticated first (see [`super::auth`]): missing or bad credentials are
+//! `UNAUTHENTICATED`, and a user over their rate limit `RESOURCE_EXHAUSTED`.
+//! Keys are then resolved in the namespace named by `x-kv-namespace`
+//! metadata, as over REST, and an op the caller holds no permission for on a
+//! key (or, for `Scan`, the pattern) is `PERMISSION_DENIED`. Any write may also fail
+//! with `RESOURCE_EXHAUSTED` (namespace quota or memory budget) or, with semi-synchronous
+//! replication, `DEADLINE_EXCEEDED` when too few replicas acknowledged it in
+//! time (the write itself is kept). Anything else the engine or WAL reports
+//! is `INTERNAL`.
+
+// The `KvStore` trait fixes every handler's error as an unboxed `Status`, so
+// the helpers feeding it return one too rather than boxing and unboxing it
+#![allow(clippy::result_large_err)]
+
 use std::sync::Arc;
-use tower_http::trace::TraceLayer;
-use tracing::Level;
 
-use crate::api::auth_middleware::AuthState;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use super::kvstore::kv_store_server::KvStore;
+use super::kvstore::{
+    BatchRequest, BatchResponse, BatchValue, DeleteRequest, DeleteResponse, GetRequest,
+    GetResponse, IncrRequest, IncrResponse, ScanRequest, ScanResponse, SetRequest, SetResponse,
+};
+use super::packed;
+use crate::api::error::{ApiError, ErrorCode};
+use crate::api::request_options::RequestNamespace;
+use crate::auth::types::AuthContext;
 use crate::auth::AuthManager;
-use crate::storage::StorageEngine;
+use crate::background::metrics::OpTimer;
+use crate::storage::namespace;
+use crate::storage::{CompressionMode, StorageEngine, StorageError};
+use crate::wal::error::WalError;
+
+// Entries fetched from the engine per page while streaming a Scan
+const SCAN_PAGE_SIZE: usize = 256;
 
-pub async fn start_rest_server(
-    addr: SocketAddr,
+pub struct KvStoreService {
     engine: Arc<StorageEngine>,
     auth_manager: Arc<AuthManager>,
-) {
-    let auth_state = AuthState {
-        auth_manager: auth_manager.clone(),
-    };
-
-    let app = Router::new()
-        .route("/v1/get", axum::routing::get(super::handlers::get_handler))
-        .route("/v1/set", post(super::handlers::set_handler))
-        .route("/v1/del", post(super::handlers::delete_handler))
-        .layer(axum::middleware::from_extractor_with_state::<
-            super::auth_middleware::AuthenticatedUser,
-            _,
-        >(auth_state))
-        .layer(TraceLayer::new_for_http().make_span_with(|request| {
-            tracing::span!(
-                Level::INFO,
-                "http_request",
-                method = %request.method(),
-                uri = %request.uri(),
-                version = ?request.version(),
-            )
+}
+
+impl KvStoreService {
+    pub fn new(engine: Arc<StorageEngine>, auth_manager: Arc<AuthManager>) -> Self {
+        Self {
+            engine,
+            auth_manager,
+        }
+    }
+
+    /// The stored key for `key` in the caller's namespace, once the caller
+    /// may do `op` on it.
+    fn authorize(&self, caller: &Caller, op: &str, key: &str) -> Result<String, Status> {
+        require_key(key)?;
+        let key = namespace::resolve_key(&caller.namespace.0, key).map_err(to_status)?;
+        self.auth_manager
+            .authorize(&caller.ctx, op, &key)
+            .map_err(|e| api_status(e.into()))?;
+        Ok(key)
+    }
+}
+
+/// Who made a call, as admitted by [`super::auth::GrpcAuthLayer`], and the
+/// namespace its keys live in.
+struct Caller {
+    ctx: AuthContext,
+    namespace: RequestNamespace,
+}
+
+impl Caller {
+    fn of<T>(request: &Request<T>) -> Result<Self, Status> {
+        let ctx = request
+            .extensions()
+            .get::<AuthContext>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("Invalid credentials"))?;
+        let namespace = RequestNamespace::from_metadata(request.metadata()).map_err(api_status)?;
+        Ok(Self { ctx, namespace })
+    }
+}
+
+#[tonic::async_trait]
+impl KvStore for KvStoreService {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let caller = Caller::of(&request)?;
+        let req = request.into_inner();
+        let key = self.authorize(&caller, "GET", &req.key)?;
+        let timer = OpTimer::start("grpc", "GET");
+
+        // A miss is an answer, not a failed request
+        let entry = self.engine.get(&key).await;
+        if matches!(entry, Ok(_) | Err(StorageError::KeyNotFound(_))) {
+            timer.ok();
+        }
+        let entry = entry.map_err(to_status)?;
+        Ok(Response::new(GetResponse {
+            found: true,
+            value: entry.value,
+            version: entry.version,
+        }))
+    }
+
+    async fn set(&self, request: Request<SetRequest>) -> Result<Response<SetResponse>, Status> {
+        let caller = Caller::of(&request)?;
+        let req = request.into_inner();
+        let key = self.authorize(&caller, "SET", &req.key)?;
+        let timer = OpTimer::start("grpc", "SET");
+        // proto3 has no "unset"; 0 means no expiry
+        let ttl = Some(req.ttl_seconds).filter(|&secs| secs > 0);
+        self.engine
+            .check_entry_size(&key, req.value.len())
+            .map_err(to_status)?;
+
+        let (value, compression) = self
+            .engine
+            .encode_value(&key, req.value, CompressionMode::Auto);
+        let version = self
+            .engine
+            .set_encoded(&key, value, compression, ttl)
+            .await
+            .map_err(to_status)?;
+
+        timer.ok();
+        Ok(Response::new(SetResponse {
+            success: true,
+            version,
+        }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let caller = Caller::of(&request)?;
+        let req = request.into_inner();
+        let key = self.authorize(&caller, "DEL", &req.key)?;
+        let timer = OpTimer::start("grpc", "DEL");
+
+        self.engine.del(&key, None).await.map_err(to_status)?;
+
+        timer.ok();
+        Ok(Response::new(DeleteResponse { success: true }))
+    }
+
+    async fn incr(&self, request: Request<IncrRequest>) -> Result<Response<IncrResponse>, Status> {
+        let caller = Caller::of(&request)?;
+        let req = request.into_inner();
+        let key = self.authorize(&caller, "SET", &req.key)?;
+        let timer = OpTimer::start("grpc", "INCR");
+
+        let new_value = self.engine.incr(&key, req.delta).await.map_err(to_status)?;
+
+        timer.ok();
+        Ok(Response::new(IncrResponse {
+            success: true,
+            new_value,
         }))
-        .with_state(engine);
+    }
+
+    type ScanStream = ReceiverStream<Result<ScanResponse, Status>>;
+
+    /// Streams every live user key matching the glob `pattern` in the
+    /// caller's namespace, up to `limit` entries (0 = no limit). System
+    /// catalog keys are never returned.
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        let caller = Caller::of(&request)?;
+        let req = request.into_inner();
+        let pattern =
+            namespace::resolve_key(&caller.namespace.0, &req.pattern).map_err(to_status)?;
+        self.auth_manager
+            .authorize(&caller.ctx, "SCAN", &pattern)
+            .map_err(|e| api_status(e.into()))?;
+        // Timed until the stream ends, so a page that fails part way through
+        // counts as an error
+        let timer = OpTimer::start("grpc", "SCAN");
+        let namespace = caller.namespace.0;
+        let limit = match req.limit {
+            0 => usize::MAX,
+            n => usize::try_from(n).unwrap_or(usize::MAX),
+        };
+
+        let cursor = Some(req.cursor.as_str()).filter(|cursor| !cursor.is_empty());
+
+        // Fetch the first page up front so an engine error (a bad cursor,
+        // say) fails the call itself
+        let first = self
+            .engine
+            .scan_in(&namespace, &req.pattern, limit.min(SCAN_PAGE_SIZE), cursor)
+            .await
+            .map_err(to_status)?;
+
+        let (tx, rx) = mpsc::channel(SCAN_PAGE_SIZE);
+        let engine = self.engine.clone();
+        tokio::spawn(async move {
+            let mut page = first;
+            let mut remaining = limit;
+            loop {
+                for (key, entry) in page.items {
+                    remaining -= 1;
+                    // A page is never fetched past the limit, so the item
+                    // that uses it up ends the page and the cursor is its
+                    let next_cursor = match &page.next_cursor {
+                        Some(cursor) if remaining == 0 => cursor.clone(),
+                        _ => String::new(),
+                    };
+                    let item = ScanResponse {
+                        key,
+                        value: entry.value,
+                        version: entry.version,
+                        next_cursor,
+                    };
+                    if tx.send(Ok(item)).await.is_err() {
+                        timer.ok();
+                        return; // client went away
+                    }
+                }
+
+                let Some(cursor) = page.next_cursor.filter(|_| remaining > 0) else {
+                    timer.ok();
+                    return;
+                };
+                page = match engine
+                    .scan_in(
+                        &namespace,
+                        &req.pattern,
+                        remaining.min(SCAN_PAGE_SIZE),
+                        Some(&cursor),
+                    )
+                    .await
+                {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let _ = tx.send(Err(to_status(e))).await;
+                        return;
+                    }
+                };
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn batch(
+        &self,
+        request: Request<BatchRequest>,
+    ) -> Result<Response<BatchResponse>, Status> {
+        let caller = Caller::of(&request)?;
+        let req = request.into_inner();
+        let keys = req
+            .keys
+            .iter()
+            .map(|key| self.authorize(&caller, "GET", key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let entries = self.engine.mget(&keys).await.map_err(to_status)?;
+
+        let values: Vec<packed::BatchValue> = req
+            .keys
+            .into_iter()
+            .zip(entries)
+            .map(|(key, entry)| packed::BatchValue {
+                key,
+                value: entry.map(|e| (e.value, e.version)),
+            })
+            .collect();
 
-    tracing::info!("Starting REST server on {}", addr);
+        let response = if req.packed {
+            let packed = packed::pack(&values);
+            BatchResponse {
+                values: Vec::new(),
+                packed_values: packed.data,
+                offsets: packed.offsets,
+                found: packed.found,
+                versions: packed.versions,
+            }
+        } else {
+            BatchResponse {
+                values: values
+                    .into_iter()
+                    .map(|v| {
+                        let found = v.value.is_some();
+                        let (value, version) = v.value.unwrap_or_default();
+                        BatchValue {
+                            key: v.key,
+                            found,
+                            value,
+                            version,
+                        }
+                    })
+                    .collect(),
+                ..Default::default()
+            }
+        };
+        Ok(Response::new(response))
+    }
+}
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+fn require_key(key: &str) -> Result<(), Status> {
+    if key.is_empty() {
+        return Err(Status::invalid_argument("key must not be empty"));
+    }
+    Ok(())
+}
+
+/// Maps engine errors onto gRPC status codes; see the table in the module docs.
+pub fn to_status(e: StorageError) -> Status {
+    match e {
+        StorageError::KeyNotFound(_) => Status::not_found(e.to_string()),
+        StorageError::InvalidRequest(_) | StorageError::NotAnInteger(_) => {
+            Status::invalid_argument(e.to_string())
+        }
+        StorageError::KeyTooLarge { .. } | StorageError::ValueTooLarge { .. } => {
+            Status::invalid_argument(e.to_string())
+        }
+        StorageError::VersionMismatch { .. } => Status::aborted(e.to_string()),
+        StorageError::MaintenanceMode | StorageError::BulkLoadInProgress => {
+            Status::unavailable(e.to_string())
+        }
+        StorageError::QuotaExceeded { .. } | StorageError::OutOfMemory { .. } => {
+            Status::resource_exhausted(e.to_string())
+        }
+        StorageError::Wal(WalError::AckTimeout { .. }) => Status::deadline_exceeded(e.to_string()),
+        StorageError::Wal(WalError::QueueFull { .. }) => Status::resource_exhausted(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+/// Maps request-level failures (authentication, rate limiting, bad
+/// metadata) onto gRPC status codes, with the same messages REST sends.
+pub fn api_status(e: ApiError) -> Status {
+    let message = e.message();
+    match e {
+        ApiError::StorageError(e) => to_status(e),
+        e => match e.code() {
+            ErrorCode::Unauthenticated | ErrorCode::AccountLocked => {
+                Status::unauthenticated(message)
+            }
+            ErrorCode::PermissionDenied => Status::permission_denied(message),
+            ErrorCode::RateLimited => Status::resource_exhausted(message),
+            ErrorCode::InvalidRequest => Status::invalid_argument(message),
+            ErrorCode::KeyNotFound => Status::not_found(message),
+            ErrorCode::Unavailable => Status::unavailable(message),
+            _ => {
+                tracing::error!(error = %e, "gRPC call failed");
+                Status::internal(message)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::types::AuthMethod;
+    use crate::catalog::types::AuditSettings;
+    use crate::catalog::CatalogManager;
+    use crate::storage::StorageConfig;
+    use crate::wal::config::{SyncPolicy, WalConfig};
+    use crate::wal::WalManager;
+    use tokio_stream::StreamExt;
+
+    async fn service() -> (KvStoreService, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("grpc_service_{}", uuid::Uuid::new_v4()));
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        engine.attach_wal(wal);
+        let auth_manager = AuthManager::new(
+            Arc::new(CatalogManager::new(engine.clone())),
+            "secret".to_string(),
+            dir.join("audit.log").to_str().unwrap().to_string(),
+            &AuditSettings::default(),
+        )
+        .unwrap();
+        (KvStoreService::new(engine, Arc::new(auth_manager)), dir)
+    }
+
+    fn caller(permissions: &[&str]) -> AuthContext {
+        AuthContext {
+            user: "alice".to_string(),
+            roles: Vec::new(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            source_ip: "127.0.0.1".parse().unwrap(),
+            auth_method: AuthMethod::Password,
+            session_id: String::new(),
+        }
+    }
+
+    // A call as the auth layer hands it on for a superuser
+    fn call<T>(message: T) -> Request<T> {
+        call_as(caller(&["*"]), message)
+    }
+
+    fn call_as<T>(ctx: AuthContext, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(ctx);
+        request
+    }
+
+    #[tokio::test]
+    async fn test_get_set_delete_incr() {
+        let (svc, dir) = service().await;
+
+        let status = svc
+            .get(call(GetRequest {
+                key: "missing".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let set = svc
+            .set(call(SetRequest {
+                key: "k".to_string(),
+                value: b"v".to_vec(),
+                ttl_seconds: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let got = svc
+            .get(call(GetRequest {
+                key: "k".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(got.value, b"v");
+        assert_eq!(got.version, set.version);
+
+        let status = svc
+            .incr(call(IncrRequest {
+                key: "k".to_string(),
+                delta: 1,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let incr = svc
+            .incr(call(IncrRequest {
+                key: "n".to_string(),
+                delta: 5,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(incr.new_value, 5);
+
+        svc.delete(call(DeleteRequest {
+            key: "k".to_string(),
+        }))
         .await
         .unwrap();
-}
\ No newline at end of file
+        let status = svc
+            .delete(call(DeleteRequest {
+                key: "k".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_scan_streams_past_one_page() {
+        let (svc, dir) = service().await;
+        let total = SCAN_PAGE_SIZE + 10;
+        for i in 0..total {
+            svc.engine
+                .set(&format!("user:{:04}", i), vec![1], None)
+                .await
+                .unwrap();
+        }
+        svc.engine.set("other", vec![1], None).await.unwrap();
+
+        let stream = svc
+            .scan(call(ScanRequest {
+                pattern: "user:*".to_string(),
+                limit: 0,
+                cursor: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let items: Vec<ScanResponse> = stream.map(|r| r.unwrap()).collect().await;
+        assert_eq!(items.len(), total);
+        assert!(items.iter().all(|item| item.key.starts_with("user:")));
+        assert!(items.iter().all(|item| item.next_cursor.is_empty()));
+
+        let stream = svc
+            .scan(call(ScanRequest {
+                pattern: "user:*".to_string(),
+                limit: 3,
+                cursor: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 3);
+
+        // Paging with the cursor of each last item visits every key once
+        let mut cursor = String::new();
+        let mut paged = Vec::new();
+        loop {
+            let stream = svc
+                .scan(call(ScanRequest {
+                    pattern: "user:*".to_string(),
+                    limit: 100,
+                    cursor,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            let page: Vec<ScanResponse> = stream.map(|r| r.unwrap()).collect().await;
+            // A cursor can lead to an empty page when the shards it skips
+            // to hold no more matches
+            cursor = page
+                .last()
+                .map(|item| item.next_cursor.clone())
+                .unwrap_or_default();
+            paged.extend(page.into_iter().map(|item| item.key));
+            if cursor.is_empty() {
+                break;
+            }
+        }
+        paged.sort();
+        paged.dedup();
+        assert_eq!(paged.len(), total);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_authorized_per_key_in_their_namespace() {
+        let (svc, dir) = service().await;
+        let get = |ctx: AuthContext, namespace: Option<&str>, key: &str| {
+            let mut request = call_as(
+                ctx,
+                GetRequest {
+                    key: key.to_string(),
+                },
+            );
+            if let Some(namespace) = namespace {
+                request
+                    .metadata_mut()
+                    .insert("x-kv-namespace", namespace.parse().unwrap());
+            }
+            request
+        };
+
+        // Not admitted by the auth layer at all
+        let status = svc
+            .get(Request::new(GetRequest {
+                key: "k".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let mut set = call_as(
+            caller(&["SET:ns:app1:*", "GET:ns:app1:*"]),
+            SetRequest {
+                key: "k".to_string(),
+                value: b"v".to_vec(),
+                ttl_seconds: 0,
+            },
+        );
+        set.metadata_mut()
+            .insert("x-kv-namespace", "app1".parse().unwrap());
+        svc.set(set).await.unwrap();
+        assert!(svc.engine.get("ns:app1:k").await.is_ok());
+
+        let tenant = caller(&["GET:ns:app1:*", "SCAN:ns:app1:*"]);
+        let got = svc
+            .get(get(tenant.clone(), Some("app1"), "k"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(got.value, b"v");
+        for (namespace, key) in [(None, "k"), (Some("app2"), "k")] {
+            let status = svc
+                .get(get(tenant.clone(), namespace, key))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        }
+
+        // Catalog keys stay out of reach even for a superuser
+        let status = svc
+            .get(get(caller(&["*"]), None, "_sys.users:admin"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut scan = call_as(
+            tenant.clone(),
+            ScanRequest {
+                pattern: "*".to_string(),
+                limit: 0,
+                cursor: String::new(),
+            },
+        );
+        scan.metadata_mut()
+            .insert("x-kv-namespace", "app1".parse().unwrap());
+        let items: Vec<ScanResponse> = svc
+            .scan(scan)
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|r| r.unwrap())
+            .collect()
+            .await;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "k");
+
+        let status = svc
+            .batch(call_as(
+                tenant,
+                BatchRequest {
+                    keys: vec!["k".to_string()],
+                    packed: false,
+                },
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use serde::Serialize;
+
+use crate::background::WorkerHealth;
+use crate::storage::StorageEngine;
+use crate::wal::WalManager;
+
+// Never written, so probing it only takes a shard read lock
+const PROBE_KEY: &str = "_sys.health_probe";
+
+// How long the engine probe may take before the engine counts as stuck
+const ENGINE_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What `/health` and `/ready` look at. Starts not ready; the server calls
+/// [`mark_ready`](Self::mark_ready) once bootstrap is done and the workers
+/// run.
+#[derive(Debug)]
+pub struct HealthState {
+    engine: Arc<StorageEngine>,
+    wal: Arc<WalManager>,
+    workers: parking_lot::RwLock<Option<WorkerHealth>>,
+    ready: AtomicBool,
+}
+
+impl HealthState {
+    pub fn new(engine: Arc<StorageEngine>, wal: Arc<WalManager>) -> Arc<Self> {
+        Arc::new(Self {
+            engine,
+            wal,
+            workers: parking_lot::RwLock::new(None),
+            ready: AtomicBool::new(false),
+        })
+    }
+
+    pub fn mark_ready(&self, workers: WorkerHealth) {
+        *self.workers.write() = Some(workers);
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Every component that is currently failing, with the reason.
+    pub async fn failing(&self) -> Vec<ComponentFailure> {
+        let mut failing = Vec::new();
+
+        if let Some(error) = self.wal.last_sync_error() {
+            failing.push(ComponentFailure::new(
+                "wal",
+                format!("last sync failed: {}", error),
+            ));
+        }
+
+        // Spawned so a wedged shard lock stalls the probe, not this check
+        let engine = self.engine.clone();
+        let probe = tokio::spawn(async move { engine.exists(PROBE_KEY).await });
+        if tokio::time::timeout(ENGINE_PROBE_TIMEOUT, probe)
+            .await
+            .map_or(true, |joined| joined.is_err())
+        {
+            failing.push(ComponentFailure::new(
+                "storage",
+                format!("no response within {:?}", ENGINE_PROBE_TIMEOUT),
+            ));
+        }
+
+        if let Some(workers) = &*self.workers.read() {
+            for worker in workers.stopped() {
+                failing.push(ComponentFailure::new(
+                    worker,
+                    "worker task has exited".to_string(),
+                ));
+            }
+        }
+
+        failing
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentFailure {
+    pub component: &'static str,
+    pub reason: String,
+}
+
+impl ComponentFailure {
+    fn new(component: &'static str, reason: String) -> Self {
+        Self { component, reason }
+    }
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    version: &'static str,
+    failing: Vec<ComponentFailure>,
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    ready: bool,
+}
+
+/// `/health` (200, or 503 naming the failing components) and `/ready`
+/// (503 until startup has finished).
+pub fn router(state: Arc<HealthState>) -> Router {
+    Router::new()
+        .route("/health", axum::routing::get(health_handler))
+        .route("/ready", axum::routing::get(ready_handler))
+        .with_state(state)
+}
+
+async fn health_handler(State(state): State<Arc<HealthState>>) -> Response {
+    let failing = state.failing().await;
+    let (status, label) = if failing.is_empty() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    let body = HealthResponse {
+        status: label,
+        version: env!("CARGO_PKG_VERSION"),
+        failing,
+    };
+    (status, Json(body)).into_response()
+}
+
+async fn ready_handler(State(state): State<Arc<HealthState>>) -> Response {
+    let ready = state.is_ready();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(ReadyResponse { ready })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+    use crate::wal::WalConfig;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use tower::ServiceExt;
+
+    async fn get(app: &Router, path: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ready_after_startup_and_health_names_dead_workers() {
+        let dir = std::env::temp_dir().join(format!("health_{}", uuid::Uuid::new_v4()));
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 2,
+            ..Default::default()
+        })
+        .await;
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = HealthState::new(engine, wal);
+        let app = router(state.clone());
+
+        let (status, body) = get(&app, "/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+
+        let workers = WorkerHealth::default();
+        let worker = tokio::spawn(std::future::pending::<()>());
+        workers.watch("metrics", &worker);
+        state.mark_ready(workers);
+        assert_eq!(get(&app, "/ready").await.0, StatusCode::OK);
+
+        let (status, body) = get(&app, "/health").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+
+        worker.abort();
+        let _ = worker.await;
+        let (status, body) = get(&app, "/health").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["failing"][0]["component"], "metrics");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
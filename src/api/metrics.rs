@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use base64::Engine;
+use prometheus::Encoder;
+use tokio::net::TcpListener;
+
+use crate::api::error::ApiError;
+use crate::config::MetricsConfig;
+use crate::storage::StorageEngine;
+use crate::wal::WalManager;
+
+pub async fn start_metrics_server(
+    listener: TcpListener,
+    engine: Arc<StorageEngine>,
+    wal: Arc<WalManager>,
+    config: MetricsConfig,
+) -> std::io::Result<()> {
+    if !config.auth_enabled() {
+        tracing::warn!("Metrics endpoint has no authentication configured");
+    }
+    let app = router(engine, wal, config);
+
+    axum::serve(listener, app).await
+}
+
+/// `/metrics`, behind bearer-token and/or basic auth when configured.
+pub fn router(engine: Arc<StorageEngine>, wal: Arc<WalManager>, config: MetricsConfig) -> Router {
+    Router::new()
+        .route("/metrics", axum::routing::get(metrics_handler))
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(config),
+            require_auth,
+        ))
+        .with_state((engine, wal))
+}
+
+async fn require_auth(
+    State(config): State<Arc<MetricsConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !config.auth_enabled() || authorized(&config, request.headers()) {
+        return next.run(request).await;
+    }
+
+    let mut response =
+        ApiError::AuthError(crate::auth::types::AuthError::InvalidCredentials).into_response();
+    let challenge = if config.basic_auth.is_some() {
+        "Basic realm=\"metrics\""
+    } else {
+        "Bearer"
+    };
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, challenge.parse().unwrap());
+    response
+}
+
+fn authorized(config: &MetricsConfig, headers: &HeaderMap) -> bool {
+    let Some(value) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+
+    if let (Some(expected), Some(token)) = (&config.bearer_token, value.strip_prefix("Bearer ")) {
+        if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+            return true;
+        }
+    }
+    if let (Some(basic), Some(encoded)) = (&config.basic_auth, value.strip_prefix("Basic ")) {
+        let expected = format!("{}:{}", basic.username, basic.password);
+        if let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded) {
+            return constant_time_eq(&decoded, expected.as_bytes());
+        }
+    }
+    false
+}
+
+// Avoids leaking how much of a guessed credential matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn metrics_handler(
+    State((engine, wal)): State<(Arc<StorageEngine>, Arc<WalManager>)>,
+) -> String {
+    // Update gauges
+    crate::background::metrics::publish_wal_gauges(&wal);
+    crate::background::metrics::publish_storage_gauges(&engine);
+
+    // Encode all metrics
+    let encoder = prometheus::TextEncoder::new();
+    let metric_families = prometheus::gather();
+    let mut buffer = Vec::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BasicAuthConfig;
+    use crate::storage::StorageConfig;
+    use crate::wal::WalConfig;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    async fn app(config: MetricsConfig) -> (Router, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("metrics_auth_{}", uuid::Uuid::new_v4()));
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 2,
+            ..Default::default()
+        })
+        .await;
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        (router(engine, wal, config), dir)
+    }
+
+    async fn scrape(app: &Router, authorization: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::get("/metrics");
+        if let Some(value) = authorization {
+            request = request.header(header::AUTHORIZATION, value);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_metrics_open_by_default() {
+        let (app, dir) = app(MetricsConfig::default()).await;
+        let (status, body) = scrape(&app, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("kvstore_key_count"));
+        assert!(body.contains("kvstore_shard_key_count{shard=\"1\"}"));
+        assert!(body.contains("kvstore_memory_usage_bytes"));
+        assert!(body.contains("kvstore_wal_queue_depth"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_metrics_auth_required_when_configured() {
+        let (app, dir) = app(MetricsConfig {
+            bearer_token: Some("scrape-token".to_string()),
+            basic_auth: Some(BasicAuthConfig {
+                username: "prometheus".to_string(),
+                password: "hunter2".to_string(),
+            }),
+        })
+        .await;
+
+        let (status, body) = scrape(&app, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains(r#""code":"UNAUTHENTICATED""#), "{}", body);
+        assert_eq!(
+            scrape(&app, Some("Bearer wrong")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let (status, body) = scrape(&app, Some("Bearer scrape-token")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("kvstore_key_count"));
+
+        let basic = base64::engine::general_purpose::STANDARD.encode("prometheus:hunter2");
+        let (status, _) = scrape(&app, Some(&format!("Basic {}", basic))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use dashmap::DashMap;
+use serde::Deserialize;
+
+use crate::api::error::ApiError;
+use crate::auth::types::AuthContext;
+
+// Idle buckets are swept at most this often
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token bucket: `per_sec` requests on average, up to `burst` at once.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+/// Per-client request limits. A client is an authenticated user, or the
+/// source IP of a request that carries no credentials.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Limit for clients none of whose roles are listed below; unset means
+    /// they are not limited.
+    #[serde(default)]
+    pub default: Option<RateLimit>,
+    /// Per-role limits. A user with several listed roles gets the most
+    /// generous one.
+    #[serde(default)]
+    pub roles: HashMap<String, RateLimit>,
+    /// Roles that are never limited.
+    #[serde(default = "default_unlimited_roles")]
+    pub unlimited_roles: Vec<String>,
+}
+
+fn default_unlimited_roles() -> Vec<String> {
+    vec!["admin".to_string()]
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            default: None,
+            roles: HashMap::new(),
+            unlimited_roles: default_unlimited_roles(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_sec).min(self.limit.burst as f64);
+        self.refilled = now;
+    }
+
+    // Long enough idle to have refilled completely, so dropping it is lossless
+    fn is_full_at(&self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens + elapsed * self.limit.per_sec >= self.limit.burst as f64
+    }
+}
+
+/// Token buckets for every client seen recently, shared by all request
+/// tasks. Buckets that have refilled completely are dropped, so idle
+/// clients don't accumulate.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: DashMap<String, Bucket>,
+    started: Instant,
+    last_sweep_ms: AtomicU64, // since `started`
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: DashMap::new(),
+            started: Instant::now(),
+            last_sweep_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// The limit for a client with `roles`, or `None` if it is not limited.
+    pub fn limit_for(&self, roles: &[String]) -> Option<RateLimit> {
+        if roles
+            .iter()
+            .any(|r| self.config.unlimited_roles.contains(r))
+        {
+            return None;
+        }
+        roles
+            .iter()
+            .filter_map(|r| self.config.roles.get(r))
+            .copied()
+            .max_by(|a, b| a.per_sec.total_cmp(&b.per_sec))
+            .or(self.config.default)
+    }
+
+    /// Takes one token from `client`'s bucket, or says how long until one
+    /// is available.
+    pub fn check(&self, client: &str, limit: RateLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.sweep(now);
+
+        let mut bucket = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: limit.burst as f64,
+                refilled: now,
+                limit,
+            });
+        // Role changes take effect on the next request
+        bucket.limit = limit;
+        bucket.refill(now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if limit.per_sec <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / limit.per_sec,
+        ))
+    }
+
+    /// [`check`](Self::check) as an API error: `RateLimited` with the wait
+    /// rounded up to whole seconds. `None` admits every request.
+    pub fn admit(&self, client: &str, limit: Option<RateLimit>) -> Result<(), ApiError> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        self.check(client, limit)
+            .map_err(|wait| ApiError::RateLimited {
+                retry_after_secs: wait.as_secs_f64().ceil().clamp(1.0, u32::MAX as f64) as u64,
+            })
+    }
+
+    /// Admits one request from the authenticated user of `ctx`. REST and
+    /// gRPC share the user's bucket.
+    pub fn admit_user(&self, ctx: &AuthContext) -> Result<(), ApiError> {
+        self.admit(&format!("user:{}", ctx.user), self.limit_for(&ctx.roles))
+    }
+
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets.len()
+    }
+
+    fn sweep(&self, now: Instant) {
+        let now_ms = now.duration_since(self.started).as_millis() as u64;
+        let last = self.last_sweep_ms.load(Ordering::Relaxed);
+        if now_ms < last + SWEEP_INTERVAL.as_millis() as u64 {
+            return;
+        }
+        // One request sweeps; the others carry on
+        if self
+            .last_sweep_ms
+            .compare_exchange(last, now_ms, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.buckets.retain(|_, bucket| !bucket.is_full_at(now));
+        }
+    }
+}
+
+/// axum middleware applying the [`RateLimiter`]; runs after authentication.
+/// An exhausted bucket answers `429 Too Many Requests` with `Retry-After`.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let (client, limit) = match request.extensions().get::<AuthContext>() {
+        Some(ctx) => (format!("user:{}", ctx.user), limiter.limit_for(&ctx.roles)),
+        None => {
+            let ip = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map_or_else(|| "unknown".to_string(), |ci| ci.0.ip().to_string());
+            (format!("ip:{}", ip), limiter.config.default)
+        }
+    };
+
+    limiter.admit(&client, limit)?;
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        let mut roles = HashMap::new();
+        roles.insert(
+            "reader".to_string(),
+            RateLimit {
+                per_sec: 10.0,
+                burst: 2,
+            },
+        );
+        RateLimiter::new(RateLimitConfig {
+            default: Some(RateLimit {
+                per_sec: 1.0,
+                burst: 1,
+            }),
+            roles,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_limits_by_role() {
+        let limiter = limiter();
+        let roles = |names: &[&str]| names.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(limiter.limit_for(&roles(&["admin", "reader"])), None);
+        assert_eq!(limiter.limit_for(&roles(&["reader"])).unwrap().burst, 2);
+        assert_eq!(limiter.limit_for(&roles(&["writer"])).unwrap().burst, 1);
+    }
+
+    #[test]
+    fn test_bucket_refills_and_idle_buckets_are_swept() {
+        let limiter = limiter();
+        let limit = RateLimit {
+            per_sec: 10.0,
+            burst: 2,
+        };
+
+        assert!(limiter.check("user:a", limit).is_ok());
+        assert!(limiter.check("user:a", limit).is_ok());
+        let wait = limiter.check("user:a", limit).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+        // Other clients have their own bucket
+        assert!(limiter.check("user:b", limit).is_ok());
+
+        std::thread::sleep(Duration::from_millis(110));
+        assert!(limiter.check("user:a", limit).is_ok());
+
+        // Both buckets are full again well before the sweep runs
+        limiter.last_sweep_ms.store(0, Ordering::Relaxed);
+        let later = Instant::now() + SWEEP_INTERVAL;
+        limiter.sweep(later);
+        assert_eq!(limiter.tracked_clients(), 0);
+    }
+}
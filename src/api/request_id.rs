@@ -0,0 +1,151 @@
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::future::BoxFuture;
+use std::task::{Context, Poll};
+use tonic::codegen::http as grpc_http;
+use tracing::Instrument;
+
+use crate::auth::audit;
+
+/// Header (REST) and metadata key (gRPC) a request ID travels in, both
+/// ways: a client may pick the ID, and every response echoes it.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longest client-chosen ID kept; anything longer gets a fresh one
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The ID a request is logged and audited under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The client's ID when it is 1 to 128 printable ASCII characters,
+    /// otherwise a new UUID.
+    pub fn from_client(value: Option<&str>) -> Self {
+        match value {
+            Some(id)
+                if !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LEN
+                    && id.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                Self(id.to_string())
+            }
+            _ => Self(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // Printable ASCII only, so it is always a valid header value
+    fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0).expect("request IDs are printable ASCII")
+    }
+}
+
+/// REST middleware: picks the request's ID, makes it available to the
+/// layers and handlers inside (as an extension, and to audit events), and
+/// echoes it on the response. Install it outside the `TraceLayer`, whose
+/// span then records it.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let id = RequestId::from_client(
+        request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok()),
+    );
+    request.extensions_mut().insert(id.clone());
+
+    let mut response = audit::with_request_id(id.0.clone(), next.run(request)).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, id.header_value());
+    response
+}
+
+/// The gRPC side of [`propagate`], as a layer for tonic's `Server`: reads
+/// the ID from request metadata, runs the call in a `grpc_request` span
+/// that carries it, and returns it in the response metadata.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrpcRequestIdLayer;
+
+impl<S> tower::Layer<S> for GrpcRequestIdLayer {
+    type Service = GrpcRequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcRequestId { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcRequestId<S> {
+    inner: S,
+}
+
+impl<S, B, R> tower::Service<grpc_http::Request<B>> for GrpcRequestId<S>
+where
+    S: tower::Service<grpc_http::Request<B>, Response = grpc_http::Response<R>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    R: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: grpc_http::Request<B>) -> Self::Future {
+        let id = RequestId::from_client(
+            request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok()),
+        );
+        let span = tracing::info_span!(
+            "grpc_request",
+            method = %request.uri().path(),
+            request_id = %id.as_str(),
+        );
+        let call = self.inner.call(request);
+
+        Box::pin(
+            async move {
+                let mut response = audit::with_request_id(id.0.clone(), call).await?;
+                let value = grpc_http::HeaderValue::from_str(id.as_str())
+                    .expect("request IDs are printable ASCII");
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_from_client() {
+        assert_eq!(RequestId::from_client(Some("req-42")).as_str(), "req-42");
+
+        let long = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        for bad in [
+            None,
+            Some(""),
+            Some("has space"),
+            Some("ünicode"),
+            Some(&long[..]),
+        ] {
+            let id = RequestId::from_client(bad);
+            assert!(uuid::Uuid::parse_str(id.as_str()).is_ok(), "{:?}", bad);
+        }
+        assert_ne!(RequestId::from_client(None), RequestId::from_client(None));
+    }
+}
@@ -0,0 +1,224 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+
+use crate::api::error::ApiError;
+use crate::storage::namespace::{self, DEFAULT_NAMESPACE};
+use crate::wal::Durability;
+
+pub const DURABILITY_HEADER: &str = "x-kv-durability";
+pub const CONSISTENCY_HEADER: &str = "x-kv-consistency";
+pub const NAMESPACE_HEADER: &str = "x-kv-namespace";
+
+/// Read guarantee requested by the client (`X-KV-Consistency`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Consistency {
+    /// Any node may answer, possibly with stale data.
+    #[default]
+    Eventual,
+    /// Only the primary answers, so a client always sees its own
+    /// acknowledged writes. Replicas refuse these reads.
+    ReadYourWrites,
+}
+
+impl std::str::FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eventual" => Ok(Consistency::Eventual),
+            "read_your_writes" => Ok(Consistency::ReadYourWrites),
+            other => Err(format!("unknown consistency level '{}'", other)),
+        }
+    }
+}
+
+/// Per-request durability and consistency. Missing headers fall back to the
+/// server defaults; unknown values are rejected with 400.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    pub durability: Durability,
+    pub consistency: Consistency,
+}
+
+impl RequestOptions {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let header = |name: &str| -> Result<Option<&str>, ApiError> {
+            headers
+                .get(name)
+                .map(|v| {
+                    v.to_str().map_err(|_| {
+                        ApiError::InvalidRequest(format!("{} is not valid ASCII", name))
+                    })
+                })
+                .transpose()
+        };
+        Self::parse(header(DURABILITY_HEADER)?, header(CONSISTENCY_HEADER)?)
+    }
+
+    /// Same keys as the REST headers, read from gRPC request metadata.
+    pub fn from_metadata(metadata: &tonic::metadata::MetadataMap) -> Result<Self, ApiError> {
+        let value = |name: &str| -> Result<Option<&str>, ApiError> {
+            metadata
+                .get(name)
+                .map(|v| {
+                    v.to_str().map_err(|_| {
+                        ApiError::InvalidRequest(format!("{} is not valid ASCII", name))
+                    })
+                })
+                .transpose()
+        };
+        Self::parse(value(DURABILITY_HEADER)?, value(CONSISTENCY_HEADER)?)
+    }
+
+    fn parse(durability: Option<&str>, consistency: Option<&str>) -> Result<Self, ApiError> {
+        let mut options = Self::default();
+        if let Some(durability) = durability {
+            options.durability = durability
+                .trim()
+                .parse()
+                .map_err(ApiError::InvalidRequest)?;
+        }
+        if let Some(consistency) = consistency {
+            options.consistency = consistency
+                .trim()
+                .parse()
+                .map_err(ApiError::InvalidRequest)?;
+        }
+        Ok(options)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestOptions
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_headers(&parts.headers)
+    }
+}
+
+/// Namespace the keys of a request live in (`X-KV-Namespace`); without the
+/// header, the default namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestNamespace(pub String);
+
+impl RequestNamespace {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let Some(value) = headers.get(NAMESPACE_HEADER) else {
+            return Ok(Self(DEFAULT_NAMESPACE.to_string()));
+        };
+        let name = value.to_str().map_err(|_| {
+            ApiError::InvalidRequest(format!("{} is not valid ASCII", NAMESPACE_HEADER))
+        })?;
+        Self::parse(name)
+    }
+
+    /// Same header, read from gRPC request metadata.
+    pub fn from_metadata(metadata: &tonic::metadata::MetadataMap) -> Result<Self, ApiError> {
+        let Some(value) = metadata.get(NAMESPACE_HEADER) else {
+            return Ok(Self(DEFAULT_NAMESPACE.to_string()));
+        };
+        let name = value.to_str().map_err(|_| {
+            ApiError::InvalidRequest(format!("{} is not valid ASCII", NAMESPACE_HEADER))
+        })?;
+        Self::parse(name)
+    }
+
+    fn parse(name: &str) -> Result<Self, ApiError> {
+        namespace::validate_namespace(name.trim())?;
+        Ok(Self(name.trim().to_string()))
+    }
+
+    /// The stored key for a key named in the request.
+    pub fn key(&self, key: &str) -> Result<String, ApiError> {
+        Ok(namespace::resolve_key(&self.0, key)?)
+    }
+
+    /// A stored key as the client named it.
+    pub fn local_key<'a>(&self, stored: &'a str) -> &'a str {
+        namespace::local_key(&self.0, stored)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestNamespace
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_headers(&parts.headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_request_options_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            RequestOptions::from_headers(&headers).unwrap(),
+            RequestOptions::default()
+        );
+
+        headers.insert(DURABILITY_HEADER, HeaderValue::from_static("sync"));
+        headers.insert(
+            CONSISTENCY_HEADER,
+            HeaderValue::from_static("read_your_writes"),
+        );
+        let options = RequestOptions::from_headers(&headers).unwrap();
+        assert_eq!(options.durability, Durability::Sync);
+        assert_eq!(options.consistency, Consistency::ReadYourWrites);
+
+        headers.insert(DURABILITY_HEADER, HeaderValue::from_static("fsync"));
+        assert!(matches!(
+            RequestOptions::from_headers(&headers),
+            Err(ApiError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_request_options_from_metadata() {
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        metadata.insert(DURABILITY_HEADER, "none".parse().unwrap());
+        let options = RequestOptions::from_metadata(&metadata).unwrap();
+        assert_eq!(options.durability, Durability::None);
+        assert_eq!(options.consistency, Consistency::Eventual);
+
+        metadata.insert(CONSISTENCY_HEADER, "strong".parse().unwrap());
+        assert!(RequestOptions::from_metadata(&metadata).is_err());
+    }
+
+    #[test]
+    fn test_request_namespace_from_headers() {
+        let mut headers = HeaderMap::new();
+        let default = RequestNamespace::from_headers(&headers).unwrap();
+        assert_eq!(default.key("user:1").unwrap(), "user:1");
+        assert!(default.key("_sys.users:admin").is_err());
+
+        headers.insert(NAMESPACE_HEADER, HeaderValue::from_static("app1"));
+        let app1 = RequestNamespace::from_headers(&headers).unwrap();
+        assert_eq!(app1.key("user:1").unwrap(), "ns:app1:user:1");
+        assert_eq!(app1.local_key("ns:app1:user:1"), "user:1");
+
+        headers.insert(NAMESPACE_HEADER, HeaderValue::from_static("_sys"));
+        assert!(RequestNamespace::from_headers(&headers).is_err());
+
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        assert_eq!(
+            RequestNamespace::from_metadata(&metadata).unwrap().0,
+            DEFAULT_NAMESPACE
+        );
+        metadata.insert(NAMESPACE_HEADER, "app1".parse().unwrap());
+        assert_eq!(RequestNamespace::from_metadata(&metadata).unwrap(), app1);
+    }
+}
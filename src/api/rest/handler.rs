@@ -1,70 +1,988 @@
-use axum::extract::{Path, Query, State};
-use axum::Json;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
 use base64::Engine;
+use futures_util::StreamExt;
 use std::sync::Arc;
+use std::time::Instant;
 
 use crate::api::auth_middleware::AuthenticatedUser;
+use crate::api::byte_range::{partial_content, ByteRange};
+use crate::api::content::{Format, Negotiated, NegotiatedBody, ValueBytes};
 use crate::api::error::ApiError;
+use crate::api::extract::{Json, Query};
+use crate::api::request_options::{Consistency, RequestNamespace, RequestOptions};
 use crate::api::rest::types::*;
-use crate::storage::StorageEngine;
+use crate::auth::types::{AuthContext, AuthError, AuthMethod};
+use crate::auth::AuthManager;
+use crate::background::metrics::OpTimer;
+use crate::storage::namespace::DEFAULT_NAMESPACE;
+use crate::storage::{ExecOutcome, NodeRole, SnapshotManager, StorageEngine, StorageError, TxnOp};
+use crate::wal::Durability;
+
+// Reserved key read by /v1/ping; it never exists, so a miss is the success path
+const PING_PROBE_KEY: &str = "_sys.ping";
 
 pub async fn get_handler(
     State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
     AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
     Query(params): Query<GetParams>,
-) -> Result<Json<GetResponse>, ApiError> {
-    engine
-        .get(&params.key)
-        .await
-        .map_err(ApiError::StorageError)?;
+) -> Result<Negotiated<GetResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    if options.consistency == Consistency::ReadYourWrites && engine.node_role() == NodeRole::Replica
+    {
+        return Err(ApiError::Unavailable(
+            "read_your_writes reads must go to the primary".to_string(),
+        ));
+    }
 
-    // Authorize
-    auth_ctx
-        .authorize(&auth_ctx, "GET", &params.key)
+    // Before touching the key, so a denied caller can't probe for existence
+    auth.authorize(&auth_ctx, "GET", &key)
         .map_err(ApiError::AuthError)?;
 
-    let entry = engine.get(&params.key).await?;
-    let value_b64 = base64::engine::general_purpose::STANDARD.encode(&entry.value);
+    // A miss is an answer, not a failed request
+    let timer = OpTimer::start("rest", "GET");
+    let entry = engine.get(&key).await;
+    if matches!(entry, Ok(_) | Err(StorageError::KeyNotFound(_))) {
+        timer.ok();
+    }
+    let entry = entry?;
+    let meta = params.meta.then(|| EntryMeta::of(&entry));
 
-    Ok(Json(GetResponse {
-        found: true,
-        value: Some(value_b64),
-        version: entry.version,
-    }))
+    Ok(Negotiated(
+        format,
+        GetResponse {
+            found: true,
+            value: Some(ValueBytes(entry.value)),
+            version: entry.version,
+            meta,
+        },
+    ))
+}
+
+/// `GET /v1/getrange?key=&start=&end=`. A `Range: bytes=...` header wins
+/// over the query indexes and gets a raw `206 Partial Content` reply.
+pub async fn getrange_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    headers: HeaderMap,
+    format: Format,
+    Query(params): Query<GetRangeParams>,
+) -> Result<Response, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "GET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    if let Some(range) = ByteRange::from_headers(&headers)? {
+        let slice = engine.getrange_slice(&key, range.start, range.end).await?;
+        return Ok(partial_content(slice));
+    }
+
+    let slice = engine
+        .getrange_slice(&key, params.start, params.end)
+        .await?;
+    Ok(Negotiated(
+        format,
+        GetRangeResponse {
+            value: ValueBytes(slice.bytes),
+            start: slice.start,
+            total_len: slice.total_len,
+        },
+    )
+    .into_response())
+}
+
+/// `GET /v1/scan?pattern=&limit=&cursor=`, within the request's namespace.
+/// System keys (`_sys.*`) are only listed for superusers, from the default
+/// namespace.
+pub async fn scan_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    format: Format,
+    Query(params): Query<ScanParams>,
+) -> Result<Negotiated<ScanResponse>, ApiError> {
+    if params.limit == 0 {
+        return Err(ApiError::InvalidRequest(
+            "limit must be at least 1".to_string(),
+        ));
+    }
+    let timer = OpTimer::start("rest", "SCAN");
+
+    let superuser = auth_ctx.permissions.iter().any(|p| p == "*");
+    let page = if superuser && namespace.0 == DEFAULT_NAMESPACE {
+        engine
+            .scan_glob(
+                &params.pattern,
+                params.limit as usize,
+                params.cursor.as_deref(),
+                true,
+            )
+            .await?
+    } else {
+        auth.authorize(&auth_ctx, "SCAN", &namespace.key(&params.pattern)?)
+            .map_err(ApiError::AuthError)?;
+        engine
+            .scan_in(
+                &namespace.0,
+                &params.pattern,
+                params.limit as usize,
+                params.cursor.as_deref(),
+            )
+            .await?
+    };
+
+    timer.ok();
+    Ok(Negotiated(
+        format,
+        ScanResponse {
+            items: page
+                .items
+                .into_iter()
+                .map(|(key, entry)| ScanItem {
+                    key,
+                    value: Some(ValueBytes(entry.value)),
+                    version: entry.version,
+                })
+                .collect(),
+            has_more: page.has_more,
+            scanned: page.scanned,
+            next_cursor: page.next_cursor,
+        },
+    ))
+}
+
+/// `GET /v1/scan/stream?pattern=`: every match, streamed as NDJSON (one
+/// `ScanItem` per line) while the engine walks the shards, instead of paged.
+/// Access is checked as for `/v1/scan`.
+pub async fn scan_stream_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    Query(params): Query<ScanStreamParams>,
+) -> Result<Response, ApiError> {
+    let superuser = auth_ctx.permissions.iter().any(|p| p == "*");
+    let include_system = superuser && namespace.0 == DEFAULT_NAMESPACE;
+    let pattern = if include_system {
+        params.pattern
+    } else {
+        let pattern = namespace.key(&params.pattern)?;
+        auth.authorize(&auth_ctx, "SCAN", &pattern)
+            .map_err(ApiError::AuthError)?;
+        pattern
+    };
+
+    let lines = engine
+        .scan_stream(&pattern, include_system)
+        .map(move |(key, entry)| {
+            let item = ScanItem {
+                key: namespace.local_key(&key).to_string(),
+                value: Some(ValueBytes(entry.value)),
+                version: entry.version,
+            };
+            let mut line = serde_json::to_vec(&item)?;
+            line.push(b'\n');
+            Ok::<_, serde_json::Error>(line)
+        });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response())
 }
 
 pub async fn set_handler(
     State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    NegotiatedBody(params): NegotiatedBody<SetParams>,
+) -> Result<Negotiated<SetResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+    let timer = OpTimer::start("rest", "SET");
+    let value = params.value.0;
+    // Checked on the value as sent; compression only shrinks it
+    engine.check_entry_size(&key, value.len())?;
+
+    let durability = match params.sync {
+        true => Durability::Sync,
+        false => options.durability,
+    };
+    let (value, compression) = engine.encode_value(&key, value, params.compression);
+    let version = durability
+        .scope(engine.set_encoded(&key, value, compression, params.ttl))
+        .await?;
+
+    timer.ok();
+    Ok(Negotiated(
+        format,
+        SetResponse {
+            success: true,
+            version,
+        },
+    ))
+}
+
+/// `POST /v1/cas`: writes only if the key is still at `expected_version`
+/// (0 for "must not exist"); otherwise 409 with the current version.
+pub async fn cas_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    NegotiatedBody(params): NegotiatedBody<CasParams>,
+) -> Result<Negotiated<SetResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    let value = params.value.0;
+
+    let version = options
+        .durability
+        .scope(engine.cas(&key, params.expected_version, value, params.ttl))
+        .await?;
+
+    Ok(Negotiated(
+        format,
+        SetResponse {
+            success: true,
+            version,
+        },
+    ))
+}
+
+/// `POST /v1/getset`: writes the key and returns the value it replaced
+/// (`null` if there was none). Needs both GET and SET permission.
+pub async fn getset_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    NegotiatedBody(params): NegotiatedBody<GetSetParams>,
+) -> Result<Negotiated<GetSetResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "GET", &key)
+        .map_err(ApiError::AuthError)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    let value = params.value.0;
+
+    let (old_value, version) = options
+        .durability
+        .scope(engine.getset_versioned(&key, value, params.ttl))
+        .await?;
+
+    Ok(Negotiated(
+        format,
+        GetSetResponse {
+            success: true,
+            old_value: old_value.map(ValueBytes),
+            version,
+        },
+    ))
+}
+
+/// `POST /v1/setnx`: writes only if the key is absent or expired. Not
+/// writing is a normal outcome (`written: false`), not an error.
+pub async fn setnx_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    NegotiatedBody(params): NegotiatedBody<SetNxParams>,
+) -> Result<Negotiated<SetNxResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    let value = params.value.0;
+
+    let version = options
+        .durability
+        .scope(engine.setnx_versioned(&key, value, params.ttl))
+        .await?;
+
+    Ok(Negotiated(
+        format,
+        SetNxResponse {
+            written: version.is_some(),
+            version,
+        },
+    ))
+}
+
+/// `POST /v1/mget`: values in request order, `null` for missing keys.
+/// Every key needs GET permission.
+pub async fn mget_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
     AuthenticatedUser(auth_ctx): AuthenticatedUser,
-    Json(params): Json<SetParams>,
-) -> Result<Json<SetResponse>, ApiError> {
-    auth_ctx
-        .authorize(&auth_ctx, "SET", &params.key)
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    Json(params): Json<MgetParams>,
+) -> Result<Negotiated<MgetResponse>, ApiError> {
+    if options.consistency == Consistency::ReadYourWrites && engine.node_role() == NodeRole::Replica
+    {
+        return Err(ApiError::Unavailable(
+            "read_your_writes reads must go to the primary".to_string(),
+        ));
+    }
+    let keys = params
+        .keys
+        .iter()
+        .map(|key| namespace.key(key))
+        .collect::<Result<Vec<_>, _>>()?;
+    for key in &keys {
+        auth.authorize(&auth_ctx, "GET", key)
+            .map_err(ApiError::AuthError)?;
+    }
+
+    let values = engine
+        .mget(&keys)
+        .await?
+        .into_iter()
+        .map(|entry| {
+            entry.map(|entry| MgetValue {
+                value: ValueBytes(entry.value),
+                version: entry.version,
+            })
+        })
+        .collect();
+
+    Ok(Negotiated(format, MgetResponse { values }))
+}
+
+/// `GET /v1/exists?key=`. Only says whether the key holds a live value
+/// right now; expired and never-written keys look the same.
+pub async fn exists_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    Query(params): Query<ExistsParams>,
+) -> Result<Json<ExistsResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "EXISTS", &key)
         .map_err(ApiError::AuthError)?;
 
-    let value = base64::engine::general_purpose::STANDARD
-        .decode(&params.value)
-        .map_err(|_| ApiError::InvalidRequest("Invalid base64 value".to_string()))?;
+    Ok(Json(ExistsResponse {
+        exists: engine.exists(&key).await,
+    }))
+}
+
+/// `POST /v1/mexists`. Every key must pass EXISTS authorization before any
+/// is looked up.
+pub async fn mexists_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    Json(params): Json<MexistsParams>,
+) -> Result<Json<MexistsResponse>, ApiError> {
+    let keys = params
+        .keys
+        .iter()
+        .map(|key| namespace.key(key))
+        .collect::<Result<Vec<_>, _>>()?;
+    for key in &keys {
+        auth.authorize(&auth_ctx, "EXISTS", key)
+            .map_err(ApiError::AuthError)?;
+    }
+
+    Ok(Json(MexistsResponse {
+        exists: engine.mexists(&keys).await?,
+    }))
+}
+
+/// `POST /v1/mset`. With `atomic` the batch is all-or-nothing and any key
+/// the caller may not SET fails the request; otherwise each key is written
+/// independently and reported in `results`.
+pub async fn mset_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    NegotiatedBody(params): NegotiatedBody<MsetParams>,
+) -> Result<Negotiated<MsetResponse>, ApiError> {
+    let mut denied = Vec::new();
+    let mut items = Vec::with_capacity(params.items.len());
+    for item in params.items {
+        let key = namespace.key(&item.key)?;
+        if let Err(e) = auth.authorize(&auth_ctx, "SET", &key) {
+            if params.atomic {
+                return Err(ApiError::AuthError(e));
+            }
+            denied.push(MsetKeyStatus {
+                key: item.key,
+                success: false,
+                version: None,
+                error: Some(e.to_string()),
+            });
+            continue;
+        }
+        items.push((key, item.value.0, item.ttl));
+    }
 
-    engine.set(&params.key, value, params.ttl).await?;
+    let written: Vec<(String, Result<u64, StorageError>)> = if params.atomic {
+        options
+            .durability
+            .scope(engine.mset_atomic(items))
+            .await?
+            .into_iter()
+            .map(|(key, version)| (key, Ok(version)))
+            .collect()
+    } else {
+        options.durability.scope(engine.mset(items)).await?
+    };
+
+    let mut results = denied;
+    for (key, result) in written {
+        match result {
+            Ok(version) => {
+                results.push(MsetKeyStatus {
+                    key: namespace.local_key(&key).to_string(),
+                    success: true,
+                    version: Some(version),
+                    error: None,
+                });
+            }
+            Err(e) => results.push(MsetKeyStatus {
+                key: namespace.local_key(&key).to_string(),
+                success: false,
+                version: None,
+                error: Some(e.to_string()),
+            }),
+        }
+    }
+
+    Ok(Negotiated(
+        format,
+        MsetResponse {
+            success: results.iter().all(|status| status.success),
+            results,
+        },
+    ))
+}
+
+/// `POST /v1/txn`: applies a list of set/del/cas ops all-or-nothing. A CAS
+/// that does not match fails the request with a conflict and nothing is
+/// written. Every key needs the permission of its op (CAS needs SET).
+pub async fn txn_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    Json(params): Json<TxnParams>,
+) -> Result<Json<TxnResponse>, ApiError> {
+    let ops = txn_ops(&auth, &auth_ctx, &namespace, params.ops)?;
+    let versions = options.durability.scope(engine.txn(ops)).await?;
 
-    // For now, version is always 1
-    Ok(Json(SetResponse {
+    Ok(Json(TxnResponse {
         success: true,
-        version: 1,
+        results: txn_results(&namespace, versions),
     }))
 }
 
+/// `POST /v1/txn/watch`: WATCH for the caller's session. Its next
+/// `/v1/txn/exec` commits only if none of `keys` changed in between.
+/// Needs GET permission on each key, and a JWT session, since an API key
+/// starts a new session with every request.
+pub async fn watch_keys_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    Json(params): Json<WatchKeysParams>,
+) -> Result<Json<WatchKeysResponse>, ApiError> {
+    let session = watch_session(&auth_ctx)?;
+    let mut keys = Vec::with_capacity(params.keys.len());
+    for key in params.keys {
+        let key = namespace.key(&key)?;
+        auth.authorize(&auth_ctx, "GET", &key)
+            .map_err(ApiError::AuthError)?;
+        keys.push(key);
+    }
+
+    let watching = engine.watch_keys(session, &keys)?;
+    Ok(Json(WatchKeysResponse {
+        success: true,
+        watching,
+    }))
+}
+
+/// `POST /v1/txn/unwatch`: drops the caller's WATCH.
+pub async fn unwatch_keys_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    engine.unwatch_keys(watch_session(&auth_ctx)?);
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// `POST /v1/txn/exec`: runs `ops` as `/v1/txn` does, but only if nothing
+/// the session watched has changed. If something has, nothing is written
+/// and the response has `committed: false` and the key that changed. The
+/// session's WATCH ends either way; an EXEC without one is a 400.
+pub async fn exec_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    Json(params): Json<TxnParams>,
+) -> Result<Json<ExecResponse>, ApiError> {
+    let session = watch_session(&auth_ctx)?;
+    let ops = txn_ops(&auth, &auth_ctx, &namespace, params.ops)?;
+    let outcome = options.durability.scope(engine.exec(session, ops)).await?;
+
+    Ok(Json(match outcome {
+        ExecOutcome::Committed(versions) => ExecResponse {
+            committed: true,
+            aborted_by: None,
+            results: txn_results(&namespace, versions),
+        },
+        ExecOutcome::Aborted { key } => ExecResponse {
+            committed: false,
+            aborted_by: Some(namespace.local_key(&key).to_string()),
+            results: Vec::new(),
+        },
+    }))
+}
+
+// The session WATCH state is kept under.
+fn watch_session(auth_ctx: &AuthContext) -> Result<&str, ApiError> {
+    match auth_ctx.auth_method {
+        AuthMethod::Jwt(_) => Ok(&auth_ctx.session_id),
+        _ => Err(ApiError::InvalidRequest(
+            "WATCH and EXEC need a JWT session".to_string(),
+        )),
+    }
+}
+
+// Decodes the ops of a txn request into stored keys, authorizing each.
+fn txn_ops(
+    auth: &AuthManager,
+    auth_ctx: &AuthContext,
+    namespace: &RequestNamespace,
+    params: Vec<TxnOpParams>,
+) -> Result<Vec<TxnOp>, ApiError> {
+    let decode = |key: &str, value: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .map_err(|_| ApiError::InvalidRequest(format!("Invalid base64 value for key {}", key)))
+    };
+
+    let mut ops = Vec::with_capacity(params.len());
+    for op in params {
+        let op = match op {
+            TxnOpParams::Set { key, value, ttl } => TxnOp::Set {
+                key: namespace.key(&key)?,
+                value: decode(&key, &value)?,
+                ttl_secs: ttl,
+            },
+            TxnOpParams::Del { key } => TxnOp::Del {
+                key: namespace.key(&key)?,
+            },
+            TxnOpParams::Cas {
+                key,
+                value,
+                expected_version,
+                ttl,
+            } => TxnOp::Cas {
+                key: namespace.key(&key)?,
+                value: decode(&key, &value)?,
+                expected_version,
+                ttl_secs: ttl,
+            },
+        };
+        let action = match op {
+            TxnOp::Del { .. } => "DEL",
+            TxnOp::Set { .. } | TxnOp::Cas { .. } => "SET",
+        };
+        auth.authorize(auth_ctx, action, op.key())
+            .map_err(ApiError::AuthError)?;
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+fn txn_results(namespace: &RequestNamespace, versions: Vec<(String, u64)>) -> Vec<TxnKeyVersion> {
+    versions
+        .into_iter()
+        .map(|(key, version)| TxnKeyVersion {
+            key: namespace.local_key(&key).to_string(),
+            version,
+        })
+        .collect()
+}
+
 pub async fn delete_handler(
     State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
     AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
     Json(params): Json<DeleteParams>,
 ) -> Result<Json<DeleteResponse>, ApiError> {
-    auth_ctx
-        .authorize(&auth_ctx, "DEL", &params.key)
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "DEL", &key)
         .map_err(ApiError::AuthError)?;
+    let timer = OpTimer::start("rest", "DEL");
 
-    engine.del(&params.key, None).await?;
+    options
+        .durability
+        .scope(engine.del(&key, params.version))
+        .await?;
 
+    timer.ok();
     Ok(Json(DeleteResponse { success: true }))
-}
\ No newline at end of file
+}
+
+pub async fn incr_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    Json(params): Json<IncrParams>,
+) -> Result<Json<IncrResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+    let timer = OpTimer::start("rest", "INCR");
+
+    let new_value = options
+        .durability
+        .scope(engine.incr(&key, params.delta))
+        .await?;
+
+    timer.ok();
+    Ok(Json(IncrResponse {
+        success: true,
+        new_value,
+    }))
+}
+
+/// `POST /v1/append`: appends bytes to the value (creating it if absent)
+/// and returns the new length. Needs the APPEND permission.
+pub async fn append_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    format: Format,
+    NegotiatedBody(params): NegotiatedBody<AppendParams>,
+) -> Result<Negotiated<AppendResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "APPEND", &key)
+        .map_err(ApiError::AuthError)?;
+
+    let length = options
+        .durability
+        .scope(engine.append(&key, params.value.0))
+        .await?;
+
+    Ok(Negotiated(
+        format,
+        AppendResponse {
+            success: true,
+            length,
+        },
+    ))
+}
+
+/// `GET /v1/ttl?key=`: seconds until the key expires.
+pub async fn ttl_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    Query(params): Query<TtlParams>,
+) -> Result<Json<TtlResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "GET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    let ttl = engine.ttl(&key).await?;
+    Ok(Json(TtlResponse { ttl }))
+}
+
+pub async fn expire_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    Json(params): Json<ExpireParams>,
+) -> Result<Json<TtlResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    options
+        .durability
+        .scope(engine.expire(&key, params.ttl))
+        .await?;
+
+    Ok(Json(TtlResponse {
+        ttl: Some(params.ttl),
+    }))
+}
+
+pub async fn persist_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    options: RequestOptions,
+    Json(params): Json<PersistParams>,
+) -> Result<Json<TtlResponse>, ApiError> {
+    let key = namespace.key(&params.key)?;
+    auth.authorize(&auth_ctx, "SET", &key)
+        .map_err(ApiError::AuthError)?;
+
+    options.durability.scope(engine.persist(&key)).await?;
+
+    Ok(Json(TtlResponse { ttl: None }))
+}
+
+pub async fn maintenance_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    Json(params): Json<MaintenanceParams>,
+) -> Result<Json<MaintenanceResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    engine.set_maintenance_mode(params.enabled);
+
+    Ok(Json(MaintenanceResponse {
+        maintenance_mode: engine.is_maintenance_mode(),
+    }))
+}
+
+/// Snapshots the whole dataset now (ADMIN).
+pub async fn create_snapshot_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    Extension(snapshots): Extension<Arc<SnapshotManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+) -> Result<Json<CreateSnapshotResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    let name = snapshots.create_snapshot(&engine).await?;
+    Ok(Json(CreateSnapshotResponse { name }))
+}
+
+/// Snapshot files on this node (ADMIN).
+pub async fn list_snapshots_handler(
+    Extension(auth): Extension<Arc<AuthManager>>,
+    Extension(snapshots): Extension<Arc<SnapshotManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+) -> Result<Json<ListSnapshotsResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    Ok(Json(ListSnapshotsResponse {
+        snapshots: snapshots.list_snapshots()?,
+    }))
+}
+
+/// Replaces the whole dataset with a snapshot (superuser). The restore is
+/// logged, so the node comes back to the restored dataset after a restart.
+pub async fn restore_snapshot_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    Extension(snapshots): Extension<Arc<SnapshotManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    Json(params): Json<RestoreSnapshotParams>,
+) -> Result<Json<RestoreSnapshotResponse>, ApiError> {
+    // Only an unscoped `*` permission covers the `*` op
+    auth.authorize(&auth_ctx, "*", "")
+        .map_err(ApiError::AuthError)?;
+
+    match snapshots
+        .restore_snapshot(&engine, &params.name, params.force)
+        .await
+    {
+        Ok(()) => Ok(Json(RestoreSnapshotResponse {
+            restored: params.name,
+        })),
+        Err(StorageError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Err(
+            ApiError::InvalidRequest(format!("no snapshot named {}", params.name)),
+        ),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revokes the caller's own JWT session.
+pub async fn logout_handler(
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+) -> Result<Json<RevokeResponse>, ApiError> {
+    let AuthMethod::Jwt(token) = &auth_ctx.auth_method else {
+        return Err(ApiError::InvalidRequest(
+            "only JWT sessions can be logged out".to_string(),
+        ));
+    };
+    let (session_id, exp) = auth.revoke_token(&auth_ctx, token).await?;
+
+    Ok(Json(RevokeResponse {
+        session_id,
+        exp: Some(exp),
+    }))
+}
+
+/// Revokes any JWT session (ADMIN). Given the token, the session is revoked
+/// until the token expires; given only its ID, for good.
+pub async fn revoke_handler(
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    Json(params): Json<RevokeParams>,
+) -> Result<Json<RevokeResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    let (session_id, exp) = match (params.token, params.session_id) {
+        (Some(token), _) => {
+            let (session_id, exp) =
+                auth.revoke_token(&auth_ctx, &token)
+                    .await
+                    .map_err(|e| match e {
+                        AuthError::JwtError(e) => {
+                            ApiError::InvalidRequest(format!("invalid token: {}", e))
+                        }
+                        e => ApiError::AuthError(e),
+                    })?;
+            (session_id, Some(exp))
+        }
+        (None, Some(session_id)) => {
+            auth.revoke_session(&auth_ctx, &session_id, None).await?;
+            (session_id, None)
+        }
+        (None, None) => {
+            return Err(ApiError::InvalidRequest(
+                "token or session_id is required".to_string(),
+            ))
+        }
+    };
+
+    Ok(Json(RevokeResponse { session_id, exp }))
+}
+
+pub async fn create_api_key_handler(
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    Json(params): Json<CreateApiKeyParams>,
+) -> Result<Json<CreateApiKeyResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    let (key_id, secret) = auth
+        .create_api_key(
+            &auth_ctx,
+            &params.owner,
+            params.permissions,
+            params.expires_at,
+        )
+        .await
+        .map_err(|e| match e {
+            AuthError::UserNotFound(user) => {
+                ApiError::InvalidRequest(format!("no such user: {}", user))
+            }
+            e => ApiError::AuthError(e),
+        })?;
+
+    Ok(Json(CreateApiKeyResponse {
+        api_key: format!("{}.{}", key_id, secret),
+        key_id,
+    }))
+}
+
+pub async fn list_api_keys_handler(
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    Query(params): Query<ListApiKeysParams>,
+) -> Result<Json<ListApiKeysResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    let keys = auth
+        .list_api_keys(&params.owner)
+        .await?
+        .into_iter()
+        .map(|key| ApiKeyInfo {
+            key_id: key.key_id,
+            owner: key.owner_user,
+            permissions: key.permissions,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            revoked: key.revoked,
+        })
+        .collect();
+
+    Ok(Json(ListApiKeysResponse { keys }))
+}
+
+pub async fn revoke_api_key_handler(
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    Json(params): Json<RevokeApiKeyParams>,
+) -> Result<Json<RevokeApiKeyResponse>, ApiError> {
+    auth.authorize(&auth_ctx, "ADMIN", "")
+        .map_err(ApiError::AuthError)?;
+
+    if !auth.revoke_api_key(&auth_ctx, &params.key_id).await? {
+        return Err(ApiError::KeyNotFound(params.key_id));
+    }
+
+    Ok(Json(RevokeApiKeyResponse { revoked: true }))
+}
+
+pub async fn ping_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+) -> Result<Json<PingResponse>, ApiError> {
+    let started = Instant::now();
+    match engine.get(PING_PROBE_KEY).await {
+        Ok(_) | Err(StorageError::KeyNotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(Json(PingResponse {
+        pong: true,
+        user: auth_ctx.user,
+        role: engine.node_role(),
+        latency_us: started.elapsed().as_micros() as u64,
+    }))
+}
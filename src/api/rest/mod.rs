@@ -1,45 +1,145 @@
+pub mod handler;
+pub mod types;
+pub mod watch;
+
 use axum::{routing::post, Router};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::net::TcpListener;
+use tower_http::compression::CompressionLayer;
 use tower_http::trace::TraceLayer;
 use tracing::Level;
 
 use crate::api::auth_middleware::AuthState;
+use crate::api::rate_limit::RateLimiter;
+use crate::api::request_id::RequestId;
 use crate::auth::AuthManager;
-use crate::storage::StorageEngine;
+use crate::connection::ConnectionManager;
+use crate::storage::{SnapshotManager, StorageEngine};
+use crate::wal::WalManager;
 
 pub async fn start_rest_server(
-    addr: SocketAddr,
+    listener: TcpListener,
+    engine: Arc<StorageEngine>,
+    wal: Arc<WalManager>,
+    auth_manager: Arc<AuthManager>,
+    connections: Arc<ConnectionManager>,
+    snapshots: Arc<SnapshotManager>,
+    limiter: Arc<RateLimiter>,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let app = router(engine, wal, auth_manager, connections, snapshots, limiter);
+
+    tracing::info!("Starting REST server on {}", listener.local_addr()?);
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await
+}
+
+/// All `/v1` routes behind the auth middleware. Writes are logged to `wal`
+/// at the durability chosen per request (`X-KV-Durability`). Every request
+/// is admitted through `connections` first (see [`crate::api::connections`]).
+/// The snapshot admin routes work on `snapshots`' directory. Authenticated
+/// requests are then rate limited per user by `limiter`. Each request gets
+/// an `X-Request-Id` (see [`crate::api::request_id`]).
+pub fn router(
     engine: Arc<StorageEngine>,
+    wal: Arc<WalManager>,
     auth_manager: Arc<AuthManager>,
-) {
+    connections: Arc<ConnectionManager>,
+    snapshots: Arc<SnapshotManager>,
+    limiter: Arc<RateLimiter>,
+) -> Router {
     let auth_state = AuthState {
         auth_manager: auth_manager.clone(),
     };
 
-    let app = Router::new()
-        .route("/v1/get", axum::routing::get(super::handlers::get_handler))
-        .route("/v1/set", post(super::handlers::set_handler))
-        .route("/v1/del", post(super::handlers::delete_handler))
-        .layer(axum::middleware::from_extractor_with_state::<
+    Router::new()
+        .route("/v1/ping", axum::routing::get(handler::ping_handler))
+        .route("/v1/get", axum::routing::get(handler::get_handler))
+        .route(
+            "/v1/getrange",
+            axum::routing::get(handler::getrange_handler),
+        )
+        .route("/v1/scan", axum::routing::get(handler::scan_handler))
+        .route("/v1/scan/stream", axum::routing::get(handler::scan_stream_handler))
+        .route("/v1/exists", axum::routing::get(handler::exists_handler))
+        .route("/v1/mexists", post(handler::mexists_handler))
+        .route("/v1/set", post(handler::set_handler))
+        .route("/v1/mget", post(handler::mget_handler))
+        .route("/v1/mset", post(handler::mset_handler))
+        .route("/v1/txn", post(handler::txn_handler))
+        .route("/v1/txn/watch", post(handler::watch_keys_handler))
+        .route("/v1/txn/unwatch", post(handler::unwatch_keys_handler))
+        .route("/v1/txn/exec", post(handler::exec_handler))
+        .route("/v1/cas", post(handler::cas_handler))
+        .route("/v1/getset", post(handler::getset_handler))
+        .route("/v1/setnx", post(handler::setnx_handler))
+        .route("/v1/del", post(handler::delete_handler))
+        .route("/v1/incr", post(handler::incr_handler))
+        .route("/v1/append", post(handler::append_handler))
+        .route("/v1/ttl", axum::routing::get(handler::ttl_handler))
+        .route("/v1/expire", post(handler::expire_handler))
+        .route("/v1/persist", post(handler::persist_handler))
+        .route("/v1/watch", axum::routing::get(watch::watch_sse_handler))
+        .route("/v1/watch/ws", axum::routing::get(watch::watch_ws_handler))
+        .route("/v1/admin/maintenance", post(handler::maintenance_handler))
+        .route(
+            "/v1/admin/snapshots",
+            post(handler::create_snapshot_handler).get(handler::list_snapshots_handler),
+        )
+        .route(
+            "/v1/admin/snapshots/restore",
+            post(handler::restore_snapshot_handler),
+        )
+        .route(
+            "/v1/admin/api_keys",
+            post(handler::create_api_key_handler).get(handler::list_api_keys_handler),
+        )
+        .route(
+            "/v1/admin/api_keys/revoke",
+            post(handler::revoke_api_key_handler),
+        )
+        .route("/v1/logout", post(handler::logout_handler))
+        .route("/v1/revoke", post(handler::revoke_handler))
+        .layer(axum::Extension(wal))
+        .layer(axum::Extension(snapshots))
+        .layer(axum::Extension(auth_manager))
+        .layer(axum::middleware::from_fn_with_state(
+            limiter,
+            super::rate_limit::rate_limit,
+        ))
+        .layer(axum::middleware::from_extractor::<
             super::auth_middleware::AuthenticatedUser,
-            _,
-        >(auth_state))
-        .layer(TraceLayer::new_for_http().make_span_with(|request| {
-            tracing::span!(
-                Level::INFO,
-                "http_request",
-                method = %request.method(),
-                uri = %request.uri(),
-                version = ?request.version(),
-            )
-        }))
-        .with_state(engine);
-
-    tracing::info!("Starting REST server on {}", addr);
-
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .await
-        .unwrap();
-}
\ No newline at end of file
+        >())
+        .layer(axum::Extension(auth_state))
+        .layer(axum::middleware::from_fn_with_state(
+            connections,
+            super::connections::track_connection,
+        ))
+        // Honors Accept-Encoding; streams (watch) and tiny bodies are left alone
+        .layer(CompressionLayer::new())
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+                let request_id = request
+                    .extensions()
+                    .get::<RequestId>()
+                    .map_or("", |id| id.as_str());
+                tracing::span!(
+                    Level::INFO,
+                    "http_request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    version = ?request.version(),
+                    request_id = %request_id,
+                )
+            }),
+        )
+        // Outermost, so every response carries the ID and the trace span sees it
+        .layer(axum::middleware::from_fn(super::request_id::propagate))
+        .with_state(engine)
+}
@@ -1,23 +1,59 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::api::content::ValueBytes;
+use crate::storage::CompressionMode;
+
+use crate::storage::{ChangeOp, ExpiryReason, NodeRole, SnapshotInfo};
+
 #[derive(Deserialize)]
 pub struct GetParams {
     pub key: String,
+    #[serde(default)]
+    pub meta: bool, // also return `meta`
 }
 
 #[derive(Serialize)]
 pub struct GetResponse {
     pub found: bool,
-    pub value: Option<String>, // base64 for binary? or use bytes
+    pub value: Option<ValueBytes>,
     pub version: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<EntryMeta>,
+}
+
+#[derive(Serialize)]
+pub struct EntryMeta {
+    pub created_at: DateTime<Utc>, // when this version was written
+    pub expires_at: Option<DateTime<Utc>>,
+    pub ttl_secs: Option<u64>, // remaining, rounded up
+    pub value_len: usize,      // uncompressed
+}
+
+impl EntryMeta {
+    pub fn of(entry: &crate::storage::KvEntry) -> Self {
+        let time = |nanos: u64| DateTime::from_timestamp_nanos(nanos as i64);
+        Self {
+            created_at: time(entry.created_at),
+            expires_at: entry.expires_at.map(time),
+            ttl_secs: entry.ttl_secs(),
+            value_len: entry.value.len(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct SetParams {
     pub key: String,
-    pub value: String, // base64-encoded
+    pub value: ValueBytes,
     #[serde(default)]
     pub ttl: Option<u64>, // seconds
+    #[serde(default)]
+    pub compression: CompressionMode, // "auto" (configured), "none", "lz4" or "zstd"
+    /// Fsync the WAL before acknowledging, whatever `X-KV-Durability` and
+    /// the global sync policy say. Costs an fsync's latency on this write.
+    #[serde(default)]
+    pub sync: bool,
 }
 
 #[derive(Serialize)]
@@ -26,9 +62,171 @@ pub struct SetResponse {
     pub version: u64,
 }
 
+#[derive(Deserialize)]
+pub struct CasParams {
+    pub key: String,
+    pub value: ValueBytes,
+    pub expected_version: u64,
+    #[serde(default)]
+    pub ttl: Option<u64>, // seconds
+}
+
+#[derive(Deserialize)]
+pub struct GetSetParams {
+    pub key: String,
+    pub value: ValueBytes,
+    #[serde(default)]
+    pub ttl: Option<u64>, // seconds
+}
+
+#[derive(Serialize)]
+pub struct GetSetResponse {
+    pub success: bool,
+    pub old_value: Option<ValueBytes>, // null if the key was absent
+    pub version: u64,
+}
+
+#[derive(Deserialize)]
+pub struct SetNxParams {
+    pub key: String,
+    pub value: ValueBytes,
+    #[serde(default)]
+    pub ttl: Option<u64>, // seconds
+}
+
+#[derive(Serialize)]
+pub struct SetNxResponse {
+    pub written: bool,
+    pub version: Option<u64>, // set when written
+}
+
+#[derive(Deserialize)]
+pub struct ExistsParams {
+    pub key: String,
+}
+
+#[derive(Serialize)]
+pub struct ExistsResponse {
+    pub exists: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MexistsParams {
+    pub keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct MexistsResponse {
+    pub exists: Vec<bool>, // parallel to `keys`
+}
+
+#[derive(Deserialize)]
+pub struct MgetParams {
+    pub keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct MgetValue {
+    pub value: ValueBytes,
+    pub version: u64,
+}
+
+#[derive(Serialize)]
+pub struct MgetResponse {
+    pub values: Vec<Option<MgetValue>>, // parallel to `keys`; null if missing
+}
+
+#[derive(Deserialize)]
+pub struct MsetItem {
+    pub key: String,
+    pub value: ValueBytes,
+    #[serde(default)]
+    pub ttl: Option<u64>, // seconds
+}
+
+#[derive(Deserialize)]
+pub struct MsetParams {
+    pub items: Vec<MsetItem>,
+    #[serde(default)]
+    pub atomic: bool, // all-or-nothing instead of per-key best effort
+}
+
+#[derive(Serialize)]
+pub struct MsetKeyStatus {
+    pub key: String,
+    pub success: bool,
+    pub version: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MsetResponse {
+    pub success: bool, // every key was written
+    pub results: Vec<MsetKeyStatus>,
+}
+
+/// One operation of a `/v1/txn` request, tagged by `op`.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum TxnOpParams {
+    Set {
+        key: String,
+        value: String, // base64-encoded
+        #[serde(default)]
+        ttl: Option<u64>, // seconds
+    },
+    Del {
+        key: String,
+    },
+    Cas {
+        key: String,
+        value: String, // base64-encoded
+        expected_version: u64,
+        #[serde(default)]
+        ttl: Option<u64>, // seconds
+    },
+}
+
+#[derive(Deserialize)]
+pub struct TxnParams {
+    pub ops: Vec<TxnOpParams>,
+}
+
+#[derive(Serialize)]
+pub struct TxnKeyVersion {
+    pub key: String,
+    pub version: u64, // 0 once deleted
+}
+
+#[derive(Serialize)]
+pub struct TxnResponse {
+    pub success: bool,
+    pub results: Vec<TxnKeyVersion>,
+}
+
+#[derive(Deserialize)]
+pub struct WatchKeysParams {
+    pub keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct WatchKeysResponse {
+    pub success: bool,
+    pub watching: usize, // keys the session now watches
+}
+
+#[derive(Serialize)]
+pub struct ExecResponse {
+    pub committed: bool,
+    pub aborted_by: Option<String>, // the watched key that changed
+    pub results: Vec<TxnKeyVersion>,
+}
+
 #[derive(Deserialize)]
 pub struct DeleteParams {
     pub key: String,
+    #[serde(default)]
+    pub version: Option<u64>, // delete only while the key is at this version
 }
 
 #[derive(Serialize)]
@@ -36,6 +234,26 @@ pub struct DeleteResponse {
     pub success: bool,
 }
 
+#[derive(Deserialize)]
+pub struct GetRangeParams {
+    pub key: String,
+    #[serde(default)]
+    pub start: i64,
+    #[serde(default = "default_range_end")]
+    pub end: i64, // inclusive; negative counts from the end
+}
+
+fn default_range_end() -> i64 {
+    -1
+}
+
+#[derive(Serialize)]
+pub struct GetRangeResponse {
+    pub value: ValueBytes,
+    pub start: usize,
+    pub total_len: usize,
+}
+
 #[derive(Deserialize)]
 pub struct IncrParams {
     pub key: String,
@@ -48,11 +266,52 @@ pub struct IncrResponse {
     pub new_value: i64,
 }
 
+#[derive(Deserialize)]
+pub struct AppendParams {
+    pub key: String,
+    pub value: ValueBytes, // the suffix
+}
+
+#[derive(Serialize)]
+pub struct AppendResponse {
+    pub success: bool,
+    pub length: usize,
+}
+
+#[derive(Deserialize)]
+pub struct TtlParams {
+    pub key: String,
+}
+
+#[derive(Deserialize)]
+pub struct ExpireParams {
+    pub key: String,
+    pub ttl: u64, // seconds
+}
+
+#[derive(Deserialize)]
+pub struct PersistParams {
+    pub key: String,
+}
+
+/// Also returned by `/v1/expire` and `/v1/persist` with the key's new TTL.
+#[derive(Serialize)]
+pub struct TtlResponse {
+    pub ttl: Option<u64>, // seconds left; None = never expires
+}
+
 #[derive(Deserialize)]
 pub struct ScanParams {
-    pub pattern: String,
+    pub pattern: String, // glob: `*` and `?`
     #[serde(default = "default_limit")]
     pub limit: u64,
+    #[serde(default)]
+    pub cursor: Option<String>, // `next_cursor` of the previous page
+}
+
+#[derive(Deserialize)]
+pub struct ScanStreamParams {
+    pub pattern: String, // glob: `*` and `?`
 }
 
 fn default_limit() -> u64 {
@@ -62,12 +321,132 @@ fn default_limit() -> u64 {
 #[derive(Serialize)]
 pub struct ScanItem {
     pub key: String,
-    pub value: Option<String>,
+    pub value: Option<ValueBytes>,
     pub version: u64,
 }
 
 #[derive(Serialize)]
 pub struct ScanResponse {
-    pub items: Vec<ScanItem>,
+    pub items: Vec<ScanItem>, // empty (not an error) when nothing matches
     pub has_more: bool,
+    pub scanned: u64, // keys examined to produce this page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceParams {
+    pub enabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct MaintenanceResponse {
+    pub maintenance_mode: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeParams {
+    pub session_id: Option<String>,
+    pub token: Option<String>, // revoked until its own expiry; wins over `session_id`
+}
+
+#[derive(Serialize)]
+pub struct RevokeResponse {
+    pub session_id: String,
+    pub exp: Option<u64>, // revocation is dropped once tokens of this expiry are invalid; None = kept
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyParams {
+    pub owner: String,
+    #[serde(default)]
+    pub permissions: Vec<String>, // on top of the owner's roles
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    pub key_id: String,
+    pub api_key: String, // `<key_id>.<secret>` for `X-API-Key`; shown only once
+}
+
+#[derive(Deserialize)]
+pub struct ListApiKeysParams {
+    pub owner: String,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyInfo {
+    pub key_id: String,
+    pub owner: String,
+    pub permissions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+#[derive(Serialize)]
+pub struct ListApiKeysResponse {
+    pub keys: Vec<ApiKeyInfo>,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeApiKeyParams {
+    pub key_id: String,
+}
+
+#[derive(Serialize)]
+pub struct RevokeApiKeyResponse {
+    pub revoked: bool,
+}
+
+// The snapshot types are also read by `kvctl snapshot`
+#[derive(Serialize, Deserialize)]
+pub struct CreateSnapshotResponse {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListSnapshotsResponse {
+    pub snapshots: Vec<SnapshotInfo>, // oldest first
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RestoreSnapshotParams {
+    pub name: String,
+    #[serde(default)]
+    pub force: bool, // wait for in-flight writes instead of refusing
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RestoreSnapshotResponse {
+    pub restored: String,
+}
+
+#[derive(Serialize)]
+pub struct PingResponse {
+    pub pong: bool,
+    pub user: String,
+    pub role: NodeRole,
+    pub latency_us: u64, // engine read round-trip
+}
+
+#[derive(Deserialize)]
+pub struct WatchParams {
+    #[serde(default)]
+    pub prefix: String, // empty = every key the caller can read
+}
+
+/// A watch message, on both the SSE stream and the WebSocket.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WatchMessage {
+    Change {
+        key: String,
+        op: ChangeOp,
+        version: u64, // 0 for deletes and expiries
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<ExpiryReason>,
+    },
+    /// This many changes were dropped because the client read too slowly
+    Lagged { missed: u64 },
 }
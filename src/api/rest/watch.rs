@@ -0,0 +1,144 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::Extension;
+use futures_util::Stream;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::api::auth_middleware::AuthenticatedUser;
+use crate::api::connections::{TrackedConnection, RESUME_TOKEN_HEADER};
+use crate::api::error::ApiError;
+use crate::api::extract::Query;
+use crate::api::request_options::RequestNamespace;
+use crate::api::rest::types::{WatchMessage, WatchParams};
+use crate::auth::types::AuthContext;
+use crate::auth::AuthManager;
+use crate::storage::{ChangeSubscription, StorageEngine};
+
+/// `GET /v1/watch?prefix=`: changes to keys under `prefix` as server-sent
+/// events, `change` for each write and `lagged` when some were dropped.
+///
+/// Nothing is spawned per watcher: the subscription lives in the response
+/// stream, which axum drops when the client goes away. The keep-alive
+/// comments are what notice a silent disconnect on a quiet prefix.
+pub async fn watch_sse_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    namespace: RequestNamespace,
+    Query(params): Query<WatchParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    let watcher = subscribe(&engine, &auth, &auth_ctx, &namespace, &params.prefix)?;
+
+    let events = futures_util::stream::unfold(
+        (watcher, namespace),
+        |(mut watcher, namespace)| async move {
+            let message = next_message(&mut watcher, &namespace).await?;
+            let name = match message {
+                WatchMessage::Change { .. } => "change",
+                WatchMessage::Lagged { .. } => "lagged",
+            };
+            let event = Event::default().event(name).json_data(&message);
+            Some((event, (watcher, namespace)))
+        },
+    );
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// `GET /v1/watch/ws?prefix=`: the same messages as [`watch_sse_handler`],
+/// one JSON text frame each.
+///
+/// The socket holds its connection entry until it closes. With session
+/// resumption on, the upgrade response carries the resume token in
+/// [`RESUME_TOKEN_HEADER`]; a client that drops can reconnect within the
+/// window presenting it in place of credentials.
+pub async fn watch_ws_handler(
+    State(engine): State<Arc<StorageEngine>>,
+    Extension(auth): Extension<Arc<AuthManager>>,
+    AuthenticatedUser(auth_ctx): AuthenticatedUser,
+    conn: Option<Extension<TrackedConnection>>,
+    namespace: RequestNamespace,
+    Query(params): Query<WatchParams>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    // Authorized before upgrading, so a refusal is a plain HTTP error
+    let watcher = subscribe(&engine, &auth, &auth_ctx, &namespace, &params.prefix)?;
+    let conn = conn.map(|Extension(conn)| conn);
+    let resume_token = match &conn {
+        Some(conn) => conn.resume_token().await,
+        None => None,
+    };
+
+    let mut response =
+        upgrade.on_upgrade(move |socket| forward_changes(socket, watcher, namespace, conn));
+    if let Some(value) = resume_token.and_then(|token| HeaderValue::from_str(&token).ok()) {
+        response.headers_mut().insert(RESUME_TOKEN_HEADER, value);
+    }
+    Ok(response)
+}
+
+// Watching a prefix needs GET on everything under it
+fn subscribe(
+    engine: &StorageEngine,
+    auth: &AuthManager,
+    auth_ctx: &AuthContext,
+    namespace: &RequestNamespace,
+    prefix: &str,
+) -> Result<ChangeSubscription, ApiError> {
+    auth.authorize(auth_ctx, "GET", &namespace.key(&format!("{}*", prefix))?)
+        .map_err(ApiError::AuthError)?;
+    Ok(engine.watch(&namespace.key(prefix)?))
+}
+
+// `None` once the engine is gone
+async fn next_message(
+    watcher: &mut ChangeSubscription,
+    namespace: &RequestNamespace,
+) -> Option<WatchMessage> {
+    match watcher.recv().await {
+        Ok(change) => Some(WatchMessage::Change {
+            key: namespace.local_key(&change.key).to_string(),
+            op: change.op,
+            version: change.version,
+            reason: change.reason,
+        }),
+        Err(RecvError::Lagged(missed)) => Some(WatchMessage::Lagged { missed }),
+        Err(RecvError::Closed) => None,
+    }
+}
+
+// Runs until the client closes the socket or stops answering, which also
+// drops the subscription and then the connection entry
+async fn forward_changes(
+    mut socket: WebSocket,
+    mut watcher: ChangeSubscription,
+    namespace: RequestNamespace,
+    conn: Option<TrackedConnection>,
+) {
+    loop {
+        let message = tokio::select! {
+            message = next_message(&mut watcher, &namespace) => match message {
+                Some(message) => message,
+                None => break,
+            },
+            // Clients have nothing to say besides pings (answered by axum)
+            // and close; reading is how a disconnect is noticed
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        let Ok(text) = serde_json::to_string(&message) else {
+            break;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+        if let Some(conn) = &conn {
+            conn.touch().await;
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::config::AppConfig;
+use crate::server::{ServerHandle, ShutdownReason};
+
+const REST_ADDR: &str = "0.0.0.0:8080";
+const GRPC_ADDR: &str = "0.0.0.0:9090";
+const METRICS_ADDR: &str = "0.0.0.0:9091";
+const HEALTH_ADDR: &str = "0.0.0.0:9092";
+
+/// Why the server stopped, mapped onto the process exit code by `main`.
+/// A clean shutdown (signal) is `Ok(())` from [`run`] and exits 0.
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Startup failed: {0}")]
+    Startup(String),
+
+    #[error("Unrecoverable runtime error: {0}")]
+    Runtime(String),
+}
+
+impl AppError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Config(_) => 2,
+            AppError::Startup(_) => 3,
+            AppError::Runtime(_) => 4,
+        }
+    }
+
+    fn startup(e: impl std::fmt::Display) -> Self {
+        AppError::Startup(e.to_string())
+    }
+}
+
+impl From<crate::config::ConfigError> for AppError {
+    fn from(e: crate::config::ConfigError) -> Self {
+        AppError::Config(e.to_string())
+    }
+}
+
+/// Reads `path`, falling back to the bundled defaults when it doesn't exist.
+pub fn load_config(path: impl AsRef<Path>) -> Result<AppConfig, AppError> {
+    let config_str = std::fs::read_to_string(path)
+        .unwrap_or_else(|_| include_str!("../default_config.toml").to_string());
+    toml::from_str(&config_str).map_err(|e| AppError::Config(e.to_string()))
+}
+
+/// Starts every subsystem and serves until a shutdown signal (`Ok`) or a
+/// server task dies (`Err(AppError::Runtime)`).
+pub async fn run(mut config: AppConfig) -> Result<(), AppError> {
+    config.validate()?;
+    config
+        .load_encryption_key()
+        .map_err(|e| AppError::Config(e.to_string()))?;
+
+    // Create data directories and verify they are writable with enough space
+    crate::preflight::run(&config).map_err(AppError::startup)?;
+
+    // Bind every listener up front so a taken port fails startup, not a task
+    let rest_listener = bind(REST_ADDR).await?;
+    let grpc_listener = bind(GRPC_ADDR).await?;
+    let metrics_listener = bind(METRICS_ADDR).await?;
+    let health_listener = bind(HEALTH_ADDR).await?;
+
+    // Initialize WAL
+    let wal = crate::wal::WalManager::new(config.wal.clone())
+        .await
+        .map_err(AppError::startup)?;
+
+    // Initialize Storage Engine
+    let engine = crate::storage::StorageEngine::try_new(config.storage.clone())
+        .await
+        .map_err(AppError::startup)?;
+    engine.attach_wal(wal.clone());
+
+    // Start health check server; /ready reports false until startup is done
+    let health = crate::api::health::HealthState::new(engine.clone(), wal.clone());
+    let health_app = crate::api::health::router(health.clone());
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(health_listener, health_app).await {
+            tracing::error!("Health server failed: {}", e);
+        }
+    });
+
+    // Load the last checkpoint and replay the WAL written since
+    let snapshots = Arc::new(
+        crate::storage::SnapshotManager::new(config.storage.snapshot_dir.clone())
+            .with_cipher(config.wal.cipher.clone()),
+    );
+    // A node without a snapshot of its own can start from the bucket's newest
+    if let Some(s3_config) = config
+        .background
+        .s3
+        .as_ref()
+        .filter(|s3| s3.restore_on_empty)
+    {
+        if snapshots
+            .newest_snapshot()
+            .map_err(AppError::startup)?
+            .is_none()
+        {
+            let downloaded = crate::background::s3_uploader::S3Uploader::new(
+                engine.clone(),
+                config.storage.snapshot_dir.clone(),
+                s3_config.bucket.clone(),
+                s3_config.region.clone(),
+                s3_config.endpoint.clone(),
+                false,
+            )
+            .await
+            .map_err(AppError::startup)?
+            .download_latest()
+            .await
+            .map_err(AppError::startup)?;
+            if let Some(path) = downloaded {
+                info!("Restored snapshot {} from S3.", path.display());
+            }
+        }
+    }
+    engine
+        .recover(&snapshots, &wal)
+        .await
+        .map_err(AppError::startup)?;
+
+    // Bootstrap system catalog
+    let bootstrapped = crate::catalog::bootstrap::bootstrap_if_needed(&engine)
+        .await
+        .map_err(AppError::startup)?;
+    if bootstrapped {
+        info!("System catalog bootstrapped.");
+    }
+
+    // Initialize Catalog Manager
+    let catalog = Arc::new(crate::catalog::CatalogManager::new(engine.clone()));
+
+    // Initialize Auth Manager
+    let audit_settings = catalog.get_audit_settings().await.unwrap_or_default();
+    let auth_settings = catalog.get_auth_settings().await.unwrap_or_default();
+    let auth = Arc::new(
+        crate::auth::AuthManager::new(
+            catalog.clone(),
+            "my_jwt_secret_123".to_string(), // ⚠️ In production, load from secure config
+            "audit.log".to_string(),
+            &audit_settings,
+        )
+        .map_err(AppError::startup)?
+        .with_jwt_claims(
+            config.auth.jwt_issuer.clone(),
+            config.auth.jwt_audience.clone(),
+        )
+        .with_settings(&auth_settings),
+    );
+    let revoked = auth
+        .load_revoked_sessions()
+        .await
+        .map_err(AppError::startup)?;
+    if revoked > 0 {
+        info!("Loaded {} revoked sessions.", revoked);
+    }
+
+    // Initialize Background Workers
+    let background_workers = crate::background::WorkerManager::new(
+        engine.clone(),
+        wal.clone(),
+        config.storage.snapshot_dir.clone(),
+        &config.background,
+    )
+    .await
+    .map_err(AppError::startup)?;
+
+    // Track client connections and close idle ones in the background
+    let connections = Arc::new(crate::connection::ConnectionManager::new(
+        config.connection.clone(),
+    ));
+    let idle_reaper = connections.spawn_idle_reaper();
+
+    // Start metrics HTTP server (Prometheus endpoint)
+    let metrics_server = crate::api::metrics::start_metrics_server(
+        metrics_listener,
+        engine.clone(),
+        wal.clone(),
+        config.metrics.clone(),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = metrics_server.await {
+            tracing::error!("Metrics server failed: {}", e);
+        }
+    });
+
+    // Start API servers; cancelling `shutdown` makes them drain and return
+    let shutdown = CancellationToken::new();
+    let limiter = Arc::new(crate::api::rate_limit::RateLimiter::new(
+        config.rate_limit.clone(),
+    ));
+    let rest_handle = tokio::spawn(crate::api::rest::start_rest_server(
+        rest_listener,
+        engine.clone(),
+        wal.clone(),
+        auth.clone(),
+        connections.clone(),
+        snapshots,
+        limiter.clone(),
+        shutdown.clone().cancelled_owned(),
+    ));
+    let grpc_handle = tokio::spawn(crate::api::grpc::start_grpc_server(
+        grpc_listener,
+        engine.clone(),
+        auth.clone(),
+        connections,
+        limiter,
+        shutdown.clone().cancelled_owned(),
+    ));
+
+    health.mark_ready(background_workers.health());
+    let server_handle = ServerHandle::new(
+        rest_handle,
+        grpc_handle,
+        background_workers,
+        auth,
+        shutdown,
+        Duration::from_millis(config.background.shutdown_phase_timeout_ms),
+    );
+
+    info!("KVStore++ ready to accept connections.");
+    info!("REST API: http://{}", REST_ADDR);
+    info!("gRPC API: http://{}", GRPC_ADDR);
+    info!("Metrics: http://{}/metrics", METRICS_ADDR);
+    info!(
+        "Health: http://{}/health, http://{}/ready",
+        HEALTH_ADDR, HEALTH_ADDR
+    );
+
+    // Wait for shutdown
+    let reason = server_handle.wait_for_shutdown().await;
+    idle_reaper.shutdown().await;
+    info!("Shutdown reason: {}", reason);
+    match reason {
+        ShutdownReason::Signal(_) | ShutdownReason::Requested => Ok(()),
+        ShutdownReason::ServerExited { .. } => Err(AppError::Runtime(reason.to_string())),
+    }
+}
+
+async fn bind(addr: &str) -> Result<TcpListener, AppError> {
+    let addr: SocketAddr = addr.parse().map_err(AppError::startup)?;
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| AppError::Startup(format!("cannot bind {}: {}", addr, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invalid_config_exits_with_code_2() {
+        let mut config = AppConfig::default();
+        config.storage.num_shards = 0;
+
+        let err = run(config).await.unwrap_err();
+        assert!(matches!(err, AppError::Config(_)));
+        assert_eq!(err.exit_code(), 2);
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: Invalid value for storage.num_shards: must be at least 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unparseable_config_is_a_config_error() {
+        let dir = std::env::temp_dir().join(format!("app_config_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[storage\nnum_shards = ").unwrap();
+
+        let err = load_config(&path).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
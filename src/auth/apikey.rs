@@ -1,25 +1,36 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::catalog::error::CatalogError;
 use crate::catalog::CatalogManager;
-use crate::storage::StorageEngine;
+use crate::storage::StorageError;
 
-pub struct ApiKeyValidator {
-    catalog: CatalogManager,
+/// Checks presented API keys (`<key_id>.<secret>`) against `_sys.api_keys:`.
+pub struct ApiKeyValidator<'a> {
+    catalog: &'a CatalogManager,
 }
 
-impl ApiKeyValidator {
-    pub fn new(catalog: CatalogManager) -> Self {
+impl<'a> ApiKeyValidator<'a> {
+    pub fn new(catalog: &'a CatalogManager) -> Self {
         Self { catalog }
     }
 
-    pub async fn validate(&self, key_id: &str) -> Result<(String, Vec<String>), crate::auth::types::AuthError> {
-        // In MVP: key_id is stored as `_sys.api_keys:<key_id>`
-        // Value is JSON: { "owner_user": "...", "permissions": [...] }
-        let key = format!("_sys.api_keys:{}", key_id);
-        let entry = self.catalog.engine.get(&key).await.map_err(|_| crate::auth::types::AuthError::InvalidCredentials)?;
+    pub async fn validate(&self, presented: &str) -> Result<(String, Vec<String>), crate::auth::types::AuthError> {
+        let (key_id, secret) = presented
+            .split_once('.')
+            .ok_or(crate::auth::types::AuthError::InvalidCredentials)?;
 
-        let api_key: ApiKeyEntry = serde_json::from_slice(&entry.value)
-            .map_err(|_| crate::auth::types::AuthError::InvalidCredentials)?;
+        let api_key = match self.catalog.get_api_key(key_id).await {
+            Ok(api_key) => api_key,
+            Err(CatalogError::Storage(StorageError::KeyNotFound(_))) => {
+                return Err(crate::auth::types::AuthError::InvalidCredentials)
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        if api_key.revoked {
+        // Both sides are digests of a random secret, so comparing them leaks
+        // nothing useful about the secret itself
+        if hash_secret(secret) != api_key.secret_sha256 || api_key.revoked {
             return Err(crate::auth::types::AuthError::InvalidCredentials);
         }
 
@@ -36,10 +47,94 @@ impl ApiKeyValidator {
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
-struct ApiKeyEntry {
-    owner_user: String,
-    permissions: Vec<String>,
-    expires_at: Option<chrono::DateTime<chrono::Utc>>,
-    revoked: bool,
-}
\ No newline at end of file
+/// The key ID part of a presented API key, safe to log.
+pub fn key_id(presented: &str) -> &str {
+    presented.split_once('.').map_or("", |(key_id, _)| key_id)
+}
+
+/// A new random API key secret (256 bits, hex).
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// How a secret is stored: a plain SHA-256 is enough for 256 random bits,
+/// and unlike a password hash it is cheap enough to check on every request.
+pub fn hash_secret(secret: &str) -> String {
+    to_hex(&Sha256::digest(secret.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::types::AuthError;
+    use crate::storage::{StorageConfig, StorageEngine};
+
+    #[tokio::test]
+    async fn test_api_key_lifecycle() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let catalog = CatalogManager::new(engine.clone());
+        let (key_id, secret) = catalog
+            .create_api_key("alice", vec!["GET".to_string()], None)
+            .await
+            .unwrap();
+
+        // Only the hash is persisted
+        let stored = engine
+            .get(&format!("_sys.api_keys:{}", key_id))
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&stored.value).contains(&secret));
+
+        let presented = format!("{}.{}", key_id, secret);
+        assert_eq!(super::key_id(&presented), key_id);
+        let (user, permissions) = catalog.api_key_validator().validate(&presented).await.unwrap();
+        assert_eq!(user, "alice");
+        assert_eq!(permissions, vec!["GET".to_string()]);
+
+        for wrong in [
+            key_id.clone(),
+            format!("{}.{}", key_id, generate_secret()),
+            format!("nosuchkey.{}", secret),
+        ] {
+            assert!(matches!(
+                catalog.api_key_validator().validate(&wrong).await,
+                Err(AuthError::InvalidCredentials)
+            ));
+        }
+
+        // An expired key and a revoked one are both refused, but still listed
+        let (expired_id, expired_secret) = catalog
+            .create_api_key(
+                "alice",
+                vec![],
+                Some(chrono::Utc::now() - chrono::Duration::minutes(1)),
+            )
+            .await
+            .unwrap();
+        assert!(catalog
+            .api_key_validator()
+            .validate(&format!("{}.{}", expired_id, expired_secret))
+            .await
+            .is_err());
+        catalog.create_api_key("bob", vec![], None).await.unwrap();
+
+        assert!(catalog.revoke_api_key(&key_id).await.unwrap());
+        assert!(!catalog.revoke_api_key("nosuchkey").await.unwrap());
+        assert!(catalog.api_key_validator().validate(&presented).await.is_err());
+
+        let listed = catalog.list_api_keys("alice").await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].key_id, key_id);
+        assert!(listed[0].revoked);
+    }
+}
@@ -1,10 +1,16 @@
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::time::SystemTime;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
 
-use serde::Serialize;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+use crate::catalog::types::{AuditFlushPolicy, AuditSettings};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditEvent {
     pub timestamp: u64,
     pub event: String,           // "login_success", "login_failed", "permission_denied"
@@ -16,26 +22,600 @@ pub struct AuditEvent {
     pub key: Option<String>,     // if permission denied
     pub success: bool,
     pub details: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>, // X-Request-Id of the API request behind it
+}
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `f` as the handling of request `request_id`; events it audits
+/// should carry [`current_request_id`].
+pub async fn with_request_id<F: std::future::Future>(request_id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(request_id, f).await
+}
+
+/// The request ID of the enclosing [`with_request_id`], if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
 }
 
+/// Which events [`AuditLogger::query`] returns; `None` matches anything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub user: Option<String>,
+    pub event: Option<String>,
+    pub success: Option<bool>,
+    pub since: Option<u64>, // unix seconds, inclusive
+    pub until: Option<u64>, // unix seconds, exclusive
+}
+
+impl AuditFilter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.user
+            .as_ref()
+            .is_none_or(|user| event.user.as_ref() == Some(user))
+            && self.event.as_ref().is_none_or(|name| &event.event == name)
+            && self.success.is_none_or(|success| event.success == success)
+            && self.since.is_none_or(|since| event.timestamp >= since)
+            && self.until.is_none_or(|until| event.timestamp < until)
+    }
+}
+
+/// Appends audit events as JSON lines. Once the file reaches
+/// `max_file_bytes` it is rotated: `audit.log` becomes `audit.log.1`, the
+/// previous `.1` becomes `.2`, and so on. Rotated files older than
+/// `retain_logs_days` are deleted at each rotation.
 pub struct AuditLogger {
-    file: std::fs::File,
+    file: Arc<Mutex<AuditFile>>,
+    policy: AuditFlushPolicy,
+    flush_timer: AtomicBool, // started, for the buffered policy
+    path: PathBuf,
+    max_file_bytes: u64,      // 0 = never rotate
+    retain: Option<Duration>, // None = keep rotated files forever
+}
+
+struct AuditFile {
+    writer: BufWriter<File>,
+    bytes: u64, // written to the current file, buffered bytes included
 }
 
 impl AuditLogger {
     pub fn new(log_path: &str) -> Result<Self, std::io::Error> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?;
+        Self::with_settings(log_path, &AuditSettings::default())
+    }
+
+    /// Opens the log with the configured flush policy. Buffered mode flushes
+    /// on a timer, spawned on the tokio runtime of the first event logged
+    /// from inside one; `fail_closed` always writes per event.
+    pub fn with_settings(log_path: &str, settings: &AuditSettings) -> Result<Self, std::io::Error> {
+        let path = PathBuf::from(log_path);
+        let file = open_append(&path)?;
+
+        let policy = if settings.fail_closed {
+            AuditFlushPolicy::PerEvent
+        } else {
+            settings.flush_policy.clone()
+        };
 
-        Ok(Self { file })
+        let logger = Self {
+            file: Arc::new(Mutex::new(file)),
+            policy,
+            flush_timer: AtomicBool::new(false),
+            path,
+            max_file_bytes: settings.max_file_bytes,
+            retain: (settings.retain_logs_days > 0)
+                .then(|| Duration::from_secs(settings.retain_logs_days as u64 * 86_400)),
+        };
+        logger.prune();
+        Ok(logger)
     }
 
-    pub fn log(&mut self, event: AuditEvent) -> Result<(), std::io::Error> {
+    pub fn policy(&self) -> &AuditFlushPolicy {
+        &self.policy
+    }
+
+    pub fn log(&self, event: AuditEvent) -> Result<(), std::io::Error> {
+        self.start_flush_timer();
         let line = serde_json::to_string(&event)?;
-        writeln!(self.file, "{}", line)?;
-        self.file.flush()?;
+        // Rotation happens under the same lock, so concurrent events never
+        // land in a file that is being renamed away
+        let mut file = self.file.lock();
+        writeln!(file.writer, "{}", line)?;
+        file.bytes += line.len() as u64 + 1;
+        if self.policy == AuditFlushPolicy::PerEvent {
+            file.writer.flush()?;
+        }
+        if self.max_file_bytes > 0 && file.bytes >= self.max_file_bytes {
+            self.rotate_locked(&mut file)?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Until there is a runtime to run it on, buffered events wait for an
+    // explicit flush, a query, a rotation or the logger being dropped
+    fn start_flush_timer(&self) {
+        let AuditFlushPolicy::Buffered { flush_ms } = self.policy else {
+            return;
+        };
+        if self.flush_timer.load(Ordering::Acquire) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if !self.flush_timer.swap(true, Ordering::AcqRel) {
+            spawn_flush_timer(
+                &runtime,
+                Arc::downgrade(&self.file),
+                Duration::from_millis(flush_ms),
+            );
+        }
+    }
+
+    /// Writes out any buffered events. Call on shutdown.
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.file.lock().writer.flush()
+    }
+
+    /// Events matching `filter` in this log and its rotations, oldest first.
+    /// Buffered events are flushed first so they are included.
+    pub fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, std::io::Error> {
+        // Held throughout so a rotation can't move files mid-read
+        let mut file = self.file.lock();
+        file.writer.flush()?;
+        query_log(&self.path, filter)
+    }
+
+    /// Moves the current file to `<path>.1`, shifting older rotations up by
+    /// one, and starts a new one. Rotated files past retention are pruned.
+    pub fn rotate(&self) -> Result<(), std::io::Error> {
+        let mut file = self.file.lock();
+        self.rotate_locked(&mut file)
+    }
+
+    fn rotate_locked(&self, file: &mut AuditFile) -> Result<(), std::io::Error> {
+        file.writer.flush()?;
+        let mut rotated = rotated_files(&self.path)?;
+        rotated.sort_by_key(|&(n, _)| std::cmp::Reverse(n));
+        for (n, older) in rotated {
+            std::fs::rename(older, rotated_path(&self.path, n + 1))?;
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        *file = open_append(&self.path)?;
+        self.prune();
+        Ok(())
+    }
+
+    // Best effort: a file that can't be inspected or removed now is retried
+    // at the next rotation
+    fn prune(&self) {
+        let Some(retain) = self.retain else {
+            return;
+        };
+        let Ok(rotated) = rotated_files(&self.path) else {
+            return;
+        };
+        let cutoff = SystemTime::now() - retain;
+        for (_, path) in rotated {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified());
+            if modified.is_ok_and(|modified| modified < cutoff) {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    tracing::warn!("Failed to prune audit log {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Reads back the log at `path` and its rotations without opening it for
+/// writing, e.g. from `kvctl`. The log may be live: a last line that is still
+/// being written is skipped, as is any line that doesn't parse.
+pub fn query_log(path: &Path, filter: &AuditFilter) -> Result<Vec<AuditEvent>, std::io::Error> {
+    let mut files = rotated_files(path)?;
+    files.sort_by_key(|&(n, _)| std::cmp::Reverse(n));
+    let files = files
+        .into_iter()
+        .map(|(_, file)| file)
+        .chain(std::iter::once(path.to_path_buf()));
+
+    let mut events = Vec::new();
+    for file in files {
+        let contents = match std::fs::read(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let Some(end) = contents.iter().rposition(|&b| b == b'\n') else {
+            continue;
+        };
+        for line in contents[..end].split(|&b| b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<AuditEvent>(line) {
+                Ok(event) if filter.matches(&event) => events.push(event),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Skipping bad audit line in {}: {}", file.display(), e),
+            }
+        }
+    }
+    Ok(events)
+}
+
+fn open_append(path: &Path) -> Result<AuditFile, std::io::Error> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(AuditFile {
+        bytes: file.metadata()?.len(),
+        writer: BufWriter::new(file),
+    })
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+// `<path>.<n>` files next to `path`, with their `n`
+fn rotated_files(path: &Path) -> Result<Vec<(u32, PathBuf)>, std::io::Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(prefix) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let mut rotated = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let n = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|suffix| suffix.strip_prefix('.'))
+            .and_then(|n| n.parse::<u32>().ok());
+        if let Some(n) = n {
+            rotated.push((n, entry.path()));
+        }
+    }
+    Ok(rotated)
+}
+
+impl Drop for AuditLogger {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            tracing::error!("Failed to flush audit log: {}", e);
+        }
+    }
+}
+
+// Stops on its own once the logger is dropped
+fn spawn_flush_timer(
+    runtime: &tokio::runtime::Handle,
+    file: Weak<Mutex<AuditFile>>,
+    interval: Duration,
+) {
+    runtime.spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let Some(file) = file.upgrade() else {
+                break;
+            };
+            let result = file.lock().writer.flush();
+            if let Err(e) = result {
+                tracing::error!("Failed to flush audit log: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u64) -> AuditEvent {
+        AuditEvent {
+            timestamp: n,
+            event: "login_success".to_string(),
+            user: Some("alice".to_string()),
+            source_ip: "127.0.0.1".to_string(),
+            auth_method: "jwt".to_string(),
+            key_id: None,
+            op: None,
+            key: None,
+            success: true,
+            details: None,
+            request_id: None,
+        }
+    }
+
+    fn lines_on_disk(path: &std::path::Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    fn temp_log(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}.log", name, uuid::Uuid::new_v4()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_per_event_writes_immediately() {
+        let path = temp_log("audit_per_event");
+        let logger = AuditLogger::new(path.to_str().unwrap()).unwrap();
+
+        logger.log(event(1)).unwrap();
+        assert_eq!(lines_on_disk(&path), 1);
+        logger.log(event(2)).unwrap();
+        assert_eq!(lines_on_disk(&path), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_buffered_flushes_on_timer_and_shutdown() {
+        let path = temp_log("audit_buffered");
+        let settings = AuditSettings {
+            flush_policy: AuditFlushPolicy::Buffered { flush_ms: 50 },
+            ..Default::default()
+        };
+        let logger = AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap();
+
+        logger.log(event(1)).unwrap();
+        assert_eq!(lines_on_disk(&path), 0);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(lines_on_disk(&path), 1);
+
+        logger.log(event(2)).unwrap();
+        drop(logger); // clean shutdown
+        assert_eq!(lines_on_disk(&path), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_buffered_logger_opens_outside_a_runtime() {
+        let path = temp_log("audit_no_runtime");
+        let settings = AuditSettings {
+            flush_policy: AuditFlushPolicy::Buffered { flush_ms: 50 },
+            ..Default::default()
+        };
+        let logger = AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap();
+
+        logger.log(event(1)).unwrap();
+        assert_eq!(lines_on_disk(&path), 0);
+        logger.flush().unwrap();
+        assert_eq!(lines_on_disk(&path), 1);
+
+        // The timer starts with the first event logged on a runtime
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            logger.log(event(2)).unwrap();
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            assert_eq!(lines_on_disk(&path), 2);
+        });
+
+        drop(logger);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fail_closed_forces_per_event() {
+        let path = temp_log("audit_fail_closed");
+        let settings = AuditSettings {
+            flush_policy: AuditFlushPolicy::Buffered { flush_ms: 60_000 },
+            fail_closed: true,
+            ..Default::default()
+        };
+        let logger = AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap();
+        assert_eq!(logger.policy(), &AuditFlushPolicy::PerEvent);
+
+        logger.log(event(1)).unwrap();
+        assert_eq!(lines_on_disk(&path), 1);
+
+        drop(logger);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_rotates_when_size_limit_reached() {
+        let dir = temp_dir("audit_rotate");
+        let path = dir.join("audit.log");
+        let line_len = serde_json::to_string(&event(1)).unwrap().len() as u64 + 1;
+        let settings = AuditSettings {
+            max_file_bytes: line_len * 3,
+            ..Default::default()
+        };
+        let logger = AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap();
+
+        for n in 0..8 {
+            logger.log(event(n)).unwrap();
+        }
+        // Two full files rotated away; the newest rotation is `.1`
+        assert_eq!(lines_on_disk(&path), 2);
+        assert_eq!(lines_on_disk(&rotated_path(&path, 1)), 3);
+        assert_eq!(lines_on_disk(&rotated_path(&path, 2)), 3);
+        let first: serde_json::Value = serde_json::from_str(
+            std::fs::read_to_string(rotated_path(&path, 2))
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(first["timestamp"], 0);
+
+        // Manual rotation shifts everything up again
+        logger.rotate().unwrap();
+        assert_eq!(lines_on_disk(&path), 0);
+        assert_eq!(lines_on_disk(&rotated_path(&path, 3)), 3);
+
+        drop(logger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotation_under_concurrent_logging_loses_nothing() {
+        let dir = temp_dir("audit_rotate_concurrent");
+        let path = dir.join("audit.log");
+        let settings = AuditSettings {
+            max_file_bytes: 4096,
+            ..Default::default()
+        };
+        let logger = Arc::new(
+            AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap(),
+        );
+
+        let writers: Vec<_> = (0..8)
+            .map(|t| {
+                let logger = logger.clone();
+                std::thread::spawn(move || {
+                    for n in 0..200 {
+                        logger.log(event(t * 1000 + n)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let mut files = vec![path.clone()];
+        files.extend(rotated_files(&path).unwrap().into_iter().map(|(_, p)| p));
+        assert!(files.len() > 2);
+        let mut seen = std::collections::HashSet::new();
+        for file in &files {
+            for line in std::fs::read_to_string(file).unwrap().lines() {
+                let event: serde_json::Value = serde_json::from_str(line).unwrap();
+                assert!(seen.insert(event["timestamp"].as_u64().unwrap()));
+            }
+        }
+        assert_eq!(seen.len(), 8 * 200);
+
+        drop(logger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotation_prunes_files_past_retention() {
+        let dir = temp_dir("audit_prune");
+        let path = dir.join("audit.log");
+        let stale = rotated_path(&path, 1);
+        let file = File::create(&stale).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(3 * 86_400))
+            .unwrap();
+        drop(file);
+
+        let settings = AuditSettings {
+            retain_logs_days: 7,
+            ..Default::default()
+        };
+        let logger = AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap();
+        logger.log(event(1)).unwrap();
+        logger.rotate().unwrap();
+        // Still within retention: shifted, not deleted
+        assert!(rotated_path(&path, 2).exists());
+        drop(logger);
+
+        let settings = AuditSettings {
+            retain_logs_days: 1,
+            ..Default::default()
+        };
+        let logger = AuditLogger::with_settings(path.to_str().unwrap(), &settings).unwrap();
+        assert!(!rotated_path(&path, 2).exists());
+        assert_eq!(lines_on_disk(&rotated_path(&path, 1)), 1);
+
+        drop(logger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_filters_across_rotations_and_skips_partial_line() {
+        let dir = temp_dir("audit_query");
+        let path = dir.join("audit.log");
+        let logger = AuditLogger::new(path.to_str().unwrap()).unwrap();
+
+        let failed = |n: u64, user: &str| AuditEvent {
+            event: "login_failed".to_string(),
+            user: Some(user.to_string()),
+            success: false,
+            ..event(n)
+        };
+        logger.log(failed(10, "alice")).unwrap();
+        logger.log(event(20)).unwrap();
+        logger.rotate().unwrap();
+        logger.log(failed(30, "bob")).unwrap();
+        logger.log(failed(40, "alice")).unwrap();
+
+        let alice_failures = AuditFilter {
+            user: Some("alice".to_string()),
+            success: Some(false),
+            ..Default::default()
+        };
+        let found = logger.query(&alice_failures).unwrap();
+        assert_eq!(
+            found.iter().map(|e| e.timestamp).collect::<Vec<_>>(),
+            vec![10, 40]
+        );
+
+        let window = AuditFilter {
+            event: Some("login_failed".to_string()),
+            since: Some(20),
+            until: Some(40),
+            ..Default::default()
+        };
+        assert_eq!(logger.query(&window).unwrap()[0].timestamp, 30);
+        assert_eq!(logger.query(&window).unwrap().len(), 1);
+
+        // A writer caught mid-line
+        drop(logger);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"timestamp\":50,\"event\":\"log").unwrap();
+        let all = query_log(&path, &AuditFilter::default()).unwrap();
+        assert_eq!(all.len(), 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events_carry_the_request_id() {
+        let dir = temp_dir("audit_request_id");
+        let path = dir.join("audit.log");
+        let logger = AuditLogger::new(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(current_request_id(), None);
+        with_request_id("req-7".to_string(), async {
+            logger
+                .log(AuditEvent {
+                    request_id: current_request_id(),
+                    ..event(1)
+                })
+                .unwrap();
+        })
+        .await;
+        logger.log(event(2)).unwrap();
+
+        let ids: Vec<Option<String>> = query_log(&path, &AuditFilter::default())
+            .unwrap()
+            .into_iter()
+            .map(|e| e.request_id)
+            .collect();
+        assert_eq!(ids, vec![Some("req-7".to_string()), None]);
+        // Left out when unset, as in lines written before the field existed
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.matches("request_id").count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
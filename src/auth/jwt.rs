@@ -2,21 +2,40 @@ use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header,
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Seconds past `exp` a token is still accepted, for clock skew.
+pub const EXP_LEEWAY_SECS: u64 = 60;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,           // username
     pub exp: usize,            // expiration (Unix timestamp)
     pub perms: Vec<String>,    // permissions (cached at login)
-    pub session_id: String,    // for revocation later
+    pub session_id: String,    // revoked via AuthManager::revoke_session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 pub struct JwtManager {
     secret: String,
+    issuer: Option<String>,   // set on generate, enforced on validate
+    audience: Option<String>, // set on generate, enforced on validate
 }
 
 impl JwtManager {
     pub fn new(secret: String) -> Self {
-        Self { secret }
+        Self { secret, issuer: None, audience: None }
+    }
+
+    pub fn with_issuer(mut self, issuer: Option<String>) -> Self {
+        self.issuer = issuer;
+        self
+    }
+
+    pub fn with_audience(mut self, audience: Option<String>) -> Self {
+        self.audience = audience;
+        self
     }
 
     pub fn generate(&self, username: &str, permissions: Vec<String>, expires_in: u64) -> Result<String, jsonwebtoken::errors::Error> {
@@ -31,14 +50,83 @@ impl JwtManager {
             exp,
             perms: permissions,
             session_id,
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
         };
 
         encode(&Header::default(), &claims, &EncodingKey::from_secret(self.secret.as_ref()))
     }
 
     pub fn validate(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-        let validation = Validation::default();
+        let mut validation = Validation::default();
+        validation.leeway = EXP_LEEWAY_SECS;
+        // Configured claims must be present, not just match when present
+        let mut required = vec!["exp"];
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+            required.push("aud");
+        }
+        validation.set_required_spec_claims(&required);
         let token_data = decode::<Claims>(token, &DecodingKey::from_secret(self.secret.as_ref()), &validation)?;
         Ok(token_data.claims)
     }
-}
\ No newline at end of file
+
+    /// The `sub` a token claims, read without checking its signature, expiry
+    /// or anything else. Only fit for bucketing failed logins: it says who the
+    /// token names, not that the caller is them.
+    pub fn unverified_subject(token: &str) -> Option<String> {
+        let mut validation = Validation::default();
+        validation.insecure_disable_signature_validation();
+        validation.validate_exp = false;
+        validation.set_required_spec_claims::<&str>(&[]);
+        decode::<Claims>(token, &DecodingKey::from_secret(&[]), &validation)
+            .ok()
+            .map(|token_data| token_data.claims.sub)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audience_mismatch_rejected() {
+        let issuer = JwtManager::new("secret".to_string())
+            .with_issuer(Some("kvstore".to_string()))
+            .with_audience(Some("billing".to_string()));
+        let token = issuer.generate("alice", vec![], 60).unwrap();
+
+        let billing = JwtManager::new("secret".to_string())
+            .with_issuer(Some("kvstore".to_string()))
+            .with_audience(Some("billing".to_string()));
+        let claims = billing.validate(&token).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.aud.as_deref(), Some("billing"));
+
+        let search = JwtManager::new("secret".to_string())
+            .with_audience(Some("search".to_string()));
+        assert!(search.validate(&token).is_err());
+
+        let other_issuer = JwtManager::new("secret".to_string())
+            .with_issuer(Some("someone-else".to_string()));
+        assert!(other_issuer.validate(&token).is_err());
+    }
+
+    #[test]
+    fn test_missing_claims_rejected_only_when_configured() {
+        let token = JwtManager::new("secret".to_string())
+            .generate("alice", vec![], 60)
+            .unwrap();
+
+        // Unset iss/aud keeps the old behavior
+        assert!(JwtManager::new("secret".to_string()).validate(&token).is_ok());
+
+        let strict = JwtManager::new("secret".to_string())
+            .with_audience(Some("billing".to_string()));
+        assert!(strict.validate(&token).is_err());
+    }
+}
@@ -1,15 +1,41 @@
 use crate::auth::audit::AuditLogger;
-use crate::auth::jwt::JwtManager;
+use crate::auth::cidr::Cidr;
+use crate::auth::jwt::{JwtManager, EXP_LEEWAY_SECS};
 use crate::auth::AuthError;
+use crate::catalog::types::{AuditSettings, AuthSettings, RevokedSession};
 use crate::catalog::CatalogManager;
+use dashmap::DashMap;
 use std::collections::HashSet;
 use std::net::IpAddr;
 use std::sync::Arc;
 
+// Credential name failed JWT logins are counted under, followed by the
+// subject the token claims so users sharing a NAT don't lock each other out;
+// a token too malformed to name one is counted per source IP alone
+const JWT_PRINCIPAL: &str = "jwt";
+
+// Above this many tracked credentials, stale failure counts are dropped so a
+// client spraying random keys can't grow the table without bound
+const MAX_TRACKED_LOGINS: usize = 10_000;
+
 pub struct AuthManager {
     catalog: Arc<CatalogManager>,
     jwt_manager: JwtManager,
     audit_logger: AuditLogger,
+    // Failed logins per (API key or `JWT_PRINCIPAL`[:claimed subject], source IP)
+    failed_logins: DashMap<(String, IpAddr), FailedLogins>,
+    login_attempt_limit: u32, // 0 = never lock
+    lockout_ms: u64,
+    // Revoked JWT session IDs -> unix seconds after which their tokens stop
+    // validating; mirrors `_sys.revoked_sessions:` in the catalog
+    revoked_sessions: DashMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct FailedLogins {
+    count: u32,
+    last_failure_ms: u64,
+    locked_until_ms: Option<u64>,
 }
 
 impl AuthManager {
@@ -17,38 +43,70 @@ impl AuthManager {
         catalog: Arc<CatalogManager>,
         jwt_secret: String,
         audit_log_path: String,
+        audit_settings: &AuditSettings,
     ) -> Result<Self, std::io::Error> {
         let jwt_manager = JwtManager::new(jwt_secret);
-        let audit_logger = AuditLogger::new(&audit_log_path)?;
+        let audit_logger = AuditLogger::with_settings(&audit_log_path, audit_settings)?;
 
+        let settings = AuthSettings::default();
         Ok(Self {
             catalog,
             jwt_manager,
             audit_logger,
+            failed_logins: DashMap::new(),
+            login_attempt_limit: settings.login_attempt_limit as u32,
+            lockout_ms: settings.lockout_duration_sec as u64 * 1000,
+            revoked_sessions: DashMap::new(),
         })
     }
 
+    /// Lock a credential out from the source IP it failed from after
+    /// `login_attempt_limit` consecutive failures, for `lockout_duration_sec`.
+    pub fn with_settings(mut self, settings: &AuthSettings) -> Self {
+        self.login_attempt_limit = settings.login_attempt_limit as u32;
+        self.lockout_ms = settings.lockout_duration_sec as u64 * 1000;
+        self
+    }
+
+    /// Expected JWT `iss`/`aud` claims: stamped on issued tokens and required
+    /// on validation. `None` leaves the claim unchecked.
+    pub fn with_jwt_claims(mut self, issuer: Option<String>, audience: Option<String>) -> Self {
+        self.jwt_manager = self.jwt_manager.with_issuer(issuer).with_audience(audience);
+        self
+    }
+
+    pub fn flush_audit(&self) -> Result<(), std::io::Error> {
+        self.audit_logger.flush()
+    }
+
     // ================
     // AUTHENTICATE
     // ================
+    /// `key` is the `<key_id>.<secret>` the client presented; only the key
+    /// ID is ever logged.
     pub async fn authenticate_api_key(
         &self,
-        key_id: &str,
+        key: &str,
         source_ip: IpAddr,
     ) -> Result<crate::auth::types::AuthContext, crate::auth::types::AuthError> {
-        match self.catalog.api_key_validator().validate(key_id).await {
-            Ok((user, direct_permissions)) => {
-                // For MVP: permissions from API key override roles
-                // Later: merge with role permissions
+        let key_id = crate::auth::apikey::key_id(key);
+        let attempt = (key_id.to_string(), source_ip);
+        self.check_lockout(&attempt, "api_key", Some(key_id))?;
 
+        match self.catalog.api_key_validator().validate(key).await {
+            Ok((user, direct_permissions)) => {
+                self.failed_logins.remove(&attempt);
                 let ctx = crate::auth::types::AuthContext {
                     user: user.clone(),
-                    roles: Vec::new(), // not used in MVP for API keys
+                    roles: Vec::new(),
                     permissions: direct_permissions.clone(),
                     source_ip,
                     auth_method: crate::auth::types::AuthMethod::ApiKey(key_id.to_string()),
                     session_id: uuid::Uuid::new_v4().to_string(),
                 };
+                self.check_account(&user, source_ip, "api_key", Some(key_id))
+                    .await?;
+                let ctx = self.with_roles(ctx).await?;
 
                 // Log success
                 self.audit_logger
@@ -66,12 +124,15 @@ impl AuthManager {
                         key: None,
                         success: true,
                         details: None,
+                        request_id: crate::auth::audit::current_request_id(),
                     })
                     .ok(); // best effort
 
                 Ok(ctx)
             }
             Err(e) => {
+                self.record_failure(attempt);
+
                 // Log failure
                 self.audit_logger
                     .log(crate::auth::audit::AuditEvent {
@@ -88,6 +149,7 @@ impl AuthManager {
                         key: None,
                         success: false,
                         details: Some(e.to_string()),
+                        request_id: crate::auth::audit::current_request_id(),
                     })
                     .ok();
 
@@ -101,10 +163,27 @@ impl AuthManager {
         token: &str,
         source_ip: IpAddr,
     ) -> Result<crate::auth::types::AuthContext, crate::auth::types::AuthError> {
-        match self.jwt_manager.validate(token) {
+        let principal = match JwtManager::unverified_subject(token) {
+            Some(sub) => format!("{}:{}", JWT_PRINCIPAL, sub),
+            None => JWT_PRINCIPAL.to_string(),
+        };
+        let attempt = (principal, source_ip);
+        self.check_lockout(&attempt, "jwt", None)?;
+
+        let validated = self
+            .jwt_manager
+            .validate(token)
+            .map_err(|e| e.to_string())
+            .and_then(|claims| {
+                if self.revoked_sessions.contains_key(&claims.session_id) {
+                    Err("session revoked".to_string())
+                } else {
+                    Ok(claims)
+                }
+            });
+        match validated {
             Ok(claims) => {
-                // Later: verify user still exists + active
-                // For now: trust the token
+                self.failed_logins.remove(&attempt);
 
                 let ctx = crate::auth::types::AuthContext {
                     user: claims.sub.clone(),
@@ -114,6 +193,10 @@ impl AuthManager {
                     auth_method: crate::auth::types::AuthMethod::Jwt(token.to_string()),
                     session_id: claims.session_id,
                 };
+                // A token outlives changes to its user, so recheck the account
+                self.check_account(&claims.sub, source_ip, "jwt", None)
+                    .await?;
+                let ctx = self.with_roles(ctx).await?;
 
                 self.audit_logger
                     .log(crate::auth::audit::AuditEvent {
@@ -130,12 +213,15 @@ impl AuthManager {
                         key: None,
                         success: true,
                         details: None,
+                        request_id: crate::auth::audit::current_request_id(),
                     })
                     .ok();
 
                 Ok(ctx)
             }
             Err(e) => {
+                self.record_failure(attempt);
+
                 self.audit_logger
                     .log(crate::auth::audit::AuditEvent {
                         timestamp: std::time::SystemTime::now()
@@ -150,7 +236,8 @@ impl AuthManager {
                         op: None,
                         key: None,
                         success: false,
-                        details: Some(e.to_string()),
+                        details: Some(e),
+                        request_id: crate::auth::audit::current_request_id(),
                     })
                     .ok();
 
@@ -159,6 +246,339 @@ impl AuthManager {
         }
     }
 
+    /// Re-checks the identity a resumed connection (see
+    /// [`ConnectionManager::resume`](crate::connection::ConnectionManager::resume))
+    /// first authenticated with, now from `source_ip`: a token is validated
+    /// again, revocation included, an API key must still be live, and the
+    /// account must still be let in. Roles are looked up afresh.
+    pub async fn resume_session(
+        &self,
+        ctx: crate::auth::types::AuthContext,
+        source_ip: IpAddr,
+    ) -> Result<crate::auth::types::AuthContext, crate::auth::types::AuthError> {
+        let permissions = match &ctx.auth_method {
+            crate::auth::types::AuthMethod::Jwt(token) => {
+                return self.authenticate_jwt(token, source_ip).await;
+            }
+            crate::auth::types::AuthMethod::ApiKey(key_id) => {
+                let api_key = match self.catalog.get_api_key(key_id).await {
+                    Ok(api_key) => api_key,
+                    Err(crate::catalog::error::CatalogError::Storage(
+                        crate::storage::StorageError::KeyNotFound(_),
+                    )) => return Err(crate::auth::types::AuthError::InvalidCredentials),
+                    Err(e) => return Err(e.into()),
+                };
+                let now = chrono::Utc::now();
+                if api_key.revoked || api_key.expires_at.is_some_and(|at| now > at) {
+                    return Err(crate::auth::types::AuthError::InvalidCredentials);
+                }
+                api_key.permissions
+            }
+            crate::auth::types::AuthMethod::Password => Vec::new(),
+        };
+
+        let key_id = match &ctx.auth_method {
+            crate::auth::types::AuthMethod::ApiKey(key_id) => Some(key_id.as_str()),
+            _ => None,
+        };
+        let auth_method = auth_method_name(&ctx.auth_method);
+        self.check_account(&ctx.user, source_ip, auth_method, key_id)
+            .await?;
+        let ctx = crate::auth::types::AuthContext {
+            roles: Vec::new(),
+            permissions,
+            source_ip,
+            ..ctx
+        };
+        self.with_roles(ctx).await
+    }
+
+    // Rejects `attempt` while it is locked out, before any credential is
+    // checked, so a locked account can't be probed. An expired lockout is
+    // cleared along with its failure count.
+    fn check_lockout(
+        &self,
+        attempt: &(String, IpAddr),
+        auth_method: &str,
+        key_id: Option<&str>,
+    ) -> Result<(), crate::auth::types::AuthError> {
+        let now = now_ms();
+        let locked_until = match self.failed_logins.get(attempt) {
+            Some(failed) => failed.locked_until_ms,
+            None => return Ok(()),
+        };
+        match locked_until {
+            Some(until) if until > now => {
+                self.audit_logger
+                    .log(crate::auth::audit::AuditEvent {
+                        timestamp: now / 1000,
+                        event: "login_locked".to_string(),
+                        user: None,
+                        source_ip: attempt.1.to_string(),
+                        auth_method: auth_method.to_string(),
+                        key_id: key_id.map(str::to_string),
+                        op: None,
+                        key: None,
+                        success: false,
+                        details: None,
+                        request_id: crate::auth::audit::current_request_id(),
+                    })
+                    .ok();
+                Err(crate::auth::types::AuthError::AccountLocked {
+                    until: until.div_ceil(1000),
+                })
+            }
+            Some(_) => {
+                self.failed_logins.remove(attempt);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Counts a failed login, locking `attempt` out once it reaches the limit.
+    // Failures further apart than the lockout duration start a new count.
+    fn record_failure(&self, attempt: (String, IpAddr)) {
+        if self.login_attempt_limit == 0 {
+            return;
+        }
+        let now = now_ms();
+        if self.failed_logins.len() > MAX_TRACKED_LOGINS {
+            self.failed_logins.retain(|_, failed| {
+                failed.locked_until_ms.is_some_and(|until| until > now)
+                    || now.saturating_sub(failed.last_failure_ms) <= self.lockout_ms
+            });
+        }
+        let mut failed = self.failed_logins.entry(attempt).or_default();
+        if now.saturating_sub(failed.last_failure_ms) > self.lockout_ms {
+            failed.count = 0;
+        }
+        failed.count += 1;
+        failed.last_failure_ms = now;
+        if failed.count >= self.login_attempt_limit {
+            failed.locked_until_ms = Some(now + self.lockout_ms);
+        }
+    }
+
+    // Rejects credentials whose user has since been removed, deactivated or
+    // has passed its `valid_until`, or may not connect from `source_ip`.
+    async fn check_account(
+        &self,
+        username: &str,
+        source_ip: IpAddr,
+        auth_method: &str,
+        key_id: Option<&str>,
+    ) -> Result<(), crate::auth::types::AuthError> {
+        let now = chrono::Utc::now();
+        let result = match self.catalog.get_user(username).await {
+            Ok(user) if !user.is_active => Err(crate::auth::types::AuthError::AccountDisabled),
+            Ok(user) if user.valid_until.is_some_and(|until| until <= now) => {
+                Err(crate::auth::types::AuthError::AccountExpired)
+            }
+            Ok(user) => check_source_ip(&user, source_ip),
+            Err(crate::catalog::error::CatalogError::Storage(
+                crate::storage::StorageError::KeyNotFound(_),
+            )) => Err(crate::auth::types::AuthError::UserNotFound(
+                username.to_string(),
+            )),
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = &result {
+            self.audit_logger
+                .log(crate::auth::audit::AuditEvent {
+                    timestamp: now_ms() / 1000,
+                    event: "login_failed".to_string(),
+                    user: Some(username.to_string()),
+                    source_ip: source_ip.to_string(),
+                    auth_method: auth_method.to_string(),
+                    key_id: key_id.map(str::to_string),
+                    op: None,
+                    key: None,
+                    success: false,
+                    details: Some(e.to_string()),
+                    request_id: crate::auth::audit::current_request_id(),
+                })
+                .ok();
+        }
+        result
+    }
+
+    // Adds the roles granted to `ctx.user`, inherited ones included, and
+    // their permissions to what the credential itself carries. Looked up on
+    // every authentication so grant changes apply without reissuing keys.
+    async fn with_roles(
+        &self,
+        mut ctx: crate::auth::types::AuthContext,
+    ) -> Result<crate::auth::types::AuthContext, crate::auth::types::AuthError> {
+        let resolved = self.catalog.resolve_roles(&ctx.user).await?;
+        for permission in resolved.permissions {
+            if !ctx.permissions.contains(&permission) {
+                ctx.permissions.push(permission);
+            }
+        }
+        ctx.roles = resolved.roles;
+        Ok(ctx)
+    }
+
+    // ================
+    // REVOKE
+    // ================
+    /// Loads the persisted session blocklist into memory; call once at startup.
+    pub async fn load_revoked_sessions(&self) -> Result<usize, crate::auth::types::AuthError> {
+        let revoked = self.catalog.revoked_sessions().await?;
+        for session in &revoked {
+            self.revoked_sessions
+                .insert(session.session_id.clone(), revoked_until(session.exp));
+        }
+        Ok(revoked.len())
+    }
+
+    /// Revokes `session_id` on behalf of `ctx`: its tokens fail authentication
+    /// from now on. `exp` is the tokens' expiry (unix seconds) as read from a
+    /// validated token; without one the session is remembered for good, as
+    /// nothing bounds how long its tokens stay valid.
+    pub async fn revoke_session(
+        &self,
+        ctx: &crate::auth::types::AuthContext,
+        session_id: &str,
+        exp: Option<u64>,
+    ) -> Result<(), crate::auth::types::AuthError> {
+        let now = now_ms() / 1000;
+        let until = revoked_until(exp);
+
+        // Revocations of tokens that can no longer validate are dropped here
+        // rather than by a timer; the catalog copies expire on their own
+        self.revoked_sessions.retain(|_, until| *until > now);
+        if until <= now {
+            return Ok(());
+        }
+        self.revoked_sessions.insert(session_id.to_string(), until);
+        self.catalog
+            .revoke_session(
+                &RevokedSession {
+                    session_id: session_id.to_string(),
+                    exp,
+                    revoked_by: ctx.user.clone(),
+                    revoked_at: chrono::Utc::now(),
+                },
+                exp.map(|_| until - now),
+            )
+            .await?;
+
+        self.audit_logger
+            .log(crate::auth::audit::AuditEvent {
+                timestamp: now,
+                event: "session_revoked".to_string(),
+                user: Some(ctx.user.clone()),
+                source_ip: ctx.source_ip.to_string(),
+                auth_method: auth_method_name(&ctx.auth_method).to_string(),
+                key_id: None,
+                op: None,
+                key: None,
+                success: true,
+                details: Some(format!("session: {}", session_id)),
+                request_id: crate::auth::audit::current_request_id(),
+            })
+            .ok();
+
+        Ok(())
+    }
+
+    /// Revokes the session of `token` until the token expires. Returns the
+    /// session and that expiry.
+    pub async fn revoke_token(
+        &self,
+        ctx: &crate::auth::types::AuthContext,
+        token: &str,
+    ) -> Result<(String, u64), crate::auth::types::AuthError> {
+        let claims = self.jwt_manager.validate(token)?;
+        let exp = claims.exp as u64;
+        self.revoke_session(ctx, &claims.session_id, Some(exp))
+            .await?;
+        Ok((claims.session_id, exp))
+    }
+
+    // ================
+    // API KEYS
+    // ================
+    /// Creates an API key for `owner` on behalf of `ctx`. Returns the key ID
+    /// and the secret, which is not stored and can't be retrieved again.
+    pub async fn create_api_key(
+        &self,
+        ctx: &crate::auth::types::AuthContext,
+        owner: &str,
+        permissions: Vec<String>,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(String, String), crate::auth::types::AuthError> {
+        match self.catalog.get_user(owner).await {
+            Ok(_) => {}
+            Err(crate::catalog::error::CatalogError::Storage(
+                crate::storage::StorageError::KeyNotFound(_),
+            )) => {
+                return Err(crate::auth::types::AuthError::UserNotFound(
+                    owner.to_string(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let (key_id, secret) = self
+            .catalog
+            .create_api_key(owner, permissions, expires_at)
+            .await?;
+        self.log_key_change(
+            ctx,
+            "api_key_created",
+            &key_id,
+            Some(format!("owner: {}", owner)),
+        );
+        Ok((key_id, secret))
+    }
+
+    pub async fn list_api_keys(
+        &self,
+        owner: &str,
+    ) -> Result<Vec<crate::catalog::ApiKey>, crate::auth::types::AuthError> {
+        Ok(self.catalog.list_api_keys(owner).await?)
+    }
+
+    /// Revokes `key_id` on behalf of `ctx`; `false` if there is no such key.
+    pub async fn revoke_api_key(
+        &self,
+        ctx: &crate::auth::types::AuthContext,
+        key_id: &str,
+    ) -> Result<bool, crate::auth::types::AuthError> {
+        let revoked = self.catalog.revoke_api_key(key_id).await?;
+        if revoked {
+            self.log_key_change(ctx, "api_key_revoked", key_id, None);
+        }
+        Ok(revoked)
+    }
+
+    fn log_key_change(
+        &self,
+        ctx: &crate::auth::types::AuthContext,
+        event: &str,
+        key_id: &str,
+        details: Option<String>,
+    ) {
+        self.audit_logger
+            .log(crate::auth::audit::AuditEvent {
+                timestamp: now_ms() / 1000,
+                event: event.to_string(),
+                user: Some(ctx.user.clone()),
+                source_ip: ctx.source_ip.to_string(),
+                auth_method: auth_method_name(&ctx.auth_method).to_string(),
+                key_id: Some(key_id.to_string()),
+                op: None,
+                key: None,
+                success: true,
+                details,
+                request_id: crate::auth::audit::current_request_id(),
+            })
+            .ok();
+    }
+
     // ================
     // AUTHORIZE
     // ================
@@ -168,9 +588,10 @@ impl AuthManager {
         op: &str,
         key: &str,
     ) -> Result<(), crate::auth::types::AuthError> {
-        // Check if user has permission
-        let has_permission = ctx.permissions.contains(&"*".to_string()) || // superuser
-                             ctx.permissions.contains(&op.to_string());
+        let has_permission = ctx
+            .permissions
+            .iter()
+            .any(|permission| crate::auth::types::Permission::parse(permission).allows(op, key));
 
         if has_permission {
             Ok(())
@@ -185,16 +606,13 @@ impl AuthManager {
                     event: "permission_denied".to_string(),
                     user: Some(ctx.user.clone()),
                     source_ip: ctx.source_ip.to_string(),
-                    auth_method: match &ctx.auth_method {
-                        crate::auth::types::AuthMethod::ApiKey(_) => "api_key".to_string(),
-                        crate::auth::types::AuthMethod::Jwt(_) => "jwt".to_string(),
-                        crate::auth::types::AuthMethod::Password => "password".to_string(),
-                    },
+                    auth_method: auth_method_name(&ctx.auth_method).to_string(),
                     key_id: None,
                     op: Some(op.to_string()),
                     key: Some(key.to_string()),
                     success: false,
                     details: Some(format!("required permission: {}", op)),
+                    request_id: crate::auth::audit::current_request_id(),
                 })
                 .ok();
 
@@ -205,3 +623,499 @@ impl AuthManager {
         }
     }
 }
+
+// Denied networks win over allowed ones. Rules that don't parse fail closed:
+// a bad allow rule allows nothing, a bad deny rule denies everything.
+fn check_source_ip(
+    user: &crate::catalog::User,
+    ip: IpAddr,
+) -> Result<(), crate::auth::types::AuthError> {
+    let matches = |rule: &String, on_error: bool| match rule.parse::<Cidr>() {
+        Ok(network) => network.contains(ip),
+        Err(e) => {
+            tracing::warn!(user = %user.username, "Bad IP rule: {}", e);
+            on_error
+        }
+    };
+    let denied = user.denied_ips.iter().any(|rule| matches(rule, true));
+    let allowed =
+        user.allowed_ips.is_empty() || user.allowed_ips.iter().any(|rule| matches(rule, false));
+    if denied || !allowed {
+        return Err(crate::auth::types::AuthError::IpNotAllowed(ip));
+    }
+    Ok(())
+}
+
+fn auth_method_name(method: &crate::auth::types::AuthMethod) -> &'static str {
+    match method {
+        crate::auth::types::AuthMethod::ApiKey(_) => "api_key",
+        crate::auth::types::AuthMethod::Jwt(_) => "jwt",
+        crate::auth::types::AuthMethod::Password => "password",
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// Unix seconds after which a session revoked with token expiry `exp` no
+// longer needs remembering; never, when the expiry is unknown
+fn revoked_until(exp: Option<u64>) -> u64 {
+    exp.map_or(u64::MAX, |exp| exp + EXP_LEEWAY_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::types::{AuthContext, AuthMethod};
+    use crate::catalog::{Grant, Role, User};
+    use crate::storage::{StorageConfig, StorageEngine};
+
+    async fn catalog() -> Arc<CatalogManager> {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        Arc::new(CatalogManager::new(engine))
+    }
+
+    fn manager(catalog: Arc<CatalogManager>, audit_path: &std::path::Path) -> AuthManager {
+        AuthManager::new(
+            catalog,
+            "secret".to_string(),
+            audit_path.to_str().unwrap().to_string(),
+            &AuditSettings::default(),
+        )
+        .unwrap()
+    }
+
+    fn audit_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("audit_{}.log", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn test_inherited_role_permissions_authorize() {
+        let catalog = catalog().await;
+        catalog
+            .set_role(&Role::new(1, "reader".to_string(), vec!["GET".to_string()]))
+            .await
+            .unwrap();
+        let mut writer = Role::new(2, "writer".to_string(), vec!["SET".to_string()]);
+        writer.inherits = vec!["reader".to_string()];
+        catalog.set_role(&writer).await.unwrap();
+        catalog
+            .set_grant(&Grant::new(
+                "alice".to_string(),
+                vec!["writer".to_string()],
+                "root".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let audit_path = audit_path();
+        let auth = manager(catalog, &audit_path);
+        let ctx = AuthContext {
+            user: "alice".to_string(),
+            roles: Vec::new(),
+            permissions: Vec::new(),
+            source_ip: "127.0.0.1".parse().unwrap(),
+            auth_method: AuthMethod::Password,
+            session_id: String::new(),
+        };
+
+        let ctx = auth.with_roles(ctx).await.unwrap();
+        assert_eq!(ctx.roles, vec!["writer", "reader"]);
+        assert!(auth.authorize(&ctx, "GET", "k").is_ok());
+        assert!(auth.authorize(&ctx, "SET", "k").is_ok());
+        assert!(auth.authorize(&ctx, "DEL", "k").is_err());
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_lock_out_before_validation() {
+        let catalog = catalog().await;
+        catalog
+            .set_user(&User::new(2, "alice".to_string(), String::new()))
+            .await
+            .unwrap();
+        let audit_path = audit_path();
+        let auth = manager(catalog, &audit_path).with_settings(&AuthSettings {
+            login_attempt_limit: 3,
+            lockout_duration_sec: 1,
+            ..Default::default()
+        });
+        let token = auth
+            .jwt_manager
+            .generate("alice", vec!["GET".to_string()], 60)
+            .unwrap();
+        let forged = JwtManager::new("guessed".to_string())
+            .generate("alice", vec!["SET".to_string()], 60)
+            .unwrap();
+        let attacker: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // A success resets the count
+        for _ in 0..2 {
+            assert!(auth.authenticate_jwt(&forged, attacker).await.is_err());
+        }
+        auth.authenticate_jwt(&token, attacker).await.unwrap();
+        for _ in 0..3 {
+            assert!(matches!(
+                auth.authenticate_jwt(&forged, attacker).await,
+                Err(AuthError::InvalidCredentials)
+            ));
+        }
+
+        // Locked: even a valid token is refused, but only from that address
+        assert!(matches!(
+            auth.authenticate_jwt(&token, attacker).await,
+            Err(AuthError::AccountLocked { .. })
+        ));
+        auth.authenticate_jwt(&token, other).await.unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        auth.authenticate_jwt(&token, attacker).await.unwrap();
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_jwt_lockout_is_per_claimed_subject() {
+        let catalog = catalog().await;
+        catalog
+            .set_user(&User::new(2, "alice".to_string(), String::new()))
+            .await
+            .unwrap();
+        let audit_path = audit_path();
+        let auth = manager(catalog, &audit_path).with_settings(&AuthSettings {
+            login_attempt_limit: 3,
+            lockout_duration_sec: 60,
+            ..Default::default()
+        });
+        let token = auth
+            .jwt_manager
+            .generate("alice", vec!["GET".to_string()], 60)
+            .unwrap();
+        let forged = JwtManager::new("guessed".to_string())
+            .generate("bob", vec![], 60)
+            .unwrap();
+        let nat: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // Bob's failures lock bob, not everyone behind the same address
+        for _ in 0..3 {
+            assert!(auth.authenticate_jwt(&forged, nat).await.is_err());
+        }
+        assert!(matches!(
+            auth.authenticate_jwt(&forged, nat).await,
+            Err(AuthError::AccountLocked { .. })
+        ));
+        auth.authenticate_jwt(&token, nat).await.unwrap();
+
+        // Tokens naming no one share the address's bucket
+        for _ in 0..3 {
+            assert!(auth.authenticate_jwt("bogus", nat).await.is_err());
+        }
+        assert!(matches!(
+            auth.authenticate_jwt("also-bogus", nat).await,
+            Err(AuthError::AccountLocked { .. })
+        ));
+        auth.authenticate_jwt(&token, nat).await.unwrap();
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_disabled_and_expired_users_are_rejected() {
+        let catalog = catalog().await;
+        let mut admin = User::new(1, "admin".to_string(), String::new());
+        admin.is_superuser = true;
+        catalog.set_user(&admin).await.unwrap();
+        let mut contractor = User::new(2, "contractor".to_string(), String::new());
+        contractor.valid_until = Some(chrono::Utc::now() - chrono::Duration::hours(1));
+        catalog.set_user(&contractor).await.unwrap();
+
+        let audit_path = audit_path();
+        let auth = manager(catalog.clone(), &audit_path);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let admin_token = auth
+            .jwt_manager
+            .generate("admin", vec!["*".to_string()], 60)
+            .unwrap();
+        auth.authenticate_jwt(&admin_token, ip).await.unwrap();
+
+        // Tokens issued before the account changed stop working
+        admin.is_active = false;
+        catalog.set_user(&admin).await.unwrap();
+        assert!(matches!(
+            auth.authenticate_jwt(&admin_token, ip).await,
+            Err(AuthError::AccountDisabled)
+        ));
+
+        let token = auth
+            .jwt_manager
+            .generate("contractor", vec!["GET".to_string()], 60)
+            .unwrap();
+        assert!(matches!(
+            auth.authenticate_jwt(&token, ip).await,
+            Err(AuthError::AccountExpired)
+        ));
+
+        let token = auth
+            .jwt_manager
+            .generate("ghost", vec!["GET".to_string()], 60)
+            .unwrap();
+        assert!(matches!(
+            auth.authenticate_jwt(&token, ip).await,
+            Err(AuthError::UserNotFound(_))
+        ));
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_revoked_sessions_fail_authentication() {
+        let catalog = catalog().await;
+        let alice = User::new(1, "alice".to_string(), String::new());
+        catalog.set_user(&alice).await.unwrap();
+
+        let audit_path = audit_path();
+        let auth = manager(catalog.clone(), &audit_path);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let generate = || {
+            auth.jwt_manager
+                .generate("alice", vec!["GET".to_string()], 60)
+                .unwrap()
+        };
+        let (laptop, phone, tablet) = (generate(), generate(), generate());
+
+        // Logging out revokes only the caller's own session
+        let ctx = auth.authenticate_jwt(&laptop, ip).await.unwrap();
+        auth.revoke_token(&ctx, &laptop).await.unwrap();
+        assert!(matches!(
+            auth.authenticate_jwt(&laptop, ip).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        let ctx = auth.authenticate_jwt(&phone, ip).await.unwrap();
+
+        // A target session revoked by ID, expiry unknown
+        let tablet_session = auth.jwt_manager.validate(&tablet).unwrap().session_id;
+        auth.revoke_session(&ctx, &tablet_session, None)
+            .await
+            .unwrap();
+        assert!(auth.authenticate_jwt(&tablet, ip).await.is_err());
+
+        // The blocklist survives a restart
+        let revoked = catalog.revoked_sessions().await.unwrap();
+        assert_eq!(revoked.len(), 2);
+        assert!(revoked.iter().all(|session| session.revoked_by == "alice"));
+        // Logging out kept the token's own expiry; the tablet's is unknown,
+        // so its revocation is kept for good
+        let kept = |session_id: &str| {
+            revoked
+                .iter()
+                .find(|session| session.session_id == session_id)
+                .unwrap()
+                .exp
+        };
+        let laptop_claims = auth.jwt_manager.validate(&laptop).unwrap();
+        assert_eq!(
+            kept(&laptop_claims.session_id),
+            Some(laptop_claims.exp as u64)
+        );
+        assert_eq!(kept(&tablet_session), None);
+        let restarted = manager(catalog.clone(), &audit_path);
+        assert_eq!(restarted.load_revoked_sessions().await.unwrap(), 2);
+        assert!(restarted.authenticate_jwt(&laptop, ip).await.is_err());
+        restarted.authenticate_jwt(&phone, ip).await.unwrap();
+
+        // Revoking a token that has long expired keeps nothing around
+        auth.revoke_session(&ctx, "stale", Some(1)).await.unwrap();
+        assert_eq!(catalog.revoked_sessions().await.unwrap().len(), 2);
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_resumed_sessions_are_rechecked() {
+        let catalog = catalog().await;
+        let mut alice = User::new(1, "alice".to_string(), String::new());
+        catalog.set_user(&alice).await.unwrap();
+
+        let audit_path = audit_path();
+        let auth = manager(catalog.clone(), &audit_path);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let token = auth
+            .jwt_manager
+            .generate("alice", vec!["GET".to_string()], 60)
+            .unwrap();
+        let jwt = auth.authenticate_jwt(&token, ip).await.unwrap();
+        let (key_id, secret) = auth
+            .create_api_key(&jwt, "alice", vec!["SET".to_string()], None)
+            .await
+            .unwrap();
+        let api_key = auth
+            .authenticate_api_key(&format!("{}.{}", key_id, secret), ip)
+            .await
+            .unwrap();
+
+        let resumed = auth.resume_session(jwt.clone(), ip).await.unwrap();
+        assert_eq!(resumed.session_id, jwt.session_id);
+        let resumed = auth.resume_session(api_key.clone(), ip).await.unwrap();
+        assert!(auth.authorize(&resumed, "SET", "k").is_ok());
+
+        // Revoked in the meantime
+        auth.revoke_token(&jwt, &token).await.unwrap();
+        assert!(matches!(
+            auth.resume_session(jwt, ip).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        auth.revoke_api_key(&api_key, &key_id).await.unwrap();
+        assert!(matches!(
+            auth.resume_session(api_key.clone(), ip).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+
+        // Disabled in the meantime
+        alice.is_active = false;
+        catalog.set_user(&alice).await.unwrap();
+        let password = AuthContext {
+            auth_method: AuthMethod::Password,
+            ..api_key
+        };
+        assert!(matches!(
+            auth.resume_session(password, ip).await,
+            Err(AuthError::AccountDisabled)
+        ));
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_api_key_authenticates_until_revoked() {
+        let catalog = catalog().await;
+        catalog
+            .set_user(&User::new(1, "alice".to_string(), String::new()))
+            .await
+            .unwrap();
+        let audit_path = audit_path();
+        let auth = manager(catalog, &audit_path);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let admin = AuthContext {
+            user: "admin".to_string(),
+            roles: Vec::new(),
+            permissions: vec!["*".to_string()],
+            source_ip: ip,
+            auth_method: AuthMethod::Password,
+            session_id: String::new(),
+        };
+
+        assert!(matches!(
+            auth.create_api_key(&admin, "ghost", vec![], None).await,
+            Err(AuthError::UserNotFound(_))
+        ));
+        let (key_id, secret) = auth
+            .create_api_key(&admin, "alice", vec!["GET".to_string()], None)
+            .await
+            .unwrap();
+        let presented = format!("{}.{}", key_id, secret);
+
+        let ctx = auth.authenticate_api_key(&presented, ip).await.unwrap();
+        assert_eq!(ctx.user, "alice");
+        assert!(matches!(&ctx.auth_method, AuthMethod::ApiKey(id) if *id == key_id));
+        auth.authorize(&ctx, "GET", "k").unwrap();
+
+        assert!(auth.revoke_api_key(&admin, &key_id).await.unwrap());
+        assert!(matches!(
+            auth.authenticate_api_key(&presented, ip).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+
+        // The secret never reaches the audit log
+        auth.flush_audit().unwrap();
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert!(audit.contains("api_key_created") && audit.contains("api_key_revoked"));
+        assert!(!audit.contains(&secret));
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_source_ip_rules_checked_after_identity() {
+        let catalog = catalog().await;
+        let mut alice = User::new(1, "alice".to_string(), String::new());
+        alice.allowed_ips = vec!["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()];
+        alice.denied_ips = vec!["10.9.0.0/16".to_string()];
+        catalog.set_user(&alice).await.unwrap();
+
+        let audit_path = audit_path();
+        let auth = manager(catalog, &audit_path);
+        let token = auth
+            .jwt_manager
+            .generate("alice", vec!["GET".to_string()], 60)
+            .unwrap();
+
+        for allowed in ["10.1.2.3", "2001:db8::7"] {
+            auth.authenticate_jwt(&token, allowed.parse().unwrap())
+                .await
+                .unwrap();
+        }
+        for denied in ["10.9.0.1", "192.168.0.1", "2001:db9::1"] {
+            let ip: IpAddr = denied.parse().unwrap();
+            assert!(matches!(
+                auth.authenticate_jwt(&token, ip).await,
+                Err(AuthError::IpNotAllowed(rejected)) if rejected == ip
+            ));
+        }
+
+        auth.flush_audit().unwrap();
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        let denial = audit
+            .lines()
+            .find(|line| line.contains("\"source_ip\":\"10.9.0.1\""))
+            .unwrap();
+        assert!(denial.contains("login_failed"));
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_prefix_scoped_permissions() {
+        let auth_ctx = |permissions: &[&str]| AuthContext {
+            user: "app1-writer".to_string(),
+            roles: Vec::new(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            source_ip: "127.0.0.1".parse().unwrap(),
+            auth_method: AuthMethod::Password,
+            session_id: String::new(),
+        };
+        let audit_path = audit_path();
+        let auth = manager(catalog().await, &audit_path);
+
+        let ctx = auth_ctx(&["GET", "SET:app1/*", "DEL:app1/config", "SCAN:app1/*"]);
+        assert!(auth.authorize(&ctx, "SET", "app1/foo").is_ok());
+        assert!(matches!(
+            auth.authorize(&ctx, "SET", "app2/foo"),
+            Err(AuthError::PermissionDenied(..))
+        ));
+        // Unscoped permissions still cover every key
+        assert!(auth.authorize(&ctx, "GET", "app2/foo").is_ok());
+        // A scope without `*` is a single key
+        assert!(auth.authorize(&ctx, "DEL", "app1/config").is_ok());
+        assert!(auth.authorize(&ctx, "DEL", "app1/config2").is_err());
+        // Scans must stay inside the prefix
+        assert!(auth.authorize(&ctx, "SCAN", "app1/*").is_ok());
+        assert!(auth.authorize(&ctx, "SCAN", "app*").is_err());
+
+        let ctx = auth_ctx(&["*:tmp/*"]);
+        assert!(auth.authorize(&ctx, "INCR", "tmp/counter").is_ok());
+        assert!(auth.authorize(&ctx, "GET", "app1/foo").is_err());
+        assert!(auth.authorize(&auth_ctx(&["*"]), "ADMIN", "").is_ok());
+
+        std::fs::remove_file(&audit_path).ok();
+    }
+}
@@ -1,5 +1,6 @@
 pub mod apikey;
 pub mod audit;
+pub mod cidr;
 pub mod jwt;
 pub mod manager;
 pub mod types;
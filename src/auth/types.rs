@@ -17,6 +17,52 @@ pub enum AuthMethod {
     Password,       // for CLI/login
 }
 
+/// A permission string, parsed: `OP` grants the op on every key, `OP:prefix*`
+/// on keys starting with `prefix`, and `OP:key` on that one key. `*` as the op
+/// stands for any op, so a bare `*` grants everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permission<'a> {
+    pub op: &'a str,
+    pub scope: Option<KeyScope<'a>>, // None = all keys
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyScope<'a> {
+    Prefix(&'a str),
+    Exact(&'a str),
+}
+
+impl<'a> Permission<'a> {
+    pub fn parse(permission: &'a str) -> Self {
+        match permission.split_once(':') {
+            Some((op, scope)) => Self {
+                op,
+                scope: Some(match scope.strip_suffix('*') {
+                    Some(prefix) => KeyScope::Prefix(prefix),
+                    None => KeyScope::Exact(scope),
+                }),
+            },
+            None => Self {
+                op: permission,
+                scope: None,
+            },
+        }
+    }
+
+    /// Whether this permission covers `op` on `key`. For a scan `key` is the
+    /// pattern, which stays inside a prefix only if it literally starts with it.
+    pub fn allows(&self, op: &str, key: &str) -> bool {
+        if self.op != "*" && self.op != op {
+            return false;
+        }
+        match self.scope {
+            None => true,
+            Some(KeyScope::Prefix(prefix)) => key.starts_with(prefix),
+            Some(KeyScope::Exact(exact)) => key == exact,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum AuthError {
     #[error("Invalid credentials")]
@@ -25,12 +71,18 @@ pub enum AuthError {
     #[error("User not found: {0}")]
     UserNotFound(String),
 
-    #[error("User inactive")]
-    UserInactive,
+    #[error("Account disabled")]
+    AccountDisabled,
 
     #[error("Account expired")]
     AccountExpired,
 
+    #[error("Too many failed logins, locked until {until} (unix seconds)")]
+    AccountLocked { until: u64 },
+
+    #[error("Connections from {0} are not allowed for this user")]
+    IpNotAllowed(IpAddr),
+
     #[error("Permission denied: {0} not allowed for user {1}")]
     PermissionDenied(String, String), // op, user
 
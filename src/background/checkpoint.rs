@@ -1,19 +1,36 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, Mutex};
 use tokio::time::sleep;
 
-use crate::storage::StorageEngine;
+use crate::storage::{ControlFile, SnapshotRetention, StorageEngine};
 use crate::wal::WalManager;
 
 use super::types::WorkerError;
 
+/// Snapshots the engine every `interval` and trims the WAL behind it. Each
+/// checkpoint, in order:
+///
+/// 1. fsyncs the WAL and takes its end offset under the same lock,
+/// 2. writes a snapshot, which holds every write logged before that offset
+///    because the engine applies a write before logging it,
+/// 3. records the snapshot and offset in the [`ControlFile`],
+/// 4. deletes the WAL segments wholly before the offset.
+///
+/// A crash at any point leaves a control file whose snapshot exists and
+/// whose WAL is intact from its offset on. Skipping the fsync
+/// ([`with_sync`](Self::with_sync)) can record an offset past the end of the
+/// log on disk, and writes logged below it after a crash are then skipped by
+/// the next recovery.
 pub struct CheckpointWorker {
     engine: Arc<StorageEngine>,
     wal: Arc<WalManager>,
     snapshot_dir: String,
     interval: Duration,
+    retention: SnapshotRetention,
+    sync: bool,
+    in_progress: Arc<Mutex<()>>, // held while a checkpoint runs
     shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
@@ -29,10 +46,26 @@ impl CheckpointWorker {
             wal,
             snapshot_dir,
             interval: Duration::from_secs(interval_sec),
+            retention: SnapshotRetention::default(),
+            sync: true,
+            in_progress: Arc::new(Mutex::new(())),
             shutdown_tx: None,
         }
     }
 
+    /// Delete local snapshots outside `retention` after each checkpoint.
+    pub fn with_retention(mut self, retention: SnapshotRetention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Whether to fsync the WAL before recording each checkpoint's offset.
+    /// On by default.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
     pub async fn start(&mut self) -> Result<tokio::task::JoinHandle<()>, WorkerError> {
         let (tx, rx) = oneshot::channel();
         self.shutdown_tx = Some(tx);
@@ -41,33 +74,19 @@ impl CheckpointWorker {
         let wal = self.wal.clone();
         let snapshot_dir = self.snapshot_dir.clone();
         let interval = self.interval;
+        let retention = self.retention.clone();
+        let sync = self.sync;
+        let in_progress = self.in_progress.clone();
 
         let handle = tokio::spawn(async move {
-            let snapshot_manager = crate::storage::snapshot::SnapshotManager::new(snapshot_dir);
+            let snapshot_manager = crate::storage::snapshot::SnapshotManager::new(snapshot_dir)
+                .with_cipher(wal.cipher().cloned());
             tokio::pin!(rx); // Pin the receiver so it can be polled multiple times
             loop {
                 tokio::select! {
                     _ = sleep(interval) => {
-                        tracing::info!("Starting checkpoint...");
-
-                        // Create snapshot
-                        match snapshot_manager.create_snapshot(&engine).await {
-                            Ok(filename) => {
-                                tracing::info!(filename = %filename, "Snapshot created");
-
-                                // Get current WAL offset
-                                let wal_offset = wal.current_offset().await;
-
-                                // Record checkpoint (in a real system, write to pg_control)
-                                // For now, just log
-                                tracing::info!(wal_offset = wal_offset, "Checkpoint recorded");
-
-                                // Optional: truncate old WAL files (not implemented here)
-                            }
-                            Err(e) => {
-                                tracing::error!("Failed to create snapshot: {}", e);
-                            }
-                        }
+                        let _running = in_progress.lock().await;
+                        run_checkpoint(&engine, &wal, &snapshot_manager, &retention, sync).await;
                     }
                     _ = &mut rx => {
                         tracing::info!("Checkpoint worker shutting down");
@@ -80,9 +99,149 @@ impl CheckpointWorker {
         Ok(handle)
     }
 
+    /// Takes a checkpoint right away, waiting for a scheduled one to finish
+    /// first. Used for the final checkpoint on shutdown.
+    pub async fn checkpoint_now(&self) {
+        let _running = self.in_progress.lock().await;
+        let snapshot_manager =
+            crate::storage::snapshot::SnapshotManager::new(self.snapshot_dir.clone())
+                .with_cipher(self.wal.cipher().cloned());
+        run_checkpoint(
+            &self.engine,
+            &self.wal,
+            &snapshot_manager,
+            &self.retention,
+            self.sync,
+        )
+        .await;
+    }
+
+    /// Resolves once no checkpoint is running.
+    pub async fn wait_idle(&self) {
+        let _ = self.in_progress.lock().await;
+    }
+
     pub fn shutdown(&mut self) {
         if let Some(tx) = self.shutdown_tx.take() {
             let _ = tx.send(());
         }
     }
 }
+
+async fn run_checkpoint(
+    engine: &Arc<StorageEngine>,
+    wal: &WalManager,
+    snapshot_manager: &crate::storage::snapshot::SnapshotManager,
+    retention: &SnapshotRetention,
+    sync: bool,
+) {
+    tracing::info!("Starting checkpoint...");
+
+    // Writes are applied before they are logged, so everything
+    // below this offset is already in the snapshot taken next
+    let wal_offset = if sync {
+        match wal.sync_offset().await {
+            Ok(offset) => offset,
+            Err(e) => {
+                tracing::error!("Failed to sync WAL, skipping checkpoint: {}", e);
+                return;
+            }
+        }
+    } else {
+        wal.current_offset().await
+    };
+
+    // Create snapshot
+    match snapshot_manager.create_snapshot(engine).await {
+        Ok(filename) => {
+            tracing::info!(filename = %filename, "Snapshot created");
+
+            // Recorded before any segment goes, so recovery never points
+            // at a deleted one
+            let control = ControlFile {
+                snapshot: filename,
+                wal_offset,
+            };
+            if let Err(e) = snapshot_manager.write_control(&control) {
+                tracing::error!("Failed to write control file, keeping the WAL: {}", e);
+                return;
+            }
+
+            // Record the checkpoint and drop the segments it covers
+            match wal.truncate_before(wal_offset).await {
+                Ok(()) => tracing::info!(wal_offset = wal_offset, "Checkpoint recorded"),
+                Err(e) => tracing::error!("Failed to truncate WAL: {}", e),
+            }
+
+            if !retention.is_unbounded() {
+                match snapshot_manager.prune_snapshots(retention, unix_now()) {
+                    Ok(pruned) if pruned.is_empty() => {}
+                    Ok(pruned) => tracing::info!(pruned = ?pruned, "Old snapshots pruned"),
+                    Err(e) => tracing::warn!("Failed to prune old snapshots: {}", e),
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("Failed to create snapshot: {}", e);
+        }
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{SnapshotManager, StorageConfig};
+    use crate::wal::config::{SyncPolicy, WalConfig};
+
+    #[tokio::test]
+    async fn test_recovery_starts_at_the_checkpoint_offset() {
+        let dir = std::env::temp_dir().join(format!("checkpoint_{}", uuid::Uuid::new_v4()));
+        let snapshot_dir = dir.join("snapshots").to_str().unwrap().to_string();
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+
+        engine.set("before", b"1".to_vec(), None).await.unwrap();
+        CheckpointWorker::new(engine.clone(), wal.clone(), snapshot_dir.clone(), 3600)
+            .checkpoint_now()
+            .await;
+        engine.set("after", b"2".to_vec(), None).await.unwrap();
+
+        let snapshots = SnapshotManager::new(snapshot_dir);
+        let control = snapshots.read_control().unwrap().unwrap();
+        // Nothing else syncs under `Never`: the barrier made the offset durable
+        assert!(control.wal_offset > 0);
+        assert_eq!(wal.synced_offset(), control.wal_offset);
+
+        // "before" comes from the snapshot; only "after" is replayed
+        let restarted = StorageEngine::new(config).await;
+        let report = restarted.recover(&snapshots, &wal).await.unwrap();
+        assert_eq!(report.snapshot, Some(control.snapshot));
+        assert_eq!(
+            (report.wal_offset, report.replayed),
+            (control.wal_offset, 1)
+        );
+        assert_eq!(restarted.get("before").await.unwrap().value, b"1");
+        assert_eq!(restarted.get("after").await.unwrap().value, b"2");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
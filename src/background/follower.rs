@@ -0,0 +1,367 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use prometheus::{register_int_counter, IntCounter};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+
+use crate::config::ReplicaBackoffConfig;
+use crate::storage::{NodeRole, StorageEngine, StorageError};
+use crate::wal::entry::WalEntry;
+
+use super::frame;
+use super::types::WorkerError;
+
+lazy_static::lazy_static! {
+    static ref RECONNECT_ATTEMPTS: IntCounter = register_int_counter!(
+        "kvstore_replica_reconnect_attempts_total",
+        "Reconnection attempts made by the replica follower"
+    ).unwrap();
+}
+
+/// Sent by the follower on every (re)connect, followed by its last applied
+/// LSN (u64 LE, 0 = nothing applied). The primary then streams every later
+/// entry as a replication frame (see [`frame`]) whose payload is
+/// `[lsn u64 LE][entry bytes]`. The follower acknowledges each applied entry
+/// by writing its LSN (u64 LE) back. A frame that fails verification drops
+/// the connection, and the follower resyncs from its last applied LSN.
+pub const HANDSHAKE_MAGIC: &[u8; 4] = b"SYNC";
+
+#[derive(Debug)]
+struct Backoff {
+    config: ReplicaBackoffConfig,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(config: ReplicaBackoffConfig) -> Self {
+        let current = Duration::from_millis(config.initial_ms);
+        Self { config, current }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        let next_ms = (delay.as_millis() as f64 * self.config.multiplier) as u64;
+        self.current = Duration::from_millis(next_ms.min(self.config.max_ms));
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = Duration::from_millis(self.config.initial_ms);
+    }
+}
+
+pub struct ReplicaFollower {
+    engine: Arc<StorageEngine>,
+    primary_addr: String,
+    backoff: ReplicaBackoffConfig,
+    applied_lsn: Arc<AtomicU64>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl ReplicaFollower {
+    pub fn new(
+        engine: Arc<StorageEngine>,
+        primary_addr: String,
+        backoff: ReplicaBackoffConfig,
+    ) -> Self {
+        Self {
+            engine,
+            primary_addr,
+            backoff,
+            applied_lsn: Arc::new(AtomicU64::new(0)),
+            shutdown_tx: None,
+        }
+    }
+
+    pub fn applied_lsn(&self) -> u64 {
+        self.applied_lsn.load(Ordering::SeqCst)
+    }
+
+    pub async fn start(&mut self) -> Result<tokio::task::JoinHandle<()>, WorkerError> {
+        let (tx, rx) = oneshot::channel();
+        self.shutdown_tx = Some(tx);
+        self.engine.set_node_role(NodeRole::Replica);
+
+        let engine = self.engine.clone();
+        let primary_addr = self.primary_addr.clone();
+        let applied_lsn = self.applied_lsn.clone();
+        let mut backoff = Backoff::new(self.backoff.clone());
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(rx); // Pin the receiver so it can be polled multiple times
+
+            loop {
+                tokio::select! {
+                    result = follow(&primary_addr, &engine, &applied_lsn, &mut backoff) => {
+                        match result {
+                            Ok(()) => tracing::warn!(primary = %primary_addr, "Primary closed replication stream"),
+                            Err(e) => tracing::warn!(primary = %primary_addr, "Replication stream failed: {}", e),
+                        }
+                    }
+                    _ = &mut rx => {
+                        tracing::info!("Replica follower shutting down");
+                        break;
+                    }
+                }
+
+                let delay = backoff.next_delay();
+                RECONNECT_ATTEMPTS.inc();
+                tracing::info!(
+                    delay_ms = delay.as_millis() as u64,
+                    "Reconnecting to primary"
+                );
+
+                tokio::select! {
+                    _ = sleep(delay) => {}
+                    _ = &mut rx => {
+                        tracing::info!("Replica follower shutting down");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(handle)
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+// One connection to the primary; returns when the stream ends or fails.
+async fn follow(
+    primary_addr: &str,
+    engine: &StorageEngine,
+    applied_lsn: &AtomicU64,
+    backoff: &mut Backoff,
+) -> Result<(), WorkerError> {
+    let mut stream = TcpStream::connect(primary_addr).await?;
+
+    let from_lsn = applied_lsn.load(Ordering::SeqCst);
+    stream.write_all(HANDSHAKE_MAGIC).await?;
+    stream.write_u64_le(from_lsn).await?;
+    backoff.reset();
+    tracing::info!(primary = %primary_addr, from_lsn = from_lsn, "Replica follower connected");
+
+    loop {
+        let Some(payload) = frame::read_frame(&mut stream).await? else {
+            return Ok(());
+        };
+        if payload.len() < 8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "replication frame too short for an LSN",
+            )
+            .into());
+        }
+        let (lsn, data) = payload.split_at(8);
+        let lsn = u64::from_le_bytes(lsn.try_into().unwrap());
+
+        // The primary may resend entries around a reconnect
+        if lsn <= applied_lsn.load(Ordering::SeqCst) {
+            continue;
+        }
+
+        // An entry that fails to apply is neither acked nor skipped: the
+        // connection drops and the follower retries it after a backoff
+        let (entry, _) = WalEntry::deserialize(data)?;
+        match engine.apply_wal_entry(&entry).await {
+            // A key can expire here before the primary's DEL for it arrives
+            Ok(()) | Err(StorageError::KeyNotFound(_)) => {}
+            Err(e) => {
+                tracing::error!(lsn = lsn, "Failed to apply WAL entry: {}", e);
+                return Err(e.into());
+            }
+        }
+        applied_lsn.store(lsn, Ordering::SeqCst);
+        stream.write_u64_le(lsn).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+    use crate::wal::entry::OpType;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn entry(i: u64) -> WalEntry {
+        WalEntry {
+            timestamp: i,
+            key: format!("key_{}", i),
+            value: format!("value_{}", i).into_bytes(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Set,
+            compression: None,
+            delta: None,
+        }
+    }
+
+    fn entry_frame(lsn: u64) -> Vec<u8> {
+        let mut payload = lsn.to_le_bytes().to_vec();
+        payload.extend_from_slice(&entry(lsn).serialize());
+        frame::encode(&payload)
+    }
+
+    // Accepts one follower, checks its handshake and streams `lsns`, followed
+    // by `trailer` (raw bytes), then waits for the follower to ack the last of
+    // `lsns`. Returns the LSN the follower resumed from.
+    async fn serve_with(
+        listener: &TcpListener,
+        lsns: std::ops::RangeInclusive<u64>,
+        trailer: &[u8],
+    ) -> u64 {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut magic = [0u8; 4];
+        stream.read_exact(&mut magic).await.unwrap();
+        assert_eq!(&magic, HANDSHAKE_MAGIC);
+        let from_lsn = stream.read_u64_le().await.unwrap();
+
+        for lsn in lsns.clone() {
+            stream.write_all(&entry_frame(lsn)).await.unwrap();
+        }
+        stream.write_all(trailer).await.unwrap();
+        stream.flush().await.unwrap();
+
+        for lsn in lsns {
+            assert_eq!(stream.read_u64_le().await.unwrap(), lsn);
+        }
+        from_lsn
+    }
+
+    async fn serve(listener: &TcpListener, lsns: std::ops::RangeInclusive<u64>) -> u64 {
+        serve_with(listener, lsns, &[]).await
+    }
+
+    fn test_engine_config() -> StorageConfig {
+        StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn fast_backoff() -> ReplicaBackoffConfig {
+        ReplicaBackoffConfig {
+            initial_ms: 10,
+            max_ms: 50,
+            multiplier: 2.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follower_reconnects_and_resumes() {
+        let engine = StorageEngine::new(test_engine_config()).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut follower = ReplicaFollower::new(engine.clone(), addr.to_string(), fast_backoff());
+        follower.start().await.unwrap();
+        let attempts_before = RECONNECT_ATTEMPTS.get();
+
+        // First session: entries 1..=5, then the primary goes away entirely
+        assert_eq!(serve(&listener, 1..=5).await, 0);
+        drop(listener);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(follower.applied_lsn(), 5);
+
+        // Primary comes back; the follower resumes after LSN 5
+        let listener = TcpListener::bind(addr).await.unwrap();
+        assert_eq!(serve(&listener, 6..=10).await, 5);
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(follower.applied_lsn(), 10);
+        assert!(RECONNECT_ATTEMPTS.get() > attempts_before);
+        for i in 1..=10 {
+            let stored = engine.get(&format!("key_{}", i)).await.unwrap();
+            assert_eq!(stored.value, format!("value_{}", i).into_bytes());
+        }
+
+        follower.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_follower_rejects_corrupt_frame_and_resyncs() {
+        let engine = StorageEngine::new(test_engine_config()).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut follower = ReplicaFollower::new(engine.clone(), addr.to_string(), fast_backoff());
+        follower.start().await.unwrap();
+
+        // Entry 4 arrives with a flipped payload bit: its frame CRC fails
+        let mut corrupt = entry_frame(4);
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0x01;
+        assert_eq!(serve_with(&listener, 1..=3, &corrupt).await, 0);
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(follower.applied_lsn(), 3);
+        assert!(engine.get("key_4").await.is_err());
+
+        // The follower reconnected on its own and resumes after LSN 3
+        assert_eq!(serve(&listener, 4..=5).await, 3);
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(follower.applied_lsn(), 5);
+        for i in 1..=5 {
+            let stored = engine.get(&format!("key_{}", i)).await.unwrap();
+            assert_eq!(stored.value, format!("value_{}", i).into_bytes());
+        }
+
+        follower.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_follower_retries_an_entry_that_fails_to_apply() {
+        let engine = StorageEngine::new(test_engine_config()).await;
+        // Not a counter, so the primary's increment can't apply yet
+        engine.set("hits", b"many".to_vec(), None).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut follower = ReplicaFollower::new(engine.clone(), addr.to_string(), fast_backoff());
+        follower.start().await.unwrap();
+
+        let incr = WalEntry {
+            delta: Some(1),
+            ..WalEntry::new(OpType::Incr, "hits", b"1".to_vec(), 2, None)
+        };
+        let mut payload = 1u64.to_le_bytes().to_vec();
+        payload.extend_from_slice(&incr.serialize());
+        let incr_frame = frame::encode(&payload);
+
+        // No ack: the follower drops the connection instead
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut handshake = [0u8; 12];
+        stream.read_exact(&mut handshake).await.unwrap();
+        stream.write_all(&incr_frame).await.unwrap();
+        assert!(stream.read_u64_le().await.is_err());
+        assert_eq!(follower.applied_lsn(), 0);
+
+        // Once it can apply, the resent entry goes through
+        engine.del("hits", None).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.read_exact(&mut handshake).await.unwrap();
+        assert_eq!(u64::from_le_bytes(handshake[4..].try_into().unwrap()), 0);
+        stream.write_all(&incr_frame).await.unwrap();
+        assert_eq!(stream.read_u64_le().await.unwrap(), 1);
+
+        assert_eq!(follower.applied_lsn(), 1);
+        assert_eq!(engine.get("hits").await.unwrap().value, b"1");
+
+        follower.shutdown();
+    }
+}
@@ -0,0 +1,136 @@
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Every replication frame is `[magic 4][len u64 LE][crc32 u32 LE][payload]`,
+/// with the CRC taken over magic, length and payload. A frame is verified as
+/// a whole before its payload is deserialized, so a desynced or corrupted
+/// stream is dropped (and the peer resyncs) instead of being applied.
+pub const FRAME_MAGIC: &[u8; 4] = b"KVRF";
+pub const FRAME_HEADER_LEN: usize = 4 + 8 + 4;
+
+/// Larger lengths can only come from a corrupted header.
+pub const MAX_FRAME_LEN: u64 = 64 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    #[error("Bad frame magic: {0:02x?}")]
+    BadMagic([u8; 4]),
+
+    #[error("Frame length {0} exceeds limit")]
+    TooLarge(u64),
+
+    #[error("Frame checksum mismatch: expected {expected:08x}, got {actual:08x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let len = (payload.len() as u64).to_le_bytes();
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(FRAME_MAGIC);
+    frame.extend_from_slice(&len);
+    frame.extend_from_slice(&checksum(&len, payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Decodes the frame at the start of `buf`. Returns the payload and the bytes
+/// consumed, or `None` when `buf` does not hold a whole frame yet.
+pub fn decode(buf: &[u8]) -> Result<Option<(&[u8], usize)>, FrameError> {
+    if buf.len() < FRAME_HEADER_LEN {
+        return Ok(None);
+    }
+    let (len, expected) = parse_header(buf[..FRAME_HEADER_LEN].try_into().unwrap())?;
+
+    let end = FRAME_HEADER_LEN + len as usize;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    let payload = &buf[FRAME_HEADER_LEN..end];
+    verify(&buf[4..12], payload, expected)?;
+    Ok(Some((payload, end)))
+}
+
+/// Reads one frame from `reader`. Returns `None` on a clean EOF between frames.
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<Vec<u8>>, FrameError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let (len, expected) = parse_header(&header)?;
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    verify(&header[4..12], &payload, expected)?;
+    Ok(Some(payload))
+}
+
+fn parse_header(header: &[u8; FRAME_HEADER_LEN]) -> Result<(u64, u32), FrameError> {
+    let magic: [u8; 4] = header[..4].try_into().unwrap();
+    if &magic != FRAME_MAGIC {
+        return Err(FrameError::BadMagic(magic));
+    }
+    let len = u64::from_le_bytes(header[4..12].try_into().unwrap());
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge(len));
+    }
+    let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    Ok((len, crc))
+}
+
+fn verify(len: &[u8], payload: &[u8], expected: u32) -> Result<(), FrameError> {
+    let actual = checksum(len, payload);
+    if actual != expected {
+        return Err(FrameError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+fn checksum(len: &[u8], payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(FRAME_MAGIC);
+    hasher.update(len);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip_and_partial() {
+        let frame = encode(b"payload");
+        assert_eq!(decode(&frame[..frame.len() - 1]).unwrap(), None);
+        assert_eq!(
+            decode(&frame).unwrap(),
+            Some((&b"payload"[..], frame.len()))
+        );
+    }
+
+    #[test]
+    fn test_frame_corruption_detected() {
+        let mut frame = encode(b"payload");
+        let last = frame.len() - 1;
+        frame[last] ^= 0x01;
+        assert!(matches!(
+            decode(&frame),
+            Err(FrameError::ChecksumMismatch { .. })
+        ));
+
+        let mut frame = encode(b"payload");
+        frame[0] = b'X';
+        assert!(matches!(decode(&frame), Err(FrameError::BadMagic(_))));
+
+        let mut frame = encode(b"payload");
+        frame[4..12].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(decode(&frame), Err(FrameError::TooLarge(_))));
+    }
+}
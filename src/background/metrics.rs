@@ -1,7 +1,10 @@
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use prometheus::{register_int_gauge, IntGauge};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, GaugeVec, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+};
 use tokio::sync::oneshot;
 use tokio::time::sleep;
 
@@ -16,15 +19,154 @@ lazy_static::lazy_static! {
         "Current WAL size in bytes"
     ).unwrap();
 
+    static ref WAL_QUEUE_DEPTH: IntGauge = register_int_gauge!(
+        "kvstore_wal_queue_depth",
+        "Appends waiting for the WAL writer task"
+    ).unwrap();
+
     static ref MEMORY_USAGE: IntGauge = register_int_gauge!(
         "kvstore_memory_usage_bytes",
-        "Estimated memory usage"
+        "Approximate bytes held by stored entries"
     ).unwrap();
 
     static ref KEY_COUNT: IntGauge = register_int_gauge!(
         "kvstore_key_count",
         "Total number of keys"
     ).unwrap();
+
+    static ref SHARD_KEYS: IntGaugeVec = register_int_gauge_vec!(
+        "kvstore_shard_key_count",
+        "Keys held by each shard",
+        &["shard"]
+    ).unwrap();
+
+    static ref SHARD_SKEW: GaugeVec = register_gauge_vec!(
+        "kvstore_shard_skew_ratio",
+        "Keys in the fullest shard over the mean shard, per shard group",
+        &["group"]
+    ).unwrap();
+
+    static ref SHARD_SKEW_WARNINGS: IntCounterVec = register_int_counter_vec!(
+        "kvstore_shard_skew_warnings_total",
+        "Skew checks that found a shard group badly unbalanced",
+        &["group"]
+    ).unwrap();
+
+    static ref OP_LATENCY: HistogramVec = register_histogram_vec!(
+        "kvstore_op_duration_seconds",
+        "Request handling time per operation, including the WAL write",
+        &["api", "op"],
+        OP_LATENCY_BUCKETS.to_vec()
+    ).unwrap();
+
+    static ref OP_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "kvstore_op_errors_total",
+        "Requests per operation that returned an error",
+        &["api", "op"]
+    ).unwrap();
+}
+
+// 50µs to 100ms: in-memory reads sit at the bottom, fsynced writes further up
+const OP_LATENCY_BUCKETS: [f64; 11] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+
+/// Times one request for `kvstore_op_duration_seconds`. Dropping it without
+/// calling [`ok`](Self::ok), e.g. on an early `?` return, also counts the
+/// request in `kvstore_op_errors_total`.
+pub struct OpTimer {
+    api: &'static str,
+    op: &'static str,
+    started: Instant,
+    ok: bool,
+}
+
+impl OpTimer {
+    pub fn start(api: &'static str, op: &'static str) -> Self {
+        Self {
+            api,
+            op,
+            started: Instant::now(),
+            ok: false,
+        }
+    }
+
+    pub fn ok(mut self) {
+        self.ok = true;
+    }
+}
+
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        let labels = [self.api, self.op];
+        OP_LATENCY
+            .with_label_values(&labels)
+            .observe(self.started.elapsed().as_secs_f64());
+        if !self.ok {
+            OP_ERRORS.with_label_values(&labels).inc();
+        }
+    }
+}
+
+/// A group is reported once its fullest shard holds this many times the mean.
+pub const SHARD_SKEW_WARN_RATIO: f64 = 4.0;
+
+/// Below this mean occupancy, random variance alone produces large ratios.
+const SHARD_SKEW_MIN_MEAN_KEYS: usize = 32;
+
+/// Publishes the WAL's size on disk and how many appends are queued for it.
+pub fn publish_wal_gauges(wal: &WalManager) {
+    match wal.disk_usage() {
+        Ok(bytes) => WAL_SIZE.set(bytes as i64),
+        Err(e) => tracing::warn!("Failed to measure WAL size: {}", e),
+    }
+    WAL_QUEUE_DEPTH.set(wal.queue_depth() as i64);
+}
+
+/// Publishes key counts, total and per shard, and the memory held by
+/// entries as the engine accounts it against `max_memory_bytes`.
+pub fn publish_storage_gauges(engine: &StorageEngine) {
+    let mut key_count = 0;
+    for (index, shard) in engine.shards.iter().enumerate() {
+        let keys = shard.len();
+        SHARD_KEYS
+            .with_label_values(&[&index.to_string()])
+            .set(keys as i64);
+        key_count += keys;
+    }
+    KEY_COUNT.set(key_count as i64);
+    MEMORY_USAGE.set(engine.memory_usage() as i64);
+}
+
+/// Publishes per-group shard skew and warns about groups whose keys pile into
+/// a few shards (e.g. a bad routing prefix). Returns the groups flagged.
+pub fn check_shard_skew(engine: &StorageEngine) -> Vec<String> {
+    let mut flagged = Vec::new();
+
+    for skew in engine.shard_skew() {
+        SHARD_SKEW.with_label_values(&[&skew.group]).set(skew.ratio);
+
+        let shards = engine
+            .shard_groups()
+            .iter()
+            .find(|g| g.name == skew.group)
+            .map_or(1, |g| g.len);
+        if skew.keys < SHARD_SKEW_MIN_MEAN_KEYS * shards || skew.ratio < SHARD_SKEW_WARN_RATIO {
+            continue;
+        }
+
+        tracing::warn!(
+            group = %skew.group,
+            keys = skew.keys,
+            max_shard_keys = skew.max_shard_keys,
+            ratio = skew.ratio,
+            "Shard occupancy is heavily skewed; check key routing and hashing"
+        );
+        SHARD_SKEW_WARNINGS.with_label_values(&[&skew.group]).inc();
+        flagged.push(skew.group);
+    }
+
+    flagged
 }
 
 pub struct MetricsWorker {
@@ -52,23 +194,18 @@ impl MetricsWorker {
         let wal = self.wal.clone();
         let interval_clone = self.interval.clone();
 
+        // Catch a bad routing/hash config at startup, not one interval later
+        check_shard_skew(&engine);
+
         let handle = tokio::spawn(async move {
             tokio::pin!(rx); // Pin the receiver so it can be polled multiple times
 
             loop {
                 tokio::select! {
                     _ = sleep(interval_clone) => {
-                        let wal_offset = wal.current_offset().await;
-                        WAL_SIZE.set(wal_offset as i64);
-
-                        let key_count = engine
-                            .shards
-                            .iter()
-                            .map(|shard| shard.len())
-                            .sum::<usize>();
-                        KEY_COUNT.set(key_count as i64);
-
-                        MEMORY_USAGE.set((key_count * 100) as i64);
+                        publish_wal_gauges(&wal);
+                        publish_storage_gauges(&engine);
+                        check_shard_skew(&engine);
                     }
                     _ = &mut rx => {
                         tracing::info!("Metrics worker shutting down");
@@ -87,3 +224,54 @@ impl MetricsWorker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{KvEntry, StorageConfig};
+
+    #[test]
+    fn test_op_timer_records_latency_and_errors() {
+        let latency = OP_LATENCY.with_label_values(&["test", "GET"]);
+        let errors = OP_ERRORS.with_label_values(&["test", "GET"]);
+        let (samples, failed) = (latency.get_sample_count(), errors.get());
+
+        OpTimer::start("test", "GET").ok();
+        assert_eq!(latency.get_sample_count(), samples + 1);
+        assert_eq!(errors.get(), failed);
+
+        drop(OpTimer::start("test", "GET"));
+        assert_eq!(latency.get_sample_count(), samples + 2);
+        assert_eq!(errors.get(), failed + 1);
+    }
+
+    #[tokio::test]
+    async fn test_shard_skew_flags_only_unbalanced_routing() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 8,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        })
+        .await;
+
+        // Placement is simulated directly so the test exercises the check,
+        // not the distribution of the key hash
+        for i in 0..1024 {
+            engine.shards[i % 8].set(format!("user:{}", i), KvEntry::new(b"v".to_vec(), None));
+        }
+        assert!(check_shard_skew(&engine).is_empty());
+        assert!(SHARD_SKEW.with_label_values(&["default"]).get() < SHARD_SKEW_WARN_RATIO);
+
+        // A broken router that sends everything to one shard
+        let warnings_before = SHARD_SKEW_WARNINGS.with_label_values(&["default"]).get();
+        for i in 0..4096 {
+            engine.shards[0].set(format!("hot:{}", i), KvEntry::new(b"v".to_vec(), None));
+        }
+        assert_eq!(check_shard_skew(&engine), vec!["default".to_string()]);
+        assert!(SHARD_SKEW.with_label_values(&["default"]).get() >= SHARD_SKEW_WARN_RATIO);
+        assert_eq!(
+            SHARD_SKEW_WARNINGS.with_label_values(&["default"]).get(),
+            warnings_before + 1
+        );
+    }
+}
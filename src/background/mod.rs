@@ -1,72 +1,175 @@
 pub mod checkpoint;
+pub mod follower;
+pub mod frame;
 pub mod metrics;
+pub mod publisher;
 pub mod replica;
 pub mod s3_uploader;
 pub mod types;
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::Mutex;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::timeout;
 
-use crate::config;
 use crate::storage::StorageEngine;
 use crate::wal::WalManager;
 
+/// Which worker tasks are still running. Clones share state, so a health
+/// check can keep one after the [`WorkerManager`] moves into the server.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerHealth {
+    tasks: Arc<parking_lot::Mutex<Vec<(&'static str, AbortHandle)>>>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl WorkerHealth {
+    /// Tracks `handle` as the task of worker `name`.
+    pub fn watch(&self, name: &'static str, handle: &JoinHandle<()>) {
+        self.tasks.lock().push((name, handle.abort_handle()));
+    }
+
+    /// Workers whose task has exited. Empty once shutdown has begun, since
+    /// workers then stop on purpose.
+    pub fn stopped(&self) -> Vec<&'static str> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Vec::new();
+        }
+        self.tasks
+            .lock()
+            .iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
 pub struct WorkerManager {
+    wal: Arc<WalManager>,
     checkpoint: Option<checkpoint::CheckpointWorker>,
+    checkpoint_handle: Option<JoinHandle<()>>,
     metrics: Option<metrics::MetricsWorker>,
     s3_uploader: Option<s3_uploader::S3Uploader>,
     replica: Option<replica::ReplicaStreamer>,
+    follower: Option<follower::ReplicaFollower>,
+    publisher: Option<publisher::ReplicaPublisher>,
+    handles: Vec<JoinHandle<()>>, // every other worker task
+    phase_timeout: Duration,
+    health: WorkerHealth,
 }
 
 impl WorkerManager {
     pub async fn new(
         engine: Arc<StorageEngine>,
         wal: Arc<WalManager>,
+        snapshot_dir: String,
         config: &crate::config::BackgroundConfig,
     ) -> Result<Self, crate::background::types::WorkerError> {
         let mut manager = Self {
+            wal: wal.clone(),
             checkpoint: None,
+            checkpoint_handle: None,
             metrics: None,
             s3_uploader: None,
             replica: None,
+            follower: None,
+            publisher: None,
+            handles: Vec::new(),
+            phase_timeout: Duration::from_millis(config.shutdown_phase_timeout_ms),
+            health: WorkerHealth::default(),
         };
 
         // Start checkpoint worker
         let mut checkpoint_worker = checkpoint::CheckpointWorker::new(
             engine.clone(),
             wal.clone(),
-            config::AppConfig::default().storage.snapshot_dir.clone(),
+            snapshot_dir.clone(),
             config.checkpoint_interval_sec,
-        );
-        let _checkpoint_handle = checkpoint_worker.start().await?;
+        )
+        .with_retention(config.snapshot_retention.clone())
+        .with_sync(config.checkpoint_sync);
+        let handle = checkpoint_worker.start().await?;
+        manager.health.watch("checkpoint", &handle);
+        manager.checkpoint_handle = Some(handle);
         manager.checkpoint = Some(checkpoint_worker);
 
         // Start metrics worker
         let mut metrics_worker =
             metrics::MetricsWorker::new(engine.clone(), wal.clone(), config.metrics_interval_ms);
-        let _metrics_handle = metrics_worker.start().await?;
+        manager.push_worker("metrics", metrics_worker.start().await?);
         manager.metrics = Some(metrics_worker);
 
         // Start S3 uploader if configured
         if let Some(s3_config) = &config.s3 {
             let mut s3_uploader = s3_uploader::S3Uploader::new(
                 engine.clone(),
-                config::AppConfig::default().storage.snapshot_dir.clone(),
+                snapshot_dir.clone(),
                 s3_config.bucket.clone(),
                 s3_config.region.clone(),
                 s3_config.endpoint.clone(),
                 s3_config.upload_after_snapshot,
             )
-            .await?;
-            let _s3_handle = s3_uploader.start().await?;
+            .await?
+            .with_vacuum_before_upload(s3_config.vacuum_before_upload)
+            .with_cipher(wal.cipher().cloned());
+            if s3_config.apply_retention {
+                s3_uploader = s3_uploader.with_retention(config.snapshot_retention.clone());
+            }
+            manager.push_worker("s3_uploader", s3_uploader.start().await?);
             manager.s3_uploader = Some(s3_uploader);
         }
 
+        // Follow a primary if configured, otherwise serve the WAL to replicas
+        if let Some(replica_config) = config.replica.as_ref().filter(|r| r.enabled) {
+            if let Some(primary_addr) = &replica_config.primary_addr {
+                let mut follower = follower::ReplicaFollower::new(
+                    engine.clone(),
+                    primary_addr.clone(),
+                    replica_config.backoff.clone(),
+                );
+                manager.push_worker("replica_follower", follower.start().await?);
+                manager.follower = Some(follower);
+            } else {
+                if replica_config.sync_mode {
+                    wal.require_acks(
+                        replica_config.ack_quorum,
+                        Duration::from_millis(replica_config.ack_timeout_ms),
+                    );
+                }
+                // Replicas see only what is logged, unlogged writes included
+                wal.set_replicated();
+                let mut publisher =
+                    publisher::ReplicaPublisher::new(wal.clone(), replica_config.bind_addr.clone());
+                manager.push_worker("replica_publisher", publisher.start().await?);
+                manager.publisher = Some(publisher);
+            }
+        }
+
         Ok(manager)
     }
 
-    pub fn shutdown(&mut self) {
+    /// Liveness of the workers this manager started.
+    pub fn health(&self) -> WorkerHealth {
+        self.health.clone()
+    }
+
+    fn push_worker(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        self.health.watch(name, &handle);
+        self.handles.push(handle);
+    }
+
+    /// Stops background work in a fixed order, each phase bounded by
+    /// `shutdown_phase_timeout_ms`:
+    ///
+    /// 1. signal every worker so no new work is started,
+    /// 2. wait for an in-progress checkpoint to finish (aborted on timeout),
+    /// 3. sync the WAL,
+    /// 4. take a final checkpoint so the next start replays as little as possible,
+    /// 5. wait for the remaining worker tasks to exit (aborted on timeout).
+    pub async fn shutdown(&mut self) {
+        self.health.stopping.store(true, Ordering::SeqCst);
         if let Some(worker) = &mut self.checkpoint {
             worker.shutdown();
         }
@@ -77,8 +180,123 @@ impl WorkerManager {
             worker.shutdown();
         }
         if let Some(worker) = &mut self.replica {
-            // ← ADDED
             worker.shutdown();
         }
+        if let Some(worker) = &mut self.follower {
+            worker.shutdown();
+        }
+        if let Some(worker) = &mut self.publisher {
+            worker.shutdown();
+        }
+
+        if let Some(mut handle) = self.checkpoint_handle.take() {
+            if timeout(self.phase_timeout, &mut handle).await.is_err() {
+                tracing::warn!("Checkpoint did not finish before shutdown timeout, aborting");
+                handle.abort();
+            }
+        }
+
+        match timeout(self.phase_timeout, self.wal.sync()).await {
+            Ok(Ok(())) => tracing::info!(
+                offset = self.wal.synced_offset(),
+                "WAL synced for shutdown"
+            ),
+            Ok(Err(e)) => tracing::error!("Failed to sync WAL on shutdown: {}", e),
+            Err(_) => tracing::error!("WAL sync timed out on shutdown"),
+        }
+
+        if let Some(worker) = &self.checkpoint {
+            if timeout(self.phase_timeout, worker.checkpoint_now())
+                .await
+                .is_err()
+            {
+                tracing::warn!("Final checkpoint did not finish before shutdown timeout");
+            }
+        }
+
+        for mut handle in self.handles.drain(..) {
+            if timeout(self.phase_timeout, &mut handle).await.is_err() {
+                tracing::warn!("Background worker did not stop before shutdown timeout, aborting");
+                handle.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackgroundConfig;
+    use crate::storage::StorageConfig;
+    use crate::wal::config::{SyncPolicy, WalConfig};
+    use crate::wal::entry::{OpType, WalEntry};
+
+    #[tokio::test]
+    async fn test_shutdown_drains_checkpoint_then_syncs_wal() {
+        let dir = std::env::temp_dir().join(format!("bg_shutdown_{}", uuid::Uuid::new_v4()));
+        let snapshot_dir = dir.join("snapshots").to_str().unwrap().to_string();
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            snapshot_dir: snapshot_dir.clone(),
+            ..Default::default()
+        })
+        .await;
+        for i in 0..1000 {
+            engine
+                .set(&format!("key_{}", i), vec![0u8; 128], None)
+                .await
+                .unwrap();
+        }
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // A zero interval keeps a checkpoint running almost continuously
+        let mut workers = WorkerManager::new(
+            engine.clone(),
+            wal.clone(),
+            snapshot_dir.clone(),
+            &BackgroundConfig {
+                checkpoint_interval_sec: 0,
+                metrics_interval_ms: 1000,
+                s3: None,
+                replica: None,
+                shutdown_phase_timeout_ms: 5000,
+                snapshot_retention: Default::default(),
+                checkpoint_sync: true,
+            },
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        wal.append(&WalEntry {
+            timestamp: 1,
+            key: "last".to_string(),
+            value: b"write".to_vec(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Set,
+            compression: None,
+            delta: None,
+        })
+        .await
+        .unwrap();
+        assert!(wal.synced_offset() < wal.current_offset().await);
+
+        workers.shutdown().await;
+
+        assert!(workers.checkpoint_handle.is_none());
+        assert!(workers.handles.is_empty());
+        assert_eq!(wal.synced_offset(), wal.current_offset().await);
+        assert!(std::fs::read_dir(&snapshot_dir).unwrap().count() > 0);
+
+        std::fs::remove_dir_all(&dir).ok();
     }
 }
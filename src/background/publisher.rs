@@ -0,0 +1,351 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+
+use crate::wal::entry::WalEntry;
+use crate::wal::WalManager;
+
+use super::follower::HANDSHAKE_MAGIC;
+use super::frame;
+use super::types::WorkerError;
+
+lazy_static::lazy_static! {
+    static ref REPLICA_LAG_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "kvstore_replica_lag_bytes",
+        "WAL bytes sent to a replica that it has not acknowledged yet",
+        &["replica"]
+    ).unwrap();
+}
+
+// Catch-up entries read ahead of a replica
+const CATCH_UP_BUFFER: usize = 256;
+
+/// Primary side of replication: accepts [`ReplicaFollower`](super::follower::ReplicaFollower)
+/// connections, replays the WAL from the LSN in each handshake, then forwards
+/// entries as they are appended.
+///
+/// An entry's LSN is the global WAL offset just past it, so a follower's last
+/// applied LSN is exactly where its catch-up replay starts. Follower acks are
+/// recorded with [`WalManager::record_ack`] for semi-synchronous writes.
+pub struct ReplicaPublisher {
+    wal: Arc<WalManager>,
+    bind_addr: String,
+    local_addr: Option<SocketAddr>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl ReplicaPublisher {
+    pub fn new(wal: Arc<WalManager>, bind_addr: String) -> Self {
+        Self {
+            wal,
+            bind_addr,
+            local_addr: None,
+            shutdown_tx: None,
+        }
+    }
+
+    /// The bound address once started (useful with port 0).
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    pub async fn start(&mut self) -> Result<tokio::task::JoinHandle<()>, WorkerError> {
+        let listener = TcpListener::bind(&self.bind_addr).await?;
+        self.local_addr = Some(listener.local_addr()?);
+        tracing::info!("Replica publisher listening on {}", listener.local_addr()?);
+
+        let (tx, rx) = oneshot::channel();
+        self.shutdown_tx = Some(tx);
+        let wal = self.wal.clone();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(rx); // Pin the receiver so it can be polled multiple times
+            loop {
+                tokio::select! {
+                    accept_result = listener.accept() => {
+                        match accept_result {
+                            Ok((stream, addr)) => {
+                                tracing::info!("Replica connected from {}", addr);
+                                let wal = wal.clone();
+                                tokio::spawn(async move {
+                                    match publish(stream, addr, &wal).await {
+                                        Ok(()) => tracing::info!("Replica {} disconnected", addr),
+                                        Err(e) => tracing::warn!("Dropping replica {}: {}", addr, e),
+                                    }
+                                });
+                            }
+                            Err(e) => tracing::error!("Replica accept error: {}", e),
+                        }
+                    }
+                    _ = &mut rx => {
+                        tracing::info!("Replica publisher shutting down");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(handle)
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+// Serves one follower until it disconnects, the WAL goes away, or it falls
+// further behind than the WAL's tail buffer.
+async fn publish(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    wal: &WalManager,
+) -> Result<(), WorkerError> {
+    let mut magic = [0u8; 4];
+    stream.read_exact(&mut magic).await?;
+    if &magic != HANDSHAKE_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "bad replication handshake",
+        )
+        .into());
+    }
+    let from_lsn = stream.read_u64_le().await?;
+
+    let replica = addr.to_string();
+    wal.record_ack(&replica, from_lsn);
+    let sent_lsn = AtomicU64::new(from_lsn);
+
+    // Acks are read while entries are written; otherwise a replica blocked
+    // on sending acks would stop reading and stall the catch-up
+    let (reader, writer) = stream.into_split();
+    let result = tokio::select! {
+        result = send_entries(writer, from_lsn, wal, &replica, &sent_lsn) => result,
+        result = read_acks(reader, wal, &replica, &sent_lsn) => result,
+    };
+
+    wal.remove_replica(&replica);
+    let _ = REPLICA_LAG_BYTES.remove_label_values(&[&replica]);
+    result
+}
+
+// Catch-up from `from_lsn`, then the live tail
+async fn send_entries(
+    mut writer: OwnedWriteHalf,
+    from_lsn: u64,
+    wal: &WalManager,
+    replica: &str,
+    sent_lsn: &AtomicU64,
+) -> Result<(), WorkerError> {
+    // Subscribe before replaying so nothing appended in between is missed;
+    // entries seen both ways are skipped by LSN
+    let mut live = wal.subscribe();
+
+    // Streamed as it is read, so neither appends nor memory wait on a
+    // replica that is far behind
+    let mut catch_up = wal.replay_stream(from_lsn, CATCH_UP_BUFFER).await;
+    let mut last = from_lsn;
+    while let Some(read) = catch_up.recv().await {
+        let (offset, entry) = read?;
+        let (lsn, frame) = entry_frame(offset, &entry);
+        if lsn <= last {
+            continue;
+        }
+        sent_lsn.store(lsn, Ordering::SeqCst);
+        writer.write_all(&frame).await?;
+        last = lsn;
+    }
+    update_lag(wal, replica, last);
+    tracing::info!(from_lsn = from_lsn, to_lsn = last, "Replica caught up");
+
+    loop {
+        match live.recv().await {
+            Ok((offset, entry)) => {
+                let (lsn, frame) = entry_frame(offset, &entry);
+                if lsn <= last {
+                    continue;
+                }
+                sent_lsn.store(lsn, Ordering::SeqCst);
+                writer.write_all(&frame).await?;
+                last = lsn;
+                update_lag(wal, replica, lsn);
+            }
+            // A slow replica must not hold back writers; it resyncs from its
+            // last applied LSN when it reconnects
+            Err(RecvError::Lagged(missed)) => return Err(WorkerError::ReplicaLagged(missed)),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+// Each ack is the replica's last applied LSN (u64 LE)
+async fn read_acks(
+    mut reader: OwnedReadHalf,
+    wal: &WalManager,
+    replica: &str,
+    sent_lsn: &AtomicU64,
+) -> Result<(), WorkerError> {
+    loop {
+        let lsn = match reader.read_u64_le().await {
+            Ok(lsn) => lsn,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        wal.record_ack(replica, lsn);
+        update_lag(wal, replica, sent_lsn.load(Ordering::SeqCst));
+    }
+}
+
+// Bytes shipped to the replica but not yet acknowledged
+fn update_lag(wal: &WalManager, replica: &str, sent_lsn: u64) {
+    let acked = wal.replica_acks().get(replica).copied().unwrap_or(0);
+    REPLICA_LAG_BYTES
+        .with_label_values(&[replica])
+        .set(sent_lsn.saturating_sub(acked) as i64);
+}
+
+// `[lsn u64 LE][entry bytes]` in a replication frame
+fn entry_frame(offset: u64, entry: &WalEntry) -> (u64, Vec<u8>) {
+    let bytes = entry.serialize();
+    let lsn = offset + bytes.len() as u64;
+    let mut payload = Vec::with_capacity(8 + bytes.len());
+    payload.extend_from_slice(&lsn.to_le_bytes());
+    payload.extend_from_slice(&bytes);
+    (lsn, frame::encode(&payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::follower::ReplicaFollower;
+    use crate::config::ReplicaBackoffConfig;
+    use crate::storage::{StorageConfig, StorageEngine};
+    use crate::wal::config::{SyncPolicy, WalConfig};
+    use crate::wal::entry::OpType;
+    use crate::wal::{Durability, WalError};
+    use std::time::Duration;
+
+    fn entry(i: u64) -> WalEntry {
+        WalEntry {
+            timestamp: i,
+            key: format!("key_{}", i),
+            value: format!("value_{}", i).into_bytes(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Set,
+            compression: None,
+            delta: None,
+        }
+    }
+
+    async fn wal(dir: &std::path::Path) -> Arc<WalManager> {
+        WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256, // catch-up spans several segments
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn follower(publisher: &ReplicaPublisher) -> (ReplicaFollower, Arc<StorageEngine>) {
+        let replica = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let mut follower = ReplicaFollower::new(
+            replica.clone(),
+            publisher.local_addr().unwrap().to_string(),
+            ReplicaBackoffConfig {
+                initial_ms: 10,
+                max_ms: 50,
+                multiplier: 2.0,
+            },
+        );
+        follower.start().await.unwrap();
+        (follower, replica)
+    }
+
+    async fn wait_until(mut done: impl FnMut() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follower_catches_up_then_tails() {
+        let dir = std::env::temp_dir().join(format!("wal_publish_{}", uuid::Uuid::new_v4()));
+        let wal = wal(&dir).await;
+        for i in 0..10 {
+            wal.append(&entry(i)).await.unwrap();
+        }
+
+        let mut publisher = ReplicaPublisher::new(wal.clone(), "127.0.0.1:0".to_string());
+        publisher.start().await.unwrap();
+        let (mut follower, replica) = follower(&publisher).await;
+
+        for i in 10..20 {
+            wal.append(&entry(i)).await.unwrap();
+        }
+
+        let end = wal.current_offset().await;
+        wait_until(|| follower.applied_lsn() == end).await;
+        assert_eq!(follower.applied_lsn(), end);
+        for i in 0..20 {
+            let stored = replica.get(&format!("key_{}", i)).await.unwrap();
+            assert_eq!(stored.value, format!("value_{}", i).into_bytes());
+        }
+
+        // Acks arrive after the entries were applied
+        wait_until(|| wal.replica_acks().values().any(|&lsn| lsn == end)).await;
+        assert_eq!(
+            wal.replica_acks().into_values().collect::<Vec<_>>(),
+            vec![end]
+        );
+
+        follower.shutdown();
+        publisher.shutdown();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_semi_sync_write_waits_for_replica() {
+        let dir = std::env::temp_dir().join(format!("wal_semi_sync_{}", uuid::Uuid::new_v4()));
+        let wal = wal(&dir).await;
+        wal.require_acks(1, Duration::from_millis(500));
+
+        let mut publisher = ReplicaPublisher::new(wal.clone(), "127.0.0.1:0".to_string());
+        publisher.start().await.unwrap();
+        let (mut follower, replica) = follower(&publisher).await;
+        wait_until(|| !wal.replica_acks().is_empty()).await;
+
+        // Returns only once the replica has applied the write
+        wal.append_with(&entry(1), Durability::Wal).await.unwrap();
+        assert!(replica.get("key_1").await.is_ok());
+
+        // A replica that went away can't hold the write up forever
+        follower.shutdown();
+        wait_until(|| wal.replica_acks().is_empty()).await;
+        let err = wal
+            .append_with(&entry(2), Durability::Wal)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WalError::AckTimeout { acked: 0, .. }));
+
+        publisher.shutdown();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -104,32 +104,20 @@ async fn handle_replica_connection(
             }
         }
 
-        // Process complete WAL entries
-        while pos < buffer.len() {
-            if buffer.len() - pos < 8 {
-                // min header size
-                break;
-            }
-
-            // Read entry size (first 8 bytes)
-            let entry_size = u64::from_le_bytes([
-                buffer[pos],
-                buffer[pos + 1],
-                buffer[pos + 2],
-                buffer[pos + 3],
-                buffer[pos + 4],
-                buffer[pos + 5],
-                buffer[pos + 6],
-                buffer[pos + 7],
-            ]) as usize;
-
-            if buffer.len() - pos < 8 + entry_size {
-                break; // need more data
-            }
-
-            // Extract WAL entry
-            let entry_data = &buffer[pos + 8..pos + 8 + entry_size];
-            pos += 8 + entry_size;
+        // Process complete frames; a frame that fails verification means the
+        // stream is out of sync, so drop the connection and let the primary
+        // reconnect rather than applying garbage.
+        loop {
+            let (entry_data, consumed) = match crate::background::frame::decode(&buffer[pos..]) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break, // need more data
+                Err(e) => {
+                    tracing::error!("Rejecting replication frame: {}", e);
+                    let _ = stream.write_all(b"ERR").await;
+                    return;
+                }
+            };
+            pos += consumed;
 
             match crate::wal::entry::WalEntry::deserialize(entry_data) {
                 Ok((entry, _)) => {
@@ -140,9 +128,12 @@ async fn handle_replica_connection(
                                 let _ = stream.write_all(b"ACK").await;
                             }
                         }
+                        // Later entries would apply out of order; drop the
+                        // connection so the primary resends from here
                         Err(e) => {
                             tracing::error!("Failed to apply WAL entry: {}", e);
                             let _ = stream.write_all(b"ERR").await;
+                            return;
                         }
                     }
                 }
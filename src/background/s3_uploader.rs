@@ -3,10 +3,11 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::storage::StorageEngine;
-use aws_sdk_s3::config::Region;
+use crate::storage::{Cipher, SnapshotInfo, SnapshotRetention, StorageEngine};
+use aws_sdk_s3::config::{BehaviorVersion, Region};
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::{Client, Config};
+use aws_sdk_s3::Client;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::oneshot;
 use tokio::time::sleep;
 
@@ -18,6 +19,9 @@ pub struct S3Uploader {
     bucket: String,
     client: Arc<Client>,
     upload_after_snapshot: bool,
+    vacuum_before_upload: bool,
+    retention: SnapshotRetention,
+    cipher: Option<Cipher>,
     shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
@@ -30,16 +34,18 @@ impl S3Uploader {
         endpoint: Option<String>,
         upload_after_snapshot: bool,
     ) -> Result<Self, WorkerError> {
-        let config = if let Some(endpoint) = endpoint {
-            Config::builder()
+        // Credentials come from the environment either way; a custom
+        // endpoint (MinIO and the like) is addressed path-style
+        let shared = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let mut config = aws_sdk_s3::config::Builder::from(&shared);
+        if let Some(endpoint) = endpoint {
+            config = config
                 .region(Region::new(region))
                 .endpoint_url(endpoint)
-                .build()
-        } else {
-            aws_config::load_from_env().await.into()
-        };
+                .force_path_style(true);
+        }
 
-        let client = Arc::new(Client::from_conf(config));
+        let client = Arc::new(Client::from_conf(config.build()));
 
         Ok(Self {
             engine,
@@ -47,18 +53,42 @@ impl S3Uploader {
             bucket,
             client,
             upload_after_snapshot,
+            vacuum_before_upload: false,
+            retention: SnapshotRetention::default(),
+            cipher: None,
             shutdown_tx: None,
         })
     }
 
+    /// Compact each snapshot (dropping expired entries) before uploading it.
+    pub fn with_vacuum_before_upload(mut self, enabled: bool) -> Self {
+        self.vacuum_before_upload = enabled;
+        self
+    }
+
+    /// Delete objects outside `retention` from the bucket after each upload.
+    pub fn with_retention(mut self, retention: SnapshotRetention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Key the snapshots are encrypted with; vacuuming has to read them.
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
     pub async fn start(&mut self) -> Result<tokio::task::JoinHandle<()>, WorkerError> {
-        let (tx, rx) = oneshot::channel();
+        let (tx, mut rx) = oneshot::channel();
         self.shutdown_tx = Some(tx);
 
         let snapshot_dir = self.snapshot_dir.clone();
         let bucket = self.bucket.clone();
         let client = self.client.clone();
         let upload_after_snapshot = self.upload_after_snapshot;
+        let vacuum_before_upload = self.vacuum_before_upload;
+        let retention = self.retention.clone();
+        let cipher = self.cipher.clone();
 
         let handle = tokio::spawn(async move {
             let mut last_snapshot = String::new();
@@ -74,7 +104,7 @@ impl S3Uploader {
                                         entries
                                             .filter_map(|e| e.ok())
                                             .map(|e| e.path())
-                                            .filter(|p| p.extension().map_or(false, |ext| ext == "bin"))
+                                            .filter(|p| p.extension().is_some_and(|ext| ext == "bin"))
                                             .collect::<Vec<_>>()
                                     })
                             }
@@ -89,10 +119,25 @@ impl S3Uploader {
                                         last_snapshot = filename.clone();
 
                                         if upload_after_snapshot {
+                                            if vacuum_before_upload {
+                                                let snapshots = crate::storage::SnapshotManager::new(snapshot_dir.clone())
+                                                    .with_cipher(cipher.clone());
+                                                if let Err(e) = snapshots.compact_snapshot(&filename).await {
+                                                    tracing::warn!(filename = %filename, error = %e, "Failed to vacuum snapshot, uploading as-is");
+                                                }
+                                            }
+
                                             tracing::info!(filename = %filename, "Uploading snapshot to S3");
                                             match upload_snapshot(&client, &bucket, &snapshot_dir, &filename).await {
                                                 Ok(_) => {
                                                     tracing::info!(filename = %filename, "Snapshot uploaded to S3");
+                                                    if !retention.is_unbounded() {
+                                                        match prune_snapshots(&client, &bucket, &retention).await {
+                                                            Ok(pruned) if pruned.is_empty() => {}
+                                                            Ok(pruned) => tracing::info!(pruned = ?pruned, "Old snapshots pruned from S3"),
+                                                            Err(e) => tracing::warn!(error = %e, "Failed to prune snapshots in S3"),
+                                                        }
+                                                    }
                                                 }
                                                 Err(e) => {
                                                     tracing::error!(filename = %filename, error = %e.to_string(), "Failed to upload snapshot");
@@ -102,12 +147,15 @@ impl S3Uploader {
                                     }
                                 }
                             }
-                            Ok(Err(e)) | Err(e) => {
+                            Ok(Err(e)) => {
                                 tracing::error!("Failed to read snapshot dir: {}", e);
                             }
+                            Err(e) => {
+                                tracing::error!("Snapshot dir listing task failed: {}", e);
+                            }
                         }
                     }
-                    _ = rx => {
+                    _ = &mut rx => {
                         tracing::info!("S3 uploader shutting down");
                         break;
                     }
@@ -118,6 +166,63 @@ impl S3Uploader {
         Ok(handle)
     }
 
+    /// Downloads the newest snapshot in the bucket (by the time in its key)
+    /// into `snapshot_dir` and returns its path, ready for
+    /// [`SnapshotManager::load_snapshot`](crate::storage::SnapshotManager::load_snapshot).
+    /// `None` if the bucket holds no snapshots. The file is written under a
+    /// temporary name and only renamed into place once its size matches
+    /// what S3 reported.
+    pub async fn download_latest(&self) -> Result<Option<PathBuf>, WorkerError> {
+        let snapshots = list_snapshots(&self.client, &self.bucket).await?;
+        let Some(SnapshotInfo {
+            name: key,
+            size_bytes: size,
+            ..
+        }) = snapshots
+            .into_iter()
+            .max_by(|a, b| (a.created_at, &a.name).cmp(&(b.created_at, &b.name)))
+        else {
+            tracing::info!(bucket = %self.bucket, "No snapshots in S3 to download");
+            return Ok(None);
+        };
+
+        tracing::info!(key = %key, "Downloading snapshot from S3");
+        let mut object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)?;
+        let expected = object.content_length().map_or(size, |len| len as u64);
+
+        let path = PathBuf::from(&self.snapshot_dir).join(&key);
+        let tmp_path = path.with_extension("bin.download");
+        let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(&tmp_path).await?);
+        let mut received = 0u64;
+        while let Some(chunk) = object.body.next().await {
+            let chunk = chunk.map_err(std::io::Error::other)?;
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+        }
+        file.flush().await?;
+        file.into_inner().sync_all().await?;
+
+        if received != expected {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(WorkerError::IncompleteDownload {
+                key,
+                expected,
+                received,
+            });
+        }
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        tracing::info!(key = %key, bytes = received, "Snapshot downloaded from S3");
+        Ok(Some(path))
+    }
+
     pub fn shutdown(&mut self) {
         if let Some(tx) = self.shutdown_tx.take() {
             let _ = tx.send(());
@@ -130,11 +235,11 @@ async fn upload_snapshot(
     bucket: &str,
     snapshot_dir: &str,
     filename: &str,
-) -> Result<(), aws_sdk_s3::Error> {
+) -> Result<(), WorkerError> {
     let path = PathBuf::from(snapshot_dir).join(filename);
     let body = ByteStream::from_path(&path)
         .await
-        .map_err(|e| aws_sdk_s3::Error::Unhandled(Box::new(e)))?;
+        .map_err(std::io::Error::other)?;
 
     client
         .put_object()
@@ -142,7 +247,208 @@ async fn upload_snapshot(
         .key(filename)
         .body(body)
         .send()
-        .await?;
+        .await
+        .map_err(aws_sdk_s3::Error::from)?;
 
     Ok(())
 }
+
+// Snapshot objects in `bucket`, listed like local ones. Objects not named
+// like a snapshot are left out, which also keeps odd keys out of local paths.
+async fn list_snapshots(
+    client: &Client,
+    bucket: &str,
+) -> Result<Vec<SnapshotInfo>, aws_sdk_s3::Error> {
+    let mut snapshots = Vec::new();
+    let mut continuation = None;
+    loop {
+        let page = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix("snapshot_")
+            .set_continuation_token(continuation)
+            .send()
+            .await?;
+        for object in page.contents() {
+            let Some(key) = object.key() else {
+                continue;
+            };
+            let Some(created_at) = crate::storage::snapshot_created_at(key) else {
+                continue;
+            };
+            snapshots.push(SnapshotInfo {
+                name: key.to_string(),
+                size_bytes: object.size().unwrap_or(0) as u64,
+                created_at,
+            });
+        }
+        match page.next_continuation_token() {
+            Some(token) if page.is_truncated().unwrap_or(false) => {
+                continuation = Some(token.to_string())
+            }
+            _ => break,
+        }
+    }
+    Ok(snapshots)
+}
+
+// Deletes the snapshot objects `retention` no longer keeps, returning their
+// keys
+async fn prune_snapshots(
+    client: &Client,
+    bucket: &str,
+    retention: &SnapshotRetention,
+) -> Result<Vec<String>, aws_sdk_s3::Error> {
+    let snapshots = list_snapshots(client, bucket).await?;
+    let mut pruned = Vec::new();
+    for snapshot in retention.expired(&snapshots, super::checkpoint::unix_now()) {
+        client
+            .delete_object()
+            .bucket(bucket)
+            .key(&snapshot.name)
+            .send()
+            .await?;
+        pruned.push(snapshot.name.clone());
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::Credentials;
+    use aws_sdk_s3::Config;
+    use axum::response::IntoResponse;
+    use std::collections::BTreeMap;
+
+    type Bucket = Arc<parking_lot::Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    // Just enough of S3 on localhost for `download_latest` and pruning:
+    // path-style ListObjectsV2 on `/<bucket>`, and GetObject and DeleteObject
+    // on `/<bucket>/<key>`
+    async fn fake_s3(bucket: Bucket) -> String {
+        let app = axum::Router::new().fallback(
+            move |method: axum::http::Method, uri: axum::http::Uri| {
+                let bucket = bucket.clone();
+                async move {
+                    let path = uri.path().trim_matches('/');
+                    let mut objects = bucket.lock();
+                    match path.split_once('/') {
+                        Some((_, key)) if method == axum::http::Method::DELETE => {
+                            objects.remove(key);
+                            axum::http::StatusCode::NO_CONTENT.into_response()
+                        }
+                        Some((_, key)) => match objects.get(key) {
+                            Some(body) => body.clone().into_response(),
+                            None => axum::http::StatusCode::NOT_FOUND.into_response(),
+                        },
+                        None => {
+                            let contents: String = objects
+                                .iter()
+                                .map(|(key, body)| {
+                                    format!(
+                                        "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                                        key,
+                                        body.len()
+                                    )
+                                })
+                                .collect();
+                            format!(
+                                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                             <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+                             <Name>{}</Name><KeyCount>{}</KeyCount><IsTruncated>false</IsTruncated>\
+                             {}</ListBucketResult>",
+                                path,
+                                objects.len(),
+                                contents
+                            )
+                            .into_response()
+                        }
+                    }
+                }
+            },
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn test_client(endpoint: String) -> Client {
+        let config = Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .build();
+        Client::from_conf(config)
+    }
+
+    #[tokio::test]
+    async fn test_download_latest_fetches_the_newest_snapshot() {
+        let bucket = Bucket::default();
+        let client = test_client(fake_s3(bucket.clone()).await);
+        let dir = std::env::temp_dir().join(format!("s3_download_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let uploader = S3Uploader {
+            engine: StorageEngine::new(Default::default()).await,
+            snapshot_dir: dir.to_str().unwrap().to_string(),
+            bucket: "backups".to_string(),
+            client: Arc::new(client),
+            upload_after_snapshot: false,
+            vacuum_before_upload: false,
+            retention: SnapshotRetention::default(),
+            cipher: None,
+            shutdown_tx: None,
+        };
+
+        // An empty bucket is not an error
+        assert_eq!(uploader.download_latest().await.unwrap(), None);
+
+        {
+            let mut objects = bucket.lock();
+            objects.insert("snapshot_100.bin".to_string(), b"older".to_vec());
+            objects.insert("snapshot_200.bin".to_string(), b"newest".to_vec());
+            objects.insert("notes.txt".to_string(), b"not a snapshot".to_vec());
+        }
+        let path = uploader.download_latest().await.unwrap().unwrap();
+        assert_eq!(path, dir.join("snapshot_200.bin"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"newest");
+        // Only the finished download is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_prune_snapshots_deletes_what_retention_drops() {
+        let bucket = Bucket::default();
+        let client = test_client(fake_s3(bucket.clone()).await);
+        {
+            let mut objects = bucket.lock();
+            for secs in [100, 200, 300] {
+                objects.insert(format!("snapshot_{}.bin", secs), b"s".to_vec());
+            }
+            objects.insert("notes.txt".to_string(), b"not a snapshot".to_vec());
+        }
+        let retention = SnapshotRetention {
+            keep_last_n: Some(2),
+            max_age_days: None,
+        };
+
+        let pruned = prune_snapshots(&client, "backups", &retention)
+            .await
+            .unwrap();
+        assert_eq!(pruned, vec!["snapshot_100.bin".to_string()]);
+        let left: Vec<String> = bucket.lock().keys().cloned().collect();
+        assert_eq!(left, ["notes.txt", "snapshot_200.bin", "snapshot_300.bin"]);
+
+        // Already within the policy
+        assert!(prune_snapshots(&client, "backups", &retention)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -11,9 +11,22 @@ pub enum WorkerError {
     #[error("S3 error: {0}")]
     S3(#[from] aws_sdk_s3::Error),
 
+    #[error("Downloaded {received} bytes of {key}, but S3 reported {expected}")]
+    IncompleteDownload {
+        key: String,
+        expected: u64,
+        received: u64,
+    },
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("Replication frame error: {0}")]
+    Frame(#[from] crate::background::frame::FrameError),
+
+    #[error("Replica fell {0} entries behind the WAL tail")]
+    ReplicaLagged(u64),
+
     #[error("Shutdown requested")]
     Shutdown,
 }
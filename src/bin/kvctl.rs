@@ -1,20 +1,16 @@
 use std::process;
 
-use kvstore::kv_store_client; // generated from proto
-use tracing_subscriber;
-
-mod kvstore {
-    tonic::include_proto!("kvstore");
-}
+use clap::Parser;
+use rust_db::ctl::KvCtl;
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let cli = crate::ctl::KvCtl::parse();
+    let cli = KvCtl::parse();
 
     if let Err(e) = cli.run().await {
         eprintln!("Error: {}", e);
         process::exit(1);
     }
-}
\ No newline at end of file
+}
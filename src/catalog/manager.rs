@@ -1,7 +1,14 @@
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
 
-use crate::catalog::types::{AuditSettings, AuthSettings, Grant, Role, User};
-use crate::storage::StorageEngine;
+use chrono::{DateTime, Utc};
+
+use crate::auth::apikey::{self, ApiKeyValidator};
+use crate::catalog::error::CatalogError;
+use crate::catalog::types::{
+    ApiKey, AuditSettings, AuthSettings, Grant, ResolvedRoles, RevokedSession, Role, User,
+};
+use crate::storage::{StorageEngine, StorageError};
 
 pub struct CatalogManager {
     pub engine: Arc<StorageEngine>,
@@ -75,6 +82,146 @@ impl CatalogManager {
         Ok(())
     }
 
+    /// Every role `username` holds, granted directly or inherited, and the
+    /// union of their permissions. Each role is visited once, so inheritance
+    /// cycles are harmless. A user with no grant has no roles, and a role
+    /// that has since been dropped grants nothing.
+    pub async fn resolve_roles(&self, username: &str) -> Result<ResolvedRoles, CatalogError> {
+        let grant = match self.get_grant(username).await {
+            Ok(grant) => grant,
+            Err(CatalogError::Storage(StorageError::KeyNotFound(_))) => {
+                return Ok(ResolvedRoles::default())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut resolved = ResolvedRoles::default();
+        let mut seen = HashSet::new();
+        let mut pending: VecDeque<String> = grant.roles.into();
+        while let Some(name) = pending.pop_front() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let role = match self.get_role(&name).await {
+                Ok(role) => role,
+                Err(CatalogError::Storage(StorageError::KeyNotFound(_))) => {
+                    tracing::warn!(user = %username, role = %name, "Skipping missing role");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for permission in role.permissions {
+                if !resolved.permissions.contains(&permission) {
+                    resolved.permissions.push(permission);
+                }
+            }
+            pending.extend(role.inherits);
+            resolved.roles.push(name);
+        }
+        Ok(resolved)
+    }
+
+    // ================
+    // API KEYS
+    // ================
+    /// Creates an API key for `owner` and returns its ID and secret. Only a
+    /// hash of the secret is stored, so this is the one time it is readable.
+    pub async fn create_api_key(
+        &self,
+        owner: &str,
+        permissions: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(String, String), CatalogError> {
+        let key_id = uuid::Uuid::new_v4().simple().to_string();
+        let secret = apikey::generate_secret();
+        let api_key = ApiKey {
+            key_id: key_id.clone(),
+            owner_user: owner.to_string(),
+            permissions,
+            secret_sha256: apikey::hash_secret(&secret),
+            created_at: Utc::now(),
+            expires_at,
+            revoked: false,
+        };
+        self.set_api_key(&api_key).await?;
+        Ok((key_id, secret))
+    }
+
+    pub async fn get_api_key(&self, key_id: &str) -> Result<ApiKey, CatalogError> {
+        let key = format!("_sys.api_keys:{}", key_id);
+        let entry = self.engine.get(&key).await?;
+        let api_key: ApiKey = serde_json::from_slice(&entry.value)?;
+        Ok(api_key)
+    }
+
+    async fn set_api_key(&self, api_key: &ApiKey) -> Result<(), CatalogError> {
+        let key = format!("_sys.api_keys:{}", api_key.key_id);
+        let value = serde_json::to_vec(api_key)?;
+        self.engine.set(&key, value, None).await?;
+        Ok(())
+    }
+
+    /// `owner`'s API keys, revoked and expired ones included.
+    pub async fn list_api_keys(&self, owner: &str) -> Result<Vec<ApiKey>, CatalogError> {
+        let page = self.engine.scan_prefix("_sys.api_keys:", usize::MAX).await;
+        let mut keys = Vec::new();
+        for (_, entry) in page.items {
+            let api_key: ApiKey = serde_json::from_slice(&entry.value)?;
+            if api_key.owner_user == owner {
+                keys.push(api_key);
+            }
+        }
+        keys.sort_by_key(|key| key.created_at);
+        Ok(keys)
+    }
+
+    /// Marks `key_id` revoked; it fails validation from then on. The record
+    /// is kept so the key still shows up in listings. `false` if there is no
+    /// such key.
+    pub async fn revoke_api_key(&self, key_id: &str) -> Result<bool, CatalogError> {
+        let mut api_key = match self.get_api_key(key_id).await {
+            Ok(api_key) => api_key,
+            Err(CatalogError::Storage(StorageError::KeyNotFound(_))) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        api_key.revoked = true;
+        self.set_api_key(&api_key).await?;
+        Ok(true)
+    }
+
+    pub fn api_key_validator(&self) -> ApiKeyValidator<'_> {
+        ApiKeyValidator::new(self)
+    }
+
+    // ================
+    // REVOKED SESSIONS
+    // ================
+    /// Persists a revoked session for `ttl_secs`, by when its token can no
+    /// longer be used anyway, so the blocklist only holds live tokens.
+    pub async fn revoke_session(
+        &self,
+        revoked: &RevokedSession,
+        ttl_secs: Option<u64>,
+    ) -> Result<(), CatalogError> {
+        let key = format!("_sys.revoked_sessions:{}", revoked.session_id);
+        let value = serde_json::to_vec(revoked)?;
+        self.engine
+            .set(&key, value, ttl_secs.map(|ttl| ttl.max(1)))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn revoked_sessions(&self) -> Result<Vec<RevokedSession>, CatalogError> {
+        let page = self
+            .engine
+            .scan_prefix("_sys.revoked_sessions:", usize::MAX)
+            .await;
+        page.items
+            .iter()
+            .map(|(_, entry)| serde_json::from_slice(&entry.value).map_err(CatalogError::from))
+            .collect()
+    }
+
     // ================
     // SETTINGS
     // ================
@@ -115,3 +262,65 @@ impl CatalogManager {
         crate::catalog::bootstrap::hash_password(password)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+
+    async fn catalog() -> CatalogManager {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        CatalogManager::new(engine)
+    }
+
+    fn role(name: &str, permissions: &[&str], inherits: &[&str]) -> Role {
+        let mut role = Role::new(
+            1,
+            name.to_string(),
+            permissions.iter().map(|p| p.to_string()).collect(),
+        );
+        role.inherits = inherits.iter().map(|r| r.to_string()).collect();
+        role
+    }
+
+    #[tokio::test]
+    async fn test_resolve_roles_follows_inheritance_and_cycles() {
+        let catalog = catalog().await;
+        assert_eq!(
+            catalog.resolve_roles("nobody").await.unwrap(),
+            ResolvedRoles::default()
+        );
+
+        // admin -> writer -> reader -> writer, plus a dangling "gone"
+        catalog
+            .set_role(&role("admin", &["ADMIN"], &["writer", "gone"]))
+            .await
+            .unwrap();
+        catalog
+            .set_role(&role("writer", &["SET", "DEL"], &["reader"]))
+            .await
+            .unwrap();
+        catalog
+            .set_role(&role("reader", &["GET", "SCAN"], &["writer"]))
+            .await
+            .unwrap();
+        catalog
+            .set_grant(&Grant::new(
+                "alice".to_string(),
+                vec!["admin".to_string(), "reader".to_string()],
+                "root".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let resolved = catalog.resolve_roles("alice").await.unwrap();
+        assert_eq!(resolved.roles, vec!["admin", "reader", "writer"]);
+        let mut permissions = resolved.permissions;
+        permissions.sort();
+        assert_eq!(permissions, vec!["ADMIN", "DEL", "GET", "SCAN", "SET"]);
+    }
+}
@@ -4,4 +4,4 @@ pub mod manager;
 pub mod types;
 
 pub use manager::CatalogManager;
-pub use types::{AuthSettings, AuditSettings, Grant, Role, User};
\ No newline at end of file
+pub use types::{ApiKey, AuthSettings, AuditSettings, Grant, ResolvedRoles, RevokedSession, Role, User};
\ No newline at end of file
@@ -15,6 +15,12 @@ pub struct User {
     pub is_active: bool,
     pub valid_until: Option<DateTime<Utc>>,
     pub metadata: HashMap<String, serde_json::Value>,
+    // Networks (CIDR) the user may connect from; empty = anywhere
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    // Networks the user may never connect from, even if allowed above
+    #[serde(default)]
+    pub denied_ips: Vec<String>,
 }
 
 impl User {
@@ -28,6 +34,8 @@ impl User {
             is_active: true,
             valid_until: None,
             metadata: HashMap::new(),
+            allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
         }
     }
 }
@@ -39,8 +47,8 @@ impl User {
 pub struct Role {
     pub oid: u32,
     pub name: String,
-    pub permissions: Vec<String>, // e.g., ["GET", "SET", "DEL", "SCAN"]
-    pub inherits: Vec<String>,    // future: role inheritance
+    pub permissions: Vec<String>, // e.g., ["GET", "SET:app1/*"]; see auth::types::Permission
+    pub inherits: Vec<String>,    // roles whose permissions this one also gets
     pub created_at: DateTime<Utc>,
 }
 
@@ -78,6 +86,41 @@ impl Grant {
     }
 }
 
+/// A user's roles after following `Role::inherits`, and the union of their
+/// permissions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResolvedRoles {
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+// ================
+// API KEY
+// ================
+/// An API key, stored as `_sys.api_keys:<key_id>`. Clients present
+/// `<key_id>.<secret>`; only the secret's SHA-256 is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub owner_user: String,
+    pub permissions: Vec<String>,
+    pub secret_sha256: String, // hex
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+/// A JWT session revoked before its token expired. Kept until `exp`, the
+/// token's own expiry (unix seconds); after that the token fails validation
+/// anyway. Kept for good when the expiry is unknown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokedSession {
+    pub session_id: String,
+    pub exp: Option<u64>,
+    pub revoked_by: String,
+    pub revoked_at: DateTime<Utc>,
+}
+
 // ================
 // SETTINGS
 // ================
@@ -107,6 +150,16 @@ pub struct AuditSettings {
     pub log_successful_logins: bool,
     pub log_failed_logins: bool,
     pub retain_logs_days: u32,
+    #[serde(default)]
+    pub flush_policy: AuditFlushPolicy,
+    #[serde(default)]
+    pub fail_closed: bool, // every event must reach disk; forces per_event
+    #[serde(default = "default_audit_max_file_bytes")]
+    pub max_file_bytes: u64, // rotate the log at this size; 0 = never
+}
+
+fn default_audit_max_file_bytes() -> u64 {
+    100 * 1024 * 1024
 }
 
 impl Default for AuditSettings {
@@ -115,6 +168,19 @@ impl Default for AuditSettings {
             log_successful_logins: true,
             log_failed_logins: true,
             retain_logs_days: 90,
+            flush_policy: AuditFlushPolicy::PerEvent,
+            fail_closed: false,
+            max_file_bytes: default_audit_max_file_bytes(),
         }
     }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditFlushPolicy {
+    #[default]
+    PerEvent,
+    Buffered {
+        flush_ms: u64,
+    },
 }
\ No newline at end of file
@@ -1,12 +1,207 @@
 use serde::Deserialize;
+use thiserror::Error;
 
 use crate::storage::StorageConfig;
+use crate::wal::config::SyncPolicy;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Invalid value for {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct AppConfig {
     pub storage: StorageConfig,
     pub wal: crate::wal::config::WalConfig,
     pub background: BackgroundConfig,
+    #[serde(default)]
+    pub preflight: PreflightConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub connection: crate::connection::config::ConnectionConfig,
+    #[serde(default)]
+    pub encryption: Option<crate::storage::EncryptionConfig>,
+    #[serde(default)]
+    pub rate_limit: crate::api::rate_limit::RateLimitConfig,
+}
+
+/// Protection for the `/metrics` endpoint. With neither credential set the
+/// endpoint stays open; with both, either one is accepted.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MetricsConfig {
+    pub bearer_token: Option<String>,
+    pub basic_auth: Option<BasicAuthConfig>,
+}
+
+impl MetricsConfig {
+    pub fn auth_enabled(&self) -> bool {
+        self.bearer_token.is_some() || self.basic_auth.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BasicAuthConfig {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthConfig {
+    /// Expected JWT `iss` claim; unset accepts any issuer.
+    pub jwt_issuer: Option<String>,
+    /// Expected JWT `aud` claim; unset accepts any audience.
+    pub jwt_audience: Option<String>,
+}
+
+impl AppConfig {
+    pub fn builder() -> AppConfigBuilder {
+        AppConfigBuilder::new()
+    }
+
+    /// Reads the key `encryption` points at into `wal.cipher`, where the WAL
+    /// and the snapshot writers pick it up.
+    pub fn load_encryption_key(&mut self) -> Result<(), crate::storage::EncryptionError> {
+        if let Some(encryption) = &self.encryption {
+            self.wal.cipher = Some(crate::storage::Cipher::load(encryption)?);
+        }
+        Ok(())
+    }
+
+    /// Rejects values that would only fail later at runtime (a zero shard
+    /// count panics on modulo, a zero interval spins a worker, ...).
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn invalid(field: &'static str, reason: &str) -> Result<(), ConfigError> {
+            Err(ConfigError::Invalid {
+                field,
+                reason: reason.to_string(),
+            })
+        }
+
+        if self.storage.num_shards == 0 {
+            return invalid("storage.num_shards", "must be at least 1");
+        }
+        if self.storage.max_batch_ops == 0 {
+            return invalid("storage.max_batch_ops", "must be at least 1");
+        }
+        if self.storage.max_key_bytes == 0 {
+            return invalid("storage.max_key_bytes", "must be at least 1");
+        }
+        if self.storage.snapshot_dir.is_empty() {
+            return invalid("storage.snapshot_dir", "must not be empty");
+        }
+        if self.wal.dir.is_empty() {
+            return invalid("wal.dir", "must not be empty");
+        }
+        if self.wal.max_file_size == 0 {
+            return invalid("wal.max_file_size", "must be greater than 0");
+        }
+        if let SyncPolicy::EveryMs(0) = self.wal.sync_policy {
+            return invalid("wal.sync_policy", "EveryMs interval must be greater than 0");
+        }
+        if self.background.checkpoint_interval_sec == 0 {
+            return invalid(
+                "background.checkpoint_interval_sec",
+                "must be greater than 0",
+            );
+        }
+        if self.background.metrics_interval_ms == 0 {
+            return invalid("background.metrics_interval_ms", "must be greater than 0");
+        }
+        if self.background.snapshot_retention.keep_last_n == Some(0) {
+            return invalid(
+                "background.snapshot_retention.keep_last_n",
+                "must be at least 1",
+            );
+        }
+        let rate_limits = self
+            .rate_limit
+            .default
+            .iter()
+            .chain(self.rate_limit.roles.values());
+        for limit in rate_limits {
+            if limit.per_sec.is_nan() || limit.per_sec <= 0.0 {
+                return invalid("rate_limit.per_sec", "must be greater than 0");
+            }
+            if limit.burst == 0 {
+                return invalid("rate_limit.burst", "must be at least 1");
+            }
+        }
+        if let Some(replica) = &self.background.replica {
+            if replica.backoff.multiplier < 1.0 {
+                return invalid(
+                    "background.replica.backoff.multiplier",
+                    "must be at least 1.0",
+                );
+            }
+            if replica.backoff.initial_ms > replica.backoff.max_ms {
+                return invalid(
+                    "background.replica.backoff.initial_ms",
+                    "must not exceed max_ms",
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assembles an [`AppConfig`] from defaults plus overrides, validating it on
+/// `build()`.
+#[derive(Debug, Clone, Default)]
+pub struct AppConfigBuilder {
+    config: AppConfig,
+}
+
+impl AppConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_shards(mut self, num_shards: usize) -> Self {
+        self.config.storage.num_shards = num_shards;
+        self
+    }
+
+    pub fn with_snapshot_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.storage.snapshot_dir = dir.into();
+        self
+    }
+
+    pub fn with_wal_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.wal.dir = dir.into();
+        self
+    }
+
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.config.wal.sync_policy = policy;
+        self
+    }
+
+    pub fn with_checkpoint_interval(mut self, interval_sec: u64) -> Self {
+        self.config.background.checkpoint_interval_sec = interval_sec;
+        self
+    }
+
+    pub fn build(self) -> Result<AppConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreflightConfig {
+    pub min_free_bytes: u64,
+}
+
+impl Default for PreflightConfig {
+    fn default() -> Self {
+        Self {
+            min_free_bytes: 64 * 1024 * 1024, // 64 MB
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -15,6 +210,42 @@ pub struct BackgroundConfig {
     pub metrics_interval_ms: u64,
     pub s3: Option<S3Config>,
     pub replica: Option<ReplicaConfig>,
+
+    /// Upper bound on each shutdown phase (checkpoint drain, WAL sync, worker stop).
+    #[serde(default = "default_shutdown_phase_timeout_ms")]
+    pub shutdown_phase_timeout_ms: u64,
+
+    /// Snapshots kept in `snapshot_dir` after each checkpoint, and in the
+    /// bucket when `s3.apply_retention` is set. Keeps everything by default.
+    #[serde(default)]
+    pub snapshot_retention: crate::storage::SnapshotRetention,
+
+    /// Fsync the WAL before each checkpoint records its offset, so the
+    /// recorded offset is never past the end of the log on disk.
+    #[serde(default = "default_checkpoint_sync")]
+    pub checkpoint_sync: bool,
+}
+
+fn default_shutdown_phase_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_checkpoint_sync() -> bool {
+    true
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval_sec: 300,
+            metrics_interval_ms: 1000,
+            s3: None,
+            replica: None,
+            shutdown_phase_timeout_ms: default_shutdown_phase_timeout_ms(),
+            snapshot_retention: Default::default(),
+            checkpoint_sync: default_checkpoint_sync(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -23,6 +254,16 @@ pub struct S3Config {
     pub region: String,
     pub endpoint: Option<String>, // for MinIO/S3-compatible
     pub upload_after_snapshot: bool,
+    /// Drop expired entries from a snapshot before uploading it.
+    #[serde(default)]
+    pub vacuum_before_upload: bool,
+    /// Also delete uploaded snapshots outside `snapshot_retention`.
+    #[serde(default)]
+    pub apply_retention: bool,
+    /// On startup with no local snapshot, download the newest one in the
+    /// bucket and recover from it.
+    #[serde(default)]
+    pub restore_on_empty: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -30,4 +271,94 @@ pub struct ReplicaConfig {
     pub enabled: bool,
     pub bind_addr: String,
     pub sync_mode: bool, // false = async
+
+    /// When set, this node follows the given primary, reconnecting on failure.
+    #[serde(default)]
+    pub primary_addr: Option<String>,
+    #[serde(default)]
+    pub backoff: ReplicaBackoffConfig,
+
+    /// With `sync_mode`, replicas that must apply a write before the primary
+    /// acknowledges it.
+    #[serde(default = "default_ack_quorum")]
+    pub ack_quorum: usize,
+    /// With `sync_mode`, how long a write waits for `ack_quorum` replicas
+    /// before failing.
+    #[serde(default = "default_ack_timeout_ms")]
+    pub ack_timeout_ms: u64,
+}
+
+fn default_ack_quorum() -> usize {
+    1
+}
+
+fn default_ack_timeout_ms() -> u64 {
+    5_000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplicaBackoffConfig {
+    pub initial_ms: u64,
+    pub max_ms: u64,
+    pub multiplier: f64,
+}
+
+impl Default for ReplicaBackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_ms: 100,
+            max_ms: 30_000,
+            multiplier: 2.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_defaults_and_overrides() {
+        let config = AppConfig::builder()
+            .with_shards(16)
+            .with_wal_dir("/tmp/kv/wal")
+            .with_sync_policy(SyncPolicy::EveryWrite)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.storage.num_shards, 16);
+        assert_eq!(config.wal.dir, "/tmp/kv/wal");
+        assert!(matches!(config.wal.sync_policy, SyncPolicy::EveryWrite));
+
+        // Untouched fields keep their defaults
+        assert_eq!(config.storage.snapshot_dir, "data/snapshots");
+        assert_eq!(config.wal.file_prefix, "wal_");
+        assert_eq!(config.background.checkpoint_interval_sec, 300);
+        assert_eq!(config.background.shutdown_phase_timeout_ms, 10_000);
+        assert!(config.background.s3.is_none());
+    }
+
+    #[test]
+    fn test_builder_rejects_bad_override() {
+        let err = AppConfig::builder().with_shards(0).build().unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::Invalid {
+                field: "storage.num_shards",
+                ..
+            }
+        ));
+
+        let err = AppConfig::builder()
+            .with_sync_policy(SyncPolicy::EveryMs(0))
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::Invalid {
+                field: "wal.sync_policy",
+                ..
+            }
+        ));
+    }
 }
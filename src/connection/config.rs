@@ -4,8 +4,17 @@ use std::time::Duration;
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConnectionConfig {
     pub max_connections: usize,
-    pub idle_timeout_sec: u64,
-    pub evict_policy: String, // "idle_then_priority" | "fifo" | "priority_then_idle"
+    pub idle_timeout_sec: u64, // 0 = never close idle connections
+    pub evict_policy: String,  // "idle_then_priority" | "fifo" | "priority_then_idle" | "reject"
+
+    /// How often the idle reaper looks for connections past their timeout.
+    #[serde(default = "default_idle_sweep_interval")]
+    pub idle_sweep_interval_ms: u64,
+
+    /// Seconds an authenticated WebSocket session is kept after the client
+    /// drops so it can be resumed with its token. 0 disables resumption.
+    #[serde(default)]
+    pub resume_window_sec: u64,
 
     #[serde(default)]
     pub per_role: std::collections::HashMap<String, RoleConnectionConfig>,
@@ -23,12 +32,18 @@ fn default_idle_timeout() -> u64 {
     300 // 5 minutes
 }
 
+fn default_idle_sweep_interval() -> u64 {
+    1000
+}
+
 impl Default for ConnectionConfig {
     fn default() -> Self {
         Self {
             max_connections: 1000,
             idle_timeout_sec: 300,
             evict_policy: "idle_then_priority".to_string(),
+            idle_sweep_interval_ms: default_idle_sweep_interval(),
+            resume_window_sec: 0,
             per_role: std::collections::HashMap::new(),
         }
     }
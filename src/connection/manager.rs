@@ -1,11 +1,12 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, RwLock};
 use tracing::{debug, warn};
 
+use crate::auth::types::AuthContext;
 use crate::connection::metrics;
 use crate::connection::types::{CloseReason, ConnectionInfo};
 
@@ -13,17 +14,30 @@ use super::config::ConnectionConfig;
 
 type ConnectionMap = DashMap<uuid::Uuid, Arc<RwLock<ConnectionInfo>>>;
 
+// Sessions of dropped clients awaiting resumption, keyed by resume token
+type DetachedMap = DashMap<String, (ConnectionInfo, Instant)>;
+
+// Maps live behind Arcs so clones (e.g. the one held by each guard) share state
 #[derive(Debug, Clone)]
 pub struct ConnectionManager {
     config: Arc<ConnectionConfig>,
-    connections: ConnectionMap,
+    connections: Arc<ConnectionMap>,
+    detached: Arc<DetachedMap>,
 }
 
 impl ConnectionManager {
     pub fn new(config: ConnectionConfig) -> Self {
         Self {
             config: Arc::new(config),
-            connections: ConnectionMap::new(),
+            connections: Arc::new(ConnectionMap::new()),
+            detached: Arc::new(DetachedMap::new()),
+        }
+    }
+
+    fn resume_window(&self) -> Option<Duration> {
+        match self.config.resume_window_sec {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
         }
     }
 
@@ -58,13 +72,22 @@ impl ConnectionManager {
     pub async fn authenticate(
         &self,
         conn_id: uuid::Uuid,
-        user: String,
+        auth: AuthContext,
         role: String,
         priority: u8,
     ) -> Result<(), ConnectionError> {
         if let Some(conn) = self.connections.get(&conn_id) {
             let mut conn_mut = conn.write().await;
+            let user = auth.user.clone();
             conn_mut.set_user(user.clone(), role.clone(), priority);
+            conn_mut.auth = Some(auth);
+            // Only a WebSocket lives long enough to be worth resuming
+            if self.resume_window().is_some()
+                && conn_mut.is_websocket
+                && conn_mut.resume_token.is_none()
+            {
+                conn_mut.resume_token = Some(uuid::Uuid::new_v4().to_string());
+            }
             metrics::inc_accepted(&role);
             metrics::inc_active(&role);
             debug!(conn_id = %conn_id, user = %user, role = %role, "Connection authenticated");
@@ -74,6 +97,54 @@ impl ConnectionManager {
         }
     }
 
+    /// Re-attaches a session dropped within the resume window, restoring its
+    /// user, role, priority and authentication without a fresh round-trip.
+    /// The token stays the same so the session can be resumed again later.
+    /// Whether that authentication still holds is the caller's to check (see
+    /// [`AuthManager::resume_session`](crate::auth::AuthManager::resume_session)).
+    pub async fn resume(
+        &self,
+        addr: std::net::SocketAddr,
+        token: &str,
+    ) -> Result<ConnectionGuard, ConnectionError> {
+        let window = self
+            .resume_window()
+            .ok_or(ConnectionError::InvalidResumeToken)?;
+        let (_, (info, detached_at)) = self
+            .detached
+            .remove(token)
+            .ok_or(ConnectionError::InvalidResumeToken)?;
+
+        if detached_at.elapsed() > window {
+            debug!(user = ?info.user, "Resume window expired");
+            return Err(ConnectionError::ResumeWindowExpired);
+        }
+
+        let guard = self.accept(addr, info.is_websocket).await?;
+        {
+            let mut conn_mut = guard.conn.write().await;
+            conn_mut.user = info.user.clone();
+            conn_mut.role = info.role.clone();
+            conn_mut.priority = info.priority;
+            conn_mut.resume_token = info.resume_token.clone();
+            conn_mut.auth = info.auth.clone();
+        }
+        guard.touch().await;
+
+        let role = info.role.as_deref().unwrap_or("unknown");
+        metrics::inc_active(role);
+        debug!(conn_id = %guard.id, user = ?info.user, "Session resumed");
+
+        Ok(guard)
+    }
+
+    fn purge_detached(&self) {
+        if let Some(window) = self.resume_window() {
+            self.detached
+                .retain(|_, (_, detached_at)| detached_at.elapsed() <= window);
+        }
+    }
+
     pub async fn touch(&self, conn_id: uuid::Uuid) {
         if let Some(conn) = self.connections.get(&conn_id) {
             conn.read().await.touch();
@@ -94,6 +165,73 @@ impl ConnectionManager {
                 CloseReason::AuthFailed => "auth_failed",
             });
             debug!(conn_id = %conn_id, reason = ?reason, "Connection closed");
+
+            // Only an unexpected client drop leaves a resumable session behind
+            if reason == CloseReason::ClientClosed && self.resume_window().is_some() {
+                if let Some(token) = guard.resume_token.clone() {
+                    self.detached.insert(token, (guard.clone(), Instant::now()));
+                }
+            }
+        }
+        self.purge_detached();
+    }
+
+    // A role's own timeout wins over the global one; 0 means never
+    fn idle_timeout_for(&self, role: Option<&str>) -> Option<Duration> {
+        let secs = role
+            .and_then(|role| self.config.per_role.get(role))
+            .map_or(self.config.idle_timeout_sec, |role| role.idle_timeout_sec);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Closes every connection idle for longer than its timeout with
+    /// `CloseReason::IdleTimeout`. Returns how many were closed.
+    pub async fn close_idle(&self) -> usize {
+        let mut idle = Vec::new();
+        for entry in self.connections.iter() {
+            let conn = entry.value().read().await;
+            if let Some(timeout) = self.idle_timeout_for(conn.role.as_deref()) {
+                if conn.idle_time() > timeout {
+                    idle.push(conn.id);
+                }
+            }
+        }
+
+        for id in &idle {
+            self.close_connection(*id, CloseReason::IdleTimeout).await;
+        }
+        idle.len()
+    }
+
+    /// Runs [`close_idle`](Self::close_idle) every `idle_sweep_interval_ms`
+    /// until the returned reaper is shut down.
+    pub fn spawn_idle_reaper(&self) -> IdleReaper {
+        let (tx, mut rx) = oneshot::channel();
+        let manager = self.clone();
+        let period = Duration::from_millis(self.config.idle_sweep_interval_ms.max(1));
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let closed = manager.close_idle().await;
+                        if closed > 0 {
+                            debug!(closed = closed, "Closed idle connections");
+                        }
+                    }
+                    _ = &mut rx => {
+                        debug!("Idle connection reaper shutting down");
+                        break;
+                    }
+                }
+            }
+        });
+
+        IdleReaper {
+            shutdown_tx: Some(tx),
+            handle,
         }
     }
 
@@ -102,6 +240,8 @@ impl ConnectionManager {
             "idle_then_priority" => self.evict_by_idle_then_priority().await,
             "fifo" => self.evict_oldest().await,
             "priority_then_idle" => self.evict_by_priority_then_idle().await,
+            // Refuse new connections instead of dropping an existing one
+            "reject" => None,
             _ => self.evict_oldest().await,
         }
     }
@@ -152,6 +292,25 @@ impl ConnectionManager {
     }
 }
 
+/// Handle to the task started by [`ConnectionManager::spawn_idle_reaper`].
+#[derive(Debug)]
+pub struct IdleReaper {
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl IdleReaper {
+    /// Stops the reaper and waits for its current sweep to finish.
+    pub async fn shutdown(mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+        if let Err(e) = (&mut self.handle).await {
+            warn!("Idle connection reaper failed: {}", e);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ConnectionGuard {
     id: uuid::Uuid,
@@ -167,6 +326,10 @@ impl ConnectionGuard {
     pub async fn touch(&self) {
         self.conn.read().await.touch();
     }
+
+    pub async fn info(&self) -> ConnectionInfo {
+        self.conn.read().await.clone()
+    }
 }
 
 impl Drop for ConnectionGuard {
@@ -187,4 +350,126 @@ pub enum ConnectionError {
     MaxConnectionsExceeded,
     #[error("Connection not found")]
     NotFound,
+    #[error("Unknown session resume token")]
+    InvalidResumeToken,
+    #[error("Session resume window expired, re-authentication required")]
+    ResumeWindowExpired,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str) -> AuthContext {
+        AuthContext {
+            user: name.to_string(),
+            roles: Vec::new(),
+            permissions: Vec::new(),
+            source_ip: "127.0.0.1".parse().unwrap(),
+            auth_method: crate::auth::types::AuthMethod::Password,
+            session_id: String::new(),
+        }
+    }
+
+    fn manager_with_resume(window_sec: u64) -> ConnectionManager {
+        ConnectionManager::new(ConnectionConfig {
+            resume_window_sec: window_sec,
+            ..Default::default()
+        })
+    }
+
+    async fn connect_and_drop(manager: &ConnectionManager) -> String {
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        let guard = manager.accept(addr, true).await.unwrap();
+        manager
+            .authenticate(guard.id(), user("alice"), "writer".to_string(), 7)
+            .await
+            .unwrap();
+        let token = guard.info().await.resume_token.unwrap();
+
+        // Drop closes the connection on a spawned task
+        drop(guard);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        token
+    }
+
+    #[tokio::test]
+    async fn test_resume_within_window() {
+        let manager = manager_with_resume(5);
+        let token = connect_and_drop(&manager).await;
+
+        let addr = "127.0.0.1:4001".parse().unwrap();
+        let guard = manager.resume(addr, &token).await.unwrap();
+        let info = guard.info().await;
+        assert_eq!(info.user.as_deref(), Some("alice"));
+        assert_eq!(info.role.as_deref(), Some("writer"));
+        assert_eq!(info.priority, 7);
+        assert_eq!(info.resume_token.as_deref(), Some(token.as_str()));
+        assert_eq!(info.auth.unwrap().user, "alice");
+
+        // A token is single-use while its session is attached
+        let again = manager.resume(addr, &token).await;
+        assert!(matches!(again, Err(ConnectionError::InvalidResumeToken)));
+    }
+
+    #[tokio::test]
+    async fn test_resume_outside_window_requires_reauth() {
+        let manager = manager_with_resume(1);
+        let token = connect_and_drop(&manager).await;
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        let addr = "127.0.0.1:4001".parse().unwrap();
+        let result = manager.resume(addr, &token).await;
+        assert!(matches!(result, Err(ConnectionError::ResumeWindowExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_idle_reaper_closes_idle_connections() {
+        let mut per_role = HashMap::new();
+        per_role.insert(
+            "batch".to_string(),
+            crate::connection::config::RoleConnectionConfig {
+                max_connections: None,
+                idle_timeout_sec: 0, // never idles out
+            },
+        );
+        let manager = ConnectionManager::new(ConnectionConfig {
+            idle_timeout_sec: 1,
+            idle_sweep_interval_ms: 50,
+            per_role,
+            ..Default::default()
+        });
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        let idle = manager.accept(addr, false).await.unwrap();
+        let busy = manager.accept(addr, false).await.unwrap();
+        let exempt = manager.accept(addr, false).await.unwrap();
+        manager
+            .authenticate(exempt.id(), user("etl"), "batch".to_string(), 1)
+            .await
+            .unwrap();
+
+        let reaper = manager.spawn_idle_reaper();
+        for _ in 0..6 {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            busy.touch().await;
+        }
+
+        assert!(!manager.connections.contains_key(&idle.id()));
+        assert!(manager.connections.contains_key(&busy.id()));
+        assert!(manager.connections.contains_key(&exempt.id()));
+        reaper.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_resume_disabled_by_default() {
+        let manager = ConnectionManager::new(ConnectionConfig::default());
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        let guard = manager.accept(addr, false).await.unwrap();
+        manager
+            .authenticate(guard.id(), user("bob"), "reader".to_string(), 1)
+            .await
+            .unwrap();
+        assert!(guard.info().await.resume_token.is_none());
+    }
 }
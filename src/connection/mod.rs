@@ -3,5 +3,5 @@ pub mod manager;
 pub mod metrics;
 pub mod types;
 
-pub use manager::{ConnectionError, ConnectionGuard, ConnectionManager};
+pub use manager::{ConnectionError, ConnectionGuard, ConnectionManager, IdleReaper};
 pub use types::{CloseReason, ConnectionInfo};
\ No newline at end of file
@@ -1,9 +1,11 @@
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::auth::types::AuthContext;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CloseReason {
     IdleTimeout,
@@ -21,8 +23,10 @@ pub struct ConnectionInfo {
     pub role: Option<String>,
     pub priority: u8, // 0 = lowest, 255 = highest (admin)
     pub connected_at: Instant,
-    pub last_active: Arc<AtomicU64>, // Unix timestamp in nanos
+    pub last_active: Arc<AtomicU64>, // nanos since the process clock base, see `clock_nanos`
     pub is_websocket: bool,
+    pub resume_token: Option<String>, // set on auth when resumption is enabled
+    pub auth: Option<AuthContext>,    // what the user authenticated as, for a resume
 }
 
 impl ConnectionInfo {
@@ -34,8 +38,10 @@ impl ConnectionInfo {
             role: None,
             priority: 0, // default lowest
             connected_at: Instant::now(),
-            last_active: Arc::new(AtomicU64::new(0)),
+            last_active: Arc::new(AtomicU64::new(clock_nanos())),
             is_websocket,
+            resume_token: None,
+            auth: None,
         }
     }
 
@@ -46,20 +52,39 @@ impl ConnectionInfo {
     }
 
     pub fn touch(&self) {
-        let now_nanos = Instant::now().elapsed().as_nanos();
-        let clamped = std::cmp::min(now_nanos, u64::MAX as u128) as u64;
-        self.last_active.store(clamped, Ordering::Relaxed);
+        self.last_active.store(clock_nanos(), Ordering::Relaxed);
     }
 
     pub fn idle_time(&self) -> Duration {
-        let now_nanos = Instant::now().elapsed().as_nanos();
-        let last_nanos = self.last_active.load(Ordering::Relaxed) as u128;
+        let last_nanos = self.last_active.load(Ordering::Relaxed);
+        // Another thread may touch between our two reads; never underflow
+        Duration::from_nanos(clock_nanos().saturating_sub(last_nanos))
+    }
+}
 
-        if last_nanos == 0 {
-            return Duration::from_secs(0);
-        }
+// Monotonic nanos since a process-wide base `Instant`, so idle times are
+// unaffected by wall-clock adjustments and fit in an `AtomicU64`.
+fn clock_nanos() -> u64 {
+    static BASE: OnceLock<Instant> = OnceLock::new();
+    let elapsed = BASE.get_or_init(Instant::now).elapsed().as_nanos();
+    std::cmp::min(elapsed, u64::MAX as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_time_tracks_touch() {
+        let conn = ConnectionInfo::new("127.0.0.1:4000".parse().unwrap(), false);
+        conn.touch();
+        std::thread::sleep(Duration::from_millis(50));
+
+        let idle = conn.idle_time();
+        assert!(idle >= Duration::from_millis(50), "idle_time {:?}", idle);
+        assert!(idle < Duration::from_secs(5), "idle_time {:?}", idle);
 
-        let delta = now_nanos.saturating_sub(last_nanos);
-        Duration::from_nanos(std::cmp::min(delta, u64::MAX as u128) as u64)
+        conn.touch();
+        assert!(conn.idle_time() < Duration::from_millis(50));
     }
 }
@@ -1,24 +1,60 @@
+use serde::de::DeserializeOwned;
+use tokio_stream::StreamExt;
+use tonic::metadata::{Ascii, MetadataValue};
 use tonic::transport::Channel;
 
-use super::types::{DeleteResponse, GetResponse, SetResponse};
+use crate::api::grpc::auth::API_KEY_METADATA;
+use crate::api::grpc::kvstore::kv_store_client::KvStoreClient as GrpcClient;
+use crate::api::grpc::kvstore::{
+    DeleteRequest, DeleteResponse, GetRequest, GetResponse, IncrRequest, IncrResponse, ScanRequest,
+    ScanResponse, SetRequest, SetResponse,
+};
+use crate::api::rest::types::{
+    CreateSnapshotResponse, ListSnapshotsResponse, RestoreSnapshotParams, RestoreSnapshotResponse,
+};
+use crate::ctl::types::KvCtlError;
+
 pub struct KvStoreClient {
-    inner: KvStoreClient<Channel>,
+    inner: GrpcClient<Channel>,
+    api_key: Option<MetadataValue<Ascii>>, // sent as `x-api-key` metadata
 }
 
 impl KvStoreClient {
-    pub async fn connect(addr: &str) -> Result<Self, crate::ctl::types::KvCtlError> {
+    pub async fn connect(
+        addr: &str,
+        api_key: Option<&str>,
+    ) -> Result<Self, crate::ctl::types::KvCtlError> {
+        let api_key = api_key
+            .map(|key| {
+                key.parse().map_err(|_| {
+                    KvCtlError::InvalidArgument("API key is not valid ASCII".to_string())
+                })
+            })
+            .transpose()?;
         let channel = Channel::from_shared(addr.to_string())
             .map_err(|e| crate::ctl::types::KvCtlError::InvalidArgument(e.to_string()))?
             .connect()
             .await?;
 
         Ok(Self {
-            inner: KvStoreClient::new(channel),
+            inner: GrpcClient::new(channel),
+            api_key,
         })
     }
 
+    // Every call carries the API key, when there is one
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(api_key) = &self.api_key {
+            request
+                .metadata_mut()
+                .insert(API_KEY_METADATA, api_key.clone());
+        }
+        request
+    }
+
     pub async fn get(&mut self, key: &str) -> Result<GetResponse, tonic::Status> {
-        let request = tonic::Request::new(GetRequest {
+        let request = self.request(GetRequest {
             key: key.to_string(),
         });
         let response = self.inner.get(request).await?;
@@ -31,7 +67,7 @@ impl KvStoreClient {
         value: Vec<u8>,
         ttl_seconds: u64,
     ) -> Result<SetResponse, tonic::Status> {
-        let request = tonic::Request::new(SetRequest {
+        let request = self.request(SetRequest {
             key: key.to_string(),
             value,
             ttl_seconds,
@@ -41,10 +77,117 @@ impl KvStoreClient {
     }
 
     pub async fn delete(&mut self, key: &str) -> Result<DeleteResponse, tonic::Status> {
-        let request = tonic::Request::new(DeleteRequest {
+        let request = self.request(DeleteRequest {
             key: key.to_string(),
         });
         let response = self.inner.delete(request).await?;
         Ok(response.into_inner())
     }
+
+    pub async fn incr(&mut self, key: &str, delta: i64) -> Result<IncrResponse, tonic::Status> {
+        let request = self.request(IncrRequest {
+            key: key.to_string(),
+            delta,
+        });
+        let response = self.inner.incr(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Collects the whole `Scan` stream; `limit` 0 means every match. When
+    /// the limit cut it short, the last item's `next_cursor` continues it.
+    pub async fn scan(
+        &mut self,
+        pattern: &str,
+        limit: u64,
+        cursor: Option<&str>,
+    ) -> Result<Vec<ScanResponse>, tonic::Status> {
+        let request = self.request(ScanRequest {
+            pattern: pattern.to_string(),
+            limit,
+            cursor: cursor.unwrap_or_default().to_string(),
+        });
+        let mut stream = self.inner.scan(request).await?.into_inner();
+
+        let mut items = Vec::new();
+        while let Some(item) = stream.next().await {
+            items.push(item?);
+        }
+        Ok(items)
+    }
+}
+
+/// The REST admin routes, for operations the gRPC API does not offer.
+pub struct AdminClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>, // sent as `X-API-Key`
+}
+
+impl AdminClient {
+    pub fn new(base_url: String, api_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    pub async fn create_snapshot(&self) -> Result<CreateSnapshotResponse, KvCtlError> {
+        self.send(self.http.post(self.url("/v1/admin/snapshots")))
+            .await
+    }
+
+    pub async fn list_snapshots(&self) -> Result<ListSnapshotsResponse, KvCtlError> {
+        self.send(self.http.get(self.url("/v1/admin/snapshots")))
+            .await
+    }
+
+    pub async fn restore_snapshot(
+        &self,
+        name: &str,
+        force: bool,
+    ) -> Result<RestoreSnapshotResponse, KvCtlError> {
+        let params = RestoreSnapshotParams {
+            name: name.to_string(),
+            force,
+        };
+        self.send(
+            self.http
+                .post(self.url("/v1/admin/snapshots/restore"))
+                .json(&params),
+        )
+        .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // Error replies carry `{"error": {"code": "...", "message": "..."}}`
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, KvCtlError> {
+        let request = match &self.api_key {
+            Some(api_key) => request.header("X-API-Key", api_key),
+            None => request,
+        };
+        let response = request.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body: serde_json::Value = response.json().await.unwrap_or_default();
+            return Err(KvCtlError::Server {
+                status: status.as_u16(),
+                message: match (
+                    body["error"]["code"].as_str(),
+                    body["error"]["message"].as_str(),
+                ) {
+                    (Some(code), Some(message)) => format!("{} ({})", message, code),
+                    _ => "no details".to_string(),
+                },
+            });
+        }
+        Ok(response.json().await?)
+    }
 }
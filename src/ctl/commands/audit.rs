@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use clap::Args;
+
+use crate::auth::audit::{query_log, AuditFilter};
+
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Audit log to read; its rotations (`<file>.1`, ...) are read too
+    #[arg(long, default_value = "audit.log")]
+    file: String,
+
+    /// Only events for this user
+    #[arg(short, long)]
+    user: Option<String>,
+
+    /// Only this event type (e.g. "login_failed")
+    #[arg(short, long)]
+    event: Option<String>,
+
+    /// Only events at or after this time (RFC 3339 or unix seconds)
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Only unsuccessful events
+    #[arg(long)]
+    failed_only: bool,
+}
+
+/// Offline: prints matching audit events as a table, oldest first.
+pub async fn run(args: AuditArgs) -> Result<(), crate::ctl::types::KvCtlError> {
+    let since = args.since.as_deref().map(parse_time).transpose()?;
+    let filter = AuditFilter {
+        user: args.user,
+        event: args.event,
+        success: args.failed_only.then_some(false),
+        since,
+        until: None,
+    };
+    let events = query_log(Path::new(&args.file), &filter)?;
+
+    println!(
+        "{:<20}  {:<18}  {:<12}  {:<15}  {:<8}  {:<3}  DETAILS",
+        "TIME", "EVENT", "USER", "SOURCE", "METHOD", "OK"
+    );
+    for event in &events {
+        let time = chrono::DateTime::from_timestamp(event.timestamp as i64, 0)
+            .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            .unwrap_or_else(|| event.timestamp.to_string());
+        println!(
+            "{:<20}  {:<18}  {:<12}  {:<15}  {:<8}  {:<3}  {}",
+            time,
+            event.event,
+            event.user.as_deref().unwrap_or("-"),
+            event.source_ip,
+            event.auth_method,
+            if event.success { "yes" } else { "no" },
+            event.details.as_deref().unwrap_or("")
+        );
+    }
+    println!("{} events", events.len());
+    Ok(())
+}
+
+fn parse_time(s: &str) -> Result<u64, crate::ctl::types::KvCtlError> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(secs);
+    }
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|t| t.timestamp().max(0) as u64)
+        .map_err(|e| {
+            crate::ctl::types::KvCtlError::InvalidArgument(format!("--since {}: {}", s, e))
+        })
+}
@@ -1,25 +1,149 @@
-use clap::Args;
+use base64::Engine;
+use clap::{Args, Subcommand};
+use serde::Serialize;
+
+use crate::ctl::client::KvStoreClient;
 
 #[derive(Args)]
 pub struct KeysArgs {
-    /// Key pattern (e.g., "user:*")
-    #[arg(short, long)]
-    pattern: Option<String>,
+    #[command(subcommand)]
+    command: KeysCommand,
 
-    /// Include system keys (_sys.*)
-    #[arg(long)]
-    include_system: bool,
+    /// Print JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
 
-    /// Limit number of results
-    #[arg(short, long, default_value_t = 100)]
-    limit: u64,
+    /// Print values as text instead of base64
+    #[arg(long, global = true)]
+    decode: bool,
 }
 
-pub async fn run(args: KeysArgs) -> Result<(), crate::ctl::types::KvCtlError> {
-    // Placeholder: connect to server and scan
-    // In MVP: not implemented — requires SCAN RPC
-    println!("Scanning keys with pattern: {:?}", args.pattern);
-    println!("Note: SCAN not implemented in gRPC yet — returning empty");
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// List keys under a prefix (system keys are never listed)
+    List {
+        /// Key prefix (e.g. "user:"); empty lists every key
+        #[arg(short, long, default_value = "")]
+        prefix: String,
+
+        /// Maximum number of keys to print
+        #[arg(short, long, default_value_t = 100)]
+        limit: u64,
+
+        /// Continue from the cursor printed by a previous listing
+        #[arg(long)]
+        cursor: Option<String>,
 
+        /// Print values too
+        #[arg(long)]
+        values: bool,
+    },
+
+    /// Print one key's value and version
+    Get { key: String },
+}
+
+#[derive(Serialize)]
+struct KeyItem {
+    key: String,
+    version: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+#[derive(Serialize)]
+struct KeyList {
+    keys: Vec<KeyItem>,
+    next_cursor: Option<String>,
+}
+
+pub async fn run(
+    args: KeysArgs,
+    server: &str,
+    api_key: Option<&str>,
+) -> Result<(), crate::ctl::types::KvCtlError> {
+    let mut client = KvStoreClient::connect(server, api_key).await?;
+
+    match args.command {
+        KeysCommand::List {
+            prefix,
+            limit,
+            cursor,
+            values,
+        } => {
+            // Scans take a glob; a literal prefix must not smuggle one in
+            if prefix.contains(['*', '?']) {
+                return Err(crate::ctl::types::KvCtlError::InvalidArgument(format!(
+                    "--prefix {:?} may not contain '*' or '?'",
+                    prefix
+                )));
+            }
+
+            let items = client
+                .scan(&format!("{}*", prefix), limit, cursor.as_deref())
+                .await?;
+            let next_cursor = items
+                .last()
+                .map(|item| item.next_cursor.clone())
+                .filter(|cursor| !cursor.is_empty());
+            let list = KeyList {
+                keys: items
+                    .into_iter()
+                    .map(|item| KeyItem {
+                        key: item.key,
+                        version: item.version,
+                        value: values.then(|| show_value(&item.value, args.decode)),
+                    })
+                    .collect(),
+                next_cursor,
+            };
+
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&list)?);
+                return Ok(());
+            }
+            for item in &list.keys {
+                match &item.value {
+                    Some(value) => println!("{}\t{}\t{}", item.key, item.version, value),
+                    None => println!("{}\t{}", item.key, item.version),
+                }
+            }
+            println!("{} keys", list.keys.len());
+            if let Some(cursor) = &list.next_cursor {
+                println!("More keys: continue with --cursor {}", cursor);
+            }
+        }
+        KeysCommand::Get { key } => {
+            let response = client.get(&key).await?;
+            if !response.found {
+                return Err(crate::ctl::types::KvCtlError::InvalidArgument(format!(
+                    "key {} not found",
+                    key
+                )));
+            }
+            let item = KeyItem {
+                key,
+                version: response.version,
+                value: Some(show_value(&response.value, args.decode)),
+            };
+
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&item)?);
+            } else {
+                println!("key:     {}", item.key);
+                println!("version: {}", item.version);
+                println!("value:   {}", item.value.unwrap_or_default());
+            }
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+// Values are arbitrary bytes, so base64 unless text was asked for
+fn show_value(value: &[u8], decode: bool) -> String {
+    if decode {
+        String::from_utf8_lossy(value).into_owned()
+    } else {
+        base64::engine::general_purpose::STANDARD.encode(value)
+    }
+}
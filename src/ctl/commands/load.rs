@@ -0,0 +1,121 @@
+use std::io::BufRead;
+
+use base64::Engine;
+use clap::Args;
+use serde::Deserialize;
+
+use crate::ctl::client::KvStoreClient;
+use crate::storage::BulkEntry;
+
+#[derive(Args)]
+pub struct LoadArgs {
+    /// JSON-lines file of {"key", "value" (base64), "ttl"} records. Without
+    /// --fast, each one is written through the running server like any SET
+    file: String,
+
+    /// Offline fast path: bypass the WAL and write one snapshot at the end.
+    /// The server must be stopped; if the load crashes, start it over.
+    #[arg(long)]
+    fast: bool,
+
+    /// Server config used to locate the data directories
+    #[arg(long, default_value = "config.toml")]
+    config: String,
+}
+
+#[derive(Deserialize)]
+struct LoadRecord {
+    key: String,
+    value: String, // base64-encoded
+    #[serde(default)]
+    ttl: Option<u64>, // seconds
+}
+
+pub async fn run(
+    args: LoadArgs,
+    server: &str,
+    api_key: Option<&str>,
+) -> Result<(), crate::ctl::types::KvCtlError> {
+    if !args.fast {
+        let entries = read_records(&args.file)?;
+        println!(
+            "Loading {} records from {} through {}...",
+            entries.len(),
+            args.file,
+            server
+        );
+
+        let mut client = KvStoreClient::connect(server, api_key).await?;
+        let mut loaded = 0;
+        for entry in entries {
+            // The gRPC API takes 0 for "no expiry"
+            client
+                .set(&entry.key, entry.value, entry.ttl_secs.unwrap_or(0))
+                .await?;
+            loaded += 1;
+        }
+        println!("Loaded {} keys", loaded);
+        return Ok(());
+    }
+
+    let config_str = std::fs::read_to_string(&args.config)?;
+    let mut config: crate::config::AppConfig = toml::from_str(&config_str)
+        .map_err(|e| crate::ctl::types::KvCtlError::InvalidArgument(e.to_string()))?;
+    config
+        .load_encryption_key()
+        .map_err(crate::storage::StorageError::from)?;
+
+    let entries = read_records(&args.file)?;
+    println!(
+        "Fast-loading {} records from {}...",
+        entries.len(),
+        args.file
+    );
+
+    let engine = crate::storage::StorageEngine::try_new(config.storage.clone()).await?;
+    let snapshots = crate::storage::SnapshotManager::new(config.storage.snapshot_dir.clone())
+        .with_cipher(config.wal.cipher.clone());
+    let wal = crate::wal::WalManager::new(config.wal.clone()).await?;
+
+    let report = crate::storage::bulk::fast_load(
+        &engine,
+        &snapshots,
+        &wal,
+        futures_util::stream::iter(entries),
+    )
+    .await?;
+
+    println!(
+        "Loaded {} keys into snapshot {} (checkpoint at WAL offset {})",
+        report.loaded, report.snapshot, report.checkpoint_offset
+    );
+    Ok(())
+}
+
+fn read_records(path: &str) -> Result<Vec<BulkEntry>, crate::ctl::types::KvCtlError> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut entries = Vec::new();
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: LoadRecord = serde_json::from_str(&line)?;
+        let value = base64::engine::general_purpose::STANDARD
+            .decode(&record.value)
+            .map_err(|_| {
+                crate::ctl::types::KvCtlError::InvalidArgument(format!(
+                    "line {}: invalid base64 value",
+                    line_no + 1
+                ))
+            })?;
+        entries.push(BulkEntry {
+            key: record.key,
+            value,
+            ttl_secs: record.ttl,
+        });
+    }
+
+    Ok(entries)
+}
@@ -1,4 +1,7 @@
+pub mod audit;
 pub mod keys;
+pub mod load;
 pub mod snapshot;
 pub mod user;
+pub mod vacuum;
 pub mod wal;
@@ -1,26 +1,51 @@
 use clap::Subcommand;
 
+use crate::ctl::client::AdminClient;
+
 #[derive(Subcommand)]
 pub enum SnapshotCommand {
     /// Create a new snapshot
     Create,
     /// List existing snapshots
     List,
-    /// Restore from snapshot
-    Restore { filename: String },
+    /// Replace the server's whole dataset with a snapshot (superuser only)
+    Restore {
+        filename: String,
+
+        /// Wait for in-flight writes instead of refusing
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-pub async fn run(cmd: SnapshotCommand) -> Result<(), crate::ctl::types::KvCtlError> {
+/// Runs against a live server over the REST admin API.
+pub async fn run(
+    cmd: SnapshotCommand,
+    client: AdminClient,
+) -> Result<(), crate::ctl::types::KvCtlError> {
     match cmd {
         SnapshotCommand::Create => {
-            println!("Creating snapshot... (not implemented — requires server RPC)");
+            let created = client.create_snapshot().await?;
+            println!("Created {}", created.name);
         }
         SnapshotCommand::List => {
-            println!("Listing snapshots... (not implemented — requires server RPC)");
+            let listed = client.list_snapshots().await?;
+            println!("{:<28}  {:<20}  {:>12}", "NAME", "CREATED", "BYTES");
+            for snapshot in &listed.snapshots {
+                let created = chrono::DateTime::from_timestamp(snapshot.created_at as i64, 0)
+                    .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+                    .unwrap_or_else(|| snapshot.created_at.to_string());
+                println!(
+                    "{:<28}  {:<20}  {:>12}",
+                    snapshot.name, created, snapshot.size_bytes
+                );
+            }
+            println!("{} snapshots", listed.snapshots.len());
         }
-        SnapshotCommand::Restore { filename } => {
-            println!("Restoring from {}... (not implemented — requires server RPC)", filename);
+        SnapshotCommand::Restore { filename, force } => {
+            let restored = client.restore_snapshot(&filename, force).await?;
+            println!("Restored {}", restored.restored);
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,55 @@
+use clap::Args;
+
+use crate::storage::SnapshotManager;
+
+#[derive(Args)]
+pub struct VacuumArgs {
+    /// Snapshot file to compact; defaults to the latest one
+    filename: Option<String>,
+
+    /// Server config used to locate the snapshot directory
+    #[arg(long, default_value = "config.toml")]
+    config: String,
+}
+
+/// Offline: rewrites a snapshot without its expired entries.
+pub async fn run(args: VacuumArgs) -> Result<(), crate::ctl::types::KvCtlError> {
+    let config_str = std::fs::read_to_string(&args.config)?;
+    let mut config: crate::config::AppConfig = toml::from_str(&config_str)
+        .map_err(|e| crate::ctl::types::KvCtlError::InvalidArgument(e.to_string()))?;
+    config
+        .load_encryption_key()
+        .map_err(crate::storage::StorageError::from)?;
+    let snapshot_dir = config.storage.snapshot_dir.clone();
+
+    let filename = match args.filename {
+        Some(filename) => filename,
+        None => latest_snapshot(&snapshot_dir)?,
+    };
+
+    let report = SnapshotManager::new(snapshot_dir)
+        .with_cipher(config.wal.cipher)
+        .compact_snapshot(&filename)
+        .await?;
+
+    println!(
+        "Vacuumed {}: kept {} keys, dropped {} expired, {} -> {} bytes",
+        filename, report.kept, report.dropped, report.bytes_before, report.bytes_after
+    );
+    Ok(())
+}
+
+// Snapshot names embed their creation time, so the largest name is the newest
+fn latest_snapshot(snapshot_dir: &str) -> Result<String, crate::ctl::types::KvCtlError> {
+    std::fs::read_dir(snapshot_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("snapshot_") && name.ends_with(".bin"))
+        .max()
+        .ok_or_else(|| {
+            crate::ctl::types::KvCtlError::InvalidArgument(format!(
+                "no snapshots in {}",
+                snapshot_dir
+            ))
+        })
+}
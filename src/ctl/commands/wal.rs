@@ -1,18 +1,130 @@
-use clap::Args;
+use std::ops::ControlFlow;
+
+use clap::{Args, Subcommand};
+
+use crate::storage::{Cipher, EncryptionConfig};
+use crate::wal::entry::WalEntry;
+use crate::wal::manager::{read_segments, split_offset};
+use crate::wal::{WalConfig, WalError};
 
 #[derive(Args)]
 pub struct WalArgs {
-    /// Number of lines to tail
-    #[arg(short, long, default_value_t = 10)]
-    lines: usize,
+    #[command(subcommand)]
+    command: WalCommand,
+}
+
+#[derive(Subcommand)]
+pub enum WalCommand {
+    /// Print WAL entries, oldest first, across every segment (offline)
+    Dump {
+        /// WAL directory (`wal.dir` in the server config)
+        #[arg(long, default_value = "data/wal")]
+        dir: String,
+
+        /// Segment file prefix (`wal.file_prefix`)
+        #[arg(long, default_value = "wal_")]
+        file_prefix: String,
+
+        /// Global offset to start at, as printed in the OFFSET column
+        #[arg(long, default_value_t = 0)]
+        from_offset: u64,
+
+        /// Stop after this many entries
+        #[arg(long)]
+        limit: Option<usize>,
 
-    /// Follow mode (like tail -f)
-    #[arg(short, long)]
-    follow: bool,
+        /// Only check checksums, reporting the first corrupt entry
+        #[arg(long)]
+        verify: bool,
+
+        /// Key file (`encryption.key_file`), to read encrypted segments
+        #[arg(long)]
+        key_file: Option<String>,
+    },
 }
 
+/// Reads the segment files directly; no server is needed, and nothing is
+/// written.
 pub async fn run(args: WalArgs) -> Result<(), crate::ctl::types::KvCtlError> {
-    println!("WAL tailing not implemented in MVP — requires direct WAL file access");
-    println!("Requested: {} lines, follow={}", args.lines, args.follow);
-    Ok(())
-}
\ No newline at end of file
+    match args.command {
+        WalCommand::Dump {
+            dir,
+            file_prefix,
+            from_offset,
+            limit,
+            verify,
+            key_file,
+        } => {
+            let cipher = key_file
+                .map(|key_file| Cipher::load(&EncryptionConfig { key_file }))
+                .transpose()
+                .map_err(WalError::from)?;
+            let config = WalConfig {
+                dir,
+                file_prefix,
+                cipher,
+                ..Default::default()
+            };
+            let limit = limit.unwrap_or(usize::MAX);
+
+            if !verify {
+                println!(
+                    "{:<16}  {:<18}  {:<24}  {:<10}  {:>8}  {:>9}  KEY",
+                    "OFFSET", "SEGMENT:BYTE", "TIMESTAMP", "OP", "VERSION", "VALUE_LEN"
+                );
+            }
+            let mut count = 0;
+            let result = read_segments(&config, from_offset, |offset, entry| {
+                if count == limit {
+                    return Ok(ControlFlow::Break(()));
+                }
+                if !verify {
+                    print_entry(offset, &entry);
+                }
+                count += 1;
+                Ok(ControlFlow::Continue(()))
+            });
+
+            match result {
+                Ok(()) => {
+                    println!(
+                        "{} entries{}",
+                        count,
+                        if verify { ", all valid" } else { "" }
+                    );
+                    Ok(())
+                }
+                Err(WalError::ReplayError { offset, reason }) => {
+                    let (sequence, in_segment) = split_offset(offset);
+                    println!(
+                        "{} valid entries, then a corrupt entry at offset {} ({}{}, byte {}): {}",
+                        count, offset, config.file_prefix, sequence, in_segment, reason
+                    );
+                    Err(WalError::ReplayError { offset, reason }.into())
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+}
+
+fn print_entry(offset: u64, entry: &WalEntry) {
+    let (sequence, in_segment) = split_offset(offset);
+    let timestamp = chrono::DateTime::from_timestamp(
+        (entry.timestamp / 1_000_000_000) as i64,
+        (entry.timestamp % 1_000_000_000) as u32,
+    )
+    .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+    .unwrap_or_else(|| entry.timestamp.to_string());
+
+    println!(
+        "{:<16}  {:<18}  {:<24}  {:<10}  {:>8}  {:>9}  {}",
+        offset,
+        format!("{}:{}", sequence, in_segment),
+        timestamp,
+        format!("{:?}", entry.op_type),
+        entry.version,
+        entry.value.len(),
+        entry.key
+    );
+}
@@ -4,6 +4,7 @@ pub mod types;
 
 use clap::{Parser, Subcommand};
 
+use self::client::AdminClient;
 use self::commands::snapshot::SnapshotCommand;
 use self::commands::user::UserCommand;
 
@@ -14,6 +15,14 @@ pub struct KvCtl {
     #[arg(short, long, default_value = "http://[::1]:9090")]
     server: String,
 
+    /// REST API address, for admin commands that need an authenticated caller
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    http: String,
+
+    /// API key (`<key_id>.<secret>`), sent to both the gRPC and REST APIs
+    #[arg(long)]
+    api_key: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -26,20 +35,40 @@ pub enum Commands {
     /// Inspect WAL
     Wal(commands::wal::WalArgs),
 
+    /// Load keys from a JSON-lines file
+    Load(commands::load::LoadArgs),
+
     /// Manage snapshots
+    #[command(subcommand)]
     Snapshot(SnapshotCommand),
 
+    /// Drop expired entries from a snapshot (offline)
+    Vacuum(commands::vacuum::VacuumArgs),
+
     /// Manage users
+    #[command(subcommand)]
     User(UserCommand),
+
+    /// Query the audit log (offline)
+    Audit(commands::audit::AuditArgs),
 }
 
 impl KvCtl {
     pub async fn run(self) -> Result<(), crate::ctl::types::KvCtlError> {
         match self.command {
-            Commands::Keys(args) => commands::keys::run(args).await,
+            Commands::Keys(args) => {
+                commands::keys::run(args, &self.server, self.api_key.as_deref()).await
+            }
             Commands::Wal(args) => commands::wal::run(args).await,
-            Commands::Snapshot(cmd) => commands::snapshot::run(cmd).await,
+            Commands::Load(args) => {
+                commands::load::run(args, &self.server, self.api_key.as_deref()).await
+            }
+            Commands::Snapshot(cmd) => {
+                commands::snapshot::run(cmd, AdminClient::new(self.http, self.api_key)).await
+            }
+            Commands::Vacuum(args) => commands::vacuum::run(args).await,
             Commands::User(cmd) => commands::user::run(cmd).await,
+            Commands::Audit(args) => commands::audit::run(args).await,
         }
     }
 }
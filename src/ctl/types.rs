@@ -6,7 +6,13 @@ pub enum KvCtlError {
     Grpc(#[from] tonic::transport::Error),
 
     #[error("RPC error: {0}")]
-    Rpc(#[from] tonic::Status),
+    Rpc(Box<tonic::Status>), // boxed: a `Status` is larger than every other variant
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Server returned {status}: {message}")]
+    Server { status: u16, message: String },
 
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
@@ -16,6 +22,18 @@ pub enum KvCtlError {
 
     #[error("Invalid argument: {0}")]
     InvalidArgument(String),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] crate::storage::error::StorageError),
+
+    #[error("WAL error: {0}")]
+    Wal(#[from] crate::wal::error::WalError),
+}
+
+impl From<tonic::Status> for KvCtlError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Rpc(Box::new(status))
+    }
 }
 
 use serde::{Deserialize, Serialize};
@@ -70,9 +88,11 @@ pub struct IncrResponse {
 
 #[derive(Deserialize)]
 pub struct ScanParams {
-    pub pattern: String,
+    pub pattern: String, // glob: `*` and `?`
     #[serde(default = "default_limit")]
     pub limit: u64,
+    #[serde(default)]
+    pub cursor: Option<String>, // `next_cursor` of the previous page
 }
 
 fn default_limit() -> u64 {
@@ -88,6 +108,8 @@ pub struct ScanItem {
 
 #[derive(Serialize)]
 pub struct ScanResponse {
-    pub items: Vec<ScanItem>,
+    pub items: Vec<ScanItem>, // empty (not an error) when nothing matches
     pub has_more: bool,
+    pub scanned: u64, // keys examined to produce this page
+    pub next_cursor: Option<String>,
 }
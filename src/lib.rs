@@ -1,11 +1,14 @@
 // src/lib.rs
 // Required so benches can reference crate modules
 pub mod api;
+pub mod app;
 pub mod auth;
 pub mod background;
 pub mod catalog;
 pub mod config;
 pub mod connection;
 pub mod ctl;
+pub mod preflight;
+pub mod server;
 pub mod storage;
 pub mod wal;
@@ -0,0 +1,141 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::config::AppConfig;
+
+const PROBE_FILE: &str = ".preflight_probe";
+
+#[derive(Error, Debug)]
+pub enum PreflightError {
+    #[error("Data directory {dir} is not writable: {source}")]
+    NotWritable {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Data directory {dir} has {available} bytes free, at least {required} required")]
+    InsufficientSpace {
+        dir: PathBuf,
+        available: u64,
+        required: u64,
+    },
+}
+
+/// Startup self-check: creates the WAL and snapshot directories, proves each
+/// one accepts a durable (fsynced) write and has enough free space. Run before
+/// any subsystem opens files so misconfigured storage fails at boot.
+pub fn run(config: &AppConfig) -> Result<(), PreflightError> {
+    let dirs = [config.wal.dir.as_str(), config.storage.snapshot_dir.as_str()];
+
+    for dir in dirs {
+        let dir = Path::new(dir);
+        probe_writable(dir).map_err(|source| PreflightError::NotWritable {
+            dir: dir.to_path_buf(),
+            source,
+        })?;
+
+        let available = fs2::available_space(dir).map_err(|source| {
+            PreflightError::NotWritable {
+                dir: dir.to_path_buf(),
+                source,
+            }
+        })?;
+        if available < config.preflight.min_free_bytes {
+            return Err(PreflightError::InsufficientSpace {
+                dir: dir.to_path_buf(),
+                available,
+                required: config.preflight.min_free_bytes,
+            });
+        }
+
+        tracing::info!(dir = %dir.display(), available = available, "Preflight check passed");
+    }
+
+    Ok(())
+}
+
+fn probe_writable(dir: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dir)?;
+
+    let path = dir.join(PROBE_FILE);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)?;
+    file.write_all(b"kvstore preflight")?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::remove_file(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackgroundConfig, PreflightConfig};
+    use crate::storage::StorageConfig;
+    use crate::wal::config::WalConfig;
+
+    fn config_for(dir: &Path, min_free_bytes: u64) -> AppConfig {
+        AppConfig {
+            storage: StorageConfig {
+                snapshot_dir: dir.join("snapshots").to_str().unwrap().to_string(),
+                ..Default::default()
+            },
+            wal: WalConfig {
+                dir: dir.join("wal").to_str().unwrap().to_string(),
+                ..Default::default()
+            },
+            background: BackgroundConfig {
+                checkpoint_interval_sec: 60,
+                metrics_interval_ms: 1000,
+                s3: None,
+                replica: None,
+                shutdown_phase_timeout_ms: 1000,
+                snapshot_retention: Default::default(),
+                checkpoint_sync: true,
+            },
+            preflight: PreflightConfig { min_free_bytes },
+            auth: Default::default(),
+            metrics: Default::default(),
+            connection: Default::default(),
+            encryption: None,
+            rate_limit: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_preflight_passes_on_writable_dirs() {
+        let dir = std::env::temp_dir().join(format!("kv_preflight_ok_{}", uuid::Uuid::new_v4()));
+        run(&config_for(&dir, 0)).unwrap();
+        assert!(!dir.join("wal").join(PROBE_FILE).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_preflight_rejects_read_only_location() {
+        // A path below a regular file can never be created, even as root
+        let blocker = std::env::temp_dir().join(format!("kv_preflight_ro_{}", uuid::Uuid::new_v4()));
+        std::fs::write(&blocker, b"not a directory").unwrap();
+
+        let err = run(&config_for(&blocker, 0)).unwrap_err();
+        assert!(matches!(err, PreflightError::NotWritable { .. }));
+        let message = err.to_string();
+        assert!(message.contains("is not writable"), "{}", message);
+        assert!(message.contains(blocker.to_str().unwrap()), "{}", message);
+
+        std::fs::remove_file(&blocker).ok();
+    }
+
+    #[test]
+    fn test_preflight_rejects_insufficient_space() {
+        let dir = std::env::temp_dir().join(format!("kv_preflight_full_{}", uuid::Uuid::new_v4()));
+        let err = run(&config_for(&dir, u64::MAX)).unwrap_err();
+        assert!(matches!(err, PreflightError::InsufficientSpace { .. }));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
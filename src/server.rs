@@ -1,32 +1,263 @@
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
-use tracing::info;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
+/// What ended [`ServerHandle::wait_for_shutdown`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Operator-requested stop (`SIGINT`/`SIGTERM`).
+    Signal(&'static str),
+    /// [`ServerHandle::shutdown_token`] was cancelled from inside the process.
+    Requested,
+    /// An API server task returned or panicked while the process was serving.
+    ServerExited { server: &'static str, error: String },
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::Signal(name) => write!(f, "received {}", name),
+            ShutdownReason::Requested => write!(f, "shutdown requested"),
+            ShutdownReason::ServerExited { server, error } => {
+                write!(f, "{} server exited: {}", server, error)
+            }
+        }
+    }
+}
+
+/// Owns the API servers and background workers for the life of the process.
+///
+/// The REST and gRPC servers should be started with
+/// `shutdown.clone().cancelled_owned()` as their shutdown signal so they stop
+/// accepting connections and drain in-flight requests once it is cancelled.
 pub struct ServerHandle {
-    rest_handle: tokio::task::JoinHandle<()>,
-    grpc_handle: tokio::task::JoinHandle<()>,
+    rest_handle: JoinHandle<std::io::Result<()>>,
+    grpc_handle: JoinHandle<Result<(), tonic::transport::Error>>,
     background_workers: crate::background::WorkerManager,
+    auth_manager: Arc<crate::auth::AuthManager>,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
 }
 
 impl ServerHandle {
-    pub async fn wait_for_shutdown(self) {
-        // Wait for Ctrl+C or SIGTERM
-        tokio::select! {
-            _ = signal::ctrl_c() => {
-                info!("Received Ctrl+C, shutting down...");
+    pub fn new(
+        rest_handle: JoinHandle<std::io::Result<()>>,
+        grpc_handle: JoinHandle<Result<(), tonic::transport::Error>>,
+        background_workers: crate::background::WorkerManager,
+        auth_manager: Arc<crate::auth::AuthManager>,
+        shutdown: CancellationToken,
+        drain_timeout: Duration,
+    ) -> Self {
+        Self {
+            rest_handle,
+            grpc_handle,
+            background_workers,
+            auth_manager,
+            shutdown,
+            drain_timeout,
+        }
+    }
+
+    /// Cancelling this token shuts the server down as if it got `SIGTERM`.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Serves until a signal, a cancelled [`shutdown_token`](Self::shutdown_token)
+    /// or a dead API server, then:
+    ///
+    /// 1. stops the API servers accepting work and waits up to `drain_timeout`
+    ///    for in-flight requests (servers still running after that are aborted),
+    /// 2. shuts down the background workers, which syncs the WAL and takes a
+    ///    final checkpoint,
+    /// 3. flushes the audit log.
+    pub async fn wait_for_shutdown(mut self) -> ShutdownReason {
+        let mut rest_done = false;
+        let mut grpc_done = false;
+
+        // Wait for Ctrl+C or SIGTERM, or for an API server to die underneath us
+        let reason = tokio::select! {
+            _ = signal::ctrl_c() => ShutdownReason::Signal("SIGINT"),
+            _ = terminate() => ShutdownReason::Signal("SIGTERM"),
+            _ = self.shutdown.cancelled() => ShutdownReason::Requested,
+            res = &mut self.rest_handle => {
+                rest_done = true;
+                server_exited("REST", res.map(|r| r.map_err(|e| e.to_string())))
             }
-            _ = signal::unix::signal(signal::unix::SignalKind::terminate()) => {
-                info!("Received SIGTERM, shutting down...");
+            res = &mut self.grpc_handle => {
+                grpc_done = true;
+                server_exited("gRPC", res.map(|r| r.map_err(|e| e.to_string())))
             }
+        };
+        match &reason {
+            ShutdownReason::ServerExited { .. } => error!("Shutting down: {}", reason),
+            _ => info!("Shutting down: {}", reason),
         }
 
-        // Shutdown background workers
-        self.background_workers.shutdown();
+        // Stop accepting work and let in-flight requests finish
+        self.shutdown.cancel();
+        if !rest_done {
+            drain("REST", &mut self.rest_handle, self.drain_timeout).await;
+        }
+        if !grpc_done {
+            drain("gRPC", &mut self.grpc_handle, self.drain_timeout).await;
+        }
 
-        // Wait for API servers to shutdown (they don't yet — we’ll add shutdown later)
-        let _ = self.rest_handle.await;
-        let _ = self.grpc_handle.await;
+        // Drain checkpoints, sync the WAL, take a final checkpoint, then stop workers
+        self.background_workers.shutdown().await;
+
+        // Buffered audit events must not be lost on a clean exit
+        if let Err(e) = self.auth_manager.flush_audit() {
+            warn!("Failed to flush audit log: {}", e);
+        }
 
         info!("Server shutdown complete.");
+        reason
+    }
+}
+
+async fn drain<T>(server: &'static str, handle: &mut JoinHandle<T>, limit: Duration) {
+    if timeout(limit, &mut *handle).await.is_err() {
+        warn!(
+            "{} server did not drain within {:?}, aborting in-flight requests",
+            server, limit
+        );
+        handle.abort();
+    } else {
+        info!("{} server drained", server);
+    }
+}
+
+async fn terminate() {
+    match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(e) => {
+            warn!("Cannot listen for SIGTERM: {}", e);
+            std::future::pending::<()>().await;
+        }
     }
-}
\ No newline at end of file
+}
+
+fn server_exited(
+    server: &'static str,
+    result: Result<Result<(), String>, tokio::task::JoinError>,
+) -> ShutdownReason {
+    let error = match result {
+        Ok(Ok(())) => "stopped unexpectedly".to_string(),
+        Ok(Err(e)) => e,
+        Err(e) => e.to_string(),
+    };
+    ShutdownReason::ServerExited { server, error }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackgroundConfig;
+    use crate::storage::{StorageConfig, StorageEngine};
+    use crate::wal::config::{SyncPolicy, WalConfig};
+    use crate::wal::entry::{OpType, WalEntry};
+    use crate::wal::WalManager;
+
+    #[tokio::test]
+    async fn test_requested_shutdown_drains_syncs_and_checkpoints() {
+        let dir = std::env::temp_dir().join(format!("server_shutdown_{}", uuid::Uuid::new_v4()));
+        let snapshot_dir = dir.join("snapshots").to_str().unwrap().to_string();
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            snapshot_dir: snapshot_dir.clone(),
+            ..Default::default()
+        })
+        .await;
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let workers = crate::background::WorkerManager::new(
+            engine.clone(),
+            wal.clone(),
+            snapshot_dir.clone(),
+            &BackgroundConfig {
+                checkpoint_interval_sec: 3600,
+                metrics_interval_ms: 1000,
+                s3: None,
+                replica: None,
+                shutdown_phase_timeout_ms: 5000,
+                snapshot_retention: Default::default(),
+                checkpoint_sync: true,
+            },
+        )
+        .await
+        .unwrap();
+        let auth = Arc::new(
+            crate::auth::AuthManager::new(
+                Arc::new(crate::catalog::CatalogManager::new(engine.clone())),
+                "secret".to_string(),
+                dir.join("audit.log").to_str().unwrap().to_string(),
+                &Default::default(),
+            )
+            .unwrap(),
+        );
+
+        // Stand-ins for the API servers that stop once shutdown begins
+        let shutdown = CancellationToken::new();
+        let rest_signal = shutdown.clone().cancelled_owned();
+        let grpc_signal = shutdown.clone().cancelled_owned();
+        let rest_handle = tokio::spawn(async move {
+            rest_signal.await;
+            Ok(())
+        });
+        let grpc_handle = tokio::spawn(async move {
+            grpc_signal.await;
+            Ok(())
+        });
+        let handle = ServerHandle::new(
+            rest_handle,
+            grpc_handle,
+            workers,
+            auth,
+            shutdown,
+            Duration::from_secs(5),
+        );
+
+        engine.set("last", b"write".to_vec(), None).await.unwrap();
+        wal.append(&WalEntry {
+            timestamp: 1,
+            key: "last".to_string(),
+            value: b"write".to_vec(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Set,
+            compression: None,
+            delta: None,
+        })
+        .await
+        .unwrap();
+        assert!(wal.synced_offset() < wal.current_offset().await);
+
+        handle.shutdown_token().cancel();
+        assert_eq!(handle.wait_for_shutdown().await, ShutdownReason::Requested);
+
+        let end = wal.current_offset().await;
+        assert_eq!(wal.synced_offset(), end);
+        let control = crate::storage::ControlFile::read(&snapshot_dir)
+            .unwrap()
+            .unwrap();
+        assert_eq!(control.wal_offset, end);
+        assert!(std::fs::read_dir(&snapshot_dir).unwrap().count() > 0);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
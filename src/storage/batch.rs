@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::storage::error::StorageError;
+use crate::storage::types::DuplicateKeyPolicy;
+
+/// Applies `policy` to a multi-key batch (MSET, txn). With `LastWins` each
+/// key keeps only its final occurrence, ordered by where that occurrence
+/// sits in the batch; with `Reject` the first repeated key fails the batch.
+pub fn resolve_duplicates<T>(
+    items: Vec<(String, T)>,
+    policy: DuplicateKeyPolicy,
+) -> Result<Vec<(String, T)>, StorageError> {
+    let mut last_seen: HashMap<&str, usize> = HashMap::with_capacity(items.len());
+    for (pos, (key, _)) in items.iter().enumerate() {
+        if last_seen.insert(key.as_str(), pos).is_some() && policy == DuplicateKeyPolicy::Reject {
+            return Err(StorageError::InvalidRequest(format!(
+                "duplicate key in batch: {}",
+                key
+            )));
+        }
+    }
+
+    if last_seen.len() == items.len() {
+        return Ok(items);
+    }
+
+    let keep: Vec<bool> = {
+        let mut keep = vec![false; items.len()];
+        for pos in last_seen.values() {
+            keep[*pos] = true;
+        }
+        keep
+    };
+    Ok(items
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(item, keep)| keep.then_some(item))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{StorageConfig, StorageEngine};
+
+    fn batch() -> Vec<(String, Vec<u8>, Option<u64>)> {
+        vec![
+            ("a".to_string(), b"1".to_vec(), None),
+            ("b".to_string(), b"2".to_vec(), None),
+            ("a".to_string(), b"3".to_vec(), None),
+        ]
+    }
+
+    async fn engine(policy: DuplicateKeyPolicy) -> std::sync::Arc<StorageEngine> {
+        StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            duplicate_keys: policy,
+            ..Default::default()
+        })
+        .await
+    }
+
+    #[test]
+    fn test_resolve_keeps_final_occurrence_in_order() {
+        let items = vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 2),
+            ("a".to_string(), 3),
+            ("c".to_string(), 4),
+        ];
+        let resolved = resolve_duplicates(items, DuplicateKeyPolicy::LastWins).unwrap();
+        assert_eq!(
+            resolved,
+            vec![
+                ("b".to_string(), 2),
+                ("a".to_string(), 3),
+                ("c".to_string(), 4)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mset_last_wins() {
+        let engine = engine(DuplicateKeyPolicy::LastWins).await;
+        engine.mset(batch()).await.unwrap();
+
+        assert_eq!(engine.get("a").await.unwrap().value, b"3");
+        assert_eq!(engine.get("b").await.unwrap().value, b"2");
+    }
+
+    #[tokio::test]
+    async fn test_mset_reject_names_duplicate() {
+        let engine = engine(DuplicateKeyPolicy::Reject).await;
+        let result = engine.mset(batch()).await;
+
+        match result {
+            Err(StorageError::InvalidRequest(msg)) => assert!(msg.ends_with(": a")),
+            other => panic!("expected InvalidRequest, got {:?}", other),
+        }
+        // Nothing from the rejected batch was written
+        assert!(engine.get("b").await.is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use futures_util::Stream;
+
+use crate::storage::engine::StorageEngine;
+use crate::storage::error::StorageError;
+use crate::storage::snapshot::SnapshotManager;
+use crate::storage::types::BulkEntry;
+use crate::wal::entry::{OpType, WalEntry};
+use crate::wal::WalManager;
+
+#[derive(Debug, Clone)]
+pub struct BulkLoadReport {
+    pub loaded: u64,
+    pub snapshot: String,
+    pub checkpoint_offset: u64, // WAL offset of the checkpoint marker
+}
+
+/// Fast path for initial data loads: entries skip the WAL entirely, then one
+/// snapshot and a single WAL checkpoint marker are written at the end.
+///
+/// The load is not crash-safe. If the process dies before the snapshot is
+/// written, nothing loaded so far survives a restart and the load must be
+/// started over from the beginning.
+pub async fn fast_load<S>(
+    engine: &StorageEngine,
+    snapshots: &SnapshotManager,
+    wal: &WalManager,
+    entries: S,
+) -> Result<BulkLoadReport, StorageError>
+where
+    S: Stream<Item = BulkEntry> + Unpin,
+{
+    let loaded = engine.bulk_load(entries).await?;
+    let snapshot = snapshots.create_snapshot(engine).await?;
+
+    let marker = WalEntry {
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64,
+        key: snapshot.clone(),
+        value: Vec::new(),
+        version: 0,
+        ttl: None,
+        op_type: OpType::Checkpoint,
+        compression: None,
+        delta: None,
+    };
+    let checkpoint_offset = wal.append(&marker).await?;
+    wal.sync().await?;
+
+    tracing::info!(loaded = loaded, snapshot = %snapshot, "Fast load complete");
+
+    Ok(BulkLoadReport {
+        loaded,
+        snapshot,
+        checkpoint_offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+    use crate::wal::config::{SyncPolicy, WalConfig};
+    use tokio_stream::wrappers::ReceiverStream;
+
+    fn entry(i: u64) -> BulkEntry {
+        BulkEntry {
+            key: format!("bulk:{:05}", i),
+            value: format!("value_{}", i).into_bytes(),
+            ttl_secs: None,
+        }
+    }
+
+    fn storage_config() -> StorageConfig {
+        StorageConfig {
+            num_shards: 8,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fast_load_snapshot_recovers() {
+        let dir = std::env::temp_dir().join(format!("bulk_{}", uuid::Uuid::new_v4()));
+        let snapshot_dir = dir.join("snapshots").to_str().unwrap().to_string();
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let snapshots = SnapshotManager::new(snapshot_dir.clone());
+
+        let engine = StorageEngine::new(storage_config()).await;
+        let entries = futures_util::stream::iter((0..10_000).map(entry));
+        let report = fast_load(&engine, &snapshots, &wal, entries).await.unwrap();
+        assert_eq!(report.loaded, 10_000);
+        assert!(!engine.is_bulk_loading());
+
+        // The WAL holds only the checkpoint marker
+        let mut logged = Vec::new();
+        wal.replay_from(0, |_, e| {
+            logged.push(e);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].op_type, OpType::Checkpoint);
+        assert_eq!(logged[0].key, report.snapshot);
+
+        // A fresh node recovers the same state from the snapshot
+        let recovered = StorageEngine::new(storage_config()).await;
+        SnapshotManager::new(snapshot_dir)
+            .load_snapshot(&recovered, &report.snapshot)
+            .await
+            .unwrap();
+        let project = |dump: Vec<(String, crate::storage::KvEntry)>| {
+            dump.into_iter()
+                .map(|(k, e)| (k, e.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            project(recovered.dump_sorted()),
+            project(engine.dump_sorted())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bulk_load_rejects_other_writes() {
+        let engine = StorageEngine::new(storage_config()).await;
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+        let loader = {
+            let engine = engine.clone();
+            tokio::spawn(async move { engine.bulk_load(ReceiverStream::new(rx)).await })
+        };
+        tx.send(entry(1)).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        assert!(engine.is_bulk_loading());
+        let result = engine.set("other", b"v".to_vec(), None).await;
+        assert!(matches!(result, Err(StorageError::BulkLoadInProgress)));
+        let result = engine
+            .bulk_load(futures_util::stream::iter(vec![entry(2)]))
+            .await;
+        assert!(matches!(result, Err(StorageError::BulkLoadInProgress)));
+
+        drop(tx);
+        assert_eq!(loader.await.unwrap().unwrap(), 1);
+        engine.set("other", b"v".to_vec(), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_bulk_load_lets_writes_back_in() {
+        let engine = StorageEngine::new(storage_config()).await;
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+        let loader = {
+            let engine = engine.clone();
+            tokio::spawn(async move { engine.bulk_load(ReceiverStream::new(rx)).await })
+        };
+        tx.send(entry(1)).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(engine.is_bulk_loading());
+
+        loader.abort();
+        assert!(loader.await.unwrap_err().is_cancelled());
+        assert!(!engine.is_bulk_loading());
+        engine.set("other", b"v".to_vec(), None).await.unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::error::StorageError;
+
+// zstd's default; the ratio gained above it rarely pays for the CPU
+const ZSTD_LEVEL: i32 = 3;
+
+/// Codec a stored value was compressed with. Also the codec byte in WAL
+/// entries, so the discriminants must not change.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgo {
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl CompressionAlgo {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(CompressionAlgo::Lz4),
+            2 => Some(CompressionAlgo::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Global value compression: values longer than `min_size` bytes are stored
+/// compressed with `algo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct CompressionConfig {
+    pub algo: CompressionAlgo,
+    pub min_size: usize,
+}
+
+/// Per-write choice of compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionMode {
+    /// Whatever `StorageConfig::compression` says
+    #[default]
+    Auto,
+    /// Stored as-is
+    None,
+    /// Compressed with lz4, whatever the value's size
+    Lz4,
+    /// Compressed with zstd, whatever the value's size
+    Zstd,
+}
+
+/// `value` compressed with `algo`, or `None` when that would not make it
+/// any smaller (already-compressed media, tiny values).
+pub fn compress(algo: CompressionAlgo, value: &[u8]) -> Option<Vec<u8>> {
+    let compressed = match algo {
+        CompressionAlgo::Lz4 => lz4_flex::compress_prepend_size(value),
+        CompressionAlgo::Zstd => zstd::bulk::compress(value, ZSTD_LEVEL).ok()?,
+    };
+    (compressed.len() < value.len()).then_some(compressed)
+}
+
+pub fn decompress(algo: CompressionAlgo, value: &[u8]) -> Result<Vec<u8>, StorageError> {
+    match algo {
+        CompressionAlgo::Lz4 => lz4_flex::decompress_size_prepended(value)
+            .map_err(|e| StorageError::Compression(e.to_string())),
+        CompressionAlgo::Zstd => {
+            zstd::stream::decode_all(value).map_err(|e| StorageError::Compression(e.to_string()))
+        }
+    }
+}
+
+/// The plain bytes of a value stored with `algo` (`None` = uncompressed).
+pub fn plain(algo: Option<CompressionAlgo>, value: &[u8]) -> Result<Cow<'_, [u8]>, StorageError> {
+    match algo {
+        Some(algo) => decompress(algo, value).map(Cow::Owned),
+        None => Ok(Cow::Borrowed(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{StorageConfig, StorageEngine};
+    use std::time::Instant;
+
+    // Roughly what a JSON document store holds: repetitive field names,
+    // varying numbers
+    fn document(i: usize, len: usize) -> Vec<u8> {
+        let mut doc = String::with_capacity(len + 64);
+        let mut n = i;
+        while doc.len() < len {
+            n = n
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            doc.push_str(&format!(
+                "{{\"id\":{},\"score\":{},\"status\":\"active\",\"tags\":[\"a\",\"b\"]}},",
+                n % 100_000,
+                (n >> 20) % 1000
+            ));
+        }
+        doc.truncate(len);
+        doc.into_bytes()
+    }
+
+    #[test]
+    fn test_round_trip_both_codecs() {
+        let value = document(7, 4096);
+        for algo in [CompressionAlgo::Lz4, CompressionAlgo::Zstd] {
+            let compressed = compress(algo, &value).unwrap();
+            assert!(compressed.len() < value.len());
+            assert_eq!(decompress(algo, &compressed).unwrap(), value);
+            assert_eq!(CompressionAlgo::from_u8(algo.as_u8()), Some(algo));
+        }
+        // Incompressible input is left alone
+        assert!(compress(CompressionAlgo::Zstd, b"abc").is_none());
+    }
+
+    // Benchmark-style: `cargo test --release zstd_4kb -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn test_zstd_4kb_throughput_and_size() {
+        const KEYS: usize = 20_000;
+        const VALUE_SIZE: usize = 4096;
+
+        async fn run(compression: Option<CompressionConfig>) -> (f64, f64, u64) {
+            let engine = StorageEngine::new(StorageConfig {
+                num_shards: 16,
+                compression,
+                ..Default::default()
+            })
+            .await;
+            let values: Vec<Vec<u8>> = (0..KEYS).map(|i| document(i, VALUE_SIZE)).collect();
+
+            let start = Instant::now();
+            for (i, value) in values.iter().enumerate() {
+                engine
+                    .set(&format!("doc:{}", i), value.clone(), None)
+                    .await
+                    .unwrap();
+            }
+            let sets = KEYS as f64 / start.elapsed().as_secs_f64();
+
+            let start = Instant::now();
+            for (i, value) in values.iter().enumerate() {
+                let entry = engine.get(&format!("doc:{}", i)).await.unwrap();
+                assert_eq!(&entry.value, value);
+            }
+            let gets = KEYS as f64 / start.elapsed().as_secs_f64();
+
+            (sets, gets, engine.memory_usage())
+        }
+
+        let (plain_sets, plain_gets, plain_bytes) = run(None).await;
+        let (zstd_sets, zstd_gets, zstd_bytes) = run(Some(CompressionConfig {
+            algo: CompressionAlgo::Zstd,
+            min_size: 1024,
+        }))
+        .await;
+        println!(
+            "{} x {}B values: plain {:.0} set/s {:.0} get/s {} bytes; zstd {:.0} set/s {:.0} get/s {} bytes ({:.1}% smaller)",
+            KEYS,
+            VALUE_SIZE,
+            plain_sets,
+            plain_gets,
+            plain_bytes,
+            zstd_sets,
+            zstd_gets,
+            zstd_bytes,
+            100.0 * (1.0 - zstd_bytes as f64 / plain_bytes as f64)
+        );
+        assert!(zstd_bytes < plain_bytes);
+    }
+}
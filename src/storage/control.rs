@@ -0,0 +1,138 @@
+use crc32fast::Hasher;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::storage::error::StorageError;
+
+/// Name of the control file, kept in the snapshot directory.
+pub const CONTROL_FILE: &str = "control";
+
+// The file is this magic, the format version (u32 LE), the bincoded
+// `ControlFile` and a CRC32 (u32 LE) of everything before it. Bump the
+// version whenever the body changes shape.
+const CONTROL_MAGIC: &[u8; 4] = b"KVCT";
+const CONTROL_VERSION: u32 = 1;
+
+/// The last completed checkpoint: the snapshot it wrote and the WAL offset
+/// recovery replays from. Every WAL entry before `wal_offset` was fsynced
+/// before the snapshot was taken, and is in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlFile {
+    pub snapshot: String,
+    pub wal_offset: u64,
+}
+
+impl ControlFile {
+    /// The control file in `dir`, or `None` before the first checkpoint.
+    /// A file that is truncated, fails its checksum or has an unknown
+    /// version is `InvalidSnapshot`.
+    pub fn read(dir: impl AsRef<Path>) -> Result<Option<Self>, StorageError> {
+        let bytes = match std::fs::read(dir.as_ref().join(CONTROL_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Self::decode(&bytes).map(Some)
+    }
+
+    /// Replaces the control file in `dir`. It is written to a temporary file,
+    /// fsynced and renamed over the old one, so a crash leaves one or the
+    /// other, never a mix.
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<(), StorageError> {
+        let dir = dir.as_ref();
+        let tmp = dir.join(format!("{}.tmp", CONTROL_FILE));
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&self.encode()?)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, dir.join(CONTROL_FILE))?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, StorageError> {
+        let mut bytes = CONTROL_MAGIC.to_vec();
+        bytes.extend_from_slice(&CONTROL_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&bincode::serialize(self)?);
+        let crc = checksum(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, StorageError> {
+        let invalid =
+            |reason: &str| StorageError::InvalidSnapshot(format!("control file {}", reason));
+        if bytes.len() < CONTROL_MAGIC.len() + 8 || !bytes.starts_with(CONTROL_MAGIC) {
+            return Err(invalid("has no header"));
+        }
+        let (body, crc) = bytes.split_at(bytes.len() - 4);
+        if checksum(body).to_le_bytes() != crc {
+            return Err(invalid("fails its checksum"));
+        }
+        let version = u32::from_le_bytes(body[4..8].try_into().unwrap());
+        if version != CONTROL_VERSION {
+            return Err(invalid(&format!("has unknown version {}", version)));
+        }
+        Ok(bincode::deserialize(&body[8..])?)
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_write_replaces_atomically() {
+        let dir = std::env::temp_dir().join(format!("control_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(ControlFile::read(&dir).unwrap(), None);
+
+        let first = ControlFile {
+            snapshot: "snapshot_1.bin".to_string(),
+            wal_offset: 10,
+        };
+        first.write(&dir).unwrap();
+
+        // A write that crashed before its rename leaves only the temp file
+        std::fs::write(dir.join(format!("{}.tmp", CONTROL_FILE)), b"half-written").unwrap();
+        assert_eq!(ControlFile::read(&dir).unwrap(), Some(first));
+
+        let second = ControlFile {
+            snapshot: "snapshot_2.bin".to_string(),
+            wal_offset: 20,
+        };
+        second.write(&dir).unwrap();
+        assert_eq!(ControlFile::read(&dir).unwrap(), Some(second));
+        assert!(!dir.join(format!("{}.tmp", CONTROL_FILE)).exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_control_rejects_damaged_files() {
+        let control = ControlFile {
+            snapshot: "snapshot_1.bin".to_string(),
+            wal_offset: 10,
+        };
+        let bytes = control.encode().unwrap();
+        assert_eq!(ControlFile::decode(&bytes).unwrap(), control);
+
+        let mut flipped = bytes.clone();
+        flipped[10] ^= 0xff;
+        for damaged in [&bytes[..bytes.len() - 1], &flipped[..], &b"KVCT"[..]] {
+            assert!(matches!(
+                ControlFile::decode(damaged),
+                Err(StorageError::InvalidSnapshot(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::io::{Read, Write};
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const NONCE_LEN: usize = 12;
+
+/// Length of [`Cipher::key_id`].
+pub const KEY_ID_LEN: usize = 8;
+
+// Plaintext bytes sealed per record by `EncryptingWriter`
+const CHUNK_LEN: usize = 1 << 20;
+
+/// Encryption at rest for WAL segments and snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct EncryptionConfig {
+    /// File holding the 32-byte key, raw or as 64 hex digits.
+    pub key_file: String,
+}
+
+#[derive(Error, Debug)]
+pub enum EncryptionError {
+    #[error("Cannot use encryption key {path}: {reason}")]
+    KeyFile { path: String, reason: String },
+
+    #[error("{0} is encrypted, but no encryption key is configured")]
+    KeyMissing(String),
+
+    #[error("{0} was encrypted with a different key")]
+    WrongKey(String),
+}
+
+/// ChaCha20-Poly1305 under one key. Every sealed record gets a fresh random
+/// nonce, stored in front of its ciphertext.
+#[derive(Clone)]
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+    key_id: [u8; KEY_ID_LEN],
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cipher")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    pub fn new(key: [u8; 32]) -> Self {
+        // Files record which key sealed them, so a wrong key is reported as
+        // such rather than as corruption. Hashed, not the key itself
+        let digest = Sha256::new()
+            .chain_update(b"kvstore encryption key id")
+            .chain_update(key)
+            .finalize();
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            key_id: digest[..KEY_ID_LEN].try_into().unwrap(),
+        }
+    }
+
+    pub fn load(config: &EncryptionConfig) -> Result<Self, EncryptionError> {
+        let error = |reason: String| EncryptionError::KeyFile {
+            path: config.key_file.clone(),
+            reason,
+        };
+        let bytes = std::fs::read(&config.key_file).map_err(|e| error(e.to_string()))?;
+        let key = match bytes.len() {
+            32 => bytes,
+            _ => decode_hex(std::str::from_utf8(&bytes).unwrap_or_default().trim())
+                .ok_or_else(|| error("expected 32 bytes, or 64 hex digits".to_string()))?,
+        };
+        Ok(Self::new(key.try_into().unwrap()))
+    }
+
+    pub fn key_id(&self) -> [u8; KEY_ID_LEN] {
+        self.key_id
+    }
+
+    /// Fails with [`EncryptionError::WrongKey`] unless `key_id`, read from
+    /// `what`, names this cipher's key.
+    pub fn check_key_id(&self, key_id: &[u8], what: &str) -> Result<(), EncryptionError> {
+        if key_id != self.key_id {
+            return Err(EncryptionError::WrongKey(what.to_string()));
+        }
+        Ok(())
+    }
+
+    /// `plaintext` encrypted and authenticated together with `aad`, which
+    /// has to be passed to [`open`](Self::open) again: nonce, then ciphertext.
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .aead
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("ChaCha20-Poly1305 encrypts any message that fits in memory");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// The plaintext of a [`seal`](Self::seal)ed record, or `None` if it was
+    /// altered (or sealed under other `aad`).
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Encrypts a byte stream as a run of sealed records, each a u32 LE length
+/// and up to 1 MiB of [`Cipher::seal`]ed plaintext. Records are bound to
+/// their position, so they cannot be reordered or dropped from the middle.
+/// Call [`finish`](Self::finish) to write the last one.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: Cipher,
+    pending: Vec<u8>,
+    index: u64,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(inner: W, cipher: Cipher) -> Self {
+        Self {
+            inner,
+            cipher,
+            pending: Vec::new(),
+            index: 0,
+        }
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.seal_pending()?;
+        Ok(self.inner)
+    }
+
+    fn seal_pending(&mut self) -> std::io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let sealed = self.cipher.seal(&self.pending, &self.index.to_le_bytes());
+        self.inner.write_all(&(sealed.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&sealed)?;
+        self.pending.clear();
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let taken = buf.len().min(CHUNK_LEN - self.pending.len());
+        self.pending.extend_from_slice(&buf[..taken]);
+        if self.pending.len() == CHUNK_LEN {
+            self.seal_pending()?;
+        }
+        Ok(taken)
+    }
+
+    // Records are only cut at full chunks or `finish`, so this just passes on
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads back what an [`EncryptingWriter`] wrote. A record that fails to
+/// open is an `InvalidData` error.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: Cipher,
+    plain: Vec<u8>,
+    pos: usize,
+    index: u64,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(inner: R, cipher: Cipher) -> Self {
+        Self {
+            inner,
+            cipher,
+            plain: Vec::new(),
+            pos: 0,
+            index: 0,
+        }
+    }
+
+    // Opens the next record; false at the end of the stream
+    fn next_record(&mut self) -> std::io::Result<bool> {
+        let mut len = [0u8; 4];
+        match self.inner.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        let mut sealed = Vec::new();
+        self.inner
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut sealed)?;
+        if sealed.len() != len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.plain = self
+            .cipher
+            .open(&sealed, &self.index.to_le_bytes())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("encrypted record {} is corrupt", self.index),
+                )
+            })?;
+        self.pos = 0;
+        self.index += 1;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if !self.next_record()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.plain.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_round_trip_and_tamper_detection() {
+        let cipher = Cipher::new([7; 32]);
+        let data: Vec<u8> = (0..3 * CHUNK_LEN + 123).map(|i| i as u8).collect();
+
+        let mut writer = EncryptingWriter::new(Vec::new(), cipher.clone());
+        writer.write_all(&data).unwrap();
+        let sealed = writer.finish().unwrap();
+        assert!(!sealed.windows(64).any(|w| w == &data[..64]));
+
+        let mut read = Vec::new();
+        DecryptingReader::new(&sealed[..], cipher.clone())
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+
+        let mut tampered = sealed.clone();
+        tampered[100] ^= 1;
+        let err = DecryptingReader::new(&tampered[..], cipher)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let other = Cipher::new([8; 32]);
+        assert!(other
+            .check_key_id(&Cipher::new([7; 32]).key_id(), "x")
+            .is_err());
+        assert!(other
+            .open(&Cipher::new([7; 32]).seal(b"hi", b""), b"")
+            .is_none());
+    }
+
+    #[test]
+    fn test_load_raw_and_hex_keys() {
+        let dir = std::env::temp_dir().join(format!("keys_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let raw = dir.join("raw.key");
+        let hex = dir.join("hex.key");
+        std::fs::write(&raw, [0xab; 32]).unwrap();
+        std::fs::write(&hex, format!("{}\n", "ab".repeat(32))).unwrap();
+
+        let load = |path: &std::path::Path| {
+            Cipher::load(&EncryptionConfig {
+                key_file: path.to_str().unwrap().to_string(),
+            })
+        };
+        assert_eq!(load(&raw).unwrap().key_id(), load(&hex).unwrap().key_id());
+        std::fs::write(&hex, "too short").unwrap();
+        assert!(matches!(load(&hex), Err(EncryptionError::KeyFile { .. })));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
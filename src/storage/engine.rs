@@ -1,215 +1,5197 @@
+use base64::Engine;
 use dashmap::DashMap;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use futures_util::{Stream, StreamExt};
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::OnceLock;
 use tokio::sync::RwLock as AsyncRwLock;
 
-use crate::storage::shard::Shard;
+use crate::storage::batch;
+use crate::storage::compression::{self, CompressionAlgo, CompressionConfig, CompressionMode};
+use crate::storage::filter::ValueFilter;
+use crate::storage::glob;
+use crate::storage::namespace::{self, ManagedMetadata, TagIndex};
+use crate::storage::optimistic::{ExecOutcome, KeyStamp, WatchSessions};
+use crate::storage::shard::{entry_size, Shard, ENTRY_OVERHEAD};
+use crate::storage::snapshot::SnapshotManager;
 use crate::storage::ttl::TtlManager;
-use crate::storage::types::KvEntry;
+use crate::storage::types::{
+    BulkEntry, DuplicateKeyPolicy, EvictionPolicy, ExpireCallback, KeyHasher, KvEntry,
+    NamespaceQuota, NamespaceUsage, NodeRole, Placement, RecoveryReport, ScanPage, ShardGroup,
+    ShardSkew, TxnOp, ValueSlice,
+};
+use crate::storage::watch::{ChangeNotifier, ChangeOp, ChangeSubscription, ExpiryReason};
 use crate::wal::entry::{OpType, WalEntry};
+use crate::wal::manager::split_offset;
+use crate::wal::{Durability, PendingAppend, WalManager, WalSlot};
 
 #[derive(Debug)]
 pub struct StorageEngine {
     pub shards: Vec<Arc<Shard>>,
+    groups: Vec<ShardGroup>, // groups[0] is the default group
+    maintenance: AtomicBool,
+    bulk_loading: AtomicBool,
+    replica: AtomicBool,
+    config_quotas: HashMap<String, NamespaceQuota>,
+    stored_quotas: RwLock<HashMap<String, NamespaceQuota>>, // from `_sys.quotas:<ns>`
+    usage: DashMap<String, NamespaceUsage>,
+    managed_namespaces: HashSet<String>,
+    tags: TagIndex,
+    duplicate_keys: DuplicateKeyPolicy,
+    max_batch_ops: usize,
+    max_key_bytes: usize,
+    max_value_bytes: usize,
+    max_memory_bytes: u64, // 0 = unbounded
+    eviction_policy: EvictionPolicy,
+    compression: Option<CompressionConfig>,
+    placement: Placement,
+    hasher: KeyHasher,
+    lru_clock: AtomicU64, // recency ticks, shared by every shard
+    evictions: Mutex<Option<Vec<(String, KvEntry)>>>, // kept while an `EvictionRecord` lives
+    write_gate: AsyncRwLock<()>, // shared by single-key writes, exclusive for atomic batches
+    on_expire: ExpireHook,
+    changes: ChangeNotifier,
+    watch_sessions: WatchSessions, // WATCH state for `exec`, per session
     ttl_manager: OnceLock<Arc<TtlManager>>, // We'll add metrics, last_wal_offset, etc. later
+    wal: OnceLock<Arc<WalManager>>,
+}
+
+#[derive(Default)]
+struct ExpireHook(RwLock<Option<ExpireCallback>>);
+
+impl std::fmt::Debug for ExpireHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ExpireHook")
+            .field(&self.0.read().is_some())
+            .finish()
+    }
 }
 
 impl StorageEngine {
+    /// Any shard count works: a key goes to the shard of its group that
+    /// `config.placement` picks. A
+    /// `num_shards` of 0 is clamped to 1 with a warning, as an empty shard
+    /// group is skipped; [`try_new`](Self::try_new) rejects it instead.
     pub async fn new(config: super::types::StorageConfig) -> Arc<Self> {
-        let shards: Vec<Arc<Shard>> = (0..config.num_shards)
-            .map(|_| Arc::new(Shard::new()))
-            .collect();
+        let num_shards = if config.num_shards == 0 {
+            tracing::warn!("num_shards is 0, using a single shard");
+            1
+        } else {
+            config.num_shards
+        };
+        let mut groups = vec![ShardGroup {
+            name: "default".to_string(),
+            prefix: String::new(),
+            start: 0,
+            len: num_shards,
+        }];
+        let mut next_start = num_shards;
+        for group in &config.shard_groups {
+            if group.num_shards == 0 {
+                tracing::warn!(group = %group.name, "Shard group has no shards, routing its keys to the default group");
+                continue;
+            }
+            groups.push(ShardGroup {
+                name: group.name.clone(),
+                prefix: group.prefix.trim_end_matches('*').to_string(),
+                start: next_start,
+                len: group.num_shards,
+            });
+            next_start += group.num_shards;
+        }
+
+        let shards: Vec<Arc<Shard>> = (0..next_start).map(|_| Arc::new(Shard::new())).collect();
 
         let engine = Arc::new(Self {
             shards,
+            groups,
+            maintenance: AtomicBool::new(false),
+            bulk_loading: AtomicBool::new(false),
+            replica: AtomicBool::new(false),
+            config_quotas: config.namespace_quotas,
+            stored_quotas: RwLock::new(HashMap::new()),
+            usage: DashMap::new(),
+            managed_namespaces: config.managed_namespaces.into_iter().collect(),
+            tags: TagIndex::default(),
+            duplicate_keys: config.duplicate_keys,
+            max_batch_ops: config.max_batch_ops,
+            max_key_bytes: config.max_key_bytes,
+            max_value_bytes: config.max_value_bytes,
+            max_memory_bytes: config.max_memory_bytes,
+            eviction_policy: config.eviction_policy,
+            compression: config.compression,
+            placement: config.placement,
+            hasher: KeyHasher::new(config.shard_hasher, config.shard_hash_seed),
+            lru_clock: AtomicU64::new(0),
+            evictions: Mutex::new(None),
+            write_gate: AsyncRwLock::new(()),
+            on_expire: ExpireHook::default(),
+            changes: ChangeNotifier::new(WATCH_BUFFER),
+            watch_sessions: WatchSessions::default(),
             ttl_manager: OnceLock::new(),
+            wal: OnceLock::new(),
         });
 
-        let ttl_manager = Arc::new(TtlManager::new(engine.clone()));
+        let ttl_manager = Arc::new(TtlManager::new(engine.clone(), config.ttl_reapers));
         ttl_manager.start_background_task().await;
         engine.ttl_manager.set(ttl_manager).unwrap();
 
         engine
     }
 
+    /// [`new`](Self::new), failing with `InvalidRequest` on a `num_shards`
+    /// of 0 rather than clamping it.
+    pub async fn try_new(
+        config: super::types::StorageConfig,
+    ) -> Result<Arc<Self>, super::error::StorageError> {
+        if config.num_shards == 0 {
+            return Err(super::error::StorageError::InvalidRequest(
+                "num_shards must be at least 1".to_string(),
+            ));
+        }
+        Ok(Self::new(config).await)
+    }
+
     pub fn ttl_manager(&self) -> &TtlManager {
         self.ttl_manager.get().expect("TTL manager not initialized")
     }
 
-    fn get_shard(&self, key: &str) -> &Arc<Shard> {
-        let hash = fxhash::hash32(key.as_bytes());
-        &self.shards[(hash as usize) % self.shards.len()]
+    /// Logs every write made from now on to `wal`. A write is applied first
+    /// and returns once its entry is appended at the durability of the
+    /// enclosing [`Durability::scope`] (the default outside one); replayed
+    /// entries and bulk loads are never logged.
+    pub fn attach_wal(&self, wal: Arc<WalManager>) {
+        if self.wal.set(wal).is_err() {
+            tracing::warn!("A WAL is already attached; ignoring the new one");
+        }
     }
 
-    pub async fn get(&self, key: &str) -> Result<KvEntry, super::error::StorageError> {
-        let shard = self.get_shard(key);
-        if let Some(entry) = shard.get(key) {
-            if entry.is_expired() {
-                shard.del(key);
-                return Err(super::error::StorageError::KeyNotFound(key.to_string()));
-            }
-            Ok(entry)
-        } else {
-            Err(super::error::StorageError::KeyNotFound(key.to_string()))
+    pub fn wal(&self) -> Option<&Arc<WalManager>> {
+        self.wal.get()
+    }
+
+    // The WAL the current write goes to, if it is logged at all. A replicated
+    // WAL takes `Durability::None` writes too, or replicas would miss them.
+    fn logging(&self) -> Option<&WalManager> {
+        self.wal
+            .get()
+            .filter(|wal| Durability::current() != Durability::None || wal.is_replicated())
+            .map(|wal| wal.as_ref())
+    }
+
+    // A copy of `value` for the log entry, or nothing if it won't be logged
+    fn for_log(&self, value: &[u8]) -> Vec<u8> {
+        match self.logging() {
+            Some(_) => value.to_vec(),
+            None => Vec::new(),
         }
     }
 
-    pub async fn set(
+    // Room in the WAL queue for a write about to be applied. Taken before
+    // the write touches a shard, so a full queue turns it away unapplied
+    // rather than leaving it in memory but not in the log. The write sends
+    // its entry from under the shard lock (see `send_log`) and waits for it
+    // once it has let go of the write gate (see `finish_log`).
+    async fn reserve_log(&self) -> Result<Option<WalSlot<'_>>, super::error::StorageError> {
+        match self.logging() {
+            Some(wal) => Ok(Some(wal.reserve().await?)),
+            None => Ok(None),
+        }
+    }
+
+    // `reserve_log` for a write of `value`, in stored form, logged as
+    // `op_type`; `replace_entry` fills in the version
+    async fn reserve_write_log(
         &self,
+        op_type: OpType,
         key: &str,
-        value: Vec<u8>,
+        value: &[u8],
+        compression: Option<CompressionAlgo>,
         ttl_secs: Option<u64>,
-    ) -> Result<(), super::error::StorageError> {
-        let shard = self.get_shard(key);
-        let entry = KvEntry::new(value, ttl_secs);
+    ) -> Result<Publish<'_>, super::error::StorageError> {
+        let slot = self.reserve_log().await?;
+        Ok(slot.map_or(Publish::Watchers, |slot| {
+            let entry = WalEntry {
+                compression,
+                ..WalEntry::new(op_type, key, value.to_vec(), 0, ttl_secs)
+            };
+            Publish::Logged(slot, entry)
+        }))
+    }
+
+    /// Registers (or clears) the hook run for every expired key. It is called
+    /// after the key is removed and with no engine lock held, so it may call
+    /// back into the engine.
+    pub fn set_on_expire(&self, callback: Option<ExpireCallback>) {
+        *self.on_expire.0.write() = callback;
+    }
 
-        // Set in shard
-        let old_entry = shard.set(key.to_string(), entry.clone());
+    /// Changes to stored keys under `prefix` from now on: every successful
+    /// set (including CAS, INCR and replayed writes), delete and removal of
+    /// an expired key.
+    pub fn watch(&self, prefix: &str) -> ChangeSubscription {
+        self.changes.subscribe(prefix)
+    }
 
-        // If TTL set, register with TTL manager
-        if let Some(expiry) = entry.expires_at {
-            self.ttl_manager
-                .get()
-                .unwrap()
-                .add(key.to_string(), expiry)
-                .await;
+    // Runs once an expired key has been removed: tells watchers, then the
+    // `on_expire` hook
+    fn expired(&self, key: &str, entry: &KvEntry, reason: ExpiryReason) {
+        self.changes.notify_expired(key, reason);
+        let callback = self.on_expire.0.read().clone();
+        if let Some(callback) = callback {
+            callback(key, entry);
+        }
+    }
+
+    /// Toggles maintenance mode. While enabled every write is rejected with
+    /// `StorageError::MaintenanceMode`; reads and background workers continue.
+    pub fn set_maintenance_mode(&self, enabled: bool) {
+        let was = self.maintenance.swap(enabled, Ordering::SeqCst);
+        if was != enabled {
+            tracing::warn!(enabled = enabled, "Maintenance mode changed");
+        }
+    }
+
+    pub fn is_maintenance_mode(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    pub fn set_node_role(&self, role: NodeRole) {
+        self.replica
+            .store(role == NodeRole::Replica, Ordering::SeqCst);
+    }
+
+    pub fn node_role(&self) -> NodeRole {
+        if self.replica.load(Ordering::SeqCst) {
+            NodeRole::Replica
+        } else {
+            NodeRole::Primary
         }
+    }
 
-        // If replacing old entry with TTL, remove from TTL manager? (optional optimization)
+    pub(crate) fn check_writable(&self) -> Result<(), super::error::StorageError> {
+        if self.is_maintenance_mode() {
+            return Err(super::error::StorageError::MaintenanceMode);
+        }
+        if self.bulk_loading.load(Ordering::SeqCst) {
+            return Err(super::error::StorageError::BulkLoadInProgress);
+        }
+        Ok(())
+    }
 
+    /// Rejects multi-op requests larger than `max_batch_ops`. Every multi-op
+    /// endpoint calls this before touching any shard.
+    pub fn check_batch_size(&self, ops: usize) -> Result<(), super::error::StorageError> {
+        if ops > self.max_batch_ops {
+            return Err(super::error::StorageError::InvalidRequest(format!(
+                "batch has {} operations, the limit is {}",
+                ops, self.max_batch_ops
+            )));
+        }
         Ok(())
     }
 
-    pub async fn del(
+    /// Rejects a write of `value_len` bytes to `key` when either is over the
+    /// configured limit. Writes check this before touching a shard, so an
+    /// oversized one never reaches the WAL; replay doesn't, so entries
+    /// logged under looser limits still recover.
+    pub fn check_entry_size(
         &self,
         key: &str,
-        _expected_version: Option<u64>,
+        value_len: usize,
     ) -> Result<(), super::error::StorageError> {
-        let shard = self.get_shard(key);
-        if shard.del(key).is_some() {
-            Ok(())
-        } else {
-            Err(super::error::StorageError::KeyNotFound(key.to_string()))
+        if key.len() > self.max_key_bytes {
+            return Err(super::error::StorageError::KeyTooLarge {
+                len: key.len(),
+                limit: self.max_key_bytes,
+            });
+        }
+        if value_len > self.max_value_bytes {
+            return Err(super::error::StorageError::ValueTooLarge {
+                key: key.to_string(),
+                len: value_len,
+                limit: self.max_value_bytes,
+            });
         }
+        Ok(())
     }
 
-    pub async fn exists(&self, key: &str) -> bool {
-        let shard = self.get_shard(key);
-        shard.exists(key) && !shard.get(key).map_or(false, |e| e.is_expired())
+    /// Approximate bytes held by every entry (see [`entry_size`]), the
+    /// figure `max_memory_bytes` is enforced against.
+    pub fn memory_usage(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.bytes()).sum()
     }
 
-    pub async fn apply_wal_entry(
+    // Records an access to `key` for LRU eviction; a no-op unless the
+    // policy needs recency.
+    fn touch(&self, shard: &Shard, key: &str) {
+        if self.eviction_policy == EvictionPolicy::AllKeysLru {
+            shard.touch(key, self.lru_clock.fetch_add(1, Ordering::Relaxed));
+        }
+    }
+
+    // Makes room for `size` bytes replacing whatever `key` holds now. The
+    // check happens before the write takes its locks, so concurrent writers
+    // can overshoot the budget by about their own entries.
+    fn reserve_memory(
         &self,
-        entry: &WalEntry,
+        shard: &Shard,
+        key: &str,
+        size: u64,
     ) -> Result<(), super::error::StorageError> {
-        match entry.op_type {
-            OpType::Set => {
-                self.set(&entry.key, entry.value.clone(), entry.ttl).await?;
+        if self.max_memory_bytes == 0 {
+            return Ok(());
+        }
+        let existing = shard.map.read().get(key).map_or(0, |e| entry_size(key, e));
+        let needed = size.saturating_sub(existing);
+
+        loop {
+            let used = self.memory_usage();
+            if used + needed <= self.max_memory_bytes {
+                return Ok(());
             }
-            OpType::Del => {
-                self.del(&entry.key, None).await?;
+            // An entry larger than the whole budget must not empty the store
+            if self.eviction_policy == EvictionPolicy::NoEviction
+                || needed > self.max_memory_bytes
+                || !self.evict_lru()
+            {
+                return Err(super::error::StorageError::OutOfMemory {
+                    used,
+                    limit: self.max_memory_bytes,
+                });
+            }
+        }
+    }
+
+    // Evicts the least recently used key of the whole engine. Evictions are
+    // not logged, so a replica or a WAL replay may still hold the key.
+    // Returns false once there is nothing left to evict.
+    fn evict_lru(&self) -> bool {
+        let oldest = self
+            .shards
+            .iter()
+            .filter_map(|shard| shard.oldest().map(|(tick, key)| (tick, key, shard)))
+            .min_by_key(|(tick, _, _)| *tick);
+        let Some((_, key, shard)) = oldest else {
+            return false;
+        };
+
+        if let Some(evicted) = self.remove_entry(shard, &key) {
+            tracing::debug!(key = %key, "Evicted least recently used key");
+            if let Some(record) = self.evictions.lock().as_mut() {
+                record.push((key, evicted));
             }
-            OpType::Incr => {
-                // For now, treat as SET — we'll add atomic INCR later
-                self.set(&entry.key, entry.value.clone(), entry.ttl).await?;
+        } else {
+            // Already gone; just make sure it can't be picked again
+            shard.forget(&key);
+        }
+        true
+    }
+
+    /// Effective quota for a namespace: a stored `_sys.quotas:<ns>` entry wins
+    /// over the configured one.
+    pub fn namespace_quota(&self, namespace: &str) -> Option<NamespaceQuota> {
+        if let Some(quota) = self.stored_quotas.read().get(namespace) {
+            return Some(quota.clone());
+        }
+        self.config_quotas.get(namespace).cloned()
+    }
+
+    pub fn namespace_usage(&self, namespace: &str) -> NamespaceUsage {
+        self.usage
+            .get(namespace)
+            .map(|usage| *usage)
+            .unwrap_or_default()
+    }
+
+    // Rejects growth past a limit; writes that shrink usage always pass.
+    fn check_quota(
+        &self,
+        namespace: &str,
+        current: NamespaceUsage,
+        next: NamespaceUsage,
+    ) -> Result<(), super::error::StorageError> {
+        let Some(quota) = self.namespace_quota(namespace) else {
+            return Ok(());
+        };
+        let exceeded = |reason: String| super::error::StorageError::QuotaExceeded {
+            namespace: namespace.to_string(),
+            reason,
+        };
+
+        if let Some(max_keys) = quota.max_keys {
+            if next.keys > current.keys && next.keys > max_keys {
+                return Err(exceeded(format!("max_keys {} reached", max_keys)));
             }
-            OpType::Cas => {
-                // For now, treat as SET — we'll add version check later
-                self.set(&entry.key, entry.value.clone(), entry.ttl).await?;
+        }
+        if let Some(max_bytes) = quota.max_bytes {
+            if next.bytes > current.bytes && next.bytes > max_bytes {
+                return Err(exceeded(format!(
+                    "{} bytes would exceed max_bytes {}",
+                    next.bytes, max_bytes
+                )));
             }
         }
         Ok(())
     }
 
-    pub async fn snapshot(&self) -> Vec<HashMap<String, KvEntry>> {
-        self.shards.iter().map(|shard| shard.snapshot()).collect()
+    fn refresh_stored_quota(&self, key: &str, value: Option<&[u8]>) {
+        let Some(namespace) = key.strip_prefix(QUOTA_KEY_PREFIX) else {
+            return;
+        };
+        let mut stored = self.stored_quotas.write();
+        match value.map(serde_json::from_slice::<NamespaceQuota>) {
+            Some(Ok(quota)) => {
+                stored.insert(namespace.to_string(), quota);
+            }
+            Some(Err(e)) => {
+                tracing::warn!(namespace = %namespace, error = %e, "Ignoring malformed quota entry");
+            }
+            None => {
+                stored.remove(namespace);
+            }
+        }
     }
 
-    pub async fn load_from_snapshot(&self, state: Vec<HashMap<String, KvEntry>>) {
-        assert_eq!(state.len(), self.shards.len());
+    // Removes a key, releasing its namespace usage and queued expiry.
+    fn remove_entry(&self, shard: &Shard, key: &str) -> Option<KvEntry> {
+        self.remove_entry_if(shard, key, |_| true)
+    }
 
-        for (shard, shard_state) in self.shards.iter().zip(state) {
-            let mut map = shard.map.write();
-            *map = shard_state;
+    // `remove_entry`, only if `check` accepts the entry (see `Shard::del_if`)
+    fn remove_entry_if(
+        &self,
+        shard: &Shard,
+        key: &str,
+        check: impl FnOnce(&KvEntry) -> bool,
+    ) -> Option<KvEntry> {
+        let removed = self.remove_from_shard(shard, key, check);
+        if removed.as_ref().is_some_and(|e| e.expires_at.is_some()) {
+            self.ttl_manager().remove(key);
         }
+        removed
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::time::{sleep, Duration};
 
-    #[tokio::test]
-    async fn test_storage_get_set_del() {
-        let config = StorageConfig {
-            num_shards: 4,
-            snapshot_dir: "test_snapshots".to_string(),
+    // Removes a key and releases its namespace usage.
+    fn remove_from_shard(
+        &self,
+        shard: &Shard,
+        key: &str,
+        check: impl FnOnce(&KvEntry) -> bool,
+    ) -> Option<KvEntry> {
+        let Some(ns) = namespace::namespace_of(key) else {
+            let removed = shard.del_if(key, check);
+            if removed.is_some() {
+                self.refresh_stored_quota(key, None);
+            }
+            return removed;
         };
-        let engine = StorageEngine::new(config);
 
-        // Set
-        engine.set("hello", b"world".to_vec(), None).await.unwrap();
+        let mut usage = self.usage.entry(ns.to_string()).or_default();
+        let removed = shard.del_if(key, check);
+        if let Some(old) = &removed {
+            usage.keys = usage.keys.saturating_sub(1);
+            usage.bytes = usage.bytes.saturating_sub(entry_bytes(key, old));
+            if self.managed_namespaces.contains(ns) {
+                self.tags.remove(ns, key);
+            }
+        }
+        removed
+    }
 
-        // Get
-        let entry = engine.get("hello").await.unwrap();
-        assert_eq!(entry.value, b"world");
+    /// Live keys in a managed namespace whose value carried `tag` in `_tags`.
+    pub async fn keys_with_tag(&self, namespace: &str, tag: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        for key in self.tags.keys(namespace, tag) {
+            if self.exists(&key).await {
+                keys.push(key);
+            }
+        }
+        keys
+    }
 
-        // Del
-        engine.del("hello", None).await.unwrap();
-        let result = engine.get("hello").await;
-        assert!(matches!(result.unwrap_err(), StorageError::KeyNotFound(_)));
+    /// Shard groups in placement order; the first entry is the default group.
+    pub fn shard_groups(&self) -> &[ShardGroup] {
+        &self.groups
     }
 
-    #[tokio::test]
-    async fn test_storage_ttl_expiry() {
-        let config = StorageConfig {
-            num_shards: 4,
-            snapshot_dir: "test_snapshots".to_string(),
+    /// Occupancy skew of every shard group, from the per-shard key counts.
+    /// Groups are measured separately since their sizes differ by design.
+    pub fn shard_skew(&self) -> Vec<ShardSkew> {
+        self.groups
+            .iter()
+            .map(|group| {
+                let counts: Vec<usize> = self.shards[group.start..group.start + group.len]
+                    .iter()
+                    .map(|shard| shard.len())
+                    .collect();
+                let keys: usize = counts.iter().sum();
+                let max_shard_keys = counts.iter().copied().max().unwrap_or(0);
+                let ratio = if keys == 0 {
+                    1.0
+                } else {
+                    max_shard_keys as f64 * group.len as f64 / keys as f64
+                };
+                ShardSkew {
+                    group: group.name.clone(),
+                    keys,
+                    max_shard_keys,
+                    ratio,
+                }
+            })
+            .collect()
+    }
+
+    // Longest matching prefix wins; unmatched keys go to the default group.
+    fn group_for_key(&self, key: &str) -> &ShardGroup {
+        self.groups[1..]
+            .iter()
+            .filter(|g| key.starts_with(&g.prefix))
+            .max_by_key(|g| g.prefix.len())
+            .unwrap_or(&self.groups[0])
+    }
+
+    /// Shards owned by the group that keys starting with `prefix` route to.
+    /// A scan restricted to that prefix only needs to walk these shards.
+    pub fn shards_for_prefix(&self, prefix: &str) -> &[Arc<Shard>] {
+        let group = self.group_for_key(prefix);
+        &self.shards[group.start..group.start + group.len]
+    }
+
+    // Shards a scan for keys starting with `prefix` has to walk. A prefix
+    // that routes to the default group may still be the start of a routed
+    // group's prefix, so matching keys can live anywhere.
+    fn shards_to_scan(&self, prefix: &str) -> &[Arc<Shard>] {
+        if self.group_for_key(prefix).prefix.is_empty() {
+            return &self.shards;
+        }
+        self.shards_for_prefix(prefix)
+    }
+
+    pub(crate) fn shard_index(&self, key: &str) -> usize {
+        let group = self.group_for_key(key);
+        group.start + self.placement.shard(&self.hasher, key, group.len)
+    }
+
+    fn get_shard(&self, key: &str) -> &Arc<Shard> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    pub async fn get(&self, key: &str) -> Result<KvEntry, super::error::StorageError> {
+        let shard = self.get_shard(key);
+        if let Some(entry) = shard.get(key) {
+            if entry.is_expired() {
+                // Unless a write replaced it since
+                if let Some(removed) = self.remove_entry_if(shard, key, KvEntry::is_expired) {
+                    self.expired(key, &removed, ExpiryReason::OnRead);
+                }
+                return Err(super::error::StorageError::KeyNotFound(key.to_string()));
+            }
+            self.touch(shard, key);
+            entry.decompressed()
+        } else {
+            Err(super::error::StorageError::KeyNotFound(key.to_string()))
+        }
+    }
+
+    /// Bytes `start..=end` of the value at `key`, copied without cloning the
+    /// whole value. Both ends are inclusive; negative indexes count from the
+    /// end (`-1` is the last byte). Out-of-bounds indexes clamp to the value,
+    /// and a range that ends before it starts is empty.
+    pub async fn getrange(
+        &self,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>, super::error::StorageError> {
+        Ok(self.getrange_slice(key, start, end).await?.bytes)
+    }
+
+    /// [`getrange`](Self::getrange) plus where the slice sits in the value,
+    /// for callers that must describe it (HTTP `Content-Range`).
+    pub async fn getrange_slice(
+        &self,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<ValueSlice, super::error::StorageError> {
+        let shard = self.get_shard(key);
+        let expired = {
+            let map = shard.map.read();
+            match map.get(key) {
+                Some(entry) if !entry.is_expired() => {
+                    self.touch(shard, key);
+                    let value = entry.plain_value()?;
+                    let total_len = value.len();
+                    let (bytes, start) = match resolve_range(total_len, start, end) {
+                        Some((first, last)) => (value[first..=last].to_vec(), first),
+                        None => (Vec::new(), 0),
+                    };
+                    return Ok(ValueSlice {
+                        bytes,
+                        start,
+                        total_len,
+                    });
+                }
+                Some(_) => true,
+                None => false,
+            }
         };
-        let engine = StorageEngine::new(config);
+        if expired {
+            // Lazily expire it the same way a plain get would
+            let _ = self.get(key).await;
+        }
+        Err(super::error::StorageError::KeyNotFound(key.to_string()))
+    }
 
-        // Set with 1s TTL
-        engine
-            .set("temp", b"expiring".to_vec(), Some(1))
-            .await
-            .unwrap();
+    /// Writes `key` and returns its new version: one past the current
+    /// version, or 1 for a key that doesn't exist.
+    pub async fn set(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<u64, super::error::StorageError> {
+        self.check_writable()?;
+        let (version, pending) = {
+            let _gate = self.write_gate.read().await;
+            self.set_entry(key, value, ttl_secs).await?
+        };
+        finish_log(pending).await?;
+        Ok(version)
+    }
 
-        // Should exist now
-        assert!(engine.get("temp").await.is_ok());
+    /// [`set`](Self::set) logged at `durability` rather than at the
+    /// enclosing [`Durability::scope`]. `Durability::Sync` fsyncs the WAL
+    /// before returning whatever the global `SyncPolicy` is, so the write
+    /// survives a crash once acknowledged; it pays a full fsync (typically
+    /// milliseconds) on every call, where `EveryMs` amortizes one over many
+    /// writes.
+    pub async fn set_with(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+        durability: Durability,
+    ) -> Result<u64, super::error::StorageError> {
+        durability.scope(self.set(key, value, ttl_secs)).await
+    }
 
-        // Wait 2s
-        sleep(Duration::from_secs(2)).await;
+    /// [`set`](Self::set) for a value already run through
+    /// [`encode_value`](Self::encode_value), so the caller can log exactly
+    /// the bytes that were stored.
+    pub async fn set_encoded(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        compression: Option<CompressionAlgo>,
+        ttl_secs: Option<u64>,
+    ) -> Result<u64, super::error::StorageError> {
+        self.check_writable()?;
+        self.check_entry_size(key, value.len())?;
+        let (version, pending) = {
+            let _gate = self.write_gate.read().await;
+            self.write_logged(
+                OpType::Set,
+                key,
+                value,
+                compression,
+                ttl_secs,
+                WriteVersion::Bump,
+            )
+            .await?
+        };
+        finish_log(pending).await?;
+        Ok(version)
+    }
 
-        // Should be expired
-        let result = engine.get("temp").await;
-        assert!(matches!(result.unwrap_err(), StorageError::KeyNotFound(_)));
+    /// `value` as it would be stored under `key`: compressed when `mode`
+    /// (or, for `Auto`, the configured threshold) asks for it and that
+    /// actually saves space. Returns the bytes and their codec.
+    pub fn encode_value(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        mode: CompressionMode,
+    ) -> (Vec<u8>, Option<CompressionAlgo>) {
+        let algo = match mode {
+            CompressionMode::Auto => self
+                .compression
+                .filter(|config| value.len() > config.min_size)
+                .map(|config| config.algo),
+            CompressionMode::None => None,
+            CompressionMode::Lz4 => Some(CompressionAlgo::Lz4),
+            CompressionMode::Zstd => Some(CompressionAlgo::Zstd),
+        };
+        match algo.filter(|_| !self.keeps_plain(key)) {
+            Some(algo) => match compression::compress(algo, &value) {
+                Some(compressed) => (compressed, Some(algo)),
+                None => (value, None),
+            },
+            None => (value, None),
+        }
     }
 
-    #[tokio::test]
-    async fn test_storage_sharding() {
-        let config = StorageConfig {
-            num_shards: 4,
-            snapshot_dir: "test_snapshots".to_string(),
+    // Values the engine parses itself (managed metadata, quota overrides)
+    // are never stored compressed
+    fn keeps_plain(&self, key: &str) -> bool {
+        key.starts_with("_sys.")
+            || namespace::namespace_of(key).is_some_and(|ns| self.managed_namespaces.contains(ns))
+    }
+
+    /// Compare-and-swap: writes `new_value` only if the key's current version
+    /// is `expected_version` (0 for "must not exist") and returns the new
+    /// version. Otherwise fails with `StorageError::VersionMismatch`.
+    pub async fn cas(
+        &self,
+        key: &str,
+        expected_version: u64,
+        new_value: Vec<u8>,
+        ttl: Option<u64>,
+    ) -> Result<u64, super::error::StorageError> {
+        self.check_writable()?;
+        self.check_entry_size(key, new_value.len())?;
+        let (version, pending) = {
+            let _gate = self.write_gate.read().await;
+            let (value, compression) = self.encode_value(key, new_value, CompressionMode::Auto);
+            self.write_logged(
+                OpType::Cas,
+                key,
+                value,
+                compression,
+                ttl,
+                WriteVersion::Expect(expected_version),
+            )
+            .await?
         };
-        let engine = StorageEngine::new(config);
+        finish_log(pending).await?;
+        Ok(version)
+    }
 
-        // Set keys
-        for i in 0..100 {
-            let key = format!("key_{}", i);
-            engine
-                .set(&key, format!("value_{}", i).into_bytes(), None)
-                .await
-                .unwrap();
+    /// Writes `key` and returns the value it replaced, or `None` if the key
+    /// was missing or expired. The old value is taken under the same shard
+    /// lock as the write, so no other write can land in between.
+    pub async fn getset(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<Option<Vec<u8>>, super::error::StorageError> {
+        let (previous, _) = self.getset_versioned(key, value, ttl_secs).await?;
+        Ok(previous)
+    }
+
+    /// [`getset`](Self::getset), also returning the key's new version.
+    pub async fn getset_versioned(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<(Option<Vec<u8>>, u64), super::error::StorageError> {
+        self.check_writable()?;
+        self.check_entry_size(key, value.len())?;
+        let (version, previous, pending) = {
+            let _gate = self.write_gate.read().await;
+            let (value, compression) = self.encode_value(key, value, CompressionMode::Auto);
+            let log = self
+                .reserve_write_log(OpType::Set, key, &value, compression, ttl_secs)
+                .await?;
+            self.replace_entry(
+                key,
+                value,
+                compression,
+                Expiry::In(ttl_secs),
+                WriteVersion::Bump,
+                log,
+            )
+            .await?
+        };
+        finish_log(pending).await?;
+        let previous = previous
+            .map(|entry| entry.decompressed().map(|entry| entry.value))
+            .transpose()?;
+        Ok((previous, version))
+    }
+
+    /// Writes `key` only if it is missing or expired (a stale value counts
+    /// as absent) and returns whether it did.
+    pub async fn setnx(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<bool, super::error::StorageError> {
+        Ok(self.setnx_versioned(key, value, ttl_secs).await?.is_some())
+    }
+
+    /// [`setnx`](Self::setnx), returning the new version if it wrote.
+    pub async fn setnx_versioned(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<Option<u64>, super::error::StorageError> {
+        // A CAS against "must not exist", which is checked under the lock
+        match self.cas(key, 0, value, ttl_secs).await {
+            Ok(version) => Ok(Some(version)),
+            Err(super::error::StorageError::VersionMismatch { .. }) => Ok(None),
+            Err(e) => Err(e),
         }
+    }
 
-        // Check all keys exist
-        for i in 0..100 {
-            let key = format!("key_{}", i);
+    async fn set_entry(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<(u64, Option<PendingAppend<'_>>), super::error::StorageError> {
+        self.check_entry_size(key, value.len())?;
+        let (value, compression) = self.encode_value(key, value, CompressionMode::Auto);
+        self.write_logged(
+            OpType::Set,
+            key,
+            value,
+            compression,
+            ttl_secs,
+            WriteVersion::Bump,
+        )
+        .await
+    }
+
+    // `write_entry`, logged as `op_type` with the value as stored. The
+    // caller waits for the entry with `finish_log`.
+    async fn write_logged(
+        &self,
+        op_type: OpType,
+        key: &str,
+        value: Vec<u8>,
+        compression: Option<CompressionAlgo>,
+        ttl_secs: Option<u64>,
+        version: WriteVersion,
+    ) -> Result<(u64, Option<PendingAppend<'_>>), super::error::StorageError> {
+        let log = self
+            .reserve_write_log(op_type, key, &value, compression, ttl_secs)
+            .await?;
+        let (version, _, pending) = self
+            .replace_entry(key, value, compression, Expiry::In(ttl_secs), version, log)
+            .await?;
+        Ok((version, pending))
+    }
+
+    // `value` is in stored form, compressed with `compression`
+    async fn write_entry(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        compression: Option<CompressionAlgo>,
+        expiry: Expiry,
+        version: WriteVersion,
+    ) -> Result<u64, super::error::StorageError> {
+        let (version, _, _) = self
+            .replace_entry(key, value, compression, expiry, version, Publish::Watchers)
+            .await?;
+        Ok(version)
+    }
+
+    // `write_entry`, also handing back the live entry it replaced. A logged
+    // write's entry is sent with the new version before the shard lock is
+    // released.
+    async fn replace_entry<'a>(
+        &'a self,
+        key: &str,
+        value: Vec<u8>,
+        compression: Option<CompressionAlgo>,
+        expiry: Expiry,
+        version: WriteVersion,
+        publish: Publish<'a>,
+    ) -> Result<(u64, Option<KvEntry>, Option<PendingAppend<'a>>), super::error::StorageError> {
+        let shard = self.get_shard(key);
+        let held = matches!(publish, Publish::Held);
+        let replaced_ttl;
+        let replaced;
+        let pending;
+
+        // Replayed entries may come from a node that compressed them anyway
+        let (value, compression) = match compression {
+            Some(algo) if self.keeps_plain(key) => (compression::decompress(algo, &value)?, None),
+            compression => (value, compression),
+        };
+
+        // Managed namespaces take TTL and tags from the value; an explicit
+        // TTL still wins. A replayed write keeps the expiry it was logged with.
+        let namespace = namespace::namespace_of(key);
+        let managed = namespace
+            .filter(|ns| self.managed_namespaces.contains(*ns))
+            .map(|_| ManagedMetadata::parse(&value));
+        let ttl_secs = match expiry {
+            Expiry::In(ttl_secs) => ttl_secs.or(managed.as_ref().and_then(|meta| meta.ttl_secs)),
+            Expiry::At(_) => None,
+        };
+        let mut entry = KvEntry::new(value, ttl_secs);
+        entry.compression = compression;
+        if let Expiry::At(expires_at) = expiry {
+            entry.expires_at = expires_at;
+        }
+
+        // A write bound to fail its version or quota check must not evict
+        // anything first. Both are checked again under the lock below.
+        if !version.replayed() {
+            {
+                let map = shard.map.read();
+                let current = map
+                    .get(key)
+                    .filter(|e| !e.is_expired())
+                    .map_or(0, |e| e.version);
+                version.next(current)?;
+                if let Some(ns) = namespace {
+                    let usage = self.namespace_usage(ns);
+                    self.check_quota(ns, usage, usage_after(usage, key, map.get(key), &entry))?;
+                }
+            }
+            self.reserve_memory(shard, key, entry_size(key, &entry))?;
+        }
+
+        // Set in shard, holding the namespace's usage entry and the shard lock
+        // so the version check, quota check and write happen atomically
+        {
+            let mut usage = namespace.map(|ns| self.usage.entry(ns.to_string()).or_default());
+            let mut map = shard.map.write();
+
+            let live = map.get(key).is_some_and(|e| !e.is_expired());
+            let current = map.get(key).filter(|_| live).map_or(0, |e| e.version);
+            entry.version = match version.next(current)? {
+                Some(version) => version,
+                None => return Ok((current, None, None)),
+            };
+
+            if let (Some(ns), Some(usage)) = (namespace, usage.as_mut()) {
+                let next = usage_after(**usage, key, map.get(key), &entry);
+                if !version.replayed() {
+                    self.check_quota(ns, **usage, next)?;
+                }
+                **usage = next;
+            }
+            shard.account(key, map.get(key), Some(&entry));
+            let previous = map.insert(key.to_string(), entry.clone());
+            replaced_ttl = previous
+                .as_ref()
+                .is_some_and(|old| old.expires_at.is_some());
+            replaced = previous.filter(|_| live);
+
+            if let (Some(ns), Some(meta)) = (namespace, managed) {
+                self.tags.update(ns, key, meta.tags);
+            }
+            pending = match publish {
+                // Logged with the TTL the entry got, counted from when it
+                // was made, so replay ends up with the same expiry
+                Publish::Logged(slot, logged) => send_log(Some((
+                    slot,
+                    WalEntry {
+                        version: entry.version,
+                        timestamp: entry.created_at,
+                        ttl: ttl_secs,
+                        ..logged
+                    },
+                ))),
+                Publish::Watchers | Publish::Held => None,
+            };
+        }
+        if namespace.is_none() {
+            self.refresh_stored_quota(key, Some(&entry.value));
+        }
+        self.touch(shard, key);
+
+        // Queue the new expiry, or cancel the one the old entry had
+        match entry.expires_at {
+            Some(expiry) => self
+                .ttl_manager()
+                .add(self.shard_index(key), key.to_string(), expiry),
+            None if replaced_ttl => self.ttl_manager().remove(key),
+            None => {}
+        }
+        if !held {
+            self.changes.notify(key, ChangeOp::Set, entry.version);
+        }
+
+        Ok((entry.version, replaced, pending))
+    }
+
+    /// Seconds until `key` expires, rounded up, or `None` if it never does.
+    /// A missing or expired key is `KeyNotFound`.
+    pub async fn ttl(&self, key: &str) -> Result<Option<u64>, super::error::StorageError> {
+        let shard = self.get_shard(key);
+        let expired = {
+            let map = shard.map.read();
+            match map.get(key) {
+                Some(entry) if !entry.is_expired() => return Ok(entry.ttl_secs()),
+                Some(_) => true,
+                None => false,
+            }
+        };
+        if expired {
+            let _ = self.get(key).await;
+        }
+        Err(super::error::StorageError::KeyNotFound(key.to_string()))
+    }
+
+    /// Sets or replaces the TTL of an existing key without touching its
+    /// value or version. A TTL of 0 is rejected; delete the key instead.
+    pub async fn expire(&self, key: &str, ttl_secs: u64) -> Result<(), super::error::StorageError> {
+        if ttl_secs == 0 {
+            return Err(super::error::StorageError::InvalidRequest(
+                "ttl must be at least 1 second".to_string(),
+            ));
+        }
+        self.check_writable()?;
+        let slot = self.reserve_log().await?;
+        let pending = self
+            .set_expiry(key, Expiry::In(Some(ttl_secs)), slot)
+            .await?;
+        finish_log(pending).await
+    }
+
+    /// Removes the TTL of an existing key, so it never expires.
+    pub async fn persist(&self, key: &str) -> Result<(), super::error::StorageError> {
+        self.check_writable()?;
+        let slot = self.reserve_log().await?;
+        let pending = self.set_expiry(key, Expiry::In(None), slot).await?;
+        finish_log(pending).await
+    }
+
+    // Logged into `slot`, if given, as an EXPIRE of the new TTL
+    async fn set_expiry<'a>(
+        &'a self,
+        key: &str,
+        expiry: Expiry,
+        slot: Option<WalSlot<'a>>,
+    ) -> Result<Option<PendingAppend<'a>>, super::error::StorageError> {
+        let _gate = self.write_gate.read().await;
+
+        let now = now_nanos();
+        let (ttl_secs, expires_at) = match expiry {
+            Expiry::In(ttl_secs) => (ttl_secs, ttl_secs.map(|ttl| now + ttl * 1_000_000_000)),
+            Expiry::At(expires_at) => (None, expires_at),
+        };
+        let pending = {
+            let shard = self.get_shard(key);
+            let mut map = shard.map.write();
+            match map.get_mut(key).filter(|e| !e.is_expired()) {
+                Some(entry) => entry.expires_at = expires_at,
+                None => return Err(super::error::StorageError::KeyNotFound(key.to_string())),
+            }
+            send_log(slot.map(|slot| {
+                let entry = WalEntry {
+                    timestamp: now,
+                    ..WalEntry::new(OpType::Expire, key, Vec::new(), 0, ttl_secs)
+                };
+                (slot, entry)
+            }))
+        };
+
+        match expires_at {
+            Some(expiry) => self
+                .ttl_manager()
+                .add(self.shard_index(key), key.to_string(), expiry),
+            None => self.ttl_manager().remove(key),
+        }
+        Ok(pending)
+    }
+
+    /// Adds `delta` to the counter at `key` and returns the new value.
+    /// Counters are stored as ASCII decimal (`b"-42"`) so they read back as
+    /// plain strings; a missing or expired key counts as 0. The shard write
+    /// lock is held across the read-modify-write so concurrent increments
+    /// never lose an update. An existing TTL is kept.
+    pub async fn incr(&self, key: &str, delta: i64) -> Result<i64, super::error::StorageError> {
+        let (value, _) = self.incr_versioned(key, delta).await?;
+        Ok(value)
+    }
+
+    /// [`incr`](Self::incr), also returning the version the key ends up at,
+    /// which is what the WAL entry for it records.
+    pub async fn incr_versioned(
+        &self,
+        key: &str,
+        delta: i64,
+    ) -> Result<(i64, u64), super::error::StorageError> {
+        self.check_writable()?;
+        // The value is an i64, far below any value limit
+        self.check_entry_size(key, 0)?;
+        let slot = self.reserve_log().await?;
+        let (value, version, pending) = self
+            .incr_entry(key, delta, None, slot)
+            .await?
+            .expect("not a replay");
+        finish_log(pending).await?;
+        Ok((value, version))
+    }
+
+    // The increment behind `incr`. Replaying a logged INCR passes the version
+    // it produced: a key already at that version or later has the increment
+    // in it, so nothing happens (`None`); otherwise the result takes exactly
+    // that version, as other replayed writes do. Logged into `slot`, if
+    // given, with the increment, which is what replay redoes.
+    async fn incr_entry<'a>(
+        &'a self,
+        key: &str,
+        delta: i64,
+        logged_version: Option<u64>,
+        slot: Option<WalSlot<'a>>,
+    ) -> Result<Option<(i64, u64, Option<PendingAppend<'a>>)>, super::error::StorageError> {
+        let _gate = self.write_gate.read().await;
+
+        let shard = self.get_shard(key);
+        // An i64 is at most 20 ASCII digits. A replay is admitted as a
+        // replayed SET is (see `WriteVersion::replayed`).
+        if logged_version.is_none() {
+            self.reserve_memory(shard, key, key.len() as u64 + 20 + ENTRY_OVERHEAD)?;
+        }
+
+        let namespace = namespace::namespace_of(key);
+        // Same lock order as set_entry: namespace usage first, then the shard
+        let mut usage = namespace.map(|ns| self.usage.entry(ns.to_string()).or_default());
+        let mut map = shard.map.write();
+
+        let live = map.get(key).filter(|e| !e.is_expired());
+        if let (Some(logged), Some(entry)) = (logged_version, live) {
+            if entry.version >= logged {
+                return Ok(None);
+            }
+        }
+        let (current, expires_at, version) = match live {
+            Some(entry) => (
+                parse_integer(key, &entry.plain_value()?)?,
+                entry.expires_at,
+                entry.version + 1,
+            ),
+            None => (0, None, 1),
+        };
+        let version = logged_version.unwrap_or(version);
+        let next_value = current.checked_add(delta).ok_or_else(|| {
+            super::error::StorageError::InvalidRequest(format!(
+                "incrementing {} by {} would overflow",
+                key, delta
+            ))
+        })?;
+
+        let mut entry = KvEntry::new(next_value.to_string().into_bytes(), None);
+        entry.expires_at = expires_at;
+        entry.version = version;
+
+        if let (Some(ns), Some(usage)) = (namespace, usage.as_mut()) {
+            let mut next = **usage;
+            match map.get(key) {
+                Some(old) => next.bytes = next.bytes.saturating_sub(entry_bytes(key, old)),
+                None => next.keys += 1,
+            }
+            next.bytes += entry_bytes(key, &entry);
+            if logged_version.is_none() {
+                self.check_quota(ns, **usage, next)?;
+            }
+            **usage = next;
+        }
+        shard.account(key, map.get(key), Some(&entry));
+        map.insert(key.to_string(), entry);
+        let pending = send_log(slot.map(|slot| {
+            let entry = WalEntry {
+                delta: Some(delta),
+                ..WalEntry::new(
+                    OpType::Incr,
+                    key,
+                    next_value.to_string().into_bytes(),
+                    version,
+                    None,
+                )
+            };
+            (slot, entry)
+        }));
+        drop(map);
+        self.touch(shard, key);
+        self.changes.notify(key, ChangeOp::Set, version);
+
+        Ok(Some((next_value, version, pending)))
+    }
+
+    /// Appends `suffix` to the value at `key` and returns the new length in
+    /// bytes. A missing or expired key starts out empty. As with
+    /// [`incr`](Self::incr), the shard write lock is held across the
+    /// read-modify-write and an existing TTL is kept.
+    pub async fn append(
+        &self,
+        key: &str,
+        suffix: Vec<u8>,
+    ) -> Result<usize, super::error::StorageError> {
+        let (len, _) = self.append_versioned(key, suffix).await?;
+        Ok(len)
+    }
+
+    /// [`append`](Self::append), also returning the version the key ends up
+    /// at, which is what the WAL entry for it records.
+    pub async fn append_versioned(
+        &self,
+        key: &str,
+        suffix: Vec<u8>,
+    ) -> Result<(usize, u64), super::error::StorageError> {
+        self.check_writable()?;
+        self.check_entry_size(key, suffix.len())?;
+        let slot = self.reserve_log().await?;
+        let (len, version, pending) = self
+            .append_entry(key, &suffix, None, slot)
+            .await?
+            .expect("not a replay");
+        finish_log(pending).await?;
+        Ok((len, version))
+    }
+
+    // The append behind `append`; a replayed one is skipped, or pinned to
+    // its logged version, exactly like a replayed increment in `incr_entry`.
+    // Logged into `slot`, if given, as the suffix; the version keeps replay
+    // from appending twice.
+    async fn append_entry<'a>(
+        &'a self,
+        key: &str,
+        suffix: &[u8],
+        logged_version: Option<u64>,
+        slot: Option<WalSlot<'a>>,
+    ) -> Result<Option<(usize, u64, Option<PendingAppend<'a>>)>, super::error::StorageError> {
+        let _gate = self.write_gate.read().await;
+
+        let shard = self.get_shard(key);
+        let existing = shard
+            .map
+            .read()
+            .get(key)
+            .map_or(key.len() as u64 + ENTRY_OVERHEAD, |e| entry_size(key, e));
+        if logged_version.is_none() {
+            self.reserve_memory(shard, key, existing + suffix.len() as u64)?;
+        }
+
+        let namespace = namespace::namespace_of(key);
+        // Same lock order as set_entry: namespace usage first, then the shard
+        let mut usage = namespace.map(|ns| self.usage.entry(ns.to_string()).or_default());
+        let mut map = shard.map.write();
+
+        let live = map.get(key).filter(|e| !e.is_expired());
+        if let (Some(logged), Some(entry)) = (logged_version, live) {
+            if entry.version >= logged {
+                return Ok(None);
+            }
+        }
+        let (mut value, expires_at, version) = match live {
+            Some(entry) => (
+                entry.plain_value()?.into_owned(),
+                entry.expires_at,
+                entry.version + 1,
+            ),
+            None => (Vec::new(), None, 1),
+        };
+        let version = logged_version.unwrap_or(version);
+        value.extend_from_slice(suffix);
+        let len = value.len();
+        if logged_version.is_none() {
+            self.check_entry_size(key, len)?;
+        }
+
+        // Recompressed as a SET of the whole value would be
+        let (value, compression) = self.encode_value(key, value, CompressionMode::Auto);
+        let mut entry = KvEntry::new(value, None);
+        entry.compression = compression;
+        entry.expires_at = expires_at;
+        entry.version = version;
+
+        if let (Some(ns), Some(usage)) = (namespace, usage.as_mut()) {
+            let mut next = **usage;
+            match map.get(key) {
+                Some(old) => next.bytes = next.bytes.saturating_sub(entry_bytes(key, old)),
+                None => next.keys += 1,
+            }
+            next.bytes += entry_bytes(key, &entry);
+            if logged_version.is_none() {
+                self.check_quota(ns, **usage, next)?;
+            }
+            **usage = next;
+        }
+        shard.account(key, map.get(key), Some(&entry));
+        map.insert(key.to_string(), entry);
+        let pending = send_log(slot.map(|slot| {
+            let entry = WalEntry::new(OpType::Append, key, suffix.to_vec(), version, None);
+            (slot, entry)
+        }));
+        drop(map);
+        self.touch(shard, key);
+        self.changes.notify(key, ChangeOp::Set, version);
+
+        Ok(Some((len, version, pending)))
+    }
+
+    /// Entries for `keys`, in the same order; `None` for missing or expired
+    /// keys. Keys are grouped by shard so each shard is locked once.
+    pub async fn mget(
+        &self,
+        keys: &[String],
+    ) -> Result<Vec<Option<KvEntry>>, super::error::StorageError> {
+        self.check_batch_size(keys.len())?;
+
+        let mut by_shard: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (pos, key) in keys.iter().enumerate() {
+            by_shard.entry(self.shard_index(key)).or_default().push(pos);
+        }
+
+        let mut entries = vec![None; keys.len()];
+        for (shard, positions) in by_shard {
+            let shard = &self.shards[shard];
+            let map = shard.map.read();
+            for pos in positions {
+                if let Some(entry) = map.get(&keys[pos]).filter(|e| !e.is_expired()) {
+                    entries[pos] = Some(entry.clone().decompressed()?);
+                    self.touch(shard, &keys[pos]);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Best-effort multi-set: writes every `(key, value, ttl_secs)` item in
+    /// order and reports each key's new version or error. Duplicate keys are
+    /// resolved by the configured `DuplicateKeyPolicy` first, so a key appears
+    /// once in the result. Batch-level problems (size, duplicates, read-only
+    /// node) fail the whole call before anything is written.
+    pub async fn mset(
+        &self,
+        items: Vec<(String, Vec<u8>, Option<u64>)>,
+    ) -> Result<Vec<(String, Result<u64, super::error::StorageError>)>, super::error::StorageError>
+    {
+        let items = self.prepare_batch(items)?;
+
+        let mut written = Vec::with_capacity(items.len());
+        {
+            let _gate = self.write_gate.read().await;
+            for (key, (value, ttl)) in items {
+                let result = self.set_entry(&key, value, ttl).await;
+                written.push((key, result));
+            }
+        }
+        let mut results = Vec::with_capacity(written.len());
+        for (key, result) in written {
+            let result = match result {
+                Ok((version, pending)) => finish_log(pending).await.map(|()| version),
+                Err(e) => Err(e),
+            };
+            results.push((key, result));
+        }
+        Ok(results)
+    }
+
+    /// All-or-nothing [`mset`](Self::mset): the items are written as one
+    /// [`txn`](Self::txn) of sets, so either every key is written or none
+    /// is, on this node, on replicas and after recovery. Returns each key's
+    /// new version.
+    pub async fn mset_atomic(
+        &self,
+        items: Vec<(String, Vec<u8>, Option<u64>)>,
+    ) -> Result<Vec<(String, u64)>, super::error::StorageError> {
+        let ops = items
+            .into_iter()
+            .map(|(key, value, ttl_secs)| TxnOp::Set {
+                key,
+                value,
+                ttl_secs,
+            })
+            .collect();
+        self.txn(ops).await
+    }
+
+    /// Applies `ops` as one transaction. Every CAS, size and quota check runs
+    /// first, and the writes then land while every other write is held off,
+    /// so no one sees part of a transaction and a failed check writes
+    /// nothing. Duplicate keys are resolved by the configured
+    /// `DuplicateKeyPolicy`. Returns each key's new version (0 once deleted;
+    /// deleting a missing key is not an error).
+    ///
+    /// The WAL gets the writes between `TxnBegin` and `TxnCommit` markers in
+    /// one append, and [`recover`](Self::recover) skips a transaction whose
+    /// commit marker never made it to disk. Keys evicted to make room are
+    /// logged as deletes with them, so replicas evict them too. If the
+    /// append fails, every write is undone, along with any eviction it
+    /// caused, before watchers hear of any of them.
+    pub async fn txn(
+        &self,
+        ops: Vec<TxnOp>,
+    ) -> Result<Vec<(String, u64)>, super::error::StorageError> {
+        match self.commit_txn(ops, &HashMap::new()).await? {
+            ExecOutcome::Committed(versions) => Ok(versions),
+            ExecOutcome::Aborted { .. } => unreachable!("nothing was watched"),
+        }
+    }
+
+    /// WATCH for `session`: notes how each of `keys` looks now, so that the
+    /// session's next [`exec`](Self::exec) commits only if none of them has
+    /// been written, deleted or had its TTL changed since. Returns how many
+    /// keys the session watches.
+    pub fn watch_keys(
+        &self,
+        session: &str,
+        keys: &[String],
+    ) -> Result<usize, super::error::StorageError> {
+        self.check_batch_size(keys.len())?;
+        let stamps = keys.iter().map(|key| (key.clone(), self.stamp(key)));
+        Ok(self.watch_sessions.watch(session, stamps))
+    }
+
+    /// Drops `session`'s WATCH without running anything.
+    pub fn unwatch_keys(&self, session: &str) {
+        self.watch_sessions.unwatch(session);
+    }
+
+    /// EXEC for `session`: applies `ops` as one [`txn`](Self::txn) if every
+    /// key the session watched is as it was, and otherwise writes nothing
+    /// and returns [`ExecOutcome::Aborted`]. The watched keys are checked
+    /// under the same lock as the writes, so nothing slips in between. The
+    /// session's WATCH ends either way.
+    ///
+    /// A session that watched nothing, or left its WATCH idle past
+    /// [`WATCH_IDLE_TIMEOUT`](crate::storage::optimistic::WATCH_IDLE_TIMEOUT),
+    /// gets `InvalidRequest` rather than an unconditional write.
+    pub async fn exec(
+        &self,
+        session: &str,
+        ops: Vec<TxnOp>,
+    ) -> Result<ExecOutcome, super::error::StorageError> {
+        let watched = self.watch_sessions.take(session).ok_or_else(|| {
+            super::error::StorageError::InvalidRequest(
+                "EXEC without a WATCH, or the WATCH expired".to_string(),
+            )
+        })?;
+        self.commit_txn(ops, &watched).await
+    }
+
+    // What `key` looks like to WATCH; `None` while it doesn't exist.
+    fn stamp(&self, key: &str) -> Option<KeyStamp> {
+        self.get_shard(key)
+            .get(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| KeyStamp::of(&entry))
+    }
+
+    // `txn`, aborted if any key of `watched` no longer has its stamp.
+    async fn commit_txn(
+        &self,
+        ops: Vec<TxnOp>,
+        watched: &HashMap<String, Option<KeyStamp>>,
+    ) -> Result<ExecOutcome, super::error::StorageError> {
+        self.check_batch_size(ops.len())?;
+        self.check_writable()?;
+        let ops = ops
+            .into_iter()
+            .map(|op| (op.key().to_string(), op))
+            .collect();
+        let ops = batch::resolve_duplicates(ops, self.duplicate_keys)?;
+        for (key, op) in &ops {
+            if let Some(value) = op.value() {
+                self.check_entry_size(key, value.len())?;
+            }
+        }
+
+        let gate = self.write_gate.write().await;
+        if let Some((key, _)) = watched
+            .iter()
+            .find(|(key, stamp)| self.stamp(key) != **stamp)
+        {
+            return Ok(ExecOutcome::Aborted { key: key.clone() });
+        }
+        self.check_batch_quotas(
+            ops.iter()
+                .filter_map(|(key, op)| Some((key.as_str(), op.value()?.len()))),
+        )?;
+        for (key, op) in &ops {
+            if let TxnOp::Cas {
+                expected_version, ..
+            } = op
+            {
+                let actual = self
+                    .get_shard(key)
+                    .get(key)
+                    .filter(|entry| !entry.is_expired())
+                    .map_or(0, |entry| entry.version);
+                if actual != *expected_version {
+                    return Err(super::error::StorageError::VersionMismatch {
+                        expected: *expected_version,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        let slot = self.reserve_log().await?;
+        let evictions = EvictionRecord::start(self);
+        // What puts things back if the transaction fails, oldest first: each
+        // key as it was before its op, after the keys evicted to make room
+        // for that op
+        let mut undo = Vec::with_capacity(ops.len());
+        let mut logged = Vec::with_capacity(ops.len());
+        let mut changed = Vec::with_capacity(ops.len());
+        let mut versions = Vec::with_capacity(ops.len());
+        for (key, op) in ops {
+            let old = self.get_shard(&key).get(&key);
+            let applied = self.apply_txn_op(&key, op).await;
+            for (evicted, entry) in evictions.take() {
+                logged.push(WalEntry::new(OpType::Del, &evicted, Vec::new(), 0, None));
+                undo.push((evicted, Some(entry)));
+            }
+            undo.push((key.clone(), old));
+            match applied {
+                Ok((version, entry)) => {
+                    logged.extend(entry.clone());
+                    changed.extend(entry);
+                    versions.push((key, version));
+                }
+                // Only a write the checks can't foresee gets here, such as
+                // one that finds the memory budget spent
+                Err(e) => {
+                    self.undo_txn(undo);
+                    return Err(e);
+                }
+            }
+        }
+        drop(evictions);
+
+        // Still holding off every other write, so a failed append can be
+        // undone. The begin marker counts the entries between the markers.
+        if let Some(slot) = slot {
+            let begin = WalEntry::new(OpType::TxnBegin, "", Vec::new(), logged.len() as u64, None);
+            let commit = WalEntry::new(OpType::TxnCommit, "", Vec::new(), 0, None);
+            logged.insert(0, begin);
+            logged.push(commit);
+            if let Err(e) = finish_log(Some(slot.send(logged))).await {
+                self.undo_txn(undo);
+                return Err(e);
+            }
+        }
+        drop(gate);
+
+        for entry in &changed {
+            match entry.op_type {
+                OpType::Del => self.changes.notify(&entry.key, ChangeOp::Del, 0),
+                _ => self
+                    .changes
+                    .notify(&entry.key, ChangeOp::Set, entry.version),
+            }
+        }
+        Ok(ExecOutcome::Committed(versions))
+    }
+
+    // Puts back everything a failed `txn` changed, newest first
+    fn undo_txn(&self, undo: Vec<(String, Option<KvEntry>)>) {
+        for (key, old) in undo.into_iter().rev() {
+            self.restore_entry(&key, old);
+        }
+    }
+
+    // One already checked op of `txn`. Returns the key's new version and
+    // what to log, if anything changed. Watchers hear of it only once the
+    // transaction commits.
+    async fn apply_txn_op(
+        &self,
+        key: &str,
+        op: TxnOp,
+    ) -> Result<(u64, Option<WalEntry>), super::error::StorageError> {
+        let (op_type, value, ttl_secs) = match op {
+            TxnOp::Set {
+                value, ttl_secs, ..
+            } => (OpType::Set, value, ttl_secs),
+            TxnOp::Cas {
+                value, ttl_secs, ..
+            } => (OpType::Cas, value, ttl_secs),
+            TxnOp::Del { .. } => {
+                let removed = self.remove_entry(self.get_shard(key), key);
+                let logged = removed.map(|_| WalEntry::new(OpType::Del, key, Vec::new(), 0, None));
+                return Ok((0, logged));
+            }
+        };
+
+        let (value, compression) = self.encode_value(key, value, CompressionMode::Auto);
+        let logged = self.for_log(&value);
+        let (version, _, _) = self
+            .replace_entry(
+                key,
+                value,
+                compression,
+                Expiry::In(ttl_secs),
+                WriteVersion::Bump,
+                Publish::Held,
+            )
+            .await?;
+        let entry = WalEntry {
+            compression,
+            ..WalEntry::new(op_type, key, logged, version, ttl_secs)
+        };
+        Ok((version, Some(entry)))
+    }
+
+    // Undoes one write of a failed `txn`: puts `old` back as the key's entry,
+    // or removes the key if it had none. The version, quota and memory
+    // checks are skipped, since this only returns to a state that passed
+    // them.
+    fn restore_entry(&self, key: &str, old: Option<KvEntry>) {
+        let shard = self.get_shard(key);
+        self.remove_entry(shard, key);
+        if let Some(old) = old {
+            shard.set(key.to_string(), old.clone());
+            self.index_loaded(self.shard_index(key), key, &old);
+            self.touch(shard, key);
+        }
+    }
+
+    fn prepare_batch(
+        &self,
+        items: Vec<(String, Vec<u8>, Option<u64>)>,
+    ) -> Result<BatchItems, super::error::StorageError> {
+        self.check_batch_size(items.len())?;
+        self.check_writable()?;
+
+        let items = items
+            .into_iter()
+            .map(|(key, value, ttl)| (key, (value, ttl)))
+            .collect();
+        batch::resolve_duplicates(items, self.duplicate_keys)
+    }
+
+    // Quota check for a whole batch of `(key, value length)` writes at once.
+    // Callers hold the write gate exclusively so usage can't move between
+    // this check and the writes.
+    fn check_batch_quotas<'a>(
+        &self,
+        items: impl IntoIterator<Item = (&'a str, usize)>,
+    ) -> Result<(), super::error::StorageError> {
+        let mut projected: HashMap<&str, (NamespaceUsage, NamespaceUsage)> = HashMap::new();
+        for (key, value_len) in items {
+            let Some(ns) = namespace::namespace_of(key) else {
+                continue;
+            };
+            let (_, next) = projected.entry(ns).or_insert_with(|| {
+                let usage = self.namespace_usage(ns);
+                (usage, usage)
+            });
+            match self.get_shard(key).get(key) {
+                Some(old) => next.bytes = next.bytes.saturating_sub(entry_bytes(key, &old)),
+                None => next.keys += 1,
+            }
+            next.bytes += (key.len() + value_len) as u64;
+        }
+
+        for (ns, (current, next)) in projected {
+            self.check_quota(ns, current, next)?;
+        }
+        Ok(())
+    }
+
+    /// Ingests `entries` straight into the shards, refusing every other write
+    /// until the stream ends. Nothing here is logged to the WAL, so the load
+    /// is only durable once the caller snapshots it (see `bulk::fast_load`).
+    /// Returns the number of entries loaded.
+    pub async fn bulk_load<S>(&self, mut entries: S) -> Result<u64, super::error::StorageError>
+    where
+        S: Stream<Item = BulkEntry> + Unpin,
+    {
+        self.check_writable()?;
+        let _loading = BulkLoading::start(self)?;
+        tracing::warn!("Bulk load started, rejecting writes until it completes");
+
+        let mut loaded = 0u64;
+        let mut result = Ok(());
+        while let Some(entry) = entries.next().await {
+            if let Err(e) = self.load_entry(entry).await {
+                result = Err(e);
+                break;
+            }
+            loaded += 1;
+        }
+
+        tracing::info!(loaded = loaded, "Bulk load finished");
+        result.map(|_| loaded)
+    }
+
+    // One bulk-loaded entry, stored like a SET but not logged
+    async fn load_entry(&self, entry: BulkEntry) -> Result<u64, super::error::StorageError> {
+        self.check_entry_size(&entry.key, entry.value.len())?;
+        let (value, compression) =
+            self.encode_value(&entry.key, entry.value, CompressionMode::Auto);
+        self.write_entry(
+            &entry.key,
+            value,
+            compression,
+            Expiry::In(entry.ttl_secs),
+            WriteVersion::Bump,
+        )
+        .await
+    }
+
+    pub fn is_bulk_loading(&self) -> bool {
+        self.bulk_loading.load(Ordering::SeqCst)
+    }
+
+    /// Deletes `key`. With `expected_version`, only while the key is still
+    /// at that version; otherwise fails with `VersionMismatch` and leaves it.
+    /// An expired key counts as missing then.
+    pub async fn del(
+        &self,
+        key: &str,
+        expected_version: Option<u64>,
+    ) -> Result<(), super::error::StorageError> {
+        self.check_writable()?;
+        let slot = self.reserve_log().await?;
+        let pending = self.del_entry(key, expected_version, slot).await?;
+        finish_log(pending).await
+    }
+
+    // Logged into `slot`, if given, once the key is gone
+    async fn del_entry<'a>(
+        &'a self,
+        key: &str,
+        expected_version: Option<u64>,
+        slot: Option<WalSlot<'a>>,
+    ) -> Result<Option<PendingAppend<'a>>, super::error::StorageError> {
+        let _gate = self.write_gate.read().await;
+
+        let shard = self.get_shard(key);
+        let mut mismatch = None;
+        let mut pending = None;
+        let removed = self.remove_entry_if(shard, key, |entry| {
+            let remove = match expected_version {
+                None => true,
+                Some(_) if entry.is_expired() => false,
+                Some(expected) if entry.version != expected => {
+                    mismatch = Some(entry.version);
+                    false
+                }
+                Some(_) => true,
+            };
+            // Still under the shard lock the removal holds
+            if remove {
+                pending = send_log(
+                    slot.map(|slot| (slot, WalEntry::new(OpType::Del, key, Vec::new(), 0, None))),
+                );
+            }
+            remove
+        });
+        if let (Some(expected), Some(actual)) = (expected_version, mismatch) {
+            return Err(super::error::StorageError::VersionMismatch { expected, actual });
+        }
+
+        if removed.is_some() {
+            self.changes.notify(key, ChangeOp::Del, 0);
+            Ok(pending)
+        } else {
+            Err(super::error::StorageError::KeyNotFound(key.to_string()))
+        }
+    }
+
+    /// [`get`](Self::get) of `key` as a client of `namespace` names it; see
+    /// [`namespace::resolve_key`].
+    pub async fn get_in(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<KvEntry, super::error::StorageError> {
+        self.get(&namespace::resolve_key(namespace, key)?).await
+    }
+
+    /// [`set`](Self::set) of `key` in `namespace`.
+    pub async fn set_in(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    ) -> Result<u64, super::error::StorageError> {
+        self.set(&namespace::resolve_key(namespace, key)?, value, ttl_secs)
+            .await
+    }
+
+    /// [`del`](Self::del) of `key` in `namespace`.
+    pub async fn del_in(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<(), super::error::StorageError> {
+        self.del(&namespace::resolve_key(namespace, key)?, None)
+            .await
+    }
+
+    /// [`scan_glob`](Self::scan_glob) confined to `namespace`, with keys
+    /// returned without their namespace prefix. System keys are never
+    /// listed, and a default-namespace scan covers the legacy flat key space.
+    pub async fn scan_in(
+        &self,
+        namespace: &str,
+        pattern: &str,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<ScanPage, super::error::StorageError> {
+        let pattern = namespace::resolve_key(namespace, pattern)?;
+        let mut page = self.scan_glob(&pattern, limit, cursor, false).await?;
+        for (key, _) in page.items.iter_mut() {
+            *key = namespace::local_key(namespace, key).to_string();
+        }
+        Ok(page)
+    }
+
+    /// Removes `key` on behalf of the TTL reaper that owns shard `shard`,
+    /// without routing through other shards. Returns `false` when the key is
+    /// no longer expired: it was rewritten, given a later TTL or persisted
+    /// after the reaper queued it.
+    pub(crate) async fn expire_in_shard(
+        &self,
+        shard: usize,
+        key: &str,
+    ) -> Result<bool, super::error::StorageError> {
+        self.check_writable()?;
+
+        let shard = &self.shards[shard];
+        match shard.map.read().get(key) {
+            Some(entry) if entry.is_expired() => {}
+            Some(_) => return Ok(false),
+            None => return Err(super::error::StorageError::KeyNotFound(key.to_string())),
+        }
+        // Rechecked under the write lock: a write may have landed since
+        match self.remove_entry_if(shard, key, KvEntry::is_expired) {
+            Some(removed) => {
+                self.expired(key, &removed, ExpiryReason::Reaped);
+                Ok(true)
+            }
+            None if shard.map.read().contains_key(key) => Ok(false),
+            None => Err(super::error::StorageError::KeyNotFound(key.to_string())),
+        }
+    }
+
+    /// Whether `key` holds a live value. An expired entry the reaper has not
+    /// removed yet counts as absent; it is neither read back nor touched.
+    pub async fn exists(&self, key: &str) -> bool {
+        let shard = self.get_shard(key);
+        let map = shard.map.read();
+        map.get(key).is_some_and(|e| !e.is_expired())
+    }
+
+    /// [`exists`](Self::exists) for each of `keys`, in order, checking each
+    /// shard under a single read lock like [`mget`](Self::mget).
+    pub async fn mexists(&self, keys: &[String]) -> Result<Vec<bool>, super::error::StorageError> {
+        self.check_batch_size(keys.len())?;
+
+        let mut by_shard: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (pos, key) in keys.iter().enumerate() {
+            by_shard.entry(self.shard_index(key)).or_default().push(pos);
+        }
+
+        let mut found = vec![false; keys.len()];
+        for (shard, positions) in by_shard {
+            let map = self.shards[shard].map.read();
+            for pos in positions {
+                found[pos] = map.get(&keys[pos]).is_some_and(|e| !e.is_expired());
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns up to `limit` live entries whose key starts with `prefix`.
+    /// An empty match is a normal, empty page — never an error.
+    pub async fn scan_prefix(&self, prefix: &str, limit: usize) -> ScanPage {
+        let mut page = ScanPage::default();
+
+        for shard in self.shards_to_scan(prefix) {
+            let map = shard.map.read();
+            for (key, entry) in map.iter() {
+                if entry.is_expired() {
+                    continue;
+                }
+                page.scanned += 1;
+                if !key.starts_with(prefix) {
+                    continue;
+                }
+                if page.items.len() == limit {
+                    page.has_more = true;
+                    return page;
+                }
+                page.items.extend(scan_item(key, entry));
+            }
+        }
+
+        page
+    }
+
+    /// Like [`scan_prefix`](Self::scan_prefix), but returns only entries whose
+    /// value matches `filter`. Best-effort and bounded: the walk stops after
+    /// `max_examined` live keys, marking the page `truncated`, so a rare match
+    /// cannot turn into a full-keyspace scan.
+    pub async fn scan_filtered(
+        &self,
+        prefix: &str,
+        filter: &ValueFilter,
+        limit: usize,
+        max_examined: u64,
+    ) -> ScanPage {
+        let mut page = ScanPage::default();
+
+        for shard in self.shards_to_scan(prefix) {
+            let map = shard.map.read();
+            for (key, entry) in map.iter() {
+                if entry.is_expired() {
+                    continue;
+                }
+                if page.scanned == max_examined {
+                    page.has_more = true;
+                    page.truncated = true;
+                    return page;
+                }
+                page.scanned += 1;
+                if !key.starts_with(prefix) {
+                    continue;
+                }
+                let Some(item) = scan_item(key, entry).filter(|(_, e)| filter.matches(&e.value))
+                else {
+                    continue;
+                };
+                if page.items.len() == limit {
+                    page.has_more = true;
+                    return page;
+                }
+                page.items.push(item);
+            }
+        }
+
+        page
+    }
+
+    /// Up to `limit` live entries whose key matches the glob `pattern` (`*`,
+    /// `?`), plus an opaque cursor for the next page (`None` when done).
+    /// Paging through a static dataset visits every key exactly once.
+    pub async fn scan(
+        &self,
+        pattern: &str,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<(Vec<(String, KvEntry)>, Option<String>), super::error::StorageError> {
+        let page = self
+            .scan_glob(pattern, limit, cursor.as_deref(), true)
+            .await?;
+        Ok((page.items, page.next_cursor))
+    }
+
+    /// [`scan`](Self::scan) returning the full page. Without `include_system`,
+    /// system catalog keys (`_sys.*`) are hidden and so are keys outside the
+    /// namespace of `pattern`: a pattern without the `ns:` prefix lists only
+    /// default-namespace keys. Shards are walked in order and each shard's
+    /// matches in key order; the cursor is the last returned `(shard, key)`,
+    /// so a page may come back short, or even empty, before the cursor ends.
+    pub async fn scan_glob(
+        &self,
+        pattern: &str,
+        limit: usize,
+        cursor: Option<&str>,
+        include_system: bool,
+    ) -> Result<ScanPage, super::error::StorageError> {
+        let (start_shard, after) = match cursor {
+            Some(cursor) => decode_scan_cursor(cursor)?,
+            None => (0, None),
+        };
+        let shards = self.shards_to_scan(glob::literal_prefix(pattern));
+        let mut page = ScanPage::default();
+
+        for (index, shard) in shards.iter().enumerate().skip(start_shard) {
+            let after = after.as_deref().filter(|_| index == start_shard);
+            let map = shard.map.read();
+
+            let mut matches: Vec<(&String, &KvEntry)> = map
+                .iter()
+                .filter(|(_, entry)| !entry.is_expired())
+                .inspect(|_| page.scanned += 1)
+                .filter(|(key, _)| {
+                    after.is_none_or(|after| key.as_str() > after)
+                        && (include_system || listed_in_scan(pattern, key))
+                        && glob::matches(pattern, key)
+                })
+                .collect();
+            matches.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+            let room = limit - page.items.len();
+            let more_here = matches.len() > room;
+            for (key, entry) in matches.into_iter().take(room) {
+                page.items
+                    .push((key.clone(), entry.clone().decompressed()?));
+            }
+
+            if page.items.len() == limit {
+                page.next_cursor = if more_here {
+                    page.items
+                        .last()
+                        .map(|(key, _)| encode_scan_cursor(index, Some(key)))
+                } else if index + 1 < shards.len() {
+                    Some(encode_scan_cursor(index + 1, None))
+                } else {
+                    None
+                };
+                page.has_more = page.next_cursor.is_some();
+                break;
+            }
+        }
+
+        Ok(page)
+    }
+
+    /// Every live entry whose key matches the glob `pattern`, produced as the
+    /// stream is polled rather than collected up front. Shards are walked in
+    /// order and each shard's matches in key order, as in
+    /// [`scan_glob`](Self::scan_glob). A shard is read-locked once to list its
+    /// matching keys, then once per batch of entries fetched, and never
+    /// across a yield, so writes carry on during the scan: a key deleted
+    /// after its shard was listed is skipped, and one added isn't seen. The
+    /// listing and fetching run on the blocking pool, a batch at a time, so
+    /// a large shard doesn't hold up the runtime. The stream ends after the
+    /// last shard.
+    pub fn scan_stream(
+        &self,
+        pattern: &str,
+        include_system: bool,
+    ) -> impl Stream<Item = (String, KvEntry)> + Send + 'static {
+        let shards = self.shards_to_scan(glob::literal_prefix(pattern)).to_vec();
+        let scan = ScanIter {
+            shards: shards.into_iter(),
+            shard: None,
+            keys: Vec::new().into_iter(),
+            ready: VecDeque::new(),
+            pattern: pattern.to_string(),
+            include_system,
+        };
+        futures_util::stream::unfold(Some(scan), |scan| async move {
+            let mut scan = scan?;
+            let (scan, batch) = tokio::task::spawn_blocking(move || {
+                let batch: Vec<_> = scan.by_ref().take(SCAN_STREAM_BATCH).collect();
+                (scan, batch)
+            })
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            // A short batch means the last shard is done
+            let next = (batch.len() == SCAN_STREAM_BATCH).then_some(scan);
+            (!batch.is_empty()).then(|| (futures_util::stream::iter(batch), next))
+        })
+        .flatten()
+    }
+
+    /// Every live user entry sorted by key, independent of shard layout.
+    /// System catalog keys (`_sys.*`) are left out. Meant for golden-file tests.
+    pub fn dump_sorted(&self) -> Vec<(String, KvEntry)> {
+        let mut entries: Vec<(String, KvEntry)> = Vec::new();
+        for shard in &self.shards {
+            let map = shard.map.read();
+            entries.extend(
+                map.iter()
+                    .filter(|(key, entry)| !key.starts_with("_sys.") && !entry.is_expired())
+                    .map(|(key, entry)| (key.clone(), entry.clone())),
+            );
+        }
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Redoes a logged write. It is not logged again, even with a WAL
+    /// attached, and goes through in maintenance mode or during a bulk
+    /// load: the write was already accepted where it was logged.
+    pub async fn apply_wal_entry(
+        &self,
+        entry: &WalEntry,
+    ) -> Result<(), super::error::StorageError> {
+        // A TTL runs from when the entry was logged, not from now. A write
+        // or EXPIRE whose key has expired since only removes the key, as the
+        // reaper did (unlogged) on the node that made it.
+        let expires_at = entry.ttl.map(|ttl| {
+            entry
+                .timestamp
+                .saturating_add(ttl.saturating_mul(1_000_000_000))
+        });
+        let expired = expires_at.is_some_and(|at| at <= now_nanos());
+        match entry.op_type {
+            OpType::Set | OpType::Incr | OpType::Cas | OpType::Expire if expired => {
+                match self.del_entry(&entry.key, None, None).await {
+                    Ok(_) | Err(super::error::StorageError::KeyNotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            // Redone as an increment when the entry says by how much, so a
+            // replica that diverged still ends up `delta` further along
+            // rather than at the primary's value
+            OpType::Incr if entry.delta.is_some() && entry.version > 0 => {
+                let delta = entry.delta.unwrap_or_default();
+                self.incr_entry(&entry.key, delta, Some(entry.version), None)
+                    .await?;
+            }
+            // The entry holds the suffix; redone once, like a delta INCR
+            OpType::Append => {
+                self.append_entry(&entry.key, &entry.value, Some(entry.version), None)
+                    .await?;
+            }
+            // An INCR without a delta (older entries) carries just the
+            // post-increment value and a CAS was already checked on the
+            // primary, so all three replay as a write at the logged version.
+            // Version 0 (older entries) just bumps.
+            OpType::Set | OpType::Incr | OpType::Cas => {
+                let _gate = self.write_gate.read().await;
+                let version = match entry.version {
+                    0 => WriteVersion::Replay,
+                    version => WriteVersion::Exact(version),
+                };
+                // Compressed values replay as logged; plain ones get this
+                // node's compression
+                let (value, compression) = match entry.compression {
+                    Some(algo) => (entry.value.clone(), Some(algo)),
+                    None => {
+                        self.encode_value(&entry.key, entry.value.clone(), CompressionMode::Auto)
+                    }
+                };
+                self.write_entry(
+                    &entry.key,
+                    value,
+                    compression,
+                    Expiry::At(expires_at),
+                    version,
+                )
+                .await?;
+            }
+            OpType::Del => {
+                self.del_entry(&entry.key, None, None).await?;
+            }
+            OpType::Expire => {
+                self.set_expiry(&entry.key, Expiry::At(expires_at), None)
+                    .await?;
+            }
+            // Replicas apply a transaction's entries as they arrive; only
+            // `recover` holds them back until the commit
+            OpType::Checkpoint | OpType::TxnBegin | OpType::TxnCommit => {}
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the engine on startup, before it serves anything: loads the
+    /// snapshot named in the control file, then replays the WAL from the
+    /// offset recorded with it. If the control file is missing or corrupt,
+    /// loads the newest snapshot instead (if any) and replays every WAL
+    /// segment still on disk, which ends in the same state, only slower. A
+    /// snapshot restored on the live node since is loaded again where its
+    /// marker is replayed.
+    pub async fn recover(
+        &self,
+        snapshots: &SnapshotManager,
+        wal: &WalManager,
+    ) -> Result<RecoveryReport, super::error::StorageError> {
+        let (snapshot, wal_offset) = match snapshots.read_control() {
+            Ok(Some(control)) => (Some(control.snapshot), control.wal_offset),
+            Ok(None) => (snapshots.newest_snapshot()?, 0),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable control file: {}", e);
+                (snapshots.newest_snapshot()?, 0)
+            }
+        };
+        if let Some(snapshot) = &snapshot {
+            snapshots.load_snapshot(self, snapshot).await?;
+        }
+
+        // Collected first: replay holds the WAL lock and can't await. A
+        // transaction's entries are held back until its commit marker, which
+        // must follow within the number of entries its begin marker counts
+        // and in the same segment (the writer never splits an append across
+        // segments). If the log moves on without one (a crash cut it
+        // short), none of them are replayed. Older logs may count more
+        // entries than they hold, so an early commit still commits.
+        struct OpenTxn {
+            segment: u64,
+            left: u64, // entries its begin marker counts that are still to come
+            entries: Vec<WalEntry>,
+        }
+        let mut entries = Vec::new();
+        let mut txn: Option<OpenTxn> = None;
+        // A snapshot restored on the live node replaced everything before
+        // its marker
+        let mut restored = None;
+        wal.replay_from(wal_offset, |offset, entry| {
+            let (segment, _) = split_offset(offset);
+            let open = match txn.take() {
+                Some(open)
+                    if open.segment == segment
+                        && entry.op_type != OpType::TxnBegin
+                        && (open.left > 0 || entry.op_type == OpType::TxnCommit) =>
+                {
+                    Some(open)
+                }
+                Some(_) => {
+                    tracing::warn!("Skipping a transaction the WAL never committed");
+                    None
+                }
+                None => None,
+            };
+            match (entry.op_type, open) {
+                (OpType::TxnBegin, _) => {
+                    txn = Some(OpenTxn {
+                        segment,
+                        left: entry.version,
+                        entries: Vec::new(),
+                    })
+                }
+                (OpType::TxnCommit, Some(open)) => entries.extend(open.entries),
+                (OpType::TxnCommit, None) => {}
+                (OpType::Checkpoint, _) if !entry.key.is_empty() => {
+                    entries.clear();
+                    restored = Some(entry.key);
+                }
+                (_, Some(mut open)) => {
+                    open.left -= 1;
+                    open.entries.push(entry);
+                    txn = Some(open);
+                }
+                (_, None) => entries.push(entry),
+            }
+            Ok(())
+        })
+        .await?;
+        if txn.is_some() {
+            tracing::warn!("Skipping a transaction the WAL ends before committing");
+        }
+        if let Some(snapshot) = &restored {
+            tracing::info!(snapshot = %snapshot, "Reloading a snapshot restored before the restart");
+            snapshots.load_snapshot(self, snapshot).await?;
+        }
+        let snapshot = restored.or(snapshot);
+        for entry in &entries {
+            match self.apply_wal_entry(entry).await {
+                // Entries from after the offset can already be in the
+                // snapshot, so a key may be gone before its DEL replays
+                Ok(()) | Err(super::error::StorageError::KeyNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let report = RecoveryReport {
+            snapshot,
+            wal_offset,
+            replayed: entries.len() as u64,
+        };
+        tracing::info!(
+            snapshot = ?report.snapshot,
+            wal_offset = wal_offset,
+            replayed = report.replayed,
+            "Recovered"
+        );
+        Ok(report)
+    }
+
+    pub async fn snapshot(&self) -> Vec<HashMap<String, KvEntry>> {
+        self.shards.iter().map(|shard| shard.snapshot()).collect()
+    }
+
+    /// [`load_from_snapshot`](Self::load_from_snapshot) on a node that is
+    /// serving writes, of `state` read from the snapshot file `snapshot`.
+    /// The write gate is held exclusively for the swap, so no write
+    /// straddles the old and new datasets. If writes are in flight this
+    /// refuses with `Concurrency`, unless `force`, which waits them out.
+    ///
+    /// With a WAL attached, a `Checkpoint` marker naming the snapshot is
+    /// logged first, and [`recover`](Self::recover) reloads the snapshot
+    /// when it replays the marker, so a restart keeps the restore. If the
+    /// marker can't be logged, nothing is restored.
+    pub async fn restore_from_snapshot(
+        &self,
+        snapshot: &str,
+        state: Vec<HashMap<String, KvEntry>>,
+        force: bool,
+    ) -> Result<(), super::error::StorageError> {
+        let _gate = if force {
+            self.write_gate.write().await
+        } else {
+            self.write_gate.try_write().map_err(|_| {
+                super::error::StorageError::Concurrency(
+                    "writes are in flight; retry, or force the restore".to_string(),
+                )
+            })?
+        };
+        if let Some(slot) = self.reserve_log().await? {
+            let marker = WalEntry::new(OpType::Checkpoint, snapshot, Vec::new(), 0, None);
+            finish_log(Some(slot.send(vec![marker]))).await?;
+        }
+        self.load_from_snapshot(state).await;
+        Ok(())
+    }
+
+    /// Replaces the dataset with `state`, one map per shard. A snapshot
+    /// taken with another shard layout or placement is re-sharded on the
+    /// way in.
+    pub async fn load_from_snapshot(&self, state: Vec<HashMap<String, KvEntry>>) {
+        if state.len() != self.shards.len() {
+            tracing::info!(
+                from = state.len(),
+                to = self.shards.len(),
+                "Re-sharding snapshot"
+            );
+        }
+
+        self.begin_load();
+        for (index, shard_state) in state.into_iter().enumerate() {
+            self.load_shard(index, shard_state);
+        }
+    }
+
+    /// Starts a load that replaces every shard, one [`load_shard`](Self::load_shard)
+    /// at a time: empties the shards and forgets what was derived from the
+    /// old dataset, namespace usage, stored quotas and tags.
+    pub fn begin_load(&self) {
+        for shard in &self.shards {
+            shard.replace(HashMap::new());
+        }
+        self.usage.clear();
+        self.stored_quotas.write().clear();
+        self.tags.clear();
+    }
+
+    /// Loads `state`, shard `index` of the snapshot, within a load started
+    /// by [`begin_load`](Self::begin_load). When every key still belongs to
+    /// shard `index` the map is installed as is; otherwise, as after a
+    /// change of shard count or placement, each entry goes to the shard
+    /// its key maps to now.
+    pub fn load_shard(&self, index: usize, state: HashMap<String, KvEntry>) {
+        let placed = self.shards.get(index).is_some_and(|shard| shard.len() == 0)
+            && state.keys().all(|key| self.shard_index(key) == index);
+        if !placed {
+            for (key, entry) in state {
+                let index = self.shard_index(&key);
+                self.index_loaded(index, &key, &entry);
+                let shard = &self.shards[index];
+                shard.set(key.clone(), entry);
+                if self.eviction_policy == EvictionPolicy::AllKeysLru {
+                    self.touch(shard, &key);
+                }
+            }
+            return;
+        }
+
+        let shard = &self.shards[index];
+        for (key, entry) in &state {
+            self.index_loaded(index, key, entry);
+        }
+
+        let keys: Vec<String> = match self.eviction_policy {
+            EvictionPolicy::AllKeysLru => state.keys().cloned().collect(),
+            EvictionPolicy::NoEviction => Vec::new(),
+        };
+        shard.replace(state);
+        for key in keys {
+            self.touch(shard, &key);
+        }
+    }
+
+    // Rebuilds what is derived from a loaded entry in shard `index`
+    fn index_loaded(&self, index: usize, key: &str, entry: &KvEntry) {
+        // Expiries queued for the replaced keys find nothing to reap
+        if let Some(expiry) = entry.expires_at {
+            self.ttl_manager().add(index, key.to_string(), expiry);
+        }
+        match namespace::namespace_of(key) {
+            Some(ns) => {
+                let mut usage = self.usage.entry(ns.to_string()).or_default();
+                usage.keys += 1;
+                usage.bytes += entry_bytes(key, entry);
+                if self.managed_namespaces.contains(ns) {
+                    let tags = ManagedMetadata::parse(&entry.value).tags;
+                    self.tags.update(ns, key, tags);
+                }
+            }
+            None => self.refresh_stored_quota(key, Some(&entry.value)),
+        }
+    }
+}
+
+const QUOTA_KEY_PREFIX: &str = "_sys.quotas:";
+
+// Changes a watcher may fall behind by before it starts missing them
+const WATCH_BUFFER: usize = 1024;
+
+// The bulk load in progress; writes are refused until it is dropped, which
+// also ends a load whose future was dropped part way
+struct BulkLoading<'a>(&'a StorageEngine);
+
+impl<'a> BulkLoading<'a> {
+    fn start(engine: &'a StorageEngine) -> Result<Self, super::error::StorageError> {
+        engine
+            .bulk_loading
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .map_err(|_| super::error::StorageError::BulkLoadInProgress)?;
+        Ok(Self(engine))
+    }
+}
+
+impl Drop for BulkLoading<'_> {
+    fn drop(&mut self) {
+        self.0.bulk_loading.store(false, Ordering::SeqCst);
+    }
+}
+
+// Records every eviction while it lives, for an atomic write that may have
+// to put them back. Its holder has the write gate to itself, so every
+// eviction in that time is one of its writes making room.
+struct EvictionRecord<'a>(&'a StorageEngine);
+
+impl<'a> EvictionRecord<'a> {
+    fn start(engine: &'a StorageEngine) -> Self {
+        *engine.evictions.lock() = Some(Vec::new());
+        Self(engine)
+    }
+
+    // Keys evicted since the last call, oldest first
+    fn take(&self) -> Vec<(String, KvEntry)> {
+        self.0
+            .evictions
+            .lock()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for EvictionRecord<'_> {
+    fn drop(&mut self) {
+        *self.0.evictions.lock() = None;
+    }
+}
+
+// A write batch keyed for `batch::resolve_duplicates`: each key's value and TTL
+type BatchItems = Vec<(String, (Vec<u8>, Option<u64>))>;
+
+// Who hears of a write besides its shard
+enum Publish<'a> {
+    Watchers,                      // unlogged writes: replayed, bulk loaded, `Durability::None`
+    Logged(WalSlot<'a>, WalEntry), // the WAL, with the new version filled in, then watchers
+    Held,                          // nobody yet: a transaction publishes once it commits
+}
+
+// When a written entry expires
+#[derive(Debug, Clone, Copy)]
+enum Expiry {
+    In(Option<u64>), // seconds from now; None = never, or a managed value's own TTL
+    At(Option<u64>), // Unix nanos, for WAL replay; None = never
+}
+
+// How `write_entry` picks the version of the entry it writes
+enum WriteVersion {
+    Bump,        // current + 1
+    Expect(u64), // current + 1, only if current matches (CAS)
+    Exact(u64),  // as logged, for WAL replay
+    Replay,      // current + 1, for replayed entries logged without a version
+}
+
+impl WriteVersion {
+    // The version a write over a key at `current` gets. `None` for a replay
+    // over a key already at that version or later, as a replayed INCR is:
+    // the write is already in it.
+    fn next(&self, current: u64) -> Result<Option<u64>, super::error::StorageError> {
+        match *self {
+            WriteVersion::Bump | WriteVersion::Replay => Ok(Some(current + 1)),
+            WriteVersion::Expect(expected) if expected == current => Ok(Some(current + 1)),
+            WriteVersion::Expect(expected) => Err(super::error::StorageError::VersionMismatch {
+                expected,
+                actual: current,
+            }),
+            WriteVersion::Exact(version) if version <= current => Ok(None),
+            WriteVersion::Exact(version) => Ok(Some(version)),
+        }
+    }
+
+    // Replayed writes were admitted where they were logged, so they skip the
+    // memory budget and quotas: refusing one would leave this node behind
+    // the log
+    fn replayed(&self) -> bool {
+        matches!(self, WriteVersion::Exact(_) | WriteVersion::Replay)
+    }
+}
+
+// Whether a scan of `pattern` that doesn't include system keys lists `key`:
+// only keys in the pattern's own namespace, so a default-namespace scan never
+// reaches into a tenant's keys
+fn listed_in_scan(pattern: &str, key: &str) -> bool {
+    !key.starts_with("_sys.") && namespace::namespace_of(key) == namespace::namespace_of(pattern)
+}
+
+// A scan result with its value decompressed. One that fails to decode is
+// logged and left out rather than failing the whole page.
+fn scan_item(key: &str, entry: &KvEntry) -> Option<(String, KvEntry)> {
+    match entry.clone().decompressed() {
+        Ok(entry) => Some((key.to_string(), entry)),
+        Err(e) => {
+            tracing::warn!(key = %key, error = %e, "Skipping undecodable value in scan");
+            None
+        }
+    }
+}
+
+// Entries `scan_stream` fetches per shard lock
+const SCAN_STREAM_BATCH: usize = 256;
+
+struct ScanIter {
+    shards: std::vec::IntoIter<Arc<Shard>>,
+    shard: Option<Arc<Shard>>,
+    keys: std::vec::IntoIter<String>, // the current shard's matches, not yet fetched
+    ready: VecDeque<(String, KvEntry)>,
+    pattern: String,
+    include_system: bool,
+}
+
+impl Iterator for ScanIter {
+    type Item = (String, KvEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while let Some((key, entry)) = self.ready.pop_front() {
+                if let Some(item) = scan_item(&key, &entry) {
+                    return Some(item);
+                }
+            }
+
+            if let Some(shard) = &self.shard {
+                let batch: Vec<String> = self.keys.by_ref().take(SCAN_STREAM_BATCH).collect();
+                if !batch.is_empty() {
+                    let map = shard.map.read();
+                    self.ready.extend(batch.into_iter().filter_map(|key| {
+                        let entry = map.get(&key).filter(|e| !e.is_expired())?.clone();
+                        Some((key, entry))
+                    }));
+                    continue;
+                }
+            }
+
+            let shard = self.shards.next()?;
+            let mut keys: Vec<String> = shard
+                .map
+                .read()
+                .iter()
+                .filter(|(key, entry)| {
+                    !entry.is_expired()
+                        && (self.include_system || listed_in_scan(&self.pattern, key))
+                        && glob::matches(&self.pattern, key)
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+            keys.sort_unstable();
+            self.keys = keys.into_iter();
+            self.shard = Some(shard);
+        }
+    }
+}
+
+// Sends a write's WAL entry into the slot reserved for it. Writes call this
+// under the lock that orders them, so each key's entries reach the log in
+// the order the writes were applied.
+fn send_log<'a>(log: Option<(WalSlot<'a>, WalEntry)>) -> Option<PendingAppend<'a>> {
+    log.map(|(slot, entry)| slot.send(vec![entry]))
+}
+
+// Waits for what `send_log` sent, at the durability of the enclosing
+// `Durability::scope`. Writes let go of the write gate first, so a slow disk
+// or replica holds up only the writes waiting on it.
+async fn finish_log(pending: Option<PendingAppend<'_>>) -> Result<(), super::error::StorageError> {
+    if let Some(pending) = pending {
+        pending.finish(Durability::current()).await?;
+    }
+    Ok(())
+}
+
+// Footprint charged against a namespace's byte quota
+fn entry_bytes(key: &str, entry: &KvEntry) -> u64 {
+    (key.len() + entry.value.len()) as u64
+}
+
+// A namespace's usage once `new` replaces `old` (if any) at `key`
+fn usage_after(
+    usage: NamespaceUsage,
+    key: &str,
+    old: Option<&KvEntry>,
+    new: &KvEntry,
+) -> NamespaceUsage {
+    let mut next = usage;
+    match old {
+        Some(old) => next.bytes = next.bytes.saturating_sub(entry_bytes(key, old)),
+        None => next.keys += 1,
+    }
+    next.bytes += entry_bytes(key, new);
+    next
+}
+
+// Scan cursors are `{shard}:{last key}` (key empty for "start of shard"),
+// base64url-encoded so clients treat them as opaque
+fn encode_scan_cursor(shard: usize, after: Option<&str>) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!(
+        "{}:{}",
+        shard,
+        after.unwrap_or_default()
+    ))
+}
+
+fn decode_scan_cursor(cursor: &str) -> Result<(usize, Option<String>), super::error::StorageError> {
+    let invalid = || super::error::StorageError::InvalidRequest("invalid scan cursor".to_string());
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(invalid)?;
+    let (shard, after) = decoded.split_once(':').ok_or_else(invalid)?;
+    let shard = shard.parse().map_err(|_| invalid())?;
+    Ok((shard, (!after.is_empty()).then(|| after.to_string())))
+}
+
+// Inclusive byte positions for GETRANGE-style indexes, or None when empty
+fn resolve_range(len: usize, start: i64, end: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if len == 0 || start > end || start >= len {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+fn now_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+// Counter values are ASCII decimal, see `StorageEngine::incr`
+fn parse_integer(key: &str, value: &[u8]) -> Result<i64, super::error::StorageError> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(|| super::error::StorageError::NotAnInteger(key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::error::StorageError;
+    use crate::storage::types::{
+        NamespaceQuota, ShardGroupConfig, ShardHashSeed, ShardHasher, StorageConfig,
+    };
+    use tokio::time::{sleep, Duration};
+
+    #[tokio::test]
+    async fn test_storage_get_set_del() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+
+        // Set
+        engine.set("hello", b"world".to_vec(), None).await.unwrap();
+
+        // Get
+        let entry = engine.get("hello").await.unwrap();
+        assert_eq!(entry.value, b"world");
+
+        // Del
+        engine.del("hello", None).await.unwrap();
+        let result = engine.get("hello").await;
+        assert!(matches!(result.unwrap_err(), StorageError::KeyNotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_storage_ttl_expiry() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+
+        // Set with 1s TTL
+        engine
+            .set("temp", b"expiring".to_vec(), Some(1))
+            .await
+            .unwrap();
+
+        // Should exist now
+        assert!(engine.get("temp").await.is_ok());
+
+        // Wait 2s
+        sleep(Duration::from_secs(2)).await;
+
+        // Should be expired
+        let result = engine.get("temp").await;
+        assert!(matches!(result.unwrap_err(), StorageError::KeyNotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_storage_entry_reports_remaining_ttl() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 2,
+            ..Default::default()
+        })
+        .await;
+
+        engine.set("short", b"v".to_vec(), Some(30)).await.unwrap();
+        engine.set("forever", b"v".to_vec(), None).await.unwrap();
+
+        let entry = engine.get("short").await.unwrap();
+        assert_eq!(entry.ttl_secs(), Some(30));
+        assert!(entry.expires_at.unwrap() > entry.created_at);
+        assert_eq!(engine.get("forever").await.unwrap().ttl_secs(), None);
+        assert_eq!(engine.ttl("short").await.unwrap(), Some(30));
+    }
+
+    // The one way to build an engine: `new` is async because it starts the
+    // TTL reaper, which must expire keys even when nobody reads them
+    #[tokio::test]
+    async fn test_storage_new_starts_the_ttl_reaper() {
+        let engine: Arc<StorageEngine> = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("temp", b"v".to_vec(), Some(1)).await.unwrap();
+        sleep(Duration::from_millis(1500)).await;
+        assert!(engine.get_shard("temp").get("temp").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_storage_sharding() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+
+        // Set keys
+        for i in 0..100 {
+            let key = format!("key_{}", i);
+            engine
+                .set(&key, format!("value_{}", i).into_bytes(), None)
+                .await
+                .unwrap();
+        }
+
+        // Check all keys exist
+        for i in 0..100 {
+            let key = format!("key_{}", i);
             let entry = engine.get(&key).await.unwrap();
             assert_eq!(entry.value, format!("value_{}", i).into_bytes());
         }
     }
+
+    #[tokio::test]
+    async fn test_storage_any_shard_count() {
+        let zero = StorageConfig {
+            num_shards: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            StorageEngine::try_new(zero.clone()).await,
+            Err(StorageError::InvalidRequest(_))
+        ));
+        assert_eq!(StorageEngine::new(zero).await.shards.len(), 1);
+
+        for num_shards in [1, 3, 7, 8] {
+            let engine = StorageEngine::try_new(StorageConfig {
+                num_shards,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            for i in 0..200 {
+                let key = format!("key_{}", i);
+                let index = engine.shard_index(&key);
+                // Masking picks the same shard modulo would
+                let hash = fxhash::hash32(key.as_bytes()) as usize;
+                assert_eq!(index, hash % num_shards);
+                engine.set(&key, vec![b'v'], None).await.unwrap();
+            }
+            let stored: usize = engine.shards.iter().map(|s| s.len()).sum();
+            assert_eq!(stored, 200);
+            if num_shards > 1 {
+                assert!(engine.shards.iter().all(|s| s.len() > 0));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_consistent_placement_moves_few_keys() {
+        let moved = |placement: Placement| {
+            (0..10_000)
+                .filter(|i| {
+                    let key = format!("key_{}", i);
+                    placement.shard(&KeyHasher::Fx, &key, 8)
+                        != placement.shard(&KeyHasher::Fx, &key, 9)
+                })
+                .count()
+        };
+        // About 1/9 of the keys move to the new shard; modulo moves ~8/9
+        assert!(moved(Placement::Consistent) < 1_500);
+        assert!(moved(Placement::Modulo) > 8_000);
+
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 5,
+            placement: Placement::Consistent,
+            ..Default::default()
+        })
+        .await;
+        for i in 0..200 {
+            engine
+                .set(&format!("k{}", i), vec![b'v'], None)
+                .await
+                .unwrap();
+        }
+        assert!(engine.shards.iter().all(|s| s.len() > 0));
+    }
+
+    #[tokio::test]
+    async fn test_storage_sip_hasher_seed_pins_placement() {
+        let engine = |hasher, seed| {
+            StorageEngine::new(StorageConfig {
+                num_shards: 8,
+                shard_hasher: hasher,
+                shard_hash_seed: seed,
+                ..Default::default()
+            })
+        };
+        let placements = |engine: &Arc<StorageEngine>| -> Vec<usize> {
+            (0..200)
+                .map(|i| engine.shard_index(&format!("key_{}", i)))
+                .collect()
+        };
+
+        let seed = |hex: &str| serde_json::from_str::<ShardHashSeed>(&format!("\"{}\"", hex));
+        let (a, b, c) = (
+            seed("0000000000000000000000000000002a").unwrap(),
+            seed("0000000000000000000000000000002b").unwrap(),
+            // Same low half as `a`: both SipHash keys come from the seed
+            seed("0000000000000001000000000000002a").unwrap(),
+        );
+        assert!(seed("1234").is_err());
+        assert!(seed("0000000000000000000000000000002g").is_err());
+
+        let pinned = engine(ShardHasher::Sip, Some(a)).await;
+        let fx = placements(&engine(ShardHasher::Fx, Some(a)).await);
+        assert_eq!(
+            placements(&pinned),
+            placements(&engine(ShardHasher::Sip, Some(a)).await)
+        );
+        assert_ne!(placements(&pinned), fx);
+        for other in [b, c] {
+            assert_ne!(
+                placements(&pinned),
+                placements(&engine(ShardHasher::Sip, Some(other)).await)
+            );
+        }
+        // Unpinned, each engine draws its own key
+        assert_ne!(
+            placements(&engine(ShardHasher::Sip, None).await),
+            placements(&engine(ShardHasher::Sip, None).await)
+        );
+
+        for i in 0..200 {
+            pinned
+                .set(&format!("key_{}", i), vec![b'v'], None)
+                .await
+                .unwrap();
+        }
+        assert!(pinned.shards.iter().all(|s| s.len() > 0));
+
+        // A snapshot from one key loads under another
+        let restored = engine(ShardHasher::Sip, None).await;
+        restored.load_from_snapshot(pinned.snapshot().await).await;
+        for i in 0..200 {
+            let key = format!("key_{}", i);
+            assert!(restored.shards[restored.shard_index(&key)]
+                .get(&key)
+                .is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_load_from_snapshot_reshards() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        for i in 0..300 {
+            engine
+                .set(&format!("k{}", i), i.to_string().into_bytes(), None)
+                .await
+                .unwrap();
+        }
+        engine.set("ns:a:x", b"v".to_vec(), Some(60)).await.unwrap();
+        let state = engine.snapshot().await;
+
+        for (num_shards, placement) in [
+            (7, Placement::Modulo),
+            (2, Placement::Consistent),
+            (4, Placement::Consistent),
+        ] {
+            let restored = StorageEngine::new(StorageConfig {
+                num_shards,
+                placement,
+                ..Default::default()
+            })
+            .await;
+            restored.set("stale", b"v".to_vec(), None).await.unwrap();
+            restored.load_from_snapshot(state.clone()).await;
+
+            assert!(restored.get("stale").await.is_err());
+            for i in 0..300 {
+                let key = format!("k{}", i);
+                let shard = &restored.shards[restored.shard_index(&key)];
+                assert_eq!(shard.get(&key).unwrap().value, i.to_string().as_bytes());
+            }
+            let stored: usize = restored.shards.iter().map(|s| s.len()).sum();
+            assert_eq!(stored, 301);
+            assert_eq!(restored.namespace_usage("a").keys, 1);
+            assert!(restored.ttl("ns:a:x").await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_prefix_routing() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            shard_groups: vec![
+                ShardGroupConfig {
+                    name: "counters".to_string(),
+                    prefix: "counters:*".to_string(),
+                    num_shards: 8,
+                },
+                ShardGroupConfig {
+                    name: "blobs".to_string(),
+                    prefix: "blobs:".to_string(),
+                    num_shards: 2,
+                },
+            ],
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+        assert_eq!(engine.shards.len(), 14);
+
+        for i in 0..50 {
+            engine
+                .set(&format!("counters:{}", i), b"1".to_vec(), None)
+                .await
+                .unwrap();
+            engine
+                .set(&format!("blobs:{}", i), vec![0u8; 64], None)
+                .await
+                .unwrap();
+            engine
+                .set(&format!("other:{}", i), b"x".to_vec(), None)
+                .await
+                .unwrap();
+        }
+
+        // Each group's shards hold exactly that group's keys
+        let counters = engine.shards_for_prefix("counters:");
+        assert_eq!(counters.len(), 8);
+        assert_eq!(counters.iter().map(|s| s.len()).sum::<usize>(), 50);
+        for shard in counters {
+            assert!(shard.snapshot().keys().all(|k| k.starts_with("counters:")));
+        }
+
+        let blobs = engine.shards_for_prefix("blobs:");
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(blobs.iter().map(|s| s.len()).sum::<usize>(), 50);
+
+        let default = engine.shards_for_prefix("other:");
+        assert_eq!(default.len(), 4);
+        assert_eq!(default.iter().map(|s| s.len()).sum::<usize>(), 50);
+
+        // Keys outside every group may share a prefix with any group
+        assert_eq!(engine.shards_to_scan("other:").len(), 14);
+        assert_eq!(engine.shards_to_scan("counters:").len(), 8);
+
+        assert_eq!(engine.shard_groups()[1].prefix, "counters:");
+    }
+
+    #[tokio::test]
+    async fn test_storage_scan_prefix_empty_match() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+
+        for i in 0..10 {
+            engine
+                .set(&format!("user:{}", i), b"v".to_vec(), None)
+                .await
+                .unwrap();
+        }
+
+        let page = engine.scan_prefix("order:", 100).await;
+        assert!(page.items.is_empty());
+        assert!(!page.has_more);
+        assert_eq!(page.scanned, 10);
+
+        let page = engine.scan_prefix("user:", 3).await;
+        assert_eq!(page.items.len(), 3);
+        assert!(page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_storage_scan_filtered_value_contains() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        })
+        .await;
+
+        for i in 0..20 {
+            let value = if i % 4 == 0 {
+                "status=failed"
+            } else {
+                "status=ok"
+            };
+            engine
+                .set(&format!("job:{}", i), value.as_bytes().to_vec(), None)
+                .await
+                .unwrap();
+        }
+        let filter = ValueFilter::Contains(b"failed".to_vec());
+
+        let page = engine.scan_filtered("job:", &filter, 100, 1000).await;
+        let mut keys: Vec<_> = page.items.iter().map(|(k, _)| k.clone()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["job:0", "job:12", "job:16", "job:4", "job:8"]);
+        assert_eq!(page.scanned, 20);
+        assert!(!page.has_more);
+        assert!(!page.truncated);
+
+        // The cap bounds the walk even though matches remain
+        let page = engine.scan_filtered("job:", &filter, 100, 5).await;
+        assert_eq!(page.scanned, 5);
+        assert!(page.has_more);
+        assert!(page.truncated);
+        assert!(page
+            .items
+            .iter()
+            .all(|(_, e)| e.value == b"status=failed".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_storage_on_expire_callback() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        })
+        .await;
+
+        let seen = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        engine.set_on_expire(Some(Arc::new(move |key: &str, entry: &KvEntry| {
+            sink.lock().push((key.to_string(), entry.value.clone()));
+        })));
+
+        // Actively expired by the reaper
+        engine
+            .set("short", b"lived".to_vec(), Some(1))
+            .await
+            .unwrap();
+        engine.set("forever", b"v".to_vec(), None).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1300)).await;
+        assert_eq!(*seen.lock(), vec![("short".to_string(), b"lived".to_vec())]);
+
+        // Lazily expired on read (never queued with the reaper)
+        let mut stale = KvEntry::new(b"stale".to_vec(), None);
+        stale.expires_at = Some(1);
+        engine.get_shard("lazy").set("lazy".to_string(), stale);
+        assert!(engine.get("lazy").await.is_err());
+        assert_eq!(seen.lock().len(), 2);
+        assert_eq!(seen.lock()[1].0, "lazy");
+    }
+
+    #[tokio::test]
+    async fn test_storage_expire_and_persist() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("k", b"v".to_vec(), None).await.unwrap();
+        assert_eq!(engine.ttl("k").await.unwrap(), None);
+        assert!(engine.ttl("missing").await.is_err());
+        assert!(engine.expire("missing", 10).await.is_err());
+        assert!(matches!(
+            engine.expire("k", 0).await,
+            Err(crate::storage::StorageError::InvalidRequest(_))
+        ));
+
+        engine.expire("k", 3).await.unwrap();
+        let first = engine.ttl("k").await.unwrap().unwrap();
+        assert_eq!(first, 3);
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let second = engine.ttl("k").await.unwrap().unwrap();
+        assert!(second < first);
+        assert_eq!(engine.get("k").await.unwrap().version, 1);
+
+        // The queued expiry must not remove the key once it is persisted
+        engine.persist("k").await.unwrap();
+        assert_eq!(engine.ttl("k").await.unwrap(), None);
+        tokio::time::sleep(std::time::Duration::from_millis(2200)).await;
+        assert_eq!(engine.get("k").await.unwrap().value, b"v");
+
+        // Shortening a TTL is honoured by the reaper
+        engine.expire("k", 1).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1300)).await;
+        assert!(engine.get_shard("k").get("k").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_storage_memory_budget() {
+        // Room for three 100-byte values under one-letter keys
+        let budget = 3 * (1 + 100 + ENTRY_OVERHEAD);
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: budget,
+            ..Default::default()
+        })
+        .await;
+
+        for key in ["a", "b", "c"] {
+            engine.set(key, vec![0; 100], None).await.unwrap();
+        }
+        assert_eq!(engine.memory_usage(), budget);
+        // Overwriting in place needs no extra room
+        engine.set("a", vec![1; 100], None).await.unwrap();
+        assert!(matches!(
+            engine.set("d", vec![0; 100], None).await,
+            Err(crate::storage::StorageError::OutOfMemory { .. })
+        ));
+
+        engine.del("b", None).await.unwrap();
+        engine.set("d", vec![0; 100], None).await.unwrap();
+        assert_eq!(engine.memory_usage(), budget);
+    }
+
+    #[tokio::test]
+    async fn test_storage_lru_eviction() {
+        let budget = 3 * (1 + 100 + ENTRY_OVERHEAD);
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: budget,
+            eviction_policy: EvictionPolicy::AllKeysLru,
+            ..Default::default()
+        })
+        .await;
+
+        for key in ["a", "b", "c"] {
+            engine.set(key, vec![0; 100], None).await.unwrap();
+        }
+        // "a" is now the most recently used, so "b" goes first
+        engine.get("a").await.unwrap();
+        engine.set("d", vec![0; 100], None).await.unwrap();
+        assert!(!engine.exists("b").await);
+
+        engine.mget(&["c".to_string()]).await.unwrap();
+        engine.set("e", vec![0; 100], None).await.unwrap();
+        assert!(!engine.exists("a").await);
+        for key in ["c", "d", "e"] {
+            assert!(engine.exists(key).await);
+        }
+        assert!(engine.memory_usage() <= budget);
+
+        // A value that can never fit fails instead of emptying the store
+        assert!(engine.set("huge", vec![0; 1024], None).await.is_err());
+        assert!(engine.exists("c").await);
+    }
+
+    #[tokio::test]
+    async fn test_storage_failed_txn_puts_back_what_it_evicted() {
+        let budget = 2 * (1 + 100 + ENTRY_OVERHEAD);
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: budget,
+            eviction_policy: EvictionPolicy::AllKeysLru,
+            ..Default::default()
+        })
+        .await;
+        engine.set("a", vec![1; 100], None).await.unwrap();
+        engine.set("b", vec![2; 100], None).await.unwrap();
+
+        // "c" evicts "a", then a value that can never fit fails the txn
+        let result = engine
+            .txn(vec![
+                TxnOp::Set {
+                    key: "c".to_string(),
+                    value: vec![3; 100],
+                    ttl_secs: None,
+                },
+                TxnOp::Set {
+                    key: "huge".to_string(),
+                    value: vec![0; 1024],
+                    ttl_secs: None,
+                },
+            ])
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::storage::StorageError::OutOfMemory { .. })
+        ));
+        assert_eq!(engine.get("a").await.unwrap().value, vec![1; 100]);
+        assert_eq!(engine.get("b").await.unwrap().value, vec![2; 100]);
+        assert!(!engine.exists("c").await);
+        assert_eq!(engine.memory_usage(), budget);
+    }
+
+    #[tokio::test]
+    async fn test_storage_mset_atomic_out_of_memory_writes_nothing() {
+        let budget = 2 * (1 + 100 + ENTRY_OVERHEAD);
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: budget,
+            eviction_policy: EvictionPolicy::AllKeysLru,
+            ..Default::default()
+        })
+        .await;
+        engine.set("a", vec![1; 100], None).await.unwrap();
+
+        let result = engine
+            .mset_atomic(vec![
+                ("b".to_string(), vec![2; 100], None),
+                ("c".to_string(), vec![3; 100], None),
+                ("huge".to_string(), vec![0; 1024], None),
+            ])
+            .await;
+        assert!(matches!(result, Err(StorageError::OutOfMemory { .. })));
+        assert_eq!(engine.get("a").await.unwrap().value, vec![1; 100]);
+        assert!(!engine.exists("b").await);
+        assert!(!engine.exists("c").await);
+    }
+
+    #[tokio::test]
+    async fn test_storage_failed_version_check_evicts_nothing() {
+        let budget = 2 * (1 + 100 + ENTRY_OVERHEAD);
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: budget,
+            eviction_policy: EvictionPolicy::AllKeysLru,
+            ..Default::default()
+        })
+        .await;
+        engine.set("a", vec![1; 100], None).await.unwrap();
+        engine.set("b", vec![2; 100], None).await.unwrap();
+
+        let result = engine.cas("c", 7, vec![3; 100], None).await;
+        assert!(matches!(result, Err(StorageError::VersionMismatch { .. })));
+        assert!(engine.exists("a").await);
+        assert!(engine.exists("b").await);
+    }
+
+    #[tokio::test]
+    async fn test_storage_mset_atomic_logs_the_keys_it_evicts() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_mset_evict_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: 2 * (1 + 100 + ENTRY_OVERHEAD),
+            eviction_policy: EvictionPolicy::AllKeysLru,
+            ..Default::default()
+        })
+        .await;
+        engine.attach_wal(wal.clone());
+        engine.set("a", vec![1; 100], None).await.unwrap();
+        engine.set("b", vec![2; 100], None).await.unwrap();
+
+        engine
+            .mset_atomic(vec![("c".to_string(), vec![3; 100], None)])
+            .await
+            .unwrap();
+        assert!(!engine.exists("a").await);
+
+        // A replica replaying the log ends up with the same keys
+        let replica = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let mut logged = Vec::new();
+        wal.replay_from(0, |_, entry| {
+            logged.push(entry);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        for entry in &logged {
+            replica.apply_wal_entry(entry).await.unwrap();
+        }
+        let keys: Vec<String> = replica.dump_sorted().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, ["b", "c"]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_value_compression() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            compression: Some(CompressionConfig {
+                algo: CompressionAlgo::Zstd,
+                min_size: 64,
+            }),
+            ..Default::default()
+        })
+        .await;
+        let large = b"abcdefgh".repeat(64);
+
+        engine.set("big", large.clone(), None).await.unwrap();
+        engine.set("small", b"abc".to_vec(), None).await.unwrap();
+        let stored = engine.get_shard("big").get("big").unwrap();
+        assert_eq!(stored.compression, Some(CompressionAlgo::Zstd));
+        assert!(stored.value.len() < large.len());
+        assert_eq!(
+            engine.get_shard("small").get("small").unwrap().compression,
+            None
+        );
+
+        // Readers only ever see the plain value
+        assert_eq!(engine.get("big").await.unwrap().value, large);
+        assert_eq!(engine.getrange("big", 8, 11).await.unwrap(), b"abcd");
+        let fetched = engine.mget(&["big".to_string()]).await.unwrap();
+        assert_eq!(fetched[0].as_ref().unwrap().value, large);
+        let (items, _) = engine.scan("b*", 10, None).await.unwrap();
+        assert_eq!(items[0].1.value, large);
+
+        // Per-write overrides, both ways
+        let (value, algo) = engine.encode_value("big", large.clone(), CompressionMode::None);
+        assert_eq!((value, algo), (large.clone(), None));
+        let (value, algo) = engine.encode_value("x", b"1".repeat(32), CompressionMode::Lz4);
+        assert_eq!(algo, Some(CompressionAlgo::Lz4));
+        engine.set_encoded("x", value, algo, None).await.unwrap();
+        assert_eq!(engine.get("x").await.unwrap().value, b"1".repeat(32));
+
+        // A compressed WAL entry replays as logged
+        let (value, compression) = engine.encode_value("big", large.clone(), CompressionMode::Auto);
+        let replica = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let entry = WalEntry {
+            timestamp: 0,
+            key: "big".to_string(),
+            value: value.clone(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Set,
+            compression,
+            delta: None,
+        };
+        replica.apply_wal_entry(&entry).await.unwrap();
+        assert_eq!(replica.get_shard("big").get("big").unwrap().value, value);
+        assert_eq!(replica.get("big").await.unwrap().value, large);
+    }
+
+    #[tokio::test]
+    async fn test_storage_getrange() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine
+            .set("blob", b"0123456789".to_vec(), None)
+            .await
+            .unwrap();
+
+        // Mid-range, inclusive on both ends
+        assert_eq!(engine.getrange("blob", 2, 5).await.unwrap(), b"2345");
+        // Negative indexes count from the end
+        assert_eq!(engine.getrange("blob", -3, -1).await.unwrap(), b"789");
+        // Past the end clamps instead of failing
+        let slice = engine.getrange_slice("blob", 7, 100).await.unwrap();
+        assert_eq!(slice.bytes, b"789");
+        assert_eq!((slice.start, slice.total_len), (7, 10));
+        assert!(engine.getrange("blob", 20, 30).await.unwrap().is_empty());
+        assert!(engine.getrange("blob", 5, 2).await.unwrap().is_empty());
+
+        assert!(matches!(
+            engine.getrange("missing", 0, -1).await.unwrap_err(),
+            StorageError::KeyNotFound(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_storage_set_bumps_version() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(engine.set("k", b"a".to_vec(), None).await.unwrap(), 1);
+        assert_eq!(engine.set("k", b"b".to_vec(), None).await.unwrap(), 2);
+        assert_eq!(engine.get("k").await.unwrap().version, 2);
+
+        // A deleted key starts over
+        engine.del("k", None).await.unwrap();
+        assert_eq!(engine.set("k", b"c".to_vec(), None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_storage_cas() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        // Expected version 0 means "create if absent"
+        assert_eq!(engine.cas("k", 0, b"a".to_vec(), None).await.unwrap(), 1);
+        assert!(matches!(
+            engine.cas("k", 0, b"b".to_vec(), None).await.unwrap_err(),
+            StorageError::VersionMismatch {
+                expected: 0,
+                actual: 1
+            }
+        ));
+        assert_eq!(engine.cas("k", 1, b"b".to_vec(), None).await.unwrap(), 2);
+        assert_eq!(engine.get("k").await.unwrap().value, b"b");
+    }
+
+    #[tokio::test]
+    async fn test_storage_cas_interleaved_single_winner() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let version = engine.set("k", b"base".to_vec(), None).await.unwrap();
+
+        for round in 0..50 {
+            let expected = version + round;
+            let a = {
+                let engine = engine.clone();
+                tokio::spawn(async move { engine.cas("k", expected, b"a".to_vec(), None).await })
+            };
+            let b = {
+                let engine = engine.clone();
+                tokio::spawn(async move { engine.cas("k", expected, b"b".to_vec(), None).await })
+            };
+            let results = [a.await.unwrap(), b.await.unwrap()];
+
+            assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+            assert_eq!(engine.get("k").await.unwrap().version, expected + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_getset_and_setnx() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(engine.getset("k", b"a".to_vec(), None).await.unwrap(), None);
+        assert_eq!(
+            engine
+                .getset_versioned("k", b"b".to_vec(), None)
+                .await
+                .unwrap(),
+            (Some(b"a".to_vec()), 2)
+        );
+        assert!(!engine.setnx("k", b"c".to_vec(), None).await.unwrap());
+        assert_eq!(engine.get("k").await.unwrap().value, b"b");
+
+        // A stale value counts as absent for both
+        let mut stale = KvEntry::new(b"old".to_vec(), None);
+        stale.expires_at = Some(1);
+        engine
+            .get_shard("gone")
+            .set("gone".to_string(), stale.clone());
+        assert_eq!(
+            engine.getset("gone", b"x".to_vec(), None).await.unwrap(),
+            None
+        );
+        engine.get_shard("nx").set("nx".to_string(), stale);
+        assert!(engine.setnx("nx", b"y".to_vec(), Some(60)).await.unwrap());
+        let entry = engine.get("nx").await.unwrap();
+        assert_eq!((entry.value, entry.version), (b"y".to_vec(), 1));
+        assert!(entry.expires_at.is_some());
+
+        // Of many concurrent SETNXes, exactly one wins
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let engine = engine.clone();
+                tokio::spawn(async move { engine.setnx("race", vec![i], None).await.unwrap() })
+            })
+            .collect();
+        let mut wins = 0;
+        for task in tasks {
+            wins += task.await.unwrap() as usize;
+        }
+        assert_eq!(wins, 1);
+    }
+
+    #[tokio::test]
+    async fn test_storage_append_keeps_ttl_and_replays_once() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            compression: Some(crate::storage::CompressionConfig {
+                algo: CompressionAlgo::Lz4,
+                min_size: 16,
+            }),
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(engine.append("k", b"hello".to_vec()).await.unwrap(), 5);
+        engine.expire("k", 60).await.unwrap();
+        let (len, version) = engine
+            .append_versioned("k", b" world".to_vec())
+            .await
+            .unwrap();
+        assert_eq!((len, version), (11, 2));
+        assert!(engine.ttl("k").await.unwrap().is_some());
+
+        // Long enough to be stored compressed, and still appended to as bytes
+        let tail = b"0123456789".repeat(10);
+        assert_eq!(engine.append("k", tail.clone()).await.unwrap(), 111);
+        let stored = engine.get_shard("k").get("k").unwrap();
+        assert_eq!(stored.compression, Some(CompressionAlgo::Lz4));
+        let mut expected = b"hello world".to_vec();
+        expected.extend_from_slice(&tail);
+        assert_eq!(engine.get("k").await.unwrap().value, expected);
+
+        // An expired value is absent, not a prefix
+        let mut stale = KvEntry::new(b"old".to_vec(), None);
+        stale.expires_at = Some(1);
+        engine.get_shard("gone").set("gone".to_string(), stale);
+        assert_eq!(engine.append("gone", b"new".to_vec()).await.unwrap(), 3);
+
+        // Replaying the same logged append twice applies it once
+        let replica = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let entry = WalEntry {
+            timestamp: 0,
+            key: "r".to_string(),
+            value: b"ab".to_vec(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Append,
+            compression: None,
+            delta: None,
+        };
+        replica.apply_wal_entry(&entry).await.unwrap();
+        replica.apply_wal_entry(&entry).await.unwrap();
+        assert_eq!(replica.get("r").await.unwrap().value, b"ab");
+    }
+
+    #[tokio::test]
+    async fn test_storage_wal_replay_preserves_version() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        let entry = WalEntry {
+            timestamp: 0,
+            key: "k".to_string(),
+            value: b"v".to_vec(),
+            version: 7,
+            ttl: None,
+            op_type: OpType::Cas,
+            compression: None,
+            delta: None,
+        };
+        engine.apply_wal_entry(&entry).await.unwrap();
+        assert_eq!(engine.get("k").await.unwrap().version, 7);
+    }
+
+    #[tokio::test]
+    async fn test_storage_recover_falls_back_on_a_corrupt_control_file() {
+        use crate::storage::control::CONTROL_FILE;
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_recover_{}", uuid::Uuid::new_v4()));
+        let snapshot_dir = dir.join("snapshots");
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let snapshots = SnapshotManager::new(snapshot_dir.to_str().unwrap().to_string());
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+
+        engine.set("a", b"1".to_vec(), None).await.unwrap();
+        let snapshot = snapshots.create_snapshot(&engine).await.unwrap();
+        engine.set("b", b"2".to_vec(), None).await.unwrap();
+        std::fs::write(snapshot_dir.join(CONTROL_FILE), b"not a control file").unwrap();
+
+        // The newest snapshot, then the whole WAL
+        let restarted = StorageEngine::new(config).await;
+        let report = restarted.recover(&snapshots, &wal).await.unwrap();
+        assert_eq!(report.snapshot, Some(snapshot));
+        assert_eq!((report.wal_offset, report.replayed), (0, 2));
+        assert_eq!(restarted.get("a").await.unwrap().value, b"1");
+        assert_eq!(restarted.get("b").await.unwrap().value, b"2");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_sync_write_survives_a_crash() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+        use crate::wal::manager::split_offset;
+
+        let dir = std::env::temp_dir().join(format!("engine_sync_{}", uuid::Uuid::new_v4()));
+        let wal_config = WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(wal_config.clone()).await.unwrap();
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+
+        engine
+            .set_with("durable", b"1".to_vec(), None, Durability::Sync)
+            .await
+            .unwrap();
+        assert_eq!(wal.synced_offset(), wal.current_offset().await);
+        engine.set("lost", b"2".to_vec(), None).await.unwrap();
+        assert!(wal.synced_offset() < wal.current_offset().await);
+
+        // Crash: everything past the last fsync never reached the disk
+        let (sequence, offset) = split_offset(wal.synced_offset());
+        drop(engine);
+        drop(wal);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(
+                dir.join("wal")
+                    .join(format!("{}{}", wal_config.file_prefix, sequence)),
+            )
+            .unwrap()
+            .set_len(offset)
+            .unwrap();
+
+        let wal = WalManager::new(wal_config).await.unwrap();
+        let snapshots = SnapshotManager::new(dir.join("snapshots").to_str().unwrap().to_string());
+        let restarted = StorageEngine::new(config).await;
+        restarted.recover(&snapshots, &wal).await.unwrap();
+        assert_eq!(restarted.get("durable").await.unwrap().value, b"1");
+        assert!(restarted.get("lost").await.is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_write_refused_by_a_full_wal_queue_is_not_applied() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_wal_full_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            queue_capacity: 1,
+            queue_wait_ms: 20,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.attach_wal(wal.clone());
+        engine.set("kept", b"1".to_vec(), None).await.unwrap();
+
+        // Whoever holds the only slot keeps the queue full
+        let slot = wal.reserve().await.unwrap();
+        let full = |result: Result<(), StorageError>| {
+            matches!(
+                result,
+                Err(StorageError::Wal(crate::wal::WalError::QueueFull { .. }))
+            )
+        };
+        assert!(full(
+            engine.set("new", b"v".to_vec(), None).await.map(|_| ())
+        ));
+        assert!(full(engine.incr("n", 1).await.map(|_| ())));
+        assert!(full(engine.del("kept", None).await));
+        assert!(full(
+            engine
+                .txn(vec![TxnOp::Del {
+                    key: "kept".to_string()
+                }])
+                .await
+                .map(|_| ())
+        ));
+        assert!(engine.get("new").await.is_err());
+        assert!(engine.get("n").await.is_err());
+        let kept = engine.get("kept").await.unwrap();
+        assert_eq!((kept.value, kept.version), (b"1".to_vec(), 1));
+
+        drop(slot);
+        engine.set("new", b"v".to_vec(), None).await.unwrap();
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_concurrent_writes_recover_as_applied() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_wal_order_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        if (writer + i) % 5 == 0 {
+                            let _ = engine.del("k", None).await;
+                        } else {
+                            let value = format!("{}-{}", writer, i).into_bytes();
+                            engine.set("k", value, None).await.unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let live = engine.get("k").await.ok();
+
+        let snapshots = SnapshotManager::new(dir.join("snapshots").to_str().unwrap().to_string());
+        let restarted = StorageEngine::new(config).await;
+        restarted.recover(&snapshots, &wal).await.unwrap();
+        let recovered = restarted.get("k").await.ok();
+        assert_eq!(
+            live.map(|e| (e.value, e.version)),
+            recovered.map(|e| (e.value, e.version))
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_replay_skips_writes_the_key_is_past() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        for (version, value) in [(2, "new"), (1, "old"), (2, "old")] {
+            engine
+                .apply_wal_entry(&WalEntry::new(
+                    OpType::Set,
+                    "k",
+                    value.as_bytes().to_vec(),
+                    version,
+                    None,
+                ))
+                .await
+                .unwrap();
+        }
+        let entry = engine.get("k").await.unwrap();
+        assert_eq!((entry.value, entry.version), (b"new".to_vec(), 2));
+    }
+
+    #[tokio::test]
+    async fn test_storage_write_waiting_for_acks_does_not_hold_off_batches() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_wal_acks_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.attach_wal(wal.clone());
+        // No replica ever acks
+        wal.require_acks(1, Duration::from_secs(30));
+
+        let waiting = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.set("slow", b"v".to_vec(), None).await }
+        });
+        sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        // A transaction needs the write gate to itself
+        let txn = Durability::None.scope(engine.txn(vec![TxnOp::Set {
+            key: "t".to_string(),
+            value: b"v".to_vec(),
+            ttl_secs: None,
+        }]));
+        tokio::time::timeout(Duration::from_secs(5), txn)
+            .await
+            .expect("the transaction waited on another write's acks")
+            .unwrap();
+
+        waiting.abort();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_replicated_wal_logs_unlogged_writes() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_replicated_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.attach_wal(wal.clone());
+        wal.set_replicated();
+        // No replica ever acks, and a `Durability::None` write doesn't wait
+        wal.require_acks(1, Duration::from_secs(30));
+        let mut tail = wal.subscribe();
+
+        let set = Durability::None.scope(engine.set("k", b"v".to_vec(), None));
+        tokio::time::timeout(Duration::from_secs(5), set)
+            .await
+            .expect("an unlogged write waited on replica acks")
+            .unwrap();
+        let (_, entry) = tail.recv().await.unwrap();
+        assert_eq!(entry.op_type, OpType::Set);
+        assert_eq!(entry.key, "k");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_txn_the_wal_fails_to_take_is_undone_unannounced() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_txn_undo_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.attach_wal(wal.clone());
+        engine.set("kept", b"1".to_vec(), None).await.unwrap();
+        let mut changes = engine.watch("");
+
+        // No replica ever acks
+        wal.require_acks(1, Duration::from_millis(50));
+        let result = engine
+            .txn(vec![
+                TxnOp::Set {
+                    key: "new".to_string(),
+                    value: b"v".to_vec(),
+                    ttl_secs: None,
+                },
+                TxnOp::Del {
+                    key: "kept".to_string(),
+                },
+            ])
+            .await;
+        assert!(matches!(
+            result,
+            Err(StorageError::Wal(crate::wal::WalError::AckTimeout { .. }))
+        ));
+        assert!(engine.get("new").await.is_err());
+        assert_eq!(engine.get("kept").await.unwrap().version, 1);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), changes.recv())
+                .await
+                .is_err(),
+            "watchers heard of a write that was undone"
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_writes_are_logged_to_the_wal() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_wal_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.attach_wal(wal.clone());
+
+        let version = engine.set("k", b"v".to_vec(), Some(60)).await.unwrap();
+        engine.incr("n", 5).await.unwrap();
+        // Not logged: the client opted out, or the write came from the log
+        Durability::None
+            .scope(engine.set("skipped", b"v".to_vec(), None))
+            .await
+            .unwrap();
+        engine
+            .apply_wal_entry(&WalEntry::new(
+                OpType::Set,
+                "replayed",
+                b"v".to_vec(),
+                3,
+                None,
+            ))
+            .await
+            .unwrap();
+        engine.del("k", None).await.unwrap();
+
+        let mut logged = Vec::new();
+        wal.replay_from(0, |_, entry| {
+            logged.push(entry);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(logged.len(), 3);
+
+        let set = &logged[0];
+        assert_eq!(set.op_type, OpType::Set);
+        assert_eq!((set.key.as_str(), set.value.as_slice()), ("k", &b"v"[..]));
+        assert_eq!((set.version, set.ttl), (version, Some(60)));
+        assert_eq!(logged[1].op_type, OpType::Incr);
+        assert_eq!((logged[1].key.as_str(), logged[1].delta), ("n", Some(5)));
+        assert_eq!(
+            (logged[2].op_type, logged[2].key.as_str()),
+            (OpType::Del, "k")
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_incr_replays_as_a_delta_once() {
+        let primary = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        primary.set("n", b"10".to_vec(), None).await.unwrap();
+        let (value, version) = primary.incr_versioned("n", 5).await.unwrap();
+        assert_eq!((value, version), (15, 2));
+
+        // A replica that had drifted to 100 at version 1
+        let replica = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        replica.set("n", b"100".to_vec(), None).await.unwrap();
+        let entry = WalEntry {
+            timestamp: 0,
+            key: "n".to_string(),
+            value: b"15".to_vec(),
+            version,
+            ttl: None,
+            op_type: OpType::Incr,
+            compression: None,
+            delta: Some(5),
+        };
+        replica.apply_wal_entry(&entry).await.unwrap();
+        let stored = replica.get("n").await.unwrap();
+        assert_eq!((stored.value, stored.version), (b"105".to_vec(), 2));
+
+        // Replaying it again (say, over a snapshot that has it) changes nothing
+        replica.apply_wal_entry(&entry).await.unwrap();
+        assert_eq!(replica.get("n").await.unwrap().value, b"105");
+
+        // Without a delta it is the logged value, as before
+        let old = WalEntry {
+            delta: None,
+            version: 3,
+            ..entry
+        };
+        replica.apply_wal_entry(&old).await.unwrap();
+        assert_eq!(replica.get("n").await.unwrap().value, b"15");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_storage_scan_stream_alongside_writes() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        for i in 0..1000 {
+            engine
+                .set(&format!("user:{:04}", i), vec![b'v'], None)
+                .await
+                .unwrap();
+        }
+        engine.set("other", vec![b'v'], None).await.unwrap();
+
+        // A static keyspace streams every match once, then ends
+        let keys: Vec<String> = engine
+            .scan_stream("user:*", true)
+            .map(|(key, _)| key)
+            .collect()
+            .await;
+        let unique: HashSet<&String> = keys.iter().collect();
+        assert_eq!((keys.len(), unique.len()), (1000, 1000));
+
+        let writer = {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                for i in 0..3000 {
+                    let key = format!("user:{:04}", i % 1500);
+                    if i % 3 == 0 {
+                        let _ = engine.del(&key, None).await;
+                    } else {
+                        engine.set(&key, vec![b'w'], None).await.unwrap();
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        // Consumed slowly while the writer runs: no deadlock, no repeats
+        let mut stream = Box::pin(engine.scan_stream("user:*", true));
+        let mut seen = HashSet::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some((key, _)) = stream.next().await {
+                assert!(seen.insert(key));
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("scan did not finish");
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_storage_scan_glob_pages_every_key_once() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 8,
+            ..Default::default()
+        })
+        .await;
+        for i in 0..57 {
+            engine
+                .set(&format!("user:{}", i), b"v".to_vec(), None)
+                .await
+                .unwrap();
+        }
+        engine.set("order:1", b"v".to_vec(), None).await.unwrap();
+        engine
+            .set("_sys.users:x", b"v".to_vec(), None)
+            .await
+            .unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (items, next) = engine.scan("user:*", 10, cursor).await.unwrap();
+            assert!(items.len() <= 10);
+            seen.extend(items.into_iter().map(|(key, _)| key));
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        seen.sort();
+        let mut expected: Vec<String> = (0..57).map(|i| format!("user:{}", i)).collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        // `?` matches one character; system keys can be hidden
+        let (items, _) = engine.scan("user:?", 100, None).await.unwrap();
+        assert_eq!(items.len(), 10);
+        let page = engine.scan_glob("*", 100, None, false).await.unwrap();
+        assert_eq!(page.items.len(), 58);
+        assert!(page.items.iter().all(|(key, _)| !key.starts_with("_sys.")));
+
+        assert!(matches!(
+            engine.scan("*", 10, Some("not a cursor".to_string())).await,
+            Err(StorageError::InvalidRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_storage_incr() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        // A missing key starts at 0
+        assert_eq!(engine.incr("counter", 5).await.unwrap(), 5);
+        assert_eq!(engine.incr("counter", -7).await.unwrap(), -2);
+        assert_eq!(engine.get("counter").await.unwrap().value, b"-2");
+
+        engine.set("text", b"abc".to_vec(), None).await.unwrap();
+        assert!(matches!(
+            engine.incr("text", 1).await.unwrap_err(),
+            StorageError::NotAnInteger(key) if key == "text"
+        ));
+
+        engine
+            .set("max", i64::MAX.to_string().into_bytes(), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.incr("max", 1).await.unwrap_err(),
+            StorageError::InvalidRequest(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_storage_incr_concurrent_no_lost_updates() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    for _ in 0..250 {
+                        engine.incr("hits", 1).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(engine.get("hits").await.unwrap().value, b"2000");
+    }
+
+    #[tokio::test]
+    async fn test_storage_mset_batch_limit() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            max_batch_ops: 10,
+            ..Default::default()
+        })
+        .await;
+        let batch = |n: usize| {
+            (0..n)
+                .map(|i| (format!("k{}", i), b"v".to_vec(), None))
+                .collect::<Vec<_>>()
+        };
+
+        match engine.mset(batch(11)).await {
+            Err(StorageError::InvalidRequest(msg)) => {
+                assert!(msg.contains("11") && msg.contains("10"), "{}", msg)
+            }
+            other => panic!("expected InvalidRequest, got {:?}", other),
+        }
+        assert!(engine.get("k0").await.is_err());
+
+        engine.mset(batch(10)).await.unwrap();
+        assert!(engine.get("k9").await.is_ok());
+
+        assert!(engine.mset_atomic(batch(11)).await.is_err());
+        let keys: Vec<String> = (0..11).map(|i| format!("k{}", i)).collect();
+        assert!(engine.mget(&keys).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_storage_mget_parallel_with_missing() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("a", b"1".to_vec(), None).await.unwrap();
+        engine.set("c", b"3".to_vec(), None).await.unwrap();
+
+        let keys = ["a", "b", "c", "a"].map(String::from);
+        let values: Vec<Option<Vec<u8>>> = engine
+            .mget(&keys)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.map(|e| e.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                Some(b"1".to_vec()),
+                None,
+                Some(b"3".to_vec()),
+                Some(b"1".to_vec())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_storage_del_with_expected_version() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("k", b"1".to_vec(), None).await.unwrap();
+        let current = engine.set("k", b"2".to_vec(), None).await.unwrap();
+
+        assert!(matches!(
+            engine.del("k", Some(current - 1)).await,
+            Err(StorageError::VersionMismatch { expected, actual })
+                if expected == current - 1 && actual == current
+        ));
+        assert_eq!(engine.get("k").await.unwrap().value, b"2".to_vec());
+
+        engine.del("k", Some(current)).await.unwrap();
+        assert!(!engine.exists("k").await);
+        assert!(matches!(
+            engine.del("k", Some(current)).await,
+            Err(StorageError::KeyNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_storage_rejects_oversized_keys_and_values() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_key_bytes: 8,
+            max_value_bytes: 16,
+            ..Default::default()
+        })
+        .await;
+
+        assert!(matches!(
+            engine.set("much_too_long", b"v".to_vec(), None).await,
+            Err(StorageError::KeyTooLarge { len: 13, limit: 8 })
+        ));
+        assert!(matches!(
+            engine.incr("much_too_long", 1).await,
+            Err(StorageError::KeyTooLarge { .. })
+        ));
+        assert!(matches!(
+            engine.set("k", vec![0; 17], None).await,
+            Err(StorageError::ValueTooLarge { len: 17, .. })
+        ));
+        assert!(matches!(
+            engine.cas("k", 0, vec![0; 17], None).await,
+            Err(StorageError::ValueTooLarge { .. })
+        ));
+        engine.set("k", vec![0; 16], None).await.unwrap();
+        assert!(matches!(
+            engine.getset("k", vec![1; 17], None).await,
+            Err(StorageError::ValueTooLarge { len: 17, .. })
+        ));
+        assert!(matches!(
+            engine.setnx("fresh", vec![1; 17], None).await,
+            Err(StorageError::ValueTooLarge { len: 17, .. })
+        ));
+        assert_eq!(engine.get("k").await.unwrap().value, vec![0; 16]);
+        assert!(!engine.exists("fresh").await);
+
+        // The result of an append counts, and a rejected one changes nothing
+        engine.set("a", vec![1; 10], None).await.unwrap();
+        assert!(matches!(
+            engine.append("a", vec![2; 7]).await,
+            Err(StorageError::ValueTooLarge { len: 17, .. })
+        ));
+        assert_eq!(engine.get("a").await.unwrap().value, vec![1; 10]);
+
+        // An atomic batch is rejected as a whole
+        let items = vec![
+            ("b".to_string(), b"ok".to_vec(), None),
+            ("c".to_string(), vec![0; 17], None),
+        ];
+        assert!(engine.mset_atomic(items).await.is_err());
+        assert!(!engine.exists("b").await);
+    }
+
+    #[tokio::test]
+    async fn test_storage_memory_usage_tracks_value_bytes() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 2,
+            ..Default::default()
+        })
+        .await;
+        engine.set("small", b"v".to_vec(), None).await.unwrap();
+        let before = engine.memory_usage();
+
+        engine.set("big", vec![7; 1 << 20], None).await.unwrap();
+        let grown = engine.memory_usage() - before;
+        assert!(grown >= 1 << 20 && grown < (1 << 20) + 1024, "{}", grown);
+
+        engine.del("big", None).await.unwrap();
+        assert_eq!(engine.memory_usage(), before);
+    }
+
+    #[tokio::test]
+    async fn test_storage_mexists_ignores_expired_keys() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("a", b"1".to_vec(), None).await.unwrap();
+        engine.set("gone", b"2".to_vec(), Some(1)).await.unwrap();
+        sleep(Duration::from_millis(1100)).await;
+
+        let keys = ["a", "gone", "missing", "a"].map(String::from);
+        assert_eq!(
+            engine.mexists(&keys).await.unwrap(),
+            vec![true, false, false, true]
+        );
+        assert!(!engine.exists("gone").await);
+        assert!(engine.get("gone").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_storage_mset_best_effort_vs_atomic() {
+        let mut quotas = HashMap::new();
+        quotas.insert(
+            "tenant".to_string(),
+            NamespaceQuota {
+                max_keys: Some(1),
+                max_bytes: None,
+            },
+        );
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            namespace_quotas: quotas,
+            ..Default::default()
+        })
+        .await;
+        let batch = || {
+            vec![
+                ("plain".to_string(), b"v".to_vec(), None),
+                ("ns:tenant:1".to_string(), b"v".to_vec(), None),
+                ("ns:tenant:2".to_string(), b"v".to_vec(), None),
+            ]
+        };
+
+        // Atomic: the second tenant key busts the quota, so nothing is written
+        assert!(matches!(
+            engine.mset_atomic(batch()).await,
+            Err(StorageError::QuotaExceeded { .. })
+        ));
+        assert!(engine.get("plain").await.is_err());
+        assert!(engine.get("ns:tenant:1").await.is_err());
+
+        // Best effort: everything but the over-quota key lands
+        let results = engine.mset(batch()).await.unwrap();
+        let failed: Vec<&str> = results
+            .iter()
+            .filter(|(_, r)| r.is_err())
+            .map(|(key, _)| key.as_str())
+            .collect();
+        assert_eq!(failed, vec!["ns:tenant:2"]);
+        assert!(engine.get("plain").await.is_ok());
+        assert!(engine.get("ns:tenant:1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_storage_txn_failing_cas_rolls_back_every_op() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let guarded = engine.set("guarded", b"v1".to_vec(), None).await.unwrap();
+        engine.set("doomed", b"keep".to_vec(), None).await.unwrap();
+
+        let ops = |expected_version| {
+            vec![
+                TxnOp::Set {
+                    key: "fresh".to_string(),
+                    value: b"new".to_vec(),
+                    ttl_secs: None,
+                },
+                TxnOp::Del {
+                    key: "doomed".to_string(),
+                },
+                TxnOp::Cas {
+                    key: "guarded".to_string(),
+                    expected_version,
+                    value: b"v2".to_vec(),
+                    ttl_secs: None,
+                },
+            ]
+        };
+
+        assert!(matches!(
+            engine.txn(ops(guarded + 1)).await,
+            Err(StorageError::VersionMismatch { .. })
+        ));
+        assert!(engine.get("fresh").await.is_err());
+        assert_eq!(engine.get("doomed").await.unwrap().value, b"keep");
+        let entry = engine.get("guarded").await.unwrap();
+        assert_eq!((entry.value, entry.version), (b"v1".to_vec(), guarded));
+
+        let versions = engine.txn(ops(guarded)).await.unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(engine.get("fresh").await.unwrap().value, b"new");
+        assert!(engine.get("doomed").await.is_err());
+        assert_eq!(engine.get("guarded").await.unwrap().value, b"v2");
+    }
+
+    #[tokio::test]
+    async fn test_storage_exec_aborts_when_a_watched_key_changes() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("balance", b"10".to_vec(), None).await.unwrap();
+        let debit = || {
+            vec![TxnOp::Set {
+                key: "balance".to_string(),
+                value: b"5".to_vec(),
+                ttl_secs: None,
+            }]
+        };
+
+        // Untouched since the WATCH: commits
+        engine
+            .watch_keys("s1", &["balance".to_string(), "absent".to_string()])
+            .unwrap();
+        assert!(matches!(
+            engine.exec("s1", debit()).await.unwrap(),
+            ExecOutcome::Committed(_)
+        ));
+        assert_eq!(engine.get("balance").await.unwrap().value, b"5");
+
+        // Written by someone else in between: aborts and writes nothing
+        engine.watch_keys("s1", &["balance".to_string()]).unwrap();
+        engine.set("balance", b"7".to_vec(), None).await.unwrap();
+        assert_eq!(
+            engine.exec("s1", debit()).await.unwrap(),
+            ExecOutcome::Aborted {
+                key: "balance".to_string()
+            }
+        );
+        assert_eq!(engine.get("balance").await.unwrap().value, b"7");
+
+        // Deleted and recreated at the very version that was watched
+        engine.set("fresh", b"a".to_vec(), None).await.unwrap();
+        engine.watch_keys("s1", &["fresh".to_string()]).unwrap();
+        engine.del("fresh", None).await.unwrap();
+        engine.set("fresh", b"b".to_vec(), None).await.unwrap();
+        assert_eq!(engine.get("fresh").await.unwrap().version, 1);
+        assert!(matches!(
+            engine.exec("s1", debit()).await.unwrap(),
+            ExecOutcome::Aborted { .. }
+        ));
+
+        // The WATCH ended with the EXEC, and other sessions never had one
+        assert!(matches!(
+            engine.exec("s1", debit()).await,
+            Err(StorageError::InvalidRequest(_))
+        ));
+        engine.watch_keys("s2", &["balance".to_string()]).unwrap();
+        engine.unwatch_keys("s2");
+        assert!(matches!(
+            engine.exec("s2", debit()).await,
+            Err(StorageError::InvalidRequest(_))
+        ));
+        assert_eq!(engine.get("balance").await.unwrap().value, b"7");
+    }
+
+    #[tokio::test]
+    async fn test_storage_recover_skips_an_uncommitted_txn() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_txn_{}", uuid::Uuid::new_v4()));
+        let wal_config = WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(wal_config.clone()).await.unwrap();
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+
+        // Deleting a missing key logs nothing, so the begin marker counts 1
+        engine
+            .txn(vec![
+                TxnOp::Del {
+                    key: "missing".to_string(),
+                },
+                TxnOp::Set {
+                    key: "committed".to_string(),
+                    value: b"1".to_vec(),
+                    ttl_secs: None,
+                },
+            ])
+            .await
+            .unwrap();
+        // A transaction a crash cut short after its first entry; the
+        // restarted node goes on in a new segment
+        let begin = WalEntry::new(OpType::TxnBegin, "", Vec::new(), 2, None);
+        let torn = WalEntry::new(OpType::Set, "torn", b"2".to_vec(), 1, None);
+        wal.append_all(&[begin, torn]).await.unwrap();
+        drop(engine);
+        drop(wal);
+        let wal = WalManager::new(wal_config).await.unwrap();
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+        engine.set("after", b"3".to_vec(), None).await.unwrap();
+        engine.set("after2", b"4".to_vec(), None).await.unwrap();
+
+        let snapshots = SnapshotManager::new(dir.join("snapshots").to_str().unwrap().to_string());
+        let restarted = StorageEngine::new(config).await;
+        let report = restarted.recover(&snapshots, &wal).await.unwrap();
+        assert_eq!(report.replayed, 3);
+        assert_eq!(restarted.get("committed").await.unwrap().value, b"1");
+        assert!(restarted.get("torn").await.is_err());
+        assert_eq!(restarted.get("after").await.unwrap().value, b"3");
+        assert_eq!(restarted.get("after2").await.unwrap().value, b"4");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_recover_keeps_ttls_from_the_original_write() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_ttl_{}", uuid::Uuid::new_v4()));
+        let wal_config = WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(wal_config).await.unwrap();
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+        engine.set("live", b"1".to_vec(), Some(3600)).await.unwrap();
+        engine.set("stale", b"old".to_vec(), None).await.unwrap();
+        engine.set("expired", b"2".to_vec(), None).await.unwrap();
+        let live = engine.get_shard("live").get("live").unwrap().expires_at;
+
+        // Writes whose TTL ran out a minute ago, as if the reaper had since
+        // dropped them without logging anything
+        let ago = now_nanos() - 60 * 1_000_000_000;
+        let overwrite = WalEntry {
+            timestamp: ago,
+            ..WalEntry::new(OpType::Set, "stale", b"new".to_vec(), 0, Some(1))
+        };
+        let expire = WalEntry {
+            timestamp: ago,
+            ..WalEntry::new(OpType::Expire, "expired", Vec::new(), 0, Some(1))
+        };
+        wal.append_all(&[overwrite, expire]).await.unwrap();
+
+        let snapshots = SnapshotManager::new(dir.join("snapshots").to_str().unwrap().to_string());
+        let restarted = StorageEngine::new(config).await;
+        restarted.recover(&snapshots, &wal).await.unwrap();
+        assert_eq!(
+            restarted.get_shard("live").get("live").unwrap().expires_at,
+            live
+        );
+        assert!(restarted.get_shard("stale").get("stale").is_none());
+        assert!(restarted.get_shard("expired").get("expired").is_none());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_replica_drops_a_write_that_already_expired() {
+        let replica = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        replica.set("k", b"old".to_vec(), None).await.unwrap();
+
+        let ago = now_nanos() - 60 * 1_000_000_000;
+        let expired = WalEntry {
+            timestamp: ago,
+            ..WalEntry::new(OpType::Set, "k", b"new".to_vec(), 2, Some(1))
+        };
+        replica.apply_wal_entry(&expired).await.unwrap();
+        assert!(replica.get_shard("k").get("k").is_none());
+
+        // One still running keeps the deadline the primary gave it
+        let running = WalEntry {
+            timestamp: ago,
+            ..WalEntry::new(OpType::Set, "k", b"new".to_vec(), 3, Some(3600))
+        };
+        replica.apply_wal_entry(&running).await.unwrap();
+        assert_eq!(
+            replica.get_shard("k").get("k").unwrap().expires_at,
+            Some(ago + 3600 * 1_000_000_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_storage_recover_keeps_a_live_restore() {
+        use crate::wal::config::{SyncPolicy, WalConfig};
+
+        let dir = std::env::temp_dir().join(format!("engine_restore_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.join("wal").to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let snapshots = SnapshotManager::new(dir.join("snapshots").to_str().unwrap().to_string());
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config.clone()).await;
+        engine.attach_wal(wal.clone());
+
+        engine.set("k", b"old".to_vec(), None).await.unwrap();
+        let filename = snapshots.create_snapshot(&engine).await.unwrap();
+        engine.set("k", b"new".to_vec(), None).await.unwrap();
+        engine.set("dropped", b"x".to_vec(), None).await.unwrap();
+        snapshots
+            .restore_snapshot(&engine, &filename, false)
+            .await
+            .unwrap();
+        engine.set("after", b"y".to_vec(), None).await.unwrap();
+
+        // Replays the whole log over the newest snapshot, restore included
+        let restarted = StorageEngine::new(config).await;
+        let report = restarted.recover(&snapshots, &wal).await.unwrap();
+        assert_eq!(report.snapshot.as_deref(), Some(filename.as_str()));
+        assert_eq!(report.replayed, 1);
+        assert_eq!(restarted.get("k").await.unwrap().value, b"old");
+        assert!(restarted.get("dropped").await.is_err());
+        assert_eq!(restarted.get("after").await.unwrap().value, b"y");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_dump_sorted_is_deterministic() {
+        let small = StorageEngine::new(StorageConfig {
+            num_shards: 2,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        })
+        .await;
+        let large = StorageEngine::new(StorageConfig {
+            num_shards: 16,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        })
+        .await;
+
+        for i in 0..20 {
+            small
+                .set(&format!("k{:02}", i), format!("v{}", i).into_bytes(), None)
+                .await
+                .unwrap();
+        }
+        small.del("k05", None).await.unwrap();
+        small
+            .set("_sys.users:admin", b"x".to_vec(), None)
+            .await
+            .unwrap();
+
+        // Same logical state, reached in reverse order with extra churn
+        large.set("k05", b"gone".to_vec(), None).await.unwrap();
+        for i in (0..20).rev() {
+            large
+                .set(&format!("k{:02}", i), b"stale".to_vec(), None)
+                .await
+                .unwrap();
+            large
+                .set(&format!("k{:02}", i), format!("v{}", i).into_bytes(), None)
+                .await
+                .unwrap();
+        }
+        large.del("k05", None).await.unwrap();
+
+        // created_at is wall-clock and version counts the churn, so compare
+        // everything else
+        let project = |dump: Vec<(String, KvEntry)>| {
+            dump.into_iter()
+                .map(|(k, e)| (k, e.value, e.expires_at))
+                .collect::<Vec<_>>()
+        };
+        let a = project(small.dump_sorted());
+        let b = project(large.dump_sorted());
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 19);
+        assert!(a.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[tokio::test]
+    async fn test_storage_namespace_quotas() {
+        let mut namespace_quotas = HashMap::new();
+        namespace_quotas.insert(
+            "tenant_a".to_string(),
+            NamespaceQuota {
+                max_keys: Some(3),
+                max_bytes: None,
+            },
+        );
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            namespace_quotas,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+
+        for i in 0..3 {
+            engine
+                .set(&format!("ns:tenant_a:{}", i), b"v".to_vec(), None)
+                .await
+                .unwrap();
+        }
+        let result = engine.set("ns:tenant_a:3", b"v".to_vec(), None).await;
+        assert!(matches!(
+            result.unwrap_err(),
+            StorageError::QuotaExceeded { namespace, .. } if namespace == "tenant_a"
+        ));
+
+        // Overwrites don't add keys; deletes free room
+        engine
+            .set("ns:tenant_a:0", b"v2".to_vec(), None)
+            .await
+            .unwrap();
+        engine.del("ns:tenant_a:1", None).await.unwrap();
+        engine
+            .set("ns:tenant_a:3", b"v".to_vec(), None)
+            .await
+            .unwrap();
+        assert_eq!(engine.namespace_usage("tenant_a").keys, 3);
+
+        // Other namespaces are unaffected
+        for i in 0..10 {
+            engine
+                .set(&format!("ns:tenant_b:{}", i), b"v".to_vec(), None)
+                .await
+                .unwrap();
+        }
+
+        // Quotas stored in the catalog apply immediately
+        engine
+            .set(
+                "_sys.quotas:tenant_b",
+                br#"{"max_bytes": 200}"#.to_vec(),
+                None,
+            )
+            .await
+            .unwrap();
+        let result = engine.set("ns:tenant_b:big", vec![0u8; 200], None).await;
+        assert!(matches!(
+            result.unwrap_err(),
+            StorageError::QuotaExceeded { .. }
+        ));
+        engine
+            .set("ns:tenant_b:small", b"v".to_vec(), None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_storage_managed_namespace_ttl_and_tags() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            managed_namespaces: vec!["sessions".to_string()],
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+
+        let value = br#"{"_ttl": 10, "_tags": ["web"], "user": "alice"}"#.to_vec();
+        engine
+            .set("ns:sessions:s1", value.clone(), None)
+            .await
+            .unwrap();
+
+        let entry = engine.get("ns:sessions:s1").await.unwrap();
+        let ttl_nanos = entry.expires_at.unwrap() - entry.created_at;
+        assert_eq!(ttl_nanos, 10 * 1_000_000_000);
+        assert_eq!(entry.value, value);
+        assert_eq!(
+            engine.keys_with_tag("sessions", "web").await,
+            vec!["ns:sessions:s1".to_string()]
+        );
+
+        // Retagging moves the key; deleting drops it from the index
+        engine
+            .set("ns:sessions:s1", br#"{"_tags": ["mobile"]}"#.to_vec(), None)
+            .await
+            .unwrap();
+        assert!(engine.keys_with_tag("sessions", "web").await.is_empty());
+        engine.del("ns:sessions:s1", None).await.unwrap();
+        assert!(engine.keys_with_tag("sessions", "mobile").await.is_empty());
+
+        // Unmanaged namespaces store the same value verbatim, with no TTL
+        engine.set("ns:other:s1", value, None).await.unwrap();
+        assert!(engine
+            .get("ns:other:s1")
+            .await
+            .unwrap()
+            .expires_at
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_storage_maintenance_mode() {
+        let config = StorageConfig {
+            num_shards: 4,
+            snapshot_dir: "test_snapshots".to_string(),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+        engine.set("stable", b"v1".to_vec(), None).await.unwrap();
+
+        engine.set_maintenance_mode(true);
+        assert!(engine.is_maintenance_mode());
+
+        // Writes are rejected, reads still served
+        let result = engine.set("stable", b"v2".to_vec(), None).await;
+        assert!(matches!(result.unwrap_err(), StorageError::MaintenanceMode));
+        let result = engine.del("stable", None).await;
+        assert!(matches!(result.unwrap_err(), StorageError::MaintenanceMode));
+        assert_eq!(engine.get("stable").await.unwrap().value, b"v1");
+
+        engine.set_maintenance_mode(false);
+        engine.set("stable", b"v2".to_vec(), None).await.unwrap();
+        assert_eq!(engine.get("stable").await.unwrap().value, b"v2");
+    }
+
+    #[tokio::test]
+    async fn test_storage_replay_skips_memory_and_quota_admission() {
+        let mut namespace_quotas = HashMap::new();
+        namespace_quotas.insert(
+            "tenant".to_string(),
+            NamespaceQuota {
+                max_keys: Some(1),
+                max_bytes: None,
+            },
+        );
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            max_memory_bytes: 2 * (1 + 100 + ENTRY_OVERHEAD),
+            eviction_policy: EvictionPolicy::NoEviction,
+            namespace_quotas,
+            ..Default::default()
+        })
+        .await;
+        engine.set("a", vec![1; 100], None).await.unwrap();
+        engine.set("ns:tenant:1", Vec::new(), None).await.unwrap();
+        assert!(engine.set("b", vec![2; 100], None).await.is_err());
+        assert!(engine.set("ns:tenant:2", Vec::new(), None).await.is_err());
+
+        // Admitted on the node that logged them, so they all replay
+        let append = WalEntry::new(OpType::Append, "c", b"x".to_vec(), 1, None);
+        let incr = WalEntry {
+            delta: Some(1),
+            ..WalEntry::new(OpType::Incr, "n", b"1".to_vec(), 1, None)
+        };
+        for entry in [
+            WalEntry::new(OpType::Set, "b", vec![2; 100], 1, None),
+            WalEntry::new(OpType::Set, "old", vec![3; 100], 0, None),
+            WalEntry::new(OpType::Set, "ns:tenant:2", b"v".to_vec(), 1, None),
+            append,
+            incr,
+        ] {
+            engine.apply_wal_entry(&entry).await.unwrap();
+        }
+        assert_eq!(engine.get("b").await.unwrap().value, vec![2; 100]);
+        assert_eq!(engine.get("old").await.unwrap().value, vec![3; 100]);
+        assert_eq!(engine.get("c").await.unwrap().value, b"x");
+        assert_eq!(engine.get("n").await.unwrap().value, b"1");
+        assert_eq!(engine.namespace_usage("tenant").keys, 2);
+    }
+
+    #[tokio::test]
+    async fn test_storage_replay_goes_through_maintenance_mode() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("gone", b"v".to_vec(), None).await.unwrap();
+        engine.set_maintenance_mode(true);
+
+        for entry in [
+            WalEntry::new(OpType::Set, "replayed", b"v".to_vec(), 1, None),
+            WalEntry::new(OpType::Expire, "replayed", Vec::new(), 0, Some(60)),
+            WalEntry::new(OpType::Del, "gone", Vec::new(), 0, None),
+        ] {
+            engine.apply_wal_entry(&entry).await.unwrap();
+        }
+        assert!(engine.get("replayed").await.unwrap().expires_at.is_some());
+        assert!(engine.get("gone").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_storage_namespaced_access() {
+        let config = StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).await;
+        engine
+            .set("user:1", b"legacy".to_vec(), None)
+            .await
+            .unwrap();
+        engine
+            .set_in("app1", "user:1", b"a".to_vec(), None)
+            .await
+            .unwrap();
+        engine
+            .set_in("app2", "user:1", b"b".to_vec(), None)
+            .await
+            .unwrap();
+
+        // Same key, three namespaces; unprefixed keys are the default one
+        assert_eq!(engine.get_in("app1", "user:1").await.unwrap().value, b"a");
+        assert_eq!(engine.get("ns:app2:user:1").await.unwrap().value, b"b");
+        assert_eq!(
+            engine.get_in("default", "user:1").await.unwrap().value,
+            b"legacy"
+        );
+
+        let page = engine.scan_in("app1", "user:*", 10, None).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].0, "user:1");
+        assert_eq!(page.items[0].1.value, b"a");
+
+        engine.del_in("app1", "user:1").await.unwrap();
+        assert!(engine.get_in("app1", "user:1").await.is_err());
+        assert!(engine.get_in("app2", "user:1").await.is_ok());
+
+        // The default namespace sees neither tenant's keys
+        assert!(engine.get_in("default", "ns:app2:user:1").await.is_err());
+        let page = engine.scan_in("default", "*", 10, None).await.unwrap();
+        let keys: Vec<&str> = page.items.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["user:1"]);
+        let streamed: Vec<(String, KvEntry)> = engine.scan_stream("*", false).collect().await;
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].0, "user:1");
+
+        // The catalog is out of reach
+        engine
+            .set("_sys.users:admin", b"x".to_vec(), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.get_in("default", "_sys.users:admin").await,
+            Err(crate::storage::StorageError::InvalidRequest(_))
+        ));
+        assert!(engine
+            .set_in("_sys", "users:admin", vec![], None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_storage_restore_refuses_during_writes_unless_forced() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        engine.set("k", b"old".to_vec(), None).await.unwrap();
+        let state = engine.snapshot().await;
+        engine.set("k", b"new".to_vec(), None).await.unwrap();
+
+        // A write in flight holds the gate shared
+        let in_flight = engine.write_gate.read().await;
+        assert!(matches!(
+            engine
+                .restore_from_snapshot("snapshot_1.bin", state.clone(), false)
+                .await,
+            Err(crate::storage::StorageError::Concurrency(_))
+        ));
+        assert_eq!(engine.get("k").await.unwrap().value, b"new");
+
+        let forced = {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                engine
+                    .restore_from_snapshot("snapshot_1.bin", state, true)
+                    .await
+            })
+        };
+        sleep(Duration::from_millis(20)).await;
+        assert!(!forced.is_finished());
+        drop(in_flight);
+        forced.await.unwrap().unwrap();
+        assert_eq!(engine.get("k").await.unwrap().value, b"old");
+    }
+
+    #[tokio::test]
+    async fn test_storage_watch_sees_sets_and_deletes() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let mut watcher = engine.watch("user:");
+
+        engine.set("order:1", b"x".to_vec(), None).await.unwrap();
+        engine.set("user:1", b"a".to_vec(), None).await.unwrap();
+        engine.incr("user:1:logins", 1).await.unwrap();
+        engine.del("user:1", None).await.unwrap();
+        // Failed writes are not changes
+        assert!(engine.del("user:1", None).await.is_err());
+        assert!(engine.cas("user:2", 5, b"b".to_vec(), None).await.is_err());
+
+        let mut seen = Vec::new();
+        engine.set("user:3", b"c".to_vec(), None).await.unwrap();
+        for _ in 0..4 {
+            let event = watcher.recv().await.unwrap();
+            seen.push((event.key, event.op, event.version));
+        }
+        assert_eq!(
+            seen,
+            vec![
+                ("user:1".to_string(), ChangeOp::Set, 1),
+                ("user:1:logins".to_string(), ChangeOp::Set, 1),
+                ("user:1".to_string(), ChangeOp::Del, 0),
+                ("user:3".to_string(), ChangeOp::Set, 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_storage_watch_sees_expiries() {
+        let engine = StorageEngine::new(StorageConfig {
+            num_shards: 4,
+            ..Default::default()
+        })
+        .await;
+        let mut watcher = engine.watch("user:");
+        let stale = || {
+            let mut entry = KvEntry::new(b"stale".to_vec(), None);
+            entry.expires_at = Some(1);
+            entry
+        };
+
+        engine.set("user:1", b"a".to_vec(), Some(1)).await.unwrap();
+        engine
+            .get_shard("user:2")
+            .set("user:2".to_string(), stale());
+        assert!(engine.get("user:2").await.is_err());
+        // Rewritten before the reaper got to it, or already gone: no event
+        engine
+            .get_shard("user:3")
+            .set("user:3".to_string(), stale());
+        engine.set("user:3", b"c".to_vec(), None).await.unwrap();
+        let shard = engine.shard_index("user:3");
+        assert!(!engine.expire_in_shard(shard, "user:3").await.unwrap());
+        let shard = engine.shard_index("user:4");
+        assert!(engine.expire_in_shard(shard, "user:4").await.is_err());
+        tokio::time::sleep(Duration::from_millis(1300)).await;
+
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            let event = watcher.recv().await.unwrap();
+            seen.push((event.key, event.op, event.reason));
+        }
+        assert_eq!(
+            seen,
+            vec![
+                ("user:1".to_string(), ChangeOp::Set, None),
+                (
+                    "user:2".to_string(),
+                    ChangeOp::Expired,
+                    Some(ExpiryReason::OnRead)
+                ),
+                ("user:3".to_string(), ChangeOp::Set, None),
+                (
+                    "user:1".to_string(),
+                    ChangeOp::Expired,
+                    Some(ExpiryReason::Reaped)
+                ),
+            ]
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), watcher.recv())
+                .await
+                .is_err()
+        );
+    }
 }
@@ -5,12 +5,8 @@ pub enum StorageError {
     #[error("Key not found: {0}")]
     KeyNotFound(String),
 
-    #[error("CAS failed: version mismatch for key {key} (expected {expected}, got {got})")]
-    CasFailed {
-        key: String,
-        expected: u64,
-        got: u64,
-    },
+    #[error("Version mismatch: expected {expected}, actual {actual}")]
+    VersionMismatch { expected: u64, actual: u64 },
 
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
@@ -18,6 +14,46 @@ pub enum StorageError {
     #[error("Serialization error: {0}")]
     Serialization(#[from] Box<bincode::ErrorKind>),
 
+    #[error("Value at key {0} is not an integer")]
+    NotAnInteger(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Concurrency error: {0}")]
     Concurrency(String),
+
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("Node is in maintenance mode, writes are temporarily disabled")]
+    MaintenanceMode,
+
+    #[error("A bulk load is in progress, writes are temporarily disabled")]
+    BulkLoadInProgress,
+
+    #[error("WAL error: {0}")]
+    Wal(#[from] crate::wal::error::WalError),
+
+    #[error("Quota exceeded for namespace {namespace}: {reason}")]
+    QuotaExceeded { namespace: String, reason: String },
+
+    #[error("Corrupt compressed value: {0}")]
+    Compression(String),
+
+    #[error(transparent)]
+    Encryption(#[from] crate::storage::encryption::EncryptionError),
+
+    #[error("Out of memory: {used} of {limit} bytes in use")]
+    OutOfMemory { used: u64, limit: u64 },
+
+    #[error("Key is {len} bytes, the limit is {limit}")]
+    KeyTooLarge { len: usize, limit: usize },
+
+    #[error("Value for key {key} is {len} bytes, the limit is {limit}")]
+    ValueTooLarge {
+        key: String,
+        len: usize,
+        limit: usize,
+    },
 }
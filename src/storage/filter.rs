@@ -0,0 +1,80 @@
+/// Server-side predicate evaluated against entry values during a scan.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueFilter {
+    /// Value contains the given byte sequence.
+    Contains(Vec<u8>),
+    /// Value length lies within the (inclusive) bounds.
+    Length {
+        min: Option<usize>,
+        max: Option<usize>,
+    },
+    /// Value is a JSON document whose field at the dotted `path` (e.g.
+    /// `user.roles.0`) equals `equals`. Non-JSON values never match.
+    JsonEq {
+        path: String,
+        equals: serde_json::Value,
+    },
+}
+
+impl ValueFilter {
+    pub fn matches(&self, value: &[u8]) -> bool {
+        match self {
+            ValueFilter::Contains(needle) => {
+                needle.is_empty() || value.windows(needle.len()).any(|w| w == needle.as_slice())
+            }
+            ValueFilter::Length { min, max } => {
+                min.is_none_or(|min| value.len() >= min) && max.is_none_or(|max| value.len() <= max)
+            }
+            ValueFilter::JsonEq { path, equals } => {
+                let Ok(doc) = serde_json::from_slice::<serde_json::Value>(value) else {
+                    return false;
+                };
+                json_lookup(&doc, path) == Some(equals)
+            }
+        }
+    }
+}
+
+fn json_lookup<'a>(doc: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(doc, |node, segment| match node {
+            serde_json::Value::Object(fields) => fields.get(segment),
+            serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_and_length() {
+        let filter = ValueFilter::Contains(b"error".to_vec());
+        assert!(filter.matches(b"disk error on sda"));
+        assert!(!filter.matches(b"all good"));
+        assert!(!filter.matches(b"err"));
+
+        let filter = ValueFilter::Length {
+            min: Some(2),
+            max: Some(4),
+        };
+        assert!(filter.matches(b"ab"));
+        assert!(filter.matches(b"abcd"));
+        assert!(!filter.matches(b"a"));
+        assert!(!filter.matches(b"abcde"));
+    }
+
+    #[test]
+    fn test_json_path_equality() {
+        let filter = ValueFilter::JsonEq {
+            path: "user.roles.1".to_string(),
+            equals: serde_json::json!("admin"),
+        };
+        assert!(filter.matches(br#"{"user":{"roles":["reader","admin"]}}"#));
+        assert!(!filter.matches(br#"{"user":{"roles":["reader"]}}"#));
+        assert!(!filter.matches(br#"{"user":"admin"}"#));
+        assert!(!filter.matches(b"not json"));
+    }
+}
@@ -0,0 +1,73 @@
+/// Redis-style key glob: `*` matches any run of characters (including none),
+/// `?` matches exactly one. Every other character matches itself.
+pub fn matches(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+
+    let (mut p, mut k) = (0, 0);
+    // Position after the last `*` and the key position it is retried from
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, k));
+            }
+            Some('?') => {
+                p += 1;
+                k += 1;
+            }
+            Some(c) if *c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star_p, star_k)) => {
+                    p = star_p;
+                    k = star_k + 1;
+                    backtrack = Some((star_p, star_k + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The part of `pattern` before its first wildcard; every matching key starts
+/// with it, so it can narrow which shards are scanned.
+pub fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("user:*", "user:42"));
+        assert!(!matches("user:*", "users:42"));
+        assert!(matches("user:?", "user:4"));
+        assert!(!matches("user:?", "user:42"));
+        assert!(matches("*:*:name", "ns:a:name"));
+        assert!(matches("a*b*c", "aXXbYYbc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+        assert!(matches("exact", "exact"));
+        assert!(!matches("exact", "exactly"));
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix("user:*"), "user:");
+        assert_eq!(literal_prefix("a?c*"), "a");
+        assert_eq!(literal_prefix("*"), "");
+        assert_eq!(literal_prefix("plain"), "plain");
+    }
+}
@@ -1,11 +1,29 @@
+pub mod batch;
+pub mod bulk;
+pub mod compression;
+pub mod control;
+pub mod encryption;
 pub mod engine;
 pub mod error;
+pub mod filter;
+pub mod glob;
+pub mod namespace;
+pub mod optimistic;
 pub mod shard;
 pub mod snapshot;
 pub mod ttl;
 pub mod types;
+pub mod watch;
 
+pub use compression::{CompressionAlgo, CompressionConfig, CompressionMode};
+pub use control::ControlFile;
+pub use encryption::{Cipher, EncryptionConfig, EncryptionError};
 pub use engine::StorageEngine;
 pub use error::StorageError;
-pub use snapshot::SnapshotManager;
-pub use types::{KvEntry, StorageConfig};
+pub use filter::ValueFilter;
+pub use optimistic::ExecOutcome;
+pub use snapshot::{snapshot_created_at, SnapshotInfo, SnapshotManager, SnapshotRetention};
+pub use watch::{ChangeEvent, ChangeOp, ChangeSubscription, ExpiryReason};
+pub use types::{
+    BulkEntry, DuplicateKeyPolicy, EvictionPolicy, ExpireCallback, KeyHasher, KvEntry, NamespaceQuota, NamespaceUsage, NodeRole, Placement, RecoveryReport, ScanPage, ShardGroup, ShardHasher, ShardGroupConfig, ShardSkew, StorageConfig, TxnOp, ValueSlice,
+};
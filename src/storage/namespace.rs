@@ -0,0 +1,236 @@
+use std::collections::{BTreeSet, HashMap};
+
+use parking_lot::RwLock;
+
+use crate::storage::error::StorageError;
+
+// Keys inside a namespace are stored as `ns:{name}:{key}`; anything without
+// that prefix belongs to the default namespace.
+pub const NAMESPACE_PREFIX: &str = "ns:";
+
+/// The namespace of unprefixed keys: the flat key space from before
+/// namespaces, so existing keys resolve unchanged.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+// Catalog keys; no namespace can reach them
+const SYSTEM_PREFIX: &str = "_sys";
+
+const MAX_NAMESPACE_LEN: usize = 64;
+
+/// Namespace names are ASCII letters, digits, `_`, `-` and `.`, so they can
+/// never contain the `:` separator or a glob metacharacter, and may not be
+/// `_sys`-anything.
+pub fn validate_namespace(namespace: &str) -> Result<(), StorageError> {
+    let valid_chars = namespace
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if namespace.is_empty() || namespace.len() > MAX_NAMESPACE_LEN || !valid_chars {
+        return Err(StorageError::InvalidRequest(format!(
+            "invalid namespace '{}'",
+            namespace
+        )));
+    }
+    if namespace.starts_with(SYSTEM_PREFIX) {
+        return Err(StorageError::InvalidRequest(format!(
+            "namespace '{}' is reserved",
+            namespace
+        )));
+    }
+    Ok(())
+}
+
+/// Stored key for `key` as a client of `namespace` sees it. Authorization
+/// checks the stored key, so a permission like `SET:ns:app1:*` confines a
+/// user to one namespace.
+pub fn resolve_key(namespace: &str, key: &str) -> Result<String, StorageError> {
+    validate_namespace(namespace)?;
+    if namespace == DEFAULT_NAMESPACE {
+        // Another namespace's stored keys are out of reach too
+        if key.starts_with(SYSTEM_PREFIX) || namespace_of(key).is_some() {
+            return Err(StorageError::InvalidRequest(format!(
+                "key '{}' is reserved",
+                key
+            )));
+        }
+        return Ok(key.to_string());
+    }
+    Ok(namespaced_key(namespace, key))
+}
+
+/// `stored` as a client of `namespace` sees it, i.e. without the prefix.
+pub fn local_key<'a>(namespace: &str, stored: &'a str) -> &'a str {
+    if namespace == DEFAULT_NAMESPACE {
+        return stored;
+    }
+    stored
+        .strip_prefix(NAMESPACE_PREFIX)
+        .and_then(|rest| rest.strip_prefix(namespace))
+        .and_then(|rest| rest.strip_prefix(':'))
+        .unwrap_or(stored)
+}
+
+/// Namespace a stored key belongs to, or `None` for the default namespace.
+pub fn namespace_of(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix(NAMESPACE_PREFIX)?;
+    rest.split_once(':').map(|(namespace, _)| namespace)
+}
+
+pub fn namespaced_key(namespace: &str, key: &str) -> String {
+    format!("{}{}:{}", NAMESPACE_PREFIX, namespace, key)
+}
+
+/// Reserved fields honoured in JSON object values of managed namespaces.
+#[derive(Debug, Default, PartialEq)]
+pub struct ManagedMetadata {
+    pub ttl_secs: Option<u64>, // `_ttl`
+    pub tags: Vec<String>,     // `_tags`
+}
+
+impl ManagedMetadata {
+    /// Non-JSON values, and malformed reserved fields, are stored as-is with
+    /// no metadata.
+    pub fn parse(value: &[u8]) -> Self {
+        let Ok(serde_json::Value::Object(fields)) = serde_json::from_slice(value) else {
+            return Self::default();
+        };
+
+        let ttl_secs = fields.get("_ttl").and_then(|ttl| ttl.as_u64());
+        let tags = fields
+            .get("_tags")
+            .and_then(|tags| tags.as_array())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|tag| tag.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self { ttl_secs, tags }
+    }
+}
+
+/// Secondary index from (namespace, tag) to stored keys.
+#[derive(Debug, Default)]
+pub struct TagIndex {
+    inner: RwLock<TagIndexInner>,
+}
+
+#[derive(Debug, Default)]
+struct TagIndexInner {
+    by_tag: HashMap<(String, String), BTreeSet<String>>,
+    by_key: HashMap<String, Vec<String>>,
+}
+
+impl TagIndex {
+    /// Replaces the tags recorded for `key`.
+    pub fn update(&self, namespace: &str, key: &str, tags: Vec<String>) {
+        let mut inner = self.inner.write();
+        inner.unlink(namespace, key);
+        for tag in &tags {
+            inner
+                .by_tag
+                .entry((namespace.to_string(), tag.clone()))
+                .or_default()
+                .insert(key.to_string());
+        }
+        if !tags.is_empty() {
+            inner.by_key.insert(key.to_string(), tags);
+        }
+    }
+
+    pub fn remove(&self, namespace: &str, key: &str) {
+        self.inner.write().unlink(namespace, key);
+    }
+
+    /// Stored keys carrying `tag`, in key order.
+    pub fn keys(&self, namespace: &str, tag: &str) -> Vec<String> {
+        self.inner
+            .read()
+            .by_tag
+            .get(&(namespace.to_string(), tag.to_string()))
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.write();
+        inner.by_tag.clear();
+        inner.by_key.clear();
+    }
+}
+
+impl TagIndexInner {
+    fn unlink(&mut self, namespace: &str, key: &str) {
+        let Some(tags) = self.by_key.remove(key) else {
+            return;
+        };
+        for tag in tags {
+            let slot = (namespace.to_string(), tag);
+            if let Some(keys) = self.by_tag.get_mut(&slot) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.by_tag.remove(&slot);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_of() {
+        assert_eq!(
+            namespace_of(&namespaced_key("tenant", "a:b")),
+            Some("tenant")
+        );
+        assert_eq!(namespace_of("plain:key"), None);
+        assert_eq!(namespace_of("ns:missing_separator"), None);
+    }
+
+    #[test]
+    fn test_resolve_key() {
+        assert_eq!(resolve_key(DEFAULT_NAMESPACE, "user:1").unwrap(), "user:1");
+        assert_eq!(resolve_key("app1", "user:1").unwrap(), "ns:app1:user:1");
+        assert_eq!(local_key("app1", "ns:app1:user:1"), "user:1");
+        assert_eq!(
+            local_key(DEFAULT_NAMESPACE, "ns:app1:user:1"),
+            "ns:app1:user:1"
+        );
+
+        // Catalog keys and names that could break out of the prefix
+        assert!(resolve_key(DEFAULT_NAMESPACE, "_sys.users:admin").is_err());
+        assert!(resolve_key(DEFAULT_NAMESPACE, "ns:app1:user:1").is_err());
+        assert!(resolve_key(DEFAULT_NAMESPACE, "ns:app1:*").is_err());
+        assert!(resolve_key(DEFAULT_NAMESPACE, "ns:plain").is_ok());
+        assert_eq!(resolve_key("app1", "_sys.x").unwrap(), "ns:app1:_sys.x");
+        for bad in [
+            "",
+            "_sys",
+            "_system",
+            "a:b",
+            "app*",
+            "x".repeat(65).as_str(),
+        ] {
+            assert!(resolve_key(bad, "k").is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_managed_metadata_parse() {
+        let meta = ManagedMetadata::parse(br#"{"_ttl": 10, "_tags": ["a", 1, "b"], "x": 1}"#);
+        assert_eq!(meta.ttl_secs, Some(10));
+        assert_eq!(meta.tags, vec!["a".to_string(), "b".to_string()]);
+
+        assert_eq!(
+            ManagedMetadata::parse(b"not json"),
+            ManagedMetadata::default()
+        );
+        assert_eq!(
+            ManagedMetadata::parse(b"[1, 2]"),
+            ManagedMetadata::default()
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::storage::types::KvEntry;
+
+/// A session's WATCH that goes this long without another WATCH is dropped,
+/// and its EXEC then fails as if nothing had been watched.
+pub const WATCH_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// What a watched key looked like. The write time tells a key that was
+/// deleted and written back up to the same version apart from one nobody
+/// touched, and the expiry catches EXPIRE and PERSIST, which leave both
+/// alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStamp {
+    version: u64,
+    written_at: u64,
+    expires_at: Option<u64>,
+}
+
+impl KeyStamp {
+    pub fn of(entry: &KvEntry) -> Self {
+        Self {
+            version: entry.version,
+            written_at: entry.created_at,
+            expires_at: entry.expires_at,
+        }
+    }
+}
+
+/// How an EXEC ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecOutcome {
+    /// Every op was applied; each key's new version, as `txn` returns them.
+    Committed(Vec<(String, u64)>),
+    /// `key` changed after it was watched, so nothing was written.
+    Aborted { key: String },
+}
+
+/// The keys each session is watching, with their stamps (`None` for a key
+/// that did not exist) as of the session's first WATCH of them.
+#[derive(Debug, Default)]
+pub struct WatchSessions {
+    sessions: DashMap<String, Watched>,
+}
+
+#[derive(Debug)]
+struct Watched {
+    keys: HashMap<String, Option<KeyStamp>>,
+    last_used: Instant,
+}
+
+impl WatchSessions {
+    /// Adds `stamps` to what `session` watches. A key it already watches
+    /// keeps its first stamp, as Redis does. Returns how many keys the
+    /// session watches.
+    pub fn watch(
+        &self,
+        session: &str,
+        stamps: impl IntoIterator<Item = (String, Option<KeyStamp>)>,
+    ) -> usize {
+        self.sessions
+            .retain(|_, watched| watched.last_used.elapsed() < WATCH_IDLE_TIMEOUT);
+        let mut watched = self
+            .sessions
+            .entry(session.to_string())
+            .or_insert_with(|| Watched {
+                keys: HashMap::new(),
+                last_used: Instant::now(),
+            });
+        watched.last_used = Instant::now();
+        for (key, stamp) in stamps {
+            watched.keys.entry(key).or_insert(stamp);
+        }
+        watched.keys.len()
+    }
+
+    /// Forgets `session`'s WATCH. Returns whether it had one.
+    pub fn unwatch(&self, session: &str) -> bool {
+        self.sessions.remove(session).is_some()
+    }
+
+    /// Ends `session`'s WATCH and returns what it watched, or `None` if it
+    /// had no WATCH or let it go idle.
+    pub fn take(&self, session: &str) -> Option<HashMap<String, Option<KeyStamp>>> {
+        self.sessions
+            .remove(session)
+            .filter(|(_, watched)| watched.last_used.elapsed() < WATCH_IDLE_TIMEOUT)
+            .map(|(_, watched)| watched.keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_keeps_the_first_stamp_until_taken() {
+        let sessions = WatchSessions::default();
+        let first = KeyStamp::of(&KvEntry::new(b"a".to_vec(), None));
+        let later = KeyStamp {
+            version: first.version + 1,
+            ..first
+        };
+
+        assert_eq!(sessions.watch("s1", [("k".to_string(), Some(first))]), 1);
+        assert_eq!(
+            sessions.watch(
+                "s1",
+                [
+                    ("k".to_string(), Some(later)),
+                    ("missing".to_string(), None)
+                ]
+            ),
+            2
+        );
+        sessions.watch("s2", [("k".to_string(), Some(later))]);
+
+        let watched = sessions.take("s1").unwrap();
+        assert_eq!(watched["k"], Some(first));
+        assert_eq!(watched["missing"], None);
+        assert!(sessions.take("s1").is_none());
+
+        assert!(sessions.unwatch("s2"));
+        assert!(!sessions.unwatch("s2"));
+        assert!(sessions.take("s2").is_none());
+    }
+}
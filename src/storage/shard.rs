@@ -1,17 +1,39 @@
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::storage::types::KvEntry;
 
+// Rough per-entry cost beyond the key and value bytes: the `KvEntry` itself,
+// the key's `String` header and the hash table slot
+pub const ENTRY_OVERHEAD: u64 = 96;
+
+/// Approximate memory held by one entry, as counted against `max_memory_bytes`.
+pub fn entry_size(key: &str, entry: &KvEntry) -> u64 {
+    key.len() as u64 + entry.value.len() as u64 + ENTRY_OVERHEAD
+}
+
 #[derive(Debug)]
 pub struct Shard {
     pub map: RwLock<HashMap<String, KvEntry>>,
+    bytes: AtomicU64, // sum of `entry_size` over `map`
+    lru: Mutex<LruIndex>,
+}
+
+/// Last access tick of every key in a shard, oldest first. Ticks come from
+/// one engine-wide clock so the oldest keys of different shards compare.
+#[derive(Debug, Default)]
+struct LruIndex {
+    ticks: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
 }
 
 impl Shard {
     pub fn new() -> Self {
         Self {
             map: RwLock::new(HashMap::new()),
+            bytes: AtomicU64::new(0),
+            lru: Mutex::new(LruIndex::default()),
         }
     }
 
@@ -22,12 +44,25 @@ impl Shard {
 
     pub fn set(&self, key: String, entry: KvEntry) -> Option<KvEntry> {
         let mut map = self.map.write();
+        self.account(&key, map.get(&key), Some(&entry));
         map.insert(key, entry)
     }
 
     pub fn del(&self, key: &str) -> Option<KvEntry> {
+        self.del_if(key, |_| true)
+    }
+
+    /// Removes `key` only if `check` accepts its entry; the check runs under
+    /// the same write lock as the removal.
+    pub fn del_if(&self, key: &str, check: impl FnOnce(&KvEntry) -> bool) -> Option<KvEntry> {
         let mut map = self.map.write();
-        map.remove(key)
+        if !map.get(key).is_some_and(check) {
+            return None;
+        }
+        let removed = map.remove(key);
+        self.account(key, removed.as_ref(), None);
+        self.forget(key);
+        removed
     }
 
     pub fn exists(&self, key: &str) -> bool {
@@ -45,4 +80,61 @@ impl Shard {
         let map = self.map.read();
         map.clone()
     }
+
+    /// Replaces the whole shard, e.g. when loading a snapshot. Recency is
+    /// reset; keys re-enter it as they are touched.
+    pub fn replace(&self, state: HashMap<String, KvEntry>) {
+        let mut map = self.map.write();
+        let bytes = state.iter().map(|(k, e)| entry_size(k, e)).sum();
+        *map = state;
+        self.bytes.store(bytes, Ordering::SeqCst);
+        *self.lru.lock() = LruIndex::default();
+    }
+
+    /// Adjusts the byte count for `key` going from `old` to `new`. Callers
+    /// that write through `map` directly must call this under its lock.
+    pub fn account(&self, key: &str, old: Option<&KvEntry>, new: Option<&KvEntry>) {
+        if let Some(new) = new {
+            self.bytes.fetch_add(entry_size(key, new), Ordering::SeqCst);
+        }
+        if let Some(old) = old {
+            self.bytes.fetch_sub(entry_size(key, old), Ordering::SeqCst);
+        }
+    }
+
+    /// Approximate memory held by this shard's entries.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    /// Marks `key` as used at `tick`.
+    pub fn touch(&self, key: &str, tick: u64) {
+        let mut lru = self.lru.lock();
+        match lru.ticks.get_mut(key) {
+            Some(old) => {
+                let old = std::mem::replace(old, tick);
+                lru.order.remove(&old);
+            }
+            None => {
+                lru.ticks.insert(key.to_string(), tick);
+            }
+        }
+        lru.order.insert(tick, key.to_string());
+    }
+
+    /// Drops `key` from the recency index.
+    pub fn forget(&self, key: &str) {
+        let mut lru = self.lru.lock();
+        if let Some(tick) = lru.ticks.remove(key) {
+            lru.order.remove(&tick);
+        }
+    }
+
+    /// The least recently touched key and when it was touched.
+    pub fn oldest(&self) -> Option<(u64, String)> {
+        let lru = self.lru.lock();
+        lru.order
+            .first_key_value()
+            .map(|(tick, key)| (*tick, key.clone()))
+    }
 }
@@ -1,11 +1,12 @@
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
-use tokio::sync::Mutex;
+use parking_lot::Mutex;
 use tokio::time::sleep;
 
 use crate::storage::engine::StorageEngine;
+use crate::storage::error::StorageError;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TtlEvent {
@@ -26,57 +27,298 @@ impl PartialOrd for TtlEvent {
         Some(self.cmp(other))
     }
 }
+/// Expiry queues sharded like the keyspace: every storage shard has its own
+/// heap, and each heap is drained by exactly one reaper task, so expiry scales
+/// with shard count and never locks across shards.
 #[derive(Debug)]
 pub struct TtlManager {
     engine: Arc<StorageEngine>,
-    queue: Arc<Mutex<BinaryHeap<TtlEvent>>>,
+    queues: Arc<Vec<Mutex<ExpiryQueue>>>, // indexed by shard
+    reapers: usize,
+}
+
+// A key has at most one live event: the one whose `expires_at` matches
+// `live`. Events superseded by a later `add` or a `remove` stay in the heap
+// until they surface and are skipped, or until a compaction drops them.
+#[derive(Debug, Default)]
+struct ExpiryQueue {
+    heap: BinaryHeap<TtlEvent>,
+    live: HashMap<String, u64>, // key -> expires_at of its live event
+    stale: usize,               // superseded events still in `heap`
+}
+
+// Heaps smaller than this are never compacted; skipping is cheap enough
+const COMPACT_MIN_LEN: usize = 1024;
+
+impl ExpiryQueue {
+    fn add(&mut self, key: String, expires_at: u64) {
+        if self.live.insert(key.clone(), expires_at).is_some() {
+            self.stale += 1;
+        }
+        self.heap.push(TtlEvent { key, expires_at });
+        self.maybe_compact();
+    }
+
+    fn remove(&mut self, key: &str) {
+        if self.live.remove(key).is_some() {
+            self.stale += 1;
+            self.maybe_compact();
+        }
+    }
+
+    // Live events due at `now`, dropping superseded ones on the way
+    fn pop_due(&mut self, now: u64) -> Vec<String> {
+        let mut due = Vec::new();
+        while self.heap.peek().is_some_and(|e| e.expires_at <= now) {
+            let event = self.heap.pop().unwrap();
+            if self.live.get(&event.key) == Some(&event.expires_at) {
+                self.live.remove(&event.key);
+                due.push(event.key);
+            } else {
+                self.stale -= 1;
+            }
+        }
+        due
+    }
+
+    // Rebuilds the heap once most of it is superseded events
+    fn maybe_compact(&mut self) {
+        if self.heap.len() >= COMPACT_MIN_LEN && self.stale * 2 > self.heap.len() {
+            let live = &self.live;
+            self.heap
+                .retain(|e| live.get(&e.key) == Some(&e.expires_at));
+            self.stale = 0;
+        }
+    }
 }
 
 impl TtlManager {
-    pub fn new(engine: Arc<StorageEngine>) -> Self {
+    /// `reapers == 0` picks one per CPU; the count is capped at the number
+    /// of shards.
+    pub fn new(engine: Arc<StorageEngine>, reapers: usize) -> Self {
+        let num_shards = engine.shards.len();
+        let reapers = if reapers == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            reapers
+        };
+
         Self {
+            queues: Arc::new(
+                (0..num_shards)
+                    .map(|_| Mutex::new(ExpiryQueue::default()))
+                    .collect(),
+            ),
+            reapers: reapers.clamp(1, num_shards.max(1)),
             engine,
-            queue: Arc::new(Mutex::new(BinaryHeap::new())),
         }
     }
 
-    pub async fn add(&self, key: String, expires_at: u64) {
-        let mut queue = self.queue.lock().await;
-        queue.push(TtlEvent { key, expires_at });
+    pub fn reapers(&self) -> usize {
+        self.reapers
+    }
+
+    /// Queues `key` for expiry on the shard that owns it, replacing any
+    /// expiry queued for it before.
+    pub fn add(&self, shard: usize, key: String, expires_at: u64) {
+        self.queues[shard].lock().add(key, expires_at);
+    }
+
+    /// Cancels the queued expiry of `key`, if any.
+    pub fn remove(&self, key: &str) {
+        let shard = self.engine.shard_index(key);
+        self.queues[shard].lock().remove(key);
+    }
+
+    /// Queued events, superseded ones included, across every shard.
+    pub fn queued(&self) -> usize {
+        self.queues.iter().map(|q| q.lock().heap.len()).sum()
     }
 
     pub async fn start_background_task(&self) {
-        let engine = self.engine.clone();
-        let queue = self.queue.clone();
-
-        tokio::spawn(async move {
-            loop {
-                sleep(Duration::from_millis(100)).await; // check 10x/sec
-
-                let mut to_delete = Vec::new();
-                {
-                    let now = SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_nanos() as u64;
-
-                    let mut queue = queue.lock().await;
-                    while let Some(event) = queue.peek().cloned() {
-                        if event.expires_at <= now {
-                            to_delete.push(queue.pop().unwrap().key);
-                        } else {
-                            break;
-                        }
-                    }
+        for reaper in 0..self.reapers {
+            let engine = self.engine.clone();
+            let queues = self.queues.clone();
+            let reapers = self.reapers;
+
+            tokio::spawn(async move {
+                loop {
+                    sleep(Duration::from_millis(100)).await; // check 10x/sec
+                    reap(&engine, &queues, reaper, reapers).await;
                 }
+            });
+        }
+    }
+}
 
-                // Delete expired keys
-                for key in to_delete {
-                    if let Err(e) = engine.del(&key, None).await {
-                        tracing::warn!(key = %key, error = %e, "Failed to delete expired key");
-                    }
+// One pass of reaper `reaper` over the shards it owns (every `reapers`-th
+// shard). Returns how many keys were expired.
+async fn reap(
+    engine: &StorageEngine,
+    queues: &[Mutex<ExpiryQueue>],
+    reaper: usize,
+    reapers: usize,
+) -> usize {
+    let mut expired = 0;
+
+    for shard in (reaper..queues.len()).step_by(reapers) {
+        // Nothing is popped while writes are refused, so it all stays queued
+        // for a later pass
+        if engine.check_writable().is_err() {
+            return expired;
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let to_delete = queues[shard].lock().pop_due(now);
+
+        // Delete expired keys; the engine re-checks each against its live
+        // entry in case it changed since it was queued
+        for key in to_delete {
+            match engine.expire_in_shard(shard, &key).await {
+                Ok(true) => expired += 1,
+                Ok(false) => {}
+                // Writes were refused after the check above; put it back
+                Err(StorageError::MaintenanceMode | StorageError::BulkLoadInProgress) => {
+                    queues[shard].lock().add(key, now)
+                }
+                Err(e) => {
+                    tracing::warn!(key = %key, error = %e, "Failed to delete expired key")
                 }
             }
-        });
+        }
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+
+    async fn engine_with(num_shards: usize, ttl_reapers: usize) -> Arc<StorageEngine> {
+        StorageEngine::new(StorageConfig {
+            num_shards,
+            snapshot_dir: "test_snapshots".to_string(),
+            ttl_reapers,
+            ..Default::default()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_ttl_keys_expire_across_shards() {
+        let engine = engine_with(16, 4).await;
+        assert_eq!(engine.ttl_manager().reapers(), 4);
+
+        for i in 0..2000 {
+            engine
+                .set(&format!("session:{}", i), b"v".to_vec(), Some(1))
+                .await
+                .unwrap();
+        }
+        engine.set("forever", b"v".to_vec(), None).await.unwrap();
+
+        sleep(Duration::from_millis(1300)).await;
+
+        // Removed by the reapers, not by lazy expiry on read
+        let remaining: usize = engine.shards.iter().map(|s| s.map.read().len()).sum();
+        assert_eq!(remaining, 1);
+        assert!(engine.exists("forever").await);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_waits_out_maintenance_mode() {
+        let engine = engine_with(4, 1).await;
+        engine.set("k", b"v".to_vec(), Some(1)).await.unwrap();
+        engine.set_maintenance_mode(true);
+
+        sleep(Duration::from_millis(1300)).await;
+        let stored: usize = engine.shards.iter().map(|s| s.map.read().len()).sum();
+        assert_eq!(stored, 1);
+        assert_eq!(engine.ttl_manager().queued(), 1);
+
+        engine.set_maintenance_mode(false);
+        sleep(Duration::from_millis(300)).await;
+        let stored: usize = engine.shards.iter().map(|s| s.map.read().len()).sum();
+        assert_eq!(stored, 0);
+    }
+
+    #[tokio::test]
+    async fn test_superseded_expiry_is_skipped() {
+        let engine = engine_with(4, 1).await;
+        engine.set("k", b"v".to_vec(), Some(1)).await.unwrap();
+        engine.set("k", b"v2".to_vec(), None).await.unwrap();
+
+        sleep(Duration::from_secs(2)).await;
+        assert_eq!(engine.get("k").await.unwrap().value, b"v2");
+    }
+
+    #[tokio::test]
+    async fn test_deleted_keys_do_not_pile_up_in_queue() {
+        let engine = engine_with(1, 1).await;
+        for i in 0..4 * COMPACT_MIN_LEN {
+            let key = format!("k{}", i);
+            engine.set(&key, b"v".to_vec(), Some(3600)).await.unwrap();
+            engine.del(&key, None).await.unwrap();
+        }
+        assert!(engine.ttl_manager().queued() <= COMPACT_MIN_LEN);
+    }
+
+    #[tokio::test]
+    async fn test_reapers_capped_at_shard_count() {
+        let engine = engine_with(2, 8).await;
+        assert_eq!(engine.ttl_manager().reapers(), 2);
+    }
+
+    // Benchmark-style: `cargo test --release expiry_throughput -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn test_expiry_throughput_scales_with_shards() {
+        const KEYS: usize = 200_000;
+
+        async fn drain(num_shards: usize) -> Duration {
+            // Keys are stamped as already expired directly, so the engine's
+            // own reapers never see them; this manager's queues are drained
+            // by hand below.
+            let engine = engine_with(num_shards, num_shards).await;
+            let manager = TtlManager::new(engine.clone(), num_shards);
+            for i in 0..KEYS {
+                let key = format!("k{}", i);
+                engine.set(&key, b"v".to_vec(), None).await.unwrap();
+                let shard = engine.shard_index(&key);
+                engine.shards[shard]
+                    .map
+                    .write()
+                    .get_mut(&key)
+                    .unwrap()
+                    .expires_at = Some(1);
+                manager.add(shard, key, 0);
+            }
+
+            let start = std::time::Instant::now();
+            let passes = (0..manager.reapers).map(|reaper| {
+                let engine = engine.clone();
+                let queues = manager.queues.clone();
+                let reapers = manager.reapers;
+                tokio::spawn(async move { reap(&engine, &queues, reaper, reapers).await })
+            });
+            let mut expired = 0;
+            for pass in passes.collect::<Vec<_>>() {
+                expired += pass.await.unwrap();
+            }
+            assert_eq!(expired, KEYS);
+            start.elapsed()
+        }
+
+        let single = drain(1).await;
+        let sharded = drain(8).await;
+        println!(
+            "expired {} keys: 1 shard {:?}, 8 shards {:?}",
+            KEYS, single, sharded
+        );
+        assert!(sharded < single);
     }
 }
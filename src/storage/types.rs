@@ -1,6 +1,12 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::time::SystemTime;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher13;
+
+use crate::storage::compression::{self, CompressionAlgo, CompressionConfig};
+use crate::storage::error::StorageError;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct KvEntry {
@@ -8,8 +14,14 @@ pub struct KvEntry {
     pub version: u64,
     pub created_at: u64,         // Unix nanos
     pub expires_at: Option<u64>, // Unix nanos, None = no expiry
+    #[serde(default)]
+    pub compression: Option<CompressionAlgo>, // codec `value` is stored with
 }
 
+/// Invoked with the key and its last entry whenever a key is expired, either
+/// by a TTL reaper or lazily on read.
+pub type ExpireCallback = std::sync::Arc<dyn Fn(&str, &KvEntry) + Send + Sync>;
+
 impl KvEntry {
     pub fn new(value: Vec<u8>, ttl_secs: Option<u64>) -> Self {
         let now = SystemTime::now()
@@ -24,7 +36,21 @@ impl KvEntry {
             version: 1,
             created_at: now,
             expires_at,
+            compression: None,
+        }
+    }
+
+    /// The value as written, decompressed if it is stored compressed.
+    pub fn plain_value(&self) -> Result<Cow<'_, [u8]>, StorageError> {
+        compression::plain(self.compression, &self.value)
+    }
+
+    /// This entry with its value decompressed, as handed back to readers.
+    pub fn decompressed(mut self) -> Result<Self, StorageError> {
+        if let Some(algo) = self.compression.take() {
+            self.value = compression::decompress(algo, &self.value)?;
         }
+        Ok(self)
     }
 
     pub fn is_expired(&self) -> bool {
@@ -38,19 +64,382 @@ impl KvEntry {
             false
         }
     }
+
+    /// Whole seconds until expiry, rounded up; `None` without a TTL.
+    pub fn ttl_secs(&self) -> Option<u64> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        self.expires_at
+            .map(|at| at.saturating_sub(now).div_ceil(1_000_000_000))
+    }
+}
+
+/// One record ingested by `StorageEngine::bulk_load`.
+#[derive(Debug, Clone)]
+pub struct BulkEntry {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub ttl_secs: Option<u64>,
+}
+
+/// One operation of a `StorageEngine::txn`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxnOp {
+    Set {
+        key: String,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    },
+    Del {
+        key: String,
+    },
+    /// Fails the whole transaction unless the key is at `expected_version`
+    /// (0 for "must not exist").
+    Cas {
+        key: String,
+        expected_version: u64,
+        value: Vec<u8>,
+        ttl_secs: Option<u64>,
+    },
+}
+
+impl TxnOp {
+    pub fn key(&self) -> &str {
+        match self {
+            TxnOp::Set { key, .. } | TxnOp::Del { key } | TxnOp::Cas { key, .. } => key,
+        }
+    }
+
+    /// The value it writes; `None` for a delete.
+    pub fn value(&self) -> Option<&[u8]> {
+        match self {
+            TxnOp::Set { value, .. } | TxnOp::Cas { value, .. } => Some(value),
+            TxnOp::Del { .. } => None,
+        }
+    }
+}
+
+/// One page of scan results. `scanned` counts every live key examined, so a
+/// targeted empty result can be told apart from a large fruitless scan.
+#[derive(Debug, Clone, Default)]
+pub struct ScanPage {
+    pub items: Vec<(String, KvEntry)>,
+    pub scanned: u64,
+    pub has_more: bool,
+    pub truncated: bool, // stopped at the examination cap, not the page limit
+    pub next_cursor: Option<String>, // glob scans only; `None` once every shard is done
+}
+
+/// What [`StorageEngine::recover`](crate::storage::StorageEngine::recover)
+/// restored: the snapshot it loaded, if any, and the WAL replayed after it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub snapshot: Option<String>,
+    pub wal_offset: u64, // where replay started
+    pub replayed: u64,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct StorageConfig {
+    /// Shards in the default group; at least 1, not necessarily a power of 2.
     pub num_shards: usize,
     pub snapshot_dir: String,
+
+    /// Optional prefix-routed shard groups (tiering). Keys that match none of
+    /// the prefixes land in the default group of `num_shards` shards.
+    #[serde(default)]
+    pub shard_groups: Vec<ShardGroupConfig>,
+
+    /// Per-namespace limits. A `_sys.quotas:<ns>` entry overrides these.
+    #[serde(default)]
+    pub namespace_quotas: HashMap<String, NamespaceQuota>,
+
+    /// Namespaces whose JSON values may carry `_ttl` and `_tags` fields.
+    #[serde(default)]
+    pub managed_namespaces: Vec<String>,
+
+    /// How MSET/txn batches naming the same key twice are handled.
+    #[serde(default)]
+    pub duplicate_keys: DuplicateKeyPolicy,
+
+    /// TTL reaper tasks. Each shard has its own expiry queue, owned by exactly
+    /// one reaper; 0 picks one per CPU, capped at the shard count.
+    #[serde(default)]
+    pub ttl_reapers: usize,
+
+    /// Most operations accepted in one multi-op request (MSET, MGET, batch,
+    /// transaction).
+    #[serde(default = "default_max_batch_ops")]
+    pub max_batch_ops: usize,
+
+    /// Largest key a write may name, in bytes.
+    #[serde(default = "default_max_key_bytes")]
+    pub max_key_bytes: usize,
+
+    /// Largest value a write may store, in bytes before compression.
+    #[serde(default = "default_max_value_bytes")]
+    pub max_value_bytes: usize,
+
+    /// Memory budget for stored entries, in approximate bytes (key + value
+    /// + a fixed per-entry overhead). 0 means unbounded.
+    #[serde(default)]
+    pub max_memory_bytes: u64,
+
+    /// What a write does once `max_memory_bytes` would be exceeded.
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicy,
+
+    /// Transparent compression of large values; `None` stores every value
+    /// as written unless a write asks otherwise.
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+
+    /// How a key picks its shard within its group.
+    #[serde(default)]
+    pub placement: Placement,
+
+    /// The hash `placement` works from.
+    #[serde(default)]
+    pub shard_hasher: ShardHasher,
+
+    /// Pins the SipHash key, so keys stay on the same shards across
+    /// restarts. Unset, each process draws a random key. Unused by `fx`.
+    /// Anyone who knows the seed can aim keys at one shard, so it must be
+    /// random and kept as secret as any other credential.
+    #[serde(default)]
+    pub shard_hash_seed: Option<ShardHashSeed>,
+}
+
+fn default_max_batch_ops() -> usize {
+    1000
+}
+
+fn default_max_key_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_max_value_bytes() -> usize {
+    512 * 1024 * 1024
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
-            num_shards: 256, // power of 2 for fast modulo
+            num_shards: 256, // any count >= 1; a power of 2 routes by mask
             snapshot_dir: "data/snapshots".to_string(),
+            shard_groups: Vec::new(),
+            namespace_quotas: HashMap::new(),
+            managed_namespaces: Vec::new(),
+            duplicate_keys: DuplicateKeyPolicy::LastWins,
+            ttl_reapers: 0,
+            max_batch_ops: default_max_batch_ops(),
+            max_key_bytes: default_max_key_bytes(),
+            max_value_bytes: default_max_value_bytes(),
+            max_memory_bytes: 0,
+            eviction_policy: EvictionPolicy::NoEviction,
+            compression: None,
+            placement: Placement::Modulo,
+            shard_hasher: ShardHasher::Fx,
+            shard_hash_seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKeyPolicy {
+    /// The final occurrence of a key is applied
+    #[default]
+    LastWins,
+    /// The whole batch fails with `InvalidRequest`
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum EvictionPolicy {
+    /// Writes that would exceed the budget fail with `OutOfMemory`
+    #[default]
+    #[serde(rename = "noeviction")]
+    NoEviction,
+    /// The least recently read or written keys are evicted to make room
+    #[serde(rename = "allkeys-lru")]
+    AllKeysLru,
+}
+
+/// Maps a key to one of a group's shards. Snapshots load under any
+/// placement or shard count, since loading re-places every key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    /// `hash % shards`: cheapest, but changing the count moves nearly
+    /// every key
+    #[default]
+    Modulo,
+    /// Jump consistent hashing: going from `n` to `n + 1` shards moves
+    /// only about `1 / (n + 1)` of the keys
+    Consistent,
+}
+
+impl Placement {
+    /// The shard, in `0..shards`, that `key` belongs to under `hasher`.
+    pub fn shard(self, hasher: &KeyHasher, key: &str, shards: usize) -> usize {
+        match self {
+            Self::Modulo => {
+                let hash = hasher.hash32(key) as usize;
+                // Same shard either way; the mask just skips the division
+                if shards.is_power_of_two() {
+                    hash & (shards - 1)
+                } else {
+                    hash % shards
+                }
+            }
+            Self::Consistent => jump_hash(hasher.hash64(key), shards),
+        }
+    }
+}
+
+/// The hash a key is placed by. Snapshots store keys rather than hashes,
+/// so one taken under another hasher or seed still loads, re-sharded on
+/// the way in (slower than a load whose keys are already in place).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShardHasher {
+    /// FxHash: fastest, but anyone can work out which keys share a shard
+    /// and pile them onto one
+    #[default]
+    Fx,
+    /// SipHash-1-3 under a secret key (`shard_hash_seed`, or random), so
+    /// colliding keys can't be chosen from outside
+    Sip,
+}
+
+/// A 128-bit SipHash key, written in config as 32 hex digits. Its `Debug`
+/// output leaves the key out.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ShardHashSeed(pub u128);
+
+impl std::fmt::Debug for ShardHashSeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ShardHashSeed(..)")
+    }
+}
+
+impl<'de> Deserialize<'de> for ShardHashSeed {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(serde::de::Error::custom(
+                "shard_hash_seed must be 32 hex digits",
+            ));
+        }
+        u128::from_str_radix(&hex, 16)
+            .map(Self)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A [`ShardHasher`] with its key drawn, fixed for an engine's lifetime.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyHasher {
+    Fx,
+    Sip(SipHasher13),
+}
+
+impl KeyHasher {
+    pub fn new(hasher: ShardHasher, seed: Option<ShardHashSeed>) -> Self {
+        match (hasher, seed) {
+            (ShardHasher::Fx, _) => Self::Fx,
+            (ShardHasher::Sip, Some(ShardHashSeed(seed))) => {
+                Self::Sip(SipHasher13::new_with_keys(seed as u64, (seed >> 64) as u64))
+            }
+            (ShardHasher::Sip, None) => {
+                Self::Sip(SipHasher13::new_with_keys(rand::random(), rand::random()))
+            }
         }
     }
+
+    pub fn hash32(&self, key: &str) -> u32 {
+        match self {
+            Self::Fx => fxhash::hash32(key.as_bytes()),
+            Self::Sip(sip) => sip.hash(key.as_bytes()) as u32,
+        }
+    }
+
+    pub fn hash64(&self, key: &str) -> u64 {
+        match self {
+            Self::Fx => fxhash::hash64(key.as_bytes()),
+            Self::Sip(sip) => sip.hash(key.as_bytes()),
+        }
+    }
+}
+
+// Lamping & Veach, "A Fast, Minimal Memory, Consistent Hash Algorithm"
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut b, mut j) = (0i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShardGroupConfig {
+    pub name: String,
+    pub prefix: String, // e.g. "counters:" — a trailing '*' is ignored
+    pub num_shards: usize,
+}
+
+/// Resolved placement of a shard group inside the engine's flat shard list.
+/// Recorded in snapshots so a restore can detect a changed layout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardGroup {
+    pub name: String,
+    pub prefix: String, // empty for the default group
+    pub start: usize,
+    pub len: usize,
+}
+
+/// A byte slice of a stored value, as returned by `StorageEngine::getrange_slice`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueSlice {
+    pub bytes: Vec<u8>,
+    pub start: usize,     // offset of the first returned byte
+    pub total_len: usize, // length of the whole stored value
+}
+
+/// Key distribution across one shard group's shards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardSkew {
+    pub group: String,
+    pub keys: usize,           // keys in the whole group
+    pub max_shard_keys: usize, // keys in the fullest shard
+    pub ratio: f64,            // fullest shard over the group mean; 1.0 is perfectly even
+}
+
+/// Limits for one namespace; `None` means unlimited. Stored as JSON under
+/// `_sys.quotas:<ns>` when set at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceQuota {
+    #[serde(default)]
+    pub max_keys: Option<u64>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>, // key + value bytes
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceUsage {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// Replication role of this node, reported by `/v1/ping`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    Primary,
+    Replica,
 }
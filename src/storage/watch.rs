@@ -0,0 +1,141 @@
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// What happened to a watched key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Set,
+    Del,
+    Expired, // removed because its TTL ran out, not by a client
+}
+
+/// How an expired key came to be removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryReason {
+    Reaped, // by the TTL reaper, on schedule
+    OnRead, // by a read that found it expired before the reaper did
+}
+
+/// One successful write, as delivered to watchers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeEvent {
+    pub key: String, // stored key, namespace prefix included
+    pub op: ChangeOp,
+    pub version: u64,                 // 0 for deletes and expiries
+    pub reason: Option<ExpiryReason>, // `Expired` events only
+}
+
+/// Fans key changes out to watchers over a bounded broadcast channel.
+/// Writers never wait on watchers: one that falls more than `capacity`
+/// events behind is told how many it missed and carries on from there.
+#[derive(Debug)]
+pub struct ChangeNotifier {
+    tx: broadcast::Sender<ChangeEvent>,
+}
+
+impl ChangeNotifier {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Publishes a change. With nobody watching this costs an atomic load,
+    /// so the write path pays nothing for the feature when it is unused.
+    pub fn notify(&self, key: &str, op: ChangeOp, version: u64) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.tx.send(ChangeEvent {
+            key: key.to_string(),
+            op,
+            version,
+            reason: None,
+        });
+    }
+
+    /// Publishes the removal of an expired key. Only call it once the key
+    /// is actually gone.
+    pub fn notify_expired(&self, key: &str, reason: ExpiryReason) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.tx.send(ChangeEvent {
+            key: key.to_string(),
+            op: ChangeOp::Expired,
+            version: 0,
+            reason: Some(reason),
+        });
+    }
+
+    /// Changes to stored keys starting with `prefix`, from now on.
+    pub fn subscribe(&self, prefix: &str) -> ChangeSubscription {
+        ChangeSubscription {
+            rx: self.tx.subscribe(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    /// Live subscriptions; dropping a [`ChangeSubscription`] releases its slot.
+    pub fn watchers(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+/// A watcher's end of a [`ChangeNotifier`].
+#[derive(Debug)]
+pub struct ChangeSubscription {
+    rx: broadcast::Receiver<ChangeEvent>,
+    prefix: String,
+}
+
+impl ChangeSubscription {
+    /// The next change under the prefix. Catalog keys (`_sys.*`) are never
+    /// delivered. `Lagged(n)` means `n` changes were dropped because this
+    /// watcher fell behind; `Closed` that the engine is gone.
+    pub async fn recv(&mut self) -> Result<ChangeEvent, RecvError> {
+        loop {
+            let event = self.rx.recv().await?;
+            if event.key.starts_with(&self.prefix) && !event.key.starts_with("_sys.") {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscription_filters_and_reports_lag() {
+        let notifier = ChangeNotifier::new(4);
+        // Nobody is watching yet, so this is dropped
+        notifier.notify("user:0", ChangeOp::Set, 1);
+
+        let mut watcher = notifier.subscribe("user:");
+        assert_eq!(notifier.watchers(), 1);
+        notifier.notify("order:1", ChangeOp::Set, 1);
+        notifier.notify("_sys.users:admin", ChangeOp::Set, 1);
+        notifier.notify("user:1", ChangeOp::Del, 0);
+        assert_eq!(
+            watcher.recv().await.unwrap(),
+            ChangeEvent {
+                key: "user:1".to_string(),
+                op: ChangeOp::Del,
+                version: 0,
+                reason: None,
+            }
+        );
+
+        for version in 1..=5 {
+            notifier.notify("user:2", ChangeOp::Set, version);
+        }
+        assert!(matches!(watcher.recv().await, Err(RecvError::Lagged(1))));
+        assert_eq!(watcher.recv().await.unwrap().version, 2);
+
+        drop(watcher);
+        assert_eq!(notifier.watchers(), 0);
+    }
+}
@@ -1,6 +1,8 @@
 use serde::Deserialize;
 use std::time::Duration;
 
+use crate::storage::encryption::Cipher;
+
 #[derive(Debug, Clone, Deserialize)]
 pub enum SyncPolicy {
     EveryWrite,
@@ -8,12 +10,102 @@ pub enum SyncPolicy {
     Never,
 }
 
+/// Per-request durability, chosen by the client (`X-KV-Durability`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Not logged; lost on crash. While replicas tail the WAL it is logged
+    /// anyway so they see it, but neither fsynced nor waited on by them.
+    None,
+    /// Appended to the WAL; fsynced according to the global `SyncPolicy`.
+    #[default]
+    Wal,
+    /// Appended and fsynced before the request is acknowledged.
+    Sync,
+}
+
+tokio::task_local! {
+    static SCOPED: Durability;
+}
+
+impl Durability {
+    /// Runs `f` with the writes it makes through a
+    /// [`StorageEngine`](crate::storage::StorageEngine) logged at this
+    /// durability rather than the default.
+    pub async fn scope<F: std::future::Future>(self, f: F) -> F::Output {
+        SCOPED.scope(self, f).await
+    }
+
+    /// The durability of the enclosing [`scope`](Self::scope), if any.
+    pub fn current() -> Self {
+        SCOPED.try_with(|durability| *durability).unwrap_or_default()
+    }
+}
+
+impl std::str::FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Durability::None),
+            "wal" => Ok(Durability::Wal),
+            "sync" => Ok(Durability::Sync),
+            other => Err(format!("unknown durability level '{}'", other)),
+        }
+    }
+}
+
+/// What replay does on reaching an entry that is corrupt or cut short.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ReplayMode {
+    /// Fail with [`WalError::ReplayError`](super::WalError::ReplayError) at
+    /// that entry.
+    #[default]
+    Strict,
+    /// Treat the rest of that segment as a torn write: log how many bytes
+    /// are discarded and carry on with the next segment. A restart opens a
+    /// fresh segment, so a torn tail need not be the end of the log.
+    BestEffort,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct WalConfig {
     pub dir: String,
     pub file_prefix: String,
     pub max_file_size: u64, // bytes
     pub sync_policy: SyncPolicy,
+
+    /// Appended entries buffered for each [`subscribe`](super::WalManager::subscribe)r;
+    /// a subscriber further behind than this is told it lagged.
+    #[serde(default = "default_tail_buffer")]
+    pub tail_buffer: usize,
+
+    #[serde(default)]
+    pub replay_mode: ReplayMode,
+
+    /// Appends waiting for the WAL writer task. A write that finds the queue
+    /// full waits up to `queue_wait_ms` for room, then fails with
+    /// [`WalError::QueueFull`](super::WalError::QueueFull).
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,
+    #[serde(default = "default_queue_wait_ms")]
+    pub queue_wait_ms: u64,
+
+    /// Encrypts new segments. Set from the top-level `encryption` section,
+    /// not from this one, so the key is loaded once for WAL and snapshots.
+    #[serde(skip)]
+    pub cipher: Option<Cipher>,
+}
+
+fn default_tail_buffer() -> usize {
+    4096
+}
+
+fn default_queue_capacity() -> usize {
+    4096
+}
+
+fn default_queue_wait_ms() -> u64 {
+    100
 }
 
 impl Default for WalConfig {
@@ -23,6 +115,11 @@ impl Default for WalConfig {
             file_prefix: "wal_".to_string(),
             max_file_size: 128 * 1024 * 1024, // 128 MB
             sync_policy: SyncPolicy::EveryMs(100),
+            tail_buffer: default_tail_buffer(),
+            replay_mode: ReplayMode::default(),
+            queue_capacity: default_queue_capacity(),
+            queue_wait_ms: default_queue_wait_ms(),
+            cipher: None,
         }
     }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@ use crc32fast::Hasher;
 use std::fmt;
 
 use super::WalError;
+use crate::storage::compression::CompressionAlgo;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -10,7 +11,12 @@ pub enum OpType {
     Set = 0,
     Del = 1,
     Incr = 2,
-    Cas = 3, // Compare-and-swap
+    Cas = 3,        // Compare-and-swap
+    Checkpoint = 4, // Marker only; key holds the snapshot it refers to
+    Expire = 5,     // TTL change only; `ttl` None clears it
+    Append = 6,     // `value` holds the appended suffix, not the result
+    TxnBegin = 7,   // Marker; `version` holds the number of entries that follow
+    TxnCommit = 8,  // Marker; the transaction's entries are only replayed up to one
 }
 
 impl OpType {
@@ -20,6 +26,11 @@ impl OpType {
             1 => Some(OpType::Del),
             2 => Some(OpType::Incr),
             3 => Some(OpType::Cas),
+            4 => Some(OpType::Checkpoint),
+            5 => Some(OpType::Expire),
+            6 => Some(OpType::Append),
+            7 => Some(OpType::TxnBegin),
+            8 => Some(OpType::TxnCommit),
             _ => None,
         }
     }
@@ -35,25 +46,59 @@ pub struct WalEntry {
     pub key: String,
     pub value: Vec<u8>,   // empty for DEL
     pub version: u64,     // for CAS/MVCC later
-    pub ttl: Option<u64>, // Unix nanos or 0 for none
+    pub ttl: Option<u64>, // seconds from `timestamp`; 0 on disk for none
     pub op_type: OpType,
+    pub compression: Option<CompressionAlgo>, // codec `value` is stored with
+    /// INCR only: the increment, which replay redoes; `value` holds the result.
+    pub delta: Option<i64>,
 }
 
+// Set in the op byte of entries that carry a delta
+const DELTA_FLAG: u8 = 0x80;
+
 impl WalEntry {
+    /// An entry for a write made now, with a plain value and no delta.
+    pub fn new(op_type: OpType, key: &str, value: Vec<u8>, version: u64, ttl: Option<u64>) -> Self {
+        Self {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64,
+            key: key.to_string(),
+            value,
+            version,
+            ttl,
+            op_type,
+            compression: None,
+            delta: None,
+        }
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut buf = BytesMut::new();
 
-        // Fixed-size header: 8+8+8+1+8 = 33 bytes
-        buf.put_u64(self.timestamp);
-        buf.put_u64(self.version);
-        buf.put_u64(self.ttl.unwrap_or(0)); // 0 = no TTL
-        buf.put_u8(self.op_type.as_u8());
-        buf.put_u64(self.key.len() as u64);
-        buf.put_u64(self.value.len() as u64);
+        // Fixed-size header, little-endian to match deserialize: 8+8+8+1+8 = 33 bytes
+        buf.put_u64_le(self.timestamp);
+        buf.put_u64_le(self.version);
+        buf.put_u64_le(self.ttl.unwrap_or(0)); // 0 = no TTL
+
+        // Op type in the low nibble, value codec in the next three bits (0 =
+        // plain, which is what entries written before compression existed
+        // carry) and the top bit set when a delta follows the value. Older
+        // entries never set that bit, so they read back without a delta
+        let codec = self.compression.map_or(0, |algo| algo.as_u8());
+        let has_delta = if self.delta.is_some() { DELTA_FLAG } else { 0 };
+        buf.put_u8(has_delta | codec << 4 | self.op_type.as_u8());
+
+        buf.put_u64_le(self.key.len() as u64);
+        buf.put_u64_le(self.value.len() as u64);
 
         // Variable data
         buf.put(self.key.as_bytes());
         buf.put(&self.value[..]);
+        if let Some(delta) = self.delta {
+            buf.put_i64_le(delta);
+        }
 
         // Calculate checksum over entire payload (excluding checksum itself)
         let mut hasher = Hasher::new();
@@ -61,7 +106,7 @@ impl WalEntry {
         let checksum = hasher.finalize();
 
         // Append checksum (4 bytes)
-        buf.put_u32(checksum);
+        buf.put_u32_le(checksum);
 
         buf.to_vec()
     }
@@ -84,7 +129,8 @@ impl WalEntry {
         let key_len = read_u64(data, &mut offset)? as usize;
         let value_len = read_u64(data, &mut offset)? as usize;
 
-        if data.len() < offset + key_len + value_len + 4 {
+        let delta_len = if op_byte & DELTA_FLAG != 0 { 8 } else { 0 };
+        if data.len() < offset + key_len + value_len + delta_len + 4 {
             return Err(WalError::InvalidEntry {
                 offset: 0,
                 reason: "incomplete data".to_string(),
@@ -102,6 +148,12 @@ impl WalEntry {
         let value = data[offset..offset + value_len].to_vec();
         offset += value_len;
 
+        let delta = if delta_len > 0 {
+            Some(read_u64(data, &mut offset)? as i64)
+        } else {
+            None
+        };
+
         let checksum_stored = read_u32(data, &mut offset)?;
 
         // Verify checksum
@@ -119,10 +171,21 @@ impl WalEntry {
 
         let ttl = if ttl_raw == 0 { None } else { Some(ttl_raw) };
 
-        let op_type = OpType::from_u8(op_byte).ok_or_else(|| WalError::InvalidEntry {
+        let op_type = OpType::from_u8(op_byte & 0x0f).ok_or_else(|| WalError::InvalidEntry {
             offset: 0,
-            reason: format!("unknown op type: {}", op_byte),
+            reason: format!("unknown op type: {}", op_byte & 0x0f),
         })?;
+        let compression = match (op_byte >> 4) & 0x07 {
+            0 => None,
+            codec => {
+                Some(
+                    CompressionAlgo::from_u8(codec).ok_or_else(|| WalError::InvalidEntry {
+                        offset: 0,
+                        reason: format!("unknown value codec: {}", codec),
+                    })?,
+                )
+            }
+        };
 
         Ok((
             WalEntry {
@@ -132,6 +195,8 @@ impl WalEntry {
                 version,
                 ttl,
                 op_type,
+                compression,
+                delta,
             },
             offset,
         ))
@@ -174,3 +239,46 @@ fn read_u8(data: &[u8], offset: &mut usize) -> Result<u8, WalError> {
     *offset += 1;
     Ok(val)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn incr(delta: Option<i64>) -> WalEntry {
+        WalEntry {
+            timestamp: 1,
+            key: "counter".to_string(),
+            value: b"15".to_vec(),
+            version: 2,
+            ttl: None,
+            op_type: OpType::Incr,
+            compression: None,
+            delta,
+        }
+    }
+
+    #[test]
+    fn test_delta_round_trips_and_old_entries_still_read() {
+        let bytes = incr(Some(-5)).serialize();
+        let (entry, consumed) = WalEntry::deserialize(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(entry.delta, Some(-5));
+        assert_eq!(entry.value, b"15");
+        assert_eq!(entry.op_type, OpType::Incr);
+
+        // Without a delta the bytes are exactly the older format
+        let mut old = BytesMut::new();
+        old.put_u64_le(1);
+        old.put_u64_le(2);
+        old.put_u64_le(0);
+        old.put_u8(OpType::Incr.as_u8());
+        old.put_u64_le(7);
+        old.put_u64_le(2);
+        old.put(&b"counter15"[..]);
+        old.put_u32_le(crc32fast::hash(&old));
+        assert_eq!(incr(None).serialize(), old.to_vec());
+        let (entry, _) = WalEntry::deserialize(&old).unwrap();
+        assert_eq!(entry.delta, None);
+        assert_eq!(entry.value, b"15");
+    }
+}
@@ -17,4 +17,17 @@ pub enum WalError {
 
     #[error("Replay stopped at offset {offset}: {reason}")]
     ReplayError { offset: u64, reason: String },
+
+    #[error("Only {acked} of {quorum} replicas acknowledged offset {offset} in time")]
+    AckTimeout {
+        offset: u64,
+        acked: usize,
+        quorum: usize,
+    },
+
+    #[error("WAL write queue is full ({capacity} writes pending)")]
+    QueueFull { capacity: usize },
+
+    #[error(transparent)]
+    Encryption(#[from] crate::storage::encryption::EncryptionError),
 }
\ No newline at end of file
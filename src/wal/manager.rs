@@ -1,84 +1,231 @@
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::Write;
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, Notify};
 use tokio::time::{sleep, Duration};
 
+use crate::storage::encryption::{Cipher, EncryptionError, KEY_ID_LEN};
 use crate::wal::entry::WalEntry;
 use crate::wal::error::WalError;
 
-use super::config::SyncPolicy;
+use super::config::{Durability, ReplayMode, SyncPolicy};
 use super::WalConfig;
 
+/// Bits of an offset holding the position inside a segment. WAL offsets are
+/// global: `((sequence - 1) << SEGMENT_OFFSET_BITS) | offset_in_segment`, so
+/// they grow monotonically across rotations and map straight back to a file.
+pub const SEGMENT_OFFSET_BITS: u32 = 40;
+
+// A segment that is rotated out is sealed with a footer: this mark, which no
+// entry starts with (it would be a timestamp in the year 2554), then the
+// length (u64 LE) and CRC32 (u32 LE) of every byte before the footer
+const FOOTER_MARK: [u8; 8] = u64::MAX.to_le_bytes();
+const FOOTER_LEN: usize = 8 + 8 + 4;
+
+// An encrypted segment starts with this mark (as a timestamp, it would again
+// be centuries away) and the id of its key. Each entry is then a u32 LE
+// length and the sealed entry, authenticated together with its global offset
+// so it cannot be moved elsewhere in the log
+const ENCRYPTED_MARK: [u8; 8] = *b"KVWE\xff\xff\xff\xff";
+const ENCRYPTED_HEADER_LEN: usize = ENCRYPTED_MARK.len() + KEY_ID_LEN;
+// Length prefix, nonce and tag around each sealed entry
+const SEALED_OVERHEAD: usize = 4 + 12 + 16;
+
+// Most queued appends the writer task takes at once; under `EveryWrite` one
+// fsync covers them all
+const WRITE_BATCH: usize = 256;
+
+/// Global offset of `offset` bytes into segment `sequence`.
+pub fn global_offset(sequence: u64, offset: u64) -> u64 {
+    ((sequence - 1) << SEGMENT_OFFSET_BITS) | offset
+}
+
+/// Splits a global offset into `(sequence, offset_in_segment)`.
+pub fn split_offset(global: u64) -> (u64, u64) {
+    (
+        (global >> SEGMENT_OFFSET_BITS) + 1,
+        global & ((1 << SEGMENT_OFFSET_BITS) - 1),
+    )
+}
+
 #[derive(Debug)]
 pub struct WalManager {
     config: WalConfig,
     current_file: Mutex<WalFileHandle>,
-    sync_task: Option<tokio::task::JoinHandle<()>>,
+    // To the writer task, the only one appending
+    queue: mpsc::Sender<QueuedWrite>,
+    synced_offset: AtomicU64, // global offset known to be on disk
+    sync_error: parking_lot::Mutex<Option<String>>, // last fsync failure, cleared by a success
+    sync_task: parking_lot::Mutex<Option<tokio::task::JoinHandle<()>>>, // EveryMs fsync loop
+    tail_tx: broadcast::Sender<(u64, WalEntry)>,
+    replica_acks: parking_lot::Mutex<HashMap<String, u64>>, // replica -> highest acked LSN
+    ack_notify: Notify,
+    write_quorum: AtomicUsize, // 0 = writes don't wait for replicas
+    ack_timeout_ms: AtomicU64,
+    replicated: AtomicBool, // replicas tail the log, so every write goes in it
+}
+
+// An append waiting for the writer task, which answers on `done` once the
+// entries are written and, under `EveryWrite`, fsynced. They are written
+// back to back, so no other append lands between them.
+struct QueuedWrite {
+    entries: Vec<WalEntry>,
+    done: Reply,
+    span: tracing::Span, // the caller's, so the writer's logs carry its request ID
 }
+
+type Reply = oneshot::Sender<Result<u64, WalError>>;
+
+/// Room for one append in the writer's queue, from
+/// [`WalManager::reserve`]. An append through it can no longer be turned
+/// away with `QueueFull`.
+pub struct WalSlot<'a> {
+    wal: &'a WalManager,
+    permit: mpsc::Permit<'a, QueuedWrite>,
+}
+
+impl<'a> WalSlot<'a> {
+    /// [`WalManager::append_all_with`] into this slot.
+    pub async fn append_all_with(
+        self,
+        entries: &[WalEntry],
+        durability: Durability,
+    ) -> Result<Option<u64>, WalError> {
+        if durability == Durability::None && !self.wal.is_replicated() {
+            return Ok(None);
+        }
+        if entries.is_empty() {
+            let offset = self.wal.current_offset().await;
+            self.wal.await_quorum(offset).await?;
+            return Ok(Some(offset));
+        }
+        self.send(entries.to_vec()).finish(durability).await
+    }
+
+    /// Queues `entries` without waiting for them. Queued appends are written
+    /// in the order they were sent, so sending under the lock that ordered
+    /// the changes they record keeps the log in that order too.
+    pub fn send(self, entries: Vec<WalEntry>) -> PendingAppend<'a> {
+        let (done, written) = oneshot::channel();
+        self.permit.send(QueuedWrite {
+            entries,
+            done,
+            span: tracing::Span::current(),
+        });
+        PendingAppend {
+            wal: self.wal,
+            written,
+        }
+    }
+}
+
+/// Entries [`sent`](WalSlot::send) to the writer and not yet waited for.
+/// They are written whether or not anyone waits.
+pub struct PendingAppend<'a> {
+    wal: &'a WalManager,
+    written: oneshot::Receiver<Result<u64, WalError>>,
+}
+
+impl PendingAppend<'_> {
+    /// Waits for the entries as [`WalManager::append_all_with`] does at
+    /// `durability`, returning the offset of the last one.
+    pub async fn finish(self, durability: Durability) -> Result<Option<u64>, WalError> {
+        let offset = self.written.await.map_err(|_| writer_stopped())??;
+        match durability {
+            Durability::None => return Ok(None),
+            Durability::Wal => {}
+            Durability::Sync => self.wal.sync().await?,
+        }
+        self.wal.await_quorum(offset).await?;
+        Ok(Some(offset))
+    }
+}
+
 #[derive(Debug)]
 struct WalFileHandle {
     file: File,
     path: PathBuf,
-    offset: u64,
+    sequence: u64,
+    offset: u64,                 // within this segment
+    checksum: crc32fast::Hasher, // over the segment's first `offset` bytes
+}
+
+impl WalFileHandle {
+    fn global_offset(&self) -> u64 {
+        global_offset(self.sequence, self.offset)
+    }
+
+    // Ends the segment with its footer; nothing is appended to it after this
+    fn seal(&mut self) -> Result<(), WalError> {
+        let mut footer = Vec::with_capacity(FOOTER_LEN);
+        footer.extend_from_slice(&FOOTER_MARK);
+        footer.extend_from_slice(&self.offset.to_le_bytes());
+        footer.extend_from_slice(&self.checksum.clone().finalize().to_le_bytes());
+        self.file.write_all(&footer)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
 }
 
 impl WalManager {
     pub async fn new(config: WalConfig) -> Result<Arc<Self>, WalError> {
         std::fs::create_dir_all(&config.dir)?;
 
-        let current_file = Self::open_next_file(&config).await?;
+        // The directory is scanned once; rotations count on from here
+        let last_sequence = Self::segments(&config)?.last().map_or(0, |(seq, _)| *seq);
+        let current_file = Self::open_segment(&config, last_sequence + 1).await?;
+        let start_offset = current_file.global_offset();
 
+        let (tail_tx, _) = broadcast::channel(config.tail_buffer.max(1));
+        let (queue, queued) = mpsc::channel(config.queue_capacity.max(1));
         let manager = Arc::new(Self {
             config: config.clone(),
             current_file: Mutex::new(current_file),
-            sync_task: None,
+            queue,
+            synced_offset: AtomicU64::new(start_offset),
+            sync_error: parking_lot::Mutex::new(None),
+            sync_task: parking_lot::Mutex::new(None),
+            tail_tx,
+            replica_acks: parking_lot::Mutex::new(HashMap::new()),
+            ack_notify: Notify::new(),
+            write_quorum: AtomicUsize::new(0),
+            ack_timeout_ms: AtomicU64::new(5_000),
+            replicated: AtomicBool::new(false),
         });
 
-        // Start background fsync task if needed
+        // Like the fsync loop, the writer holds only a weak reference; it
+        // stops once the manager, and with it the queue's sender, is dropped
+        tokio::spawn(Self::run_writer(Arc::downgrade(&manager), queued));
+
+        // Start background fsync task if needed. It holds only a weak
+        // reference, so dropping the last `Arc` still drops the manager
         if let SyncPolicy::EveryMs(interval_ms) = config.sync_policy {
-            let manager_clone = Arc::clone(&manager);
+            let weak = Arc::downgrade(&manager);
             let handle = tokio::spawn(async move {
                 let interval = Duration::from_millis(interval_ms);
                 loop {
                     sleep(interval).await;
-                    if let Err(e) = manager_clone.sync().await {
+                    let Some(manager) = weak.upgrade() else {
+                        break;
+                    };
+                    if let Err(e) = manager.sync().await {
                         tracing::error!("WAL sync error: {}", e);
                     }
                 }
             });
-
-            // Use interior mutability to store the handle
-            Arc::get_mut(&mut Arc::clone(&manager))
-                .expect("No other Arc references exist")
-                .sync_task = Some(handle);
+            *manager.sync_task.lock() = Some(handle);
         }
 
         Ok(manager)
     }
 
-    async fn open_next_file(config: &WalConfig) -> Result<WalFileHandle, WalError> {
+    async fn open_segment(config: &WalConfig, sequence: u64) -> Result<WalFileHandle, WalError> {
         let dir = Path::new(&config.dir);
-        let mut max_seq = 0u64;
-
-        // Find highest sequence number
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if let Some(filename) = path.file_name().and_then(|s| s.to_str()) {
-                if filename.starts_with(&config.file_prefix) {
-                    if let Some(seq_str) = filename.strip_prefix(&config.file_prefix) {
-                        if let Ok(seq) = seq_str.parse::<u64>() {
-                            max_seq = max_seq.max(seq);
-                        }
-                    }
-                }
-            }
-        }
-
-        let next_seq = max_seq + 1;
-        let filename = format!("{}{}", config.file_prefix, next_seq);
+        let filename = format!("{}{}", config.file_prefix, sequence);
         let path = dir.join(filename);
 
         let file = OpenOptions::new()
@@ -88,93 +235,1262 @@ impl WalManager {
             .open(&path)?;
 
         let metadata = file.metadata()?;
-        let offset = metadata.len();
+        let mut offset = metadata.len();
+        let mut checksum = crc32fast::Hasher::new();
+        if offset > 0 {
+            checksum.update(&std::fs::read(&path)?);
+        } else if let Some(cipher) = &config.cipher {
+            let mut header = ENCRYPTED_MARK.to_vec();
+            header.extend_from_slice(&cipher.key_id());
+            (&file).write_all(&header)?;
+            checksum.update(&header);
+            offset = header.len() as u64;
+        }
 
         tracing::info!(path = %path.display(), offset = offset, "Opened new WAL file");
 
-        Ok(WalFileHandle { file, path, offset })
+        Ok(WalFileHandle {
+            file,
+            path,
+            sequence,
+            offset,
+            checksum,
+        })
+    }
+
+    /// Every `{file_prefix}{sequence}` segment in `config.dir`, oldest first.
+    fn segments(config: &WalConfig) -> Result<Vec<(u64, PathBuf)>, WalError> {
+        let mut segments = Vec::new();
+        for entry in std::fs::read_dir(&config.dir)? {
+            let path = entry?.path();
+            let seq = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .and_then(|name| name.strip_prefix(&config.file_prefix))
+                .and_then(|seq| seq.parse::<u64>().ok());
+            if let Some(seq) = seq {
+                segments.push((seq, path));
+            }
+        }
+        segments.sort_unstable_by_key(|(seq, _)| *seq);
+        Ok(segments)
     }
 
+    /// Appends `entry` through the writer task and returns its offset once
+    /// it is written, and fsynced if the policy is `EveryWrite`. When the
+    /// writer falls behind, appends queue up to `queue_capacity`; past that
+    /// an append waits `queue_wait_ms` for room and then fails with
+    /// [`WalError::QueueFull`].
     pub async fn append(&self, entry: &WalEntry) -> Result<u64, WalError> {
-        let serialized = entry.serialize();
+        self.enqueue(vec![entry.clone()]).await
+    }
+
+    /// [`append`](Self::append) for a group of entries that must sit next to
+    /// each other in the log, such as a transaction between its markers.
+    /// Returns the offset of the last one. If writing fails partway, the
+    /// entries before the failure stay in the log.
+    pub async fn append_all(&self, entries: &[WalEntry]) -> Result<u64, WalError> {
+        if entries.is_empty() {
+            return Ok(self.current_offset().await);
+        }
+        self.enqueue(entries.to_vec()).await
+    }
+
+    async fn enqueue(&self, entries: Vec<WalEntry>) -> Result<u64, WalError> {
+        let pending = self.reserve().await?.send(entries);
+        pending.written.await.map_err(|_| writer_stopped())?
+    }
+
+    /// Takes room for one append in the writer's queue, waiting up to
+    /// `queue_wait_ms` like [`append`](Self::append) does before failing
+    /// with [`WalError::QueueFull`]. A caller that must not change anything
+    /// it then can't log reserves first; dropping the slot unused gives the
+    /// room back.
+    pub async fn reserve(&self) -> Result<WalSlot<'_>, WalError> {
+        let wait = Duration::from_millis(self.config.queue_wait_ms);
+        match tokio::time::timeout(wait, self.queue.reserve()).await {
+            Ok(Ok(permit)) => Ok(WalSlot { wal: self, permit }),
+            Ok(Err(_)) => Err(writer_stopped()),
+            Err(_) => Err(WalError::QueueFull {
+                capacity: self.queue.max_capacity(),
+            }),
+        }
+    }
+
+    /// Appends waiting for the writer task.
+    pub fn queue_depth(&self) -> usize {
+        self.queue.max_capacity() - self.queue.capacity()
+    }
+
+    async fn run_writer(manager: Weak<Self>, mut queued: mpsc::Receiver<QueuedWrite>) {
+        let mut batch = Vec::with_capacity(WRITE_BATCH);
+        while queued.recv_many(&mut batch, WRITE_BATCH).await > 0 {
+            let Some(manager) = manager.upgrade() else {
+                break;
+            };
+            let written = manager.write_batch(std::mem::take(&mut batch)).await;
+            // Let go first, so a caller dropping the last `Arc` drops the manager
+            drop(manager);
+            for (done, result) in written {
+                let _ = done.send(result);
+            }
+        }
+    }
+
+    async fn write_batch(&self, batch: Vec<QueuedWrite>) -> Vec<(Reply, Result<u64, WalError>)> {
         let mut handle = self.current_file.lock().await;
+        let mut written = Vec::with_capacity(batch.len());
+        for write in batch {
+            let (offsets, result) = self.write_group(&mut handle, &write.entries).await;
+            written.push((write, offsets, result));
+        }
 
-        // Check if we need to rotate
-        if handle.offset + serialized.len() as u64 > self.config.max_file_size {
-            *handle = Self::open_next_file(&self.config).await?;
+        // One fsync acknowledges the whole batch
+        if let SyncPolicy::EveryWrite = self.config.sync_policy {
+            if written.iter().any(|(_, _, result)| result.is_ok()) {
+                if let Err(e) = self.sync_file(&handle) {
+                    for (_, _, result) in &mut written {
+                        if result.is_ok() {
+                            *result = Err(std::io::Error::other(e.to_string()).into());
+                        }
+                    }
+                }
+            }
+        }
+
+        written
+            .into_iter()
+            .map(|(write, offsets, result)| {
+                if result.is_ok() {
+                    for (offset, entry) in offsets.into_iter().zip(write.entries) {
+                        write.span.in_scope(|| {
+                            tracing::trace!(offset = offset, key = %entry.key, op = ?entry.op_type, "WAL entry appended")
+                        });
+                        // Published under the file lock so subscribers see entries in WAL order
+                        if self.tail_tx.receiver_count() > 0 {
+                            let _ = self.tail_tx.send((offset, entry));
+                        }
+                    }
+                }
+                (write.done, result)
+            })
+            .collect()
+    }
+
+    // Writes one queued append: its entries back to back in a single
+    // segment, so a group that runs on into a new segment was cut short by a
+    // crash and replay can tell. If an entry fails, the ones before it are
+    // cut off again. Returns each entry's offset and the last one's, or the
+    // error.
+    async fn write_group(
+        &self,
+        handle: &mut WalFileHandle,
+        entries: &[WalEntry],
+    ) -> (Vec<u64>, Result<u64, WalError>) {
+        let records: Vec<Vec<u8>> = entries.iter().map(WalEntry::serialize).collect();
+        let len = records.iter().map(|r| self.record_len(r)).sum();
+        if let Err(e) = self.make_room(handle, len).await {
+            return (Vec::new(), Err(e));
+        }
+
+        let (start, checksum) = (handle.offset, handle.checksum.clone());
+        let mut offsets = Vec::with_capacity(records.len());
+        for record in records {
+            match self.write_entry(handle, record) {
+                Ok(offset) => offsets.push(offset),
+                Err(e) => {
+                    match handle.file.set_len(start) {
+                        Ok(()) => {
+                            handle.offset = start;
+                            handle.checksum = checksum;
+                        }
+                        Err(cut) => {
+                            tracing::error!(error = %cut, "Failed to cut off a partly written WAL append")
+                        }
+                    }
+                    return (Vec::new(), Err(e));
+                }
+            }
+        }
+        let last = offsets.last().copied().unwrap_or(0);
+        (offsets, Ok(last))
+    }
+
+    // Bytes a serialized entry takes in a segment
+    fn record_len(&self, serialized: &[u8]) -> u64 {
+        match self.config.cipher {
+            Some(_) => (serialized.len() + SEALED_OVERHEAD) as u64,
+            None => serialized.len() as u64,
+        }
+    }
+
+    // Rotates unless `len` more bytes fit the segment. A group larger than
+    // `max_file_size` can't fit any segment, so it gets one of its own
+    // rather than an endless run of empty ones
+    async fn make_room(&self, handle: &mut WalFileHandle, len: u64) -> Result<(), WalError> {
+        let empty = if self.config.cipher.is_some() {
+            ENCRYPTED_HEADER_LEN as u64
+        } else {
+            0
+        };
+        if handle.offset > empty && handle.offset + len > self.config.max_file_size {
+            handle.seal()?;
+            let next = handle.sequence + 1;
+            *handle = Self::open_segment(&self.config, next).await?;
+        }
+        Ok(())
+    }
+
+    fn write_entry(
+        &self,
+        handle: &mut WalFileHandle,
+        mut serialized: Vec<u8>,
+    ) -> Result<u64, WalError> {
+        if let Some(cipher) = &self.config.cipher {
+            let sealed = cipher.seal(&serialized, &handle.global_offset().to_le_bytes());
+            serialized = (sealed.len() as u32).to_le_bytes().to_vec();
+            serialized.extend_from_slice(&sealed);
         }
 
         // Write
         handle.file.write_all(&serialized)?;
-        let entry_offset = handle.offset;
+        handle.checksum.update(&serialized);
+        let entry_offset = handle.global_offset();
         handle.offset += serialized.len() as u64;
 
-        // Fsync if policy is EveryWrite
-        if let SyncPolicy::EveryWrite = self.config.sync_policy {
-            handle.file.sync_all()?;
+        Ok(entry_offset)
+    }
+
+    /// Appends `entry` with a per-request durability level. Returns the entry
+    /// offset, or `None` for `Durability::None`, which skips the WAL unless
+    /// it is [replicated](Self::set_replicated).
+    ///
+    /// Once [`require_acks`](Self::require_acks) has been called, a logged
+    /// entry is also only acknowledged after enough replicas applied it.
+    pub async fn append_with(
+        &self,
+        entry: &WalEntry,
+        durability: Durability,
+    ) -> Result<Option<u64>, WalError> {
+        self.append_all_with(std::slice::from_ref(entry), durability)
+            .await
+    }
+
+    /// [`append_all`](Self::append_all) with a per-request durability level,
+    /// as [`append_with`](Self::append_with). Replicas must have applied the
+    /// last entry.
+    pub async fn append_all_with(
+        &self,
+        entries: &[WalEntry],
+        durability: Durability,
+    ) -> Result<Option<u64>, WalError> {
+        if durability == Durability::None && !self.is_replicated() {
+            return Ok(None);
         }
+        self.reserve()
+            .await?
+            .append_all_with(entries, durability)
+            .await
+    }
 
-        tracing::trace!(offset = entry_offset, key = %entry.key, op = ?entry.op_type, "WAL entry appended");
+    /// Semi-synchronous replication: from now on every
+    /// [`append_with`](Self::append_with) waits for `quorum` replicas, and
+    /// fails with [`WalError::AckTimeout`] after `timeout`. The entry itself
+    /// stays logged (and is still shipped to replicas) when that happens.
+    pub fn require_acks(&self, quorum: usize, timeout: Duration) {
+        self.write_quorum.store(quorum, Ordering::SeqCst);
+        self.ack_timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::SeqCst);
+    }
 
-        Ok(entry_offset)
+    /// Marks the log as shipped to replicas. From now on `Durability::None`
+    /// writes are logged too, as replicas only see what is in the log; they
+    /// still wait for neither an fsync nor replica acks.
+    pub fn set_replicated(&self) {
+        self.replicated.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`set_replicated`](Self::set_replicated) has been called.
+    pub fn is_replicated(&self) -> bool {
+        self.replicated.load(Ordering::SeqCst)
+    }
+
+    /// Records that `replica` has applied every entry up to `lsn` (the offset
+    /// just past its last applied entry).
+    pub fn record_ack(&self, replica: &str, lsn: u64) {
+        {
+            let mut acks = self.replica_acks.lock();
+            let acked = acks.entry(replica.to_string()).or_insert(0);
+            *acked = (*acked).max(lsn);
+        }
+        self.ack_notify.notify_waiters();
+    }
+
+    /// Forgets a disconnected replica; it no longer counts towards a quorum.
+    pub fn remove_replica(&self, replica: &str) {
+        self.replica_acks.lock().remove(replica);
+    }
+
+    /// Highest acknowledged LSN of every connected replica.
+    pub fn replica_acks(&self) -> HashMap<String, u64> {
+        self.replica_acks.lock().clone()
+    }
+
+    // `wait_for_acks` at the configured quorum, if there is one
+    async fn await_quorum(&self, offset: u64) -> Result<(), WalError> {
+        let quorum = self.write_quorum.load(Ordering::SeqCst);
+        if quorum > 0 {
+            self.wait_for_acks(offset, quorum).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits until at least `quorum` replicas have applied the entry
+    /// appended at `offset`, or the configured ack timeout passes.
+    pub async fn wait_for_acks(&self, offset: u64, quorum: usize) -> Result<(), WalError> {
+        // An entry is applied once a replica acks an LSN past its start
+        let acked = || {
+            self.replica_acks
+                .lock()
+                .values()
+                .filter(|&&lsn| lsn > offset)
+                .count()
+        };
+
+        let timeout = Duration::from_millis(self.ack_timeout_ms.load(Ordering::SeqCst));
+        let wait = async {
+            loop {
+                // Registered before checking so an ack in between isn't missed
+                let notified = self.ack_notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if acked() >= quorum {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| WalError::AckTimeout {
+                offset,
+                acked: acked(),
+                quorum,
+            })
     }
 
     pub async fn sync(&self) -> Result<(), WalError> {
         let handle = self.current_file.lock().await;
-        handle.file.sync_all()?;
+        self.sync_file(&handle)
+    }
+
+    /// [`sync`](Self::sync), returning the offset it made durable: every
+    /// entry before it is on disk. Read under the same lock as the fsync, so
+    /// no append can slip in between.
+    pub async fn sync_offset(&self) -> Result<u64, WalError> {
+        let handle = self.current_file.lock().await;
+        self.sync_file(&handle)?;
+        Ok(handle.global_offset())
+    }
+
+    fn sync_file(&self, handle: &WalFileHandle) -> Result<(), WalError> {
+        if let Err(e) = handle.file.sync_all() {
+            *self.sync_error.lock() = Some(e.to_string());
+            return Err(e.into());
+        }
+        *self.sync_error.lock() = None;
+        self.synced_offset
+            .store(handle.global_offset(), Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Deletes every segment that lies wholly before `offset`, typically the
+    /// offset a checkpoint's snapshot covers. Recovery finds where replay
+    /// starts in the [control file](crate::storage::ControlFile), so record
+    /// `offset` there first. The active segment is never deleted.
+    pub async fn truncate_before(&self, offset: u64) -> Result<(), WalError> {
+        // Held so the active segment can't rotate underneath us
+        let handle = self.current_file.lock().await;
+
+        let (first_kept, _) = split_offset(offset);
+        let mut removed = 0;
+        for (seq, path) in Self::segments(&self.config)? {
+            if seq >= first_kept || seq == handle.sequence {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+
+        tracing::info!(offset = offset, removed = removed, "WAL truncated");
         Ok(())
     }
 
+    /// Bytes used by every segment on disk.
+    pub fn disk_usage(&self) -> Result<u64, WalError> {
+        let mut total = 0;
+        for (_, path) in Self::segments(&self.config)? {
+            total += std::fs::metadata(path)?.len();
+        }
+        Ok(total)
+    }
+
+    /// Replays every entry at or after the global `start_offset`, walking the
+    /// segments in sequence order. The callback gets each entry's global
+    /// offset. An offset inside an already-truncated segment starts at the
+    /// oldest segment still on disk. Damage is handled as `replay_mode` says.
     pub async fn replay_from(
         &self,
         start_offset: u64,
         mut callback: impl FnMut(u64, WalEntry) -> Result<(), WalError>,
     ) -> Result<(), WalError> {
-        let mut handle = self.current_file.lock().await;
+        // Held so nothing is appended or rotated while we read
+        let _handle = self.current_file.lock().await;
+
+        read_segments(&self.config, start_offset, |offset, entry| {
+            callback(offset, entry).map(|()| ControlFlow::Continue(()))
+        })
+    }
 
-        // Seek to start offset
-        handle.file.seek(SeekFrom::Start(start_offset))?;
-
-        let mut buf = Vec::new();
-        let mut offset = start_offset;
-
-        loop {
-            match handle.file.read_to_end(&mut buf) {
-                Ok(0) => break, // EOF
-                Ok(_) => {
-                    let mut pos = 0;
-                    while pos < buf.len() {
-                        match WalEntry::deserialize(&buf[pos..]) {
-                            Ok((entry, consumed)) => {
-                                callback(offset + pos as u64, entry)?;
-                                pos += consumed;
-                            }
-                            Err(e) => {
-                                return Err(WalError::ReplayError {
-                                    offset: offset + pos as u64,
-                                    reason: e.to_string(),
-                                });
-                            }
+    /// [`replay_from`](Self::replay_from) up to where the log is now, without
+    /// holding off appends. The segments are read on a blocking thread that
+    /// hands entries over through a channel of `capacity`, so a slow reader
+    /// holds no more than that in memory. The channel closes after the last
+    /// entry, or after the first error.
+    pub async fn replay_stream(
+        &self,
+        start_offset: u64,
+        capacity: usize,
+    ) -> mpsc::Receiver<Result<(u64, WalEntry), WalError>> {
+        // Everything before this offset is fully written
+        let end_offset = self.current_offset().await;
+        let config = self.config.clone();
+        let (tx, rx) = mpsc::channel(capacity);
+        tokio::task::spawn_blocking(move || {
+            let read =
+                read_segments_until(&config, start_offset, Some(end_offset), |offset, entry| {
+                    // Gone reader: nobody wants the rest
+                    Ok(match tx.blocking_send(Ok((offset, entry))) {
+                        Ok(()) => ControlFlow::Continue(()),
+                        Err(_) => ControlFlow::Break(()),
+                    })
+                });
+            if let Err(e) = read {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        rx
+    }
+
+    /// Every entry appended from now on, with its global offset. Pair with
+    /// [`replay_from`](Self::replay_from) (subscribing first) to catch up on
+    /// older entries without a gap; the overlap then has to be skipped by offset.
+    pub fn subscribe(&self) -> broadcast::Receiver<(u64, WalEntry)> {
+        self.tail_tx.subscribe()
+    }
+
+    pub fn synced_offset(&self) -> u64 {
+        self.synced_offset.load(Ordering::SeqCst)
+    }
+
+    /// Why the most recent fsync failed, or `None` if it succeeded (or none
+    /// has run yet).
+    pub fn last_sync_error(&self) -> Option<String> {
+        self.sync_error.lock().clone()
+    }
+
+    pub async fn current_offset(&self) -> u64 {
+        self.current_file.lock().await.global_offset()
+    }
+
+    /// The key new segments are encrypted with, if any.
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.config.cipher.as_ref()
+    }
+}
+
+/// [`WalManager::replay_from`] without a `WalManager`: reads the segments in
+/// `config.dir` and creates, locks and writes nothing, so offline tools can
+/// walk the log of a stopped server. The callback can break off the walk.
+///
+/// A corrupt or torn entry, or a sealed segment whose footer does not match
+/// its contents, is handled per `config.replay_mode`: in strict mode it stops
+/// the walk with a [`WalError::ReplayError`] at its global offset.
+///
+/// Encrypted segments are read with `config.cipher`. Without it, or with a
+/// different key than the segment was written with, this fails with
+/// [`WalError::Encryption`] whatever the replay mode. Plain segments are
+/// read either way.
+pub fn read_segments(
+    config: &WalConfig,
+    start_offset: u64,
+    callback: impl FnMut(u64, WalEntry) -> Result<ControlFlow<()>, WalError>,
+) -> Result<(), WalError> {
+    read_segments_until(config, start_offset, None, callback)
+}
+
+// `read_segments`, stopping at the global `end_offset` if given: the log as
+// it was when it reached there, while appends may go on past it
+fn read_segments_until(
+    config: &WalConfig,
+    start_offset: u64,
+    end_offset: Option<u64>,
+    mut callback: impl FnMut(u64, WalEntry) -> Result<ControlFlow<()>, WalError>,
+) -> Result<(), WalError> {
+    let (start_sequence, start_in_segment) = split_offset(start_offset);
+    let end = end_offset.map(split_offset);
+    for (sequence, path) in WalManager::segments(config)? {
+        if sequence < start_sequence {
+            continue;
+        }
+        if end.is_some_and(|(end_sequence, _)| sequence > end_sequence) {
+            break;
+        }
+        let in_segment = if sequence == start_sequence {
+            start_in_segment
+        } else {
+            0
+        };
+
+        // Read whole, so a footer can be checked against the entire segment
+        let mut buf = std::fs::read(&path)?;
+        if let Some((_, end_in_segment)) = end.filter(|(end_sequence, _)| sequence == *end_sequence)
+        {
+            buf.truncate(end_in_segment as usize);
+        }
+
+        let cipher = segment_cipher(config, &buf, &path)?;
+        let mut pos = in_segment as usize;
+        if cipher.is_some() {
+            pos = pos.max(ENCRYPTED_HEADER_LEN);
+        }
+        while pos < buf.len() {
+            let offset = global_offset(sequence, pos as u64);
+            let damage = if buf[pos..].starts_with(&FOOTER_MARK) {
+                match check_footer(&buf, pos) {
+                    Ok(()) => break,
+                    Err(reason) => reason,
+                }
+            } else {
+                let read = match cipher {
+                    Some(cipher) => open_entry(cipher, &buf[pos..], offset),
+                    None => WalEntry::deserialize(&buf[pos..]).map_err(corruption),
+                };
+                match read {
+                    Ok((entry, consumed)) => {
+                        if callback(offset, entry)?.is_break() {
+                            return Ok(());
                         }
+                        pos += consumed;
+                        continue;
                     }
+                    Err(reason) => reason,
+                }
+            };
+
+            match config.replay_mode {
+                ReplayMode::Strict => {
+                    return Err(WalError::ReplayError {
+                        offset,
+                        reason: damage,
+                    })
+                }
+                ReplayMode::BestEffort => {
+                    tracing::warn!(
+                        segment = %path.display(),
+                        offset = offset,
+                        discarded_bytes = buf.len() - pos,
+                        reason = %damage,
+                        "Discarding the damaged tail of a WAL segment"
+                    );
                     break;
                 }
-                Err(e) => return Err(WalError::Io(e)),
             }
         }
+    }
 
-        Ok(())
+    Ok(())
+}
+
+// The cipher an encrypted segment is read with, or `None` for a plain one
+fn segment_cipher<'a>(
+    config: &'a WalConfig,
+    buf: &[u8],
+    path: &Path,
+) -> Result<Option<&'a Cipher>, WalError> {
+    if !buf.starts_with(&ENCRYPTED_MARK) {
+        return Ok(None);
     }
+    // A header cut short is left for the reader to report as damage
+    let Some(key_id) = buf.get(ENCRYPTED_MARK.len()..ENCRYPTED_HEADER_LEN) else {
+        return Ok(None);
+    };
+    let what = || format!("WAL segment {}", path.display());
+    let cipher = config
+        .cipher
+        .as_ref()
+        .ok_or_else(|| EncryptionError::KeyMissing(what()))?;
+    cipher.check_key_id(key_id, &what())?;
+    Ok(Some(cipher))
+}
 
-    pub async fn current_offset(&self) -> u64 {
-        self.current_file.lock().await.offset
+// Decrypts the entry at the start of `buf`, written at global `offset`
+fn open_entry(cipher: &Cipher, buf: &[u8], offset: u64) -> Result<(WalEntry, usize), String> {
+    let Some(len) = buf.get(..4) else {
+        return Err("torn encrypted entry".to_string());
+    };
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    let Some(sealed) = buf.get(4..4 + len) else {
+        return Err("torn encrypted entry".to_string());
+    };
+    let plain = cipher
+        .open(sealed, &offset.to_le_bytes())
+        .ok_or_else(|| "encrypted entry failed authentication".to_string())?;
+    let (entry, _) = WalEntry::deserialize(&plain).map_err(corruption)?;
+    Ok((entry, 4 + len))
+}
+
+// Whether the footer at `pos` seals exactly the bytes before it
+fn check_footer(buf: &[u8], pos: usize) -> Result<(), String> {
+    let Some(footer) = buf.get(pos..pos + FOOTER_LEN) else {
+        return Err("torn segment footer".to_string());
+    };
+    let length = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+    let expected = u32::from_le_bytes(footer[16..20].try_into().unwrap());
+    if length != pos as u64 {
+        return Err(format!(
+            "segment footer covers {} bytes, but it is at byte {}",
+            length, pos
+        ));
+    }
+    let got = crc32fast::hash(&buf[..pos]);
+    if got != expected {
+        return Err(format!(
+            "segment checksum mismatch: expected {:08x}, got {:08x}",
+            expected, got
+        ));
+    }
+    if buf.len() > pos + FOOTER_LEN {
+        return Err("data after the segment footer".to_string());
+    }
+    Ok(())
+}
+
+fn writer_stopped() -> WalError {
+    std::io::Error::other("WAL writer task has stopped").into()
+}
+
+// What is wrong with an entry; `deserialize` only sees the entry's own
+// bytes, so the offsets in its errors are meaningless here
+fn corruption(e: WalError) -> String {
+    match e {
+        WalError::InvalidEntry { reason, .. } => reason,
+        WalError::ChecksumMismatch { expected, got, .. } => format!(
+            "checksum mismatch: expected {:08x}, got {:08x}",
+            expected, got
+        ),
+        e => e.to_string(),
     }
 }
 
 impl Drop for WalManager {
     fn drop(&mut self) {
-        if let Some(handle) = self.sync_task.take() {
+        if let Some(handle) = self.sync_task.get_mut().take() {
             handle.abort();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::entry::OpType;
+
+    fn entry(key: &str) -> WalEntry {
+        WalEntry {
+            timestamp: 1,
+            key: key.to_string(),
+            value: b"v".to_vec(),
+            version: 1,
+            ttl: None,
+            op_type: OpType::Set,
+            compression: None,
+            delta: None,
+        }
+    }
+
+    #[test]
+    fn test_global_offset_round_trip() {
+        assert_eq!(global_offset(1, 0), 0);
+        assert_eq!(split_offset(global_offset(3, 1234)), (3, 1234));
+        assert!(global_offset(2, 0) > global_offset(1, (1 << SEGMENT_OFFSET_BITS) - 1));
+    }
+
+    #[tokio::test]
+    async fn test_truncate_before_removes_superseded_segments() {
+        let dir = std::env::temp_dir().join(format!("wal_truncate_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 64, // a couple of entries per segment
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config.clone()).await.unwrap();
+        for i in 0..10 {
+            wal.append(&entry(&format!("key{}", i))).await.unwrap();
+        }
+        let segments = WalManager::segments(&config).unwrap();
+        assert!(segments.len() >= 3);
+
+        // Checkpoint in the middle of the newest segment
+        let checkpoint = wal.current_offset().await;
+        wal.truncate_before(checkpoint).await.unwrap();
+
+        let remaining = WalManager::segments(&config).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0], *segments.last().unwrap());
+
+        // The active segment keeps working
+        wal.append(&entry("after")).await.unwrap();
+        assert!(wal.current_offset().await > checkpoint);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_replay_spans_segments() {
+        let dir = std::env::temp_dir().join(format!("wal_replay_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256,
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config.clone()).await.unwrap();
+
+        let mut offsets = Vec::new();
+        for i in 0..100 {
+            offsets.push(wal.append(&entry(&format!("key{}", i))).await.unwrap());
+        }
+        // Leaves an empty trailing segment behind
+        drop(wal);
+        let wal = WalManager::new(config.clone()).await.unwrap();
+        assert!(WalManager::segments(&config).unwrap().len() > 3);
+
+        let mut replayed = Vec::new();
+        wal.replay_from(0, |offset, e| {
+            replayed.push((offset, e.key));
+            Ok(())
+        })
+        .await
+        .unwrap();
+        let expected: Vec<_> = (0..100)
+            .map(|i| (offsets[i], format!("key{}", i)))
+            .collect();
+        assert_eq!(replayed, expected);
+        assert!(replayed.windows(2).all(|w| w[0].0 < w[1].0));
+
+        // Resume from an entry in the middle of the second segment
+        let (first, _) = split_offset(offsets[0]);
+        let resume = offsets
+            .iter()
+            .position(|o| {
+                let (sequence, in_segment) = split_offset(*o);
+                sequence == first + 1 && in_segment > 0
+            })
+            .unwrap();
+        let mut keys = Vec::new();
+        wal.replay_from(offsets[resume], |_, e| {
+            keys.push(e.key);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(keys.len(), 100 - resume);
+        assert_eq!(keys[0], format!("key{}", resume));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_replay_stream_lets_appends_through() {
+        let dir = std::env::temp_dir().join(format!("wal_stream_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256,
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config).await.unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..20 {
+            offsets.push(wal.append(&entry(&format!("key{}", i))).await.unwrap());
+        }
+
+        // The reader is stalled on a full channel; appends carry on
+        let mut stream = wal.replay_stream(offsets[5], 1).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            for i in 20..40 {
+                wal.append(&entry(&format!("key{}", i))).await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        // Ends where the log was when the stream started
+        let mut keys = Vec::new();
+        while let Some(read) = stream.recv().await {
+            keys.push(read.unwrap().1.key);
+        }
+        let expected: Vec<_> = (5..20).map(|i| format!("key{}", i)).collect();
+        assert_eq!(keys, expected);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_read_segments_stops_at_first_corruption() {
+        let dir = std::env::temp_dir().join(format!("wal_read_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256,
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config.clone()).await.unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..20 {
+            offsets.push(wal.append(&entry(&format!("key{}", i))).await.unwrap());
+        }
+        drop(wal);
+
+        // Stopping early
+        let mut keys = Vec::new();
+        read_segments(&config, 0, |_, e| {
+            keys.push(e.key);
+            Ok(if keys.len() == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        })
+        .unwrap();
+        assert_eq!(keys, vec!["key0", "key1", "key2"]);
+
+        // Flip the last value byte of entry 12, wherever its segment is
+        let (sequence, in_segment) = split_offset(offsets[12]);
+        let path = dir.join(format!("{}{}", config.file_prefix, sequence));
+        let mut bytes = std::fs::read(&path).unwrap();
+        let value_end = in_segment as usize + entry("key12").serialize().len() - 5;
+        bytes[value_end] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut read = 0;
+        let err = read_segments(&config, 0, |_, _| {
+            read += 1;
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap_err();
+        assert_eq!(read, 12);
+        match err {
+            WalError::ReplayError { offset, reason } => {
+                assert_eq!(offset, offsets[12]);
+                assert!(reason.starts_with("checksum mismatch"), "{}", reason);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_best_effort_replay_discards_torn_tail() {
+        let dir = std::env::temp_dir().join(format!("wal_torn_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            replay_mode: ReplayMode::BestEffort,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config.clone()).await.unwrap();
+        for i in 0..10 {
+            wal.append(&entry(&format!("key{}", i))).await.unwrap();
+        }
+        let torn_at = wal.current_offset().await;
+        drop(wal);
+
+        // A crash mid-write, then a restart that logs into a new segment
+        let (sequence, _) = split_offset(torn_at);
+        let path = dir.join(format!("{}{}", config.file_prefix, sequence));
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.extend_from_slice(&entry("torn").serialize()[..20]);
+        bytes.extend_from_slice(b"garbage");
+        std::fs::write(&path, bytes).unwrap();
+        let wal = WalManager::new(config.clone()).await.unwrap();
+        wal.append(&entry("after")).await.unwrap();
+
+        let mut keys = Vec::new();
+        wal.replay_from(0, |_, e| {
+            keys.push(e.key);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(keys.len(), 11);
+        assert_eq!(keys[9], "key9");
+        assert_eq!(keys[10], "after");
+
+        let strict = WalConfig {
+            replay_mode: ReplayMode::Strict,
+            ..config
+        };
+        let mut read = 0;
+        let err = read_segments(&strict, 0, |_, _| {
+            read += 1;
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap_err();
+        assert_eq!(read, 10);
+        assert!(matches!(err, WalError::ReplayError { offset, .. } if offset == torn_at));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_segment_footer_catches_a_missing_entry() {
+        let dir = std::env::temp_dir().join(format!("wal_footer_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256,
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config.clone()).await.unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..20 {
+            offsets.push(wal.append(&entry(&format!("key{}", i))).await.unwrap());
+        }
+        drop(wal);
+        let mut read = 0;
+        read_segments(&config, 0, |_, _| {
+            read += 1;
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        assert_eq!(read, 20);
+
+        // Cut the second entry out of the first, sealed, segment: every
+        // remaining entry is intact, but the footer no longer fits
+        let (sequence, _) = split_offset(offsets[0]);
+        let (_, second) = split_offset(offsets[1]);
+        let (_, third) = split_offset(offsets[2]);
+        assert_eq!(split_offset(offsets[2]).0, sequence);
+        let path = dir.join(format!("{}{}", config.file_prefix, sequence));
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.drain(second as usize..third as usize);
+        std::fs::write(&path, bytes).unwrap();
+
+        match read_segments(&config, 0, |_, _| Ok(ControlFlow::Continue(()))) {
+            Err(WalError::ReplayError { reason, .. }) => {
+                assert!(reason.starts_with("segment footer covers"), "{}", reason)
+            }
+            other => panic!("footer not checked: {:?}", other),
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_rotation_with_oversized_entries_and_tiny_segments() {
+        let dir = std::env::temp_dir().join(format!("wal_rotate_{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256,
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let wal = WalManager::new(config.clone()).await.unwrap();
+
+        // Larger than a whole segment: written, alone, into the next one
+        wal.append(&entry("small")).await.unwrap();
+        let mut big = entry("big");
+        big.value = vec![b'x'; 1000];
+        let big_offset = wal.append(&big).await.unwrap();
+        let after_offset = wal.append(&entry("after")).await.unwrap();
+        assert_eq!(split_offset(big_offset), (2, 0));
+        assert_eq!(split_offset(after_offset), (3, 0));
+
+        // Every append rotates
+        let tiny = WalConfig {
+            max_file_size: 1,
+            ..config.clone()
+        };
+        drop(wal);
+        let wal = WalManager::new(tiny.clone()).await.unwrap();
+        for i in 0..200 {
+            let offset = wal.append(&entry(&format!("key{}", i))).await.unwrap();
+            assert_eq!(split_offset(offset), (4 + i, 0));
+        }
+
+        let sequences: Vec<u64> = WalManager::segments(&tiny)
+            .unwrap()
+            .into_iter()
+            .map(|(sequence, _)| sequence)
+            .collect();
+        assert_eq!(sequences, (1..=203).collect::<Vec<_>>());
+        let mut keys = Vec::new();
+        wal.replay_from(0, |_, e| {
+            keys.push(e.key);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(keys.len(), 203);
+        assert_eq!(keys[1], "big");
+        assert_eq!(keys[202], "key199");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_segments_round_trip_and_reject_a_wrong_key() {
+        let dir = std::env::temp_dir().join(format!("wal_encrypted_{}", uuid::Uuid::new_v4()));
+        let plain = WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            max_file_size: 256,
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let encrypted = WalConfig {
+            cipher: Some(Cipher::new([1; 32])),
+            ..plain.clone()
+        };
+
+        // Segments written before encryption was turned on stay readable
+        let wal = WalManager::new(plain.clone()).await.unwrap();
+        wal.append(&entry("plain")).await.unwrap();
+        drop(wal);
+        let wal = WalManager::new(encrypted.clone()).await.unwrap();
+        for i in 0..20 {
+            wal.append(&entry(&format!("secret{}", i))).await.unwrap();
+        }
+        drop(wal);
+
+        let (_, path) = WalManager::segments(&plain).unwrap().pop().unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        assert!(bytes.starts_with(&ENCRYPTED_MARK));
+        assert!(!bytes.windows(6).any(|w| w == b"secret"));
+
+        let mut keys = Vec::new();
+        read_segments(&encrypted, 0, |_, e| {
+            keys.push(e.key);
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+        assert_eq!(keys.len(), 21);
+        assert_eq!(keys[0], "plain");
+        assert_eq!(keys[20], "secret19");
+
+        let skip = |_, _| Ok(ControlFlow::Continue(()));
+        assert!(matches!(
+            read_segments(&plain, 0, skip),
+            Err(WalError::Encryption(EncryptionError::KeyMissing(_)))
+        ));
+        let wrong_key = WalConfig {
+            cipher: Some(Cipher::new([2; 32])),
+            replay_mode: ReplayMode::BestEffort,
+            ..plain
+        };
+        assert!(matches!(
+            read_segments(&wrong_key, 0, skip),
+            Err(WalError::Encryption(EncryptionError::WrongKey(_)))
+        ));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_periodic_sync_runs_and_stops_on_drop() {
+        let dir = std::env::temp_dir().join(format!("wal_every_ms_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::EveryMs(10),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        wal.append(&entry("k")).await.unwrap();
+        assert_eq!(wal.synced_offset(), 0);
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while wal.synced_offset() < wal.current_offset().await {
+                sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the sync task never synced");
+
+        let task = wal.sync_task.lock().as_ref().unwrap().abort_handle();
+        let weak = Arc::downgrade(&wal);
+        drop(wal);
+        assert!(weak.upgrade().is_none());
+        sleep(Duration::from_millis(50)).await;
+        assert!(task.is_finished());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_full_write_queue_pushes_back() {
+        let dir = std::env::temp_dir().join(format!("wal_queue_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::EveryWrite,
+            queue_capacity: 2,
+            queue_wait_ms: 20,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // A stalled disk: the writer takes a batch and blocks on the file,
+        // two more appends fill the queue and the rest give up waiting
+        let stall = wal.current_file.lock().await;
+        let appends: Vec<_> = (0..6)
+            .map(|i| {
+                let wal = wal.clone();
+                tokio::spawn(async move { wal.append(&entry(&format!("k{}", i))).await })
+            })
+            .collect();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(wal.queue_depth(), 2);
+        let (rejected, waiting): (Vec<_>, Vec<_>) =
+            appends.into_iter().partition(|append| append.is_finished());
+        // Nothing is acknowledged before it is on disk
+        assert!((3..=4).contains(&waiting.len()));
+        for append in rejected {
+            assert!(matches!(
+                append.await.unwrap(),
+                Err(WalError::QueueFull { capacity: 2 })
+            ));
+        }
+
+        drop(stall);
+        let acked = waiting.len();
+        let mut offsets = Vec::new();
+        for append in waiting {
+            offsets.push(append.await.unwrap().unwrap());
+        }
+        offsets.sort_unstable();
+        offsets.dedup();
+        assert_eq!(offsets.len(), acked);
+        assert_eq!(wal.synced_offset(), wal.current_offset().await);
+        assert_eq!(wal.queue_depth(), 0);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_append_with_durability_levels() {
+        let dir = std::env::temp_dir().join(format!("wal_durability_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // none: nothing is logged
+        assert_eq!(wal.append_with(&entry("a"), Durability::None).await.unwrap(), None);
+        assert_eq!(wal.current_offset().await, 0);
+
+        // wal: logged, but left to the global policy to fsync
+        assert_eq!(wal.append_with(&entry("b"), Durability::Wal).await.unwrap(), Some(0));
+        assert!(wal.current_offset().await > 0);
+        assert_eq!(wal.synced_offset(), 0);
+
+        // sync: logged and fsynced before returning
+        let offset = wal.append_with(&entry("c"), Durability::Sync).await.unwrap();
+        assert!(offset.unwrap() > 0);
+        assert_eq!(wal.synced_offset(), wal.current_offset().await);
+
+        let mut keys = Vec::new();
+        wal.replay_from(0, |_, e| {
+            keys.push(e.key);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(keys, vec!["b", "c"]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_replicated_wal_logs_durability_none() {
+        let dir = std::env::temp_dir().join(format!("wal_replicated_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        wal.set_replicated();
+
+        // Logged for the replicas, though the caller gets no offset to wait on
+        assert_eq!(wal.append_with(&entry("a"), Durability::None).await.unwrap(), None);
+        assert!(wal.current_offset().await > 0);
+        assert_eq!(wal.synced_offset(), 0);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_append_waits_for_replica_quorum() {
+        let dir = std::env::temp_dir().join(format!("wal_acks_{}", uuid::Uuid::new_v4()));
+        let wal = WalManager::new(WalConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        wal.require_acks(2, Duration::from_millis(100));
+
+        // Nobody connected: the write is logged but times out
+        let err = wal
+            .append_with(&entry("a"), Durability::Wal)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            WalError::AckTimeout {
+                offset: 0,
+                acked: 0,
+                quorum: 2
+            }
+        ));
+        let end = wal.current_offset().await;
+
+        // Two replicas apply it while we wait
+        wal.record_ack("r1", end);
+        let acker = {
+            let wal = wal.clone();
+            tokio::spawn(async move {
+                sleep(Duration::from_millis(20)).await;
+                wal.record_ack("r2", end);
+            })
+        };
+        wal.wait_for_acks(0, 2).await.unwrap();
+        acker.await.unwrap();
+
+        // A disconnected replica no longer counts
+        wal.remove_replica("r2");
+        assert_eq!(wal.replica_acks().len(), 1);
+        assert!(wal.wait_for_acks(0, 2).await.is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -3,7 +3,7 @@ pub mod entry;
 pub mod error;
 pub mod manager;
 
-pub use config::WalConfig;
+pub use config::{Durability, ReplayMode, WalConfig};
 pub use entry::{OpType, WalEntry};
 pub use error::WalError;
-pub use manager::WalManager;
+pub use manager::{PendingAppend, WalManager, WalSlot};
@@ -1,7 +1,7 @@
-use kvstore_plus_plus::config::AppConfig;
-use kvstore_plus_plus::storage::StorageConfig;
-use kvstore_plus_plus::wal::config::WalConfig;
+use rust_db::config::AppConfig;
+use rust_db::storage::StorageConfig;
 use std::fs;
+use std::sync::Arc;
 use tempfile::TempDir;
 
 #[tokio::test]
@@ -10,33 +10,23 @@ async fn test_full_system_integration() {
     let data_dir = temp_dir.path().join("data");
     fs::create_dir_all(&data_dir).unwrap();
 
-    let config = AppConfig {
-        storage: StorageConfig {
-            num_shards: 4,
-            snapshot_dir: data_dir.join("snapshots").to_str().unwrap().to_string(),
-        },
-        wal: WalConfig {
-            dir: data_dir.join("wal").to_str().unwrap().to_string(),
-            file_prefix: "wal_".to_string(),
-            max_file_size: 1024 * 1024,
-            sync_policy: kvstore_plus_plus::wal::config::SyncPolicy::Never,
-        },
-        background: kvstore_plus_plus::config::BackgroundConfig {
-            checkpoint_interval_sec: 60,
-            metrics_interval_ms: 1000,
-            s3: None,
-            replica: None,
-        },
-    };
+    let config = AppConfig::builder()
+        .with_shards(4)
+        .with_snapshot_dir(data_dir.join("snapshots").to_str().unwrap())
+        .with_wal_dir(data_dir.join("wal").to_str().unwrap())
+        .with_sync_policy(rust_db::wal::config::SyncPolicy::Never)
+        .with_checkpoint_interval(60)
+        .build()
+        .unwrap();
 
     // Initialize WAL
-    let wal = Arc::new(kvstore_plus_plus::wal::WalManager::new(config.wal.clone()).await.unwrap());
+    let _wal = rust_db::wal::WalManager::new(config.wal.clone()).await.unwrap();
 
     // Initialize Storage
-    let engine = kvstore_plus_plus::storage::StorageEngine::new(config.storage.clone());
+    let engine = rust_db::storage::StorageEngine::new(config.storage.clone()).await;
 
     // Bootstrap catalog
-    let _ = kvstore_plus_plus::catalog::bootstrap::bootstrap_if_needed(&engine).await.unwrap();
+    let _ = rust_db::catalog::bootstrap::bootstrap_if_needed(&engine).await.unwrap();
 
     // Test SET
     engine
@@ -51,4 +41,536 @@ async fn test_full_system_integration() {
     // Test DEL
     engine.del("integration_test", None).await.unwrap();
     assert!(engine.get("integration_test").await.is_err());
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_ping_requires_auth() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app) = rest_app(&temp_dir, "ping-secret").await;
+    add_user(&engine, "canary").await;
+
+    // No credentials
+    let response = app
+        .clone()
+        .oneshot(Request::get("/v1/ping").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Valid JWT
+    let token = rust_db::auth::jwt::JwtManager::new("ping-secret".to_string())
+        .generate("canary", vec![], 60)
+        .unwrap();
+    let response = app
+        .oneshot(
+            Request::get("/v1/ping")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["pong"], true);
+    assert_eq!(json["user"], "canary");
+    assert_eq!(json["role"], "primary");
+}
+
+// The REST router over a fresh engine, with JWTs signed by `secret`
+async fn rest_app(
+    temp_dir: &TempDir,
+    secret: &str,
+) -> (Arc<rust_db::storage::StorageEngine>, axum::Router) {
+    rest_app_with(temp_dir, secret, Default::default()).await
+}
+
+// `rest_app` admitting clients under `connection_config`
+async fn rest_app_with(
+    temp_dir: &TempDir,
+    secret: &str,
+    connection_config: rust_db::connection::config::ConnectionConfig,
+) -> (Arc<rust_db::storage::StorageEngine>, axum::Router) {
+    let engine = rust_db::storage::StorageEngine::new(StorageConfig {
+        num_shards: 4,
+        ..Default::default()
+    })
+    .await;
+    let catalog = Arc::new(rust_db::catalog::CatalogManager::new(engine.clone()));
+    let audit_path = temp_dir.path().join("audit.log");
+    let auth_manager = Arc::new(
+        rust_db::auth::AuthManager::new(
+            catalog,
+            secret.to_string(),
+            audit_path.to_str().unwrap().to_string(),
+            &Default::default(),
+        )
+        .unwrap(),
+    );
+    let wal = rust_db::wal::WalManager::new(rust_db::wal::WalConfig {
+        dir: temp_dir.path().join("wal").to_str().unwrap().to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine.attach_wal(wal.clone());
+    let connections = Arc::new(rust_db::connection::ConnectionManager::new(
+        connection_config,
+    ));
+    let snapshot_dir = temp_dir.path().join("snapshots");
+    let snapshots = Arc::new(rust_db::storage::SnapshotManager::new(
+        snapshot_dir.to_str().unwrap().to_string(),
+    ));
+    let limiter = Arc::new(rust_db::api::rate_limit::RateLimiter::new(
+        Default::default(),
+    ));
+    let app = rust_db::api::rest::router(
+        engine.clone(),
+        wal,
+        auth_manager,
+        connections,
+        snapshots,
+        limiter,
+    );
+    (engine, app)
+}
+
+#[tokio::test]
+async fn test_get_without_permission_is_denied_before_lookup() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app) = rest_app(&temp_dir, "get-secret").await;
+    add_user(&engine, "nobody").await;
+    engine.set("secret", b"v".to_vec(), None).await.unwrap();
+
+    // Authenticated, but without any permissions
+    let token = rust_db::auth::jwt::JwtManager::new("get-secret".to_string())
+        .generate("nobody", vec![], 60)
+        .unwrap();
+    for key in ["secret", "missing"] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/v1/get?key={}", key))
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        // Same answer whether or not the key exists
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "key {}", key);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "PERMISSION_DENIED", "{}", body);
+        assert!(!body.to_string().contains("not found"), "{}", body);
+    }
+}
+
+// Adds `username` to the catalog, so tokens issued for it are accepted
+async fn add_user(engine: &Arc<rust_db::storage::StorageEngine>, username: &str) {
+    let catalog = rust_db::catalog::CatalogManager::new(engine.clone());
+    let user = rust_db::catalog::User::new(1, username.to_string(), String::new());
+    catalog.set_user(&user).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_scan_with_no_matches_is_an_empty_page() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app) = rest_app(&temp_dir, "scan-secret").await;
+    add_user(&engine, "reader").await;
+    for i in 0..10 {
+        engine
+            .set(&format!("user:{}", i), b"v".to_vec(), None)
+            .await
+            .unwrap();
+    }
+
+    let token = rust_db::auth::jwt::JwtManager::new("scan-secret".to_string())
+        .generate("reader", vec!["SCAN".to_string()], 60)
+        .unwrap();
+    let response = app
+        .oneshot(
+            Request::get("/v1/scan?pattern=order:*&limit=10")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["items"], serde_json::json!([]));
+    assert_eq!(json["has_more"], false);
+    assert!(json["scanned"].as_u64().unwrap() > 0, "{}", json);
+}
+
+#[tokio::test]
+async fn test_getrange_with_range_header_is_partial_content() {
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use tower::ServiceExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app) = rest_app(&temp_dir, "range-secret").await;
+    add_user(&engine, "reader").await;
+    engine
+        .set("blob", b"0123456789".to_vec(), None)
+        .await
+        .unwrap();
+
+    let token = rust_db::auth::jwt::JwtManager::new("range-secret".to_string())
+        .generate("reader", vec!["GET".to_string()], 60)
+        .unwrap();
+    let get = |range: &str| {
+        app.clone().oneshot(
+            Request::get("/v1/getrange?key=blob")
+                .header("Authorization", format!("Bearer {}", token))
+                .header(header::RANGE, range)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let response = get("bytes=2-5").await.unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"2345");
+
+    // Past the end of the value
+    let response = get("bytes=20-").await.unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"]["code"], "RANGE_NOT_SATISFIABLE", "{}", json);
+}
+
+// Sends `request` with a msgpack `Accept` and decodes the msgpack reply
+async fn msgpack_call<R: serde::de::DeserializeOwned>(
+    app: &axum::Router,
+    request: axum::http::Request<axum::body::Body>,
+) -> R {
+    use axum::http::{header, StatusCode};
+    use tower::ServiceExt;
+
+    let mut request = request;
+    request.headers_mut().insert(
+        header::ACCEPT,
+        rust_db::api::content::MSGPACK.parse().unwrap(),
+    );
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        rust_db::api::content::MSGPACK
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    rmp_serde::from_slice(&body).unwrap()
+}
+
+// A msgpack POST of `body` to `path`, authorized by `token`
+fn msgpack_post<B: serde::Serialize>(
+    path: &str,
+    token: &str,
+    body: &B,
+) -> axum::http::Request<axum::body::Body> {
+    axum::http::Request::post(path)
+        .header("Authorization", format!("Bearer {}", token))
+        .header(
+            axum::http::header::CONTENT_TYPE,
+            rust_db::api::content::MSGPACK,
+        )
+        .body(axum::body::Body::from(rmp_serde::to_vec_named(body).unwrap()))
+        .unwrap()
+}
+
+// A router plus a token for "writer", who may do everything these tests do
+async fn msgpack_app(
+    temp_dir: &TempDir,
+) -> (Arc<rust_db::storage::StorageEngine>, axum::Router, String) {
+    let (engine, app) = rest_app(temp_dir, "msgpack-secret").await;
+    add_user(&engine, "writer").await;
+    let permissions = ["GET", "SET", "APPEND"].map(String::from).to_vec();
+    let token = rust_db::auth::jwt::JwtManager::new("msgpack-secret".to_string())
+        .generate("writer", permissions, 60)
+        .unwrap();
+    (engine, app, token)
+}
+
+#[derive(serde::Serialize)]
+struct ValueBody<'a> {
+    key: &'a str,
+    value: rust_db::api::content::ValueBytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_version: Option<u64>,
+}
+
+#[tokio::test]
+async fn test_msgpack_cas_round_trip() {
+    #[derive(serde::Deserialize)]
+    struct Reply {
+        success: bool,
+        version: u64,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app, token) = msgpack_app(&temp_dir).await;
+    let version = engine.set("k", b"a".to_vec(), None).await.unwrap();
+
+    let body = ValueBody {
+        key: "k",
+        value: rust_db::api::content::ValueBytes(vec![0, 255]),
+        expected_version: Some(version),
+    };
+    let reply: Reply = msgpack_call(&app, msgpack_post("/v1/cas", &token, &body)).await;
+    assert!(reply.success);
+    assert!(reply.version > version);
+    assert_eq!(engine.get("k").await.unwrap().value, [0, 255]);
+}
+
+#[tokio::test]
+async fn test_msgpack_getset_round_trip() {
+    #[derive(serde::Deserialize)]
+    struct Reply {
+        success: bool,
+        old_value: Option<rust_db::api::content::ValueBytes>,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app, token) = msgpack_app(&temp_dir).await;
+    engine.set("k", vec![1, 254], None).await.unwrap();
+
+    let body = ValueBody {
+        key: "k",
+        value: rust_db::api::content::ValueBytes(vec![0]),
+        expected_version: None,
+    };
+    let reply: Reply = msgpack_call(&app, msgpack_post("/v1/getset", &token, &body)).await;
+    assert!(reply.success);
+    assert_eq!(reply.old_value.unwrap().0, [1, 254]);
+    assert_eq!(engine.get("k").await.unwrap().value, [0]);
+}
+
+#[tokio::test]
+async fn test_msgpack_setnx_round_trip() {
+    #[derive(serde::Deserialize)]
+    struct Reply {
+        written: bool,
+        version: Option<u64>,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app, token) = msgpack_app(&temp_dir).await;
+
+    let body = ValueBody {
+        key: "k",
+        value: rust_db::api::content::ValueBytes(vec![0, 255]),
+        expected_version: None,
+    };
+    let reply: Reply = msgpack_call(&app, msgpack_post("/v1/setnx", &token, &body)).await;
+    assert!(reply.written);
+    assert!(reply.version.is_some());
+    assert_eq!(engine.get("k").await.unwrap().value, [0, 255]);
+}
+
+#[tokio::test]
+async fn test_msgpack_mset_round_trip() {
+    #[derive(serde::Serialize)]
+    struct Body<'a> {
+        items: Vec<ValueBody<'a>>,
+    }
+    #[derive(serde::Deserialize)]
+    struct Status {
+        key: String,
+        success: bool,
+    }
+    #[derive(serde::Deserialize)]
+    struct Reply {
+        success: bool,
+        results: Vec<Status>,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app, token) = msgpack_app(&temp_dir).await;
+
+    let items = ["a", "b"]
+        .into_iter()
+        .map(|key| ValueBody {
+            key,
+            value: rust_db::api::content::ValueBytes(vec![0, 255]),
+            expected_version: None,
+        })
+        .collect();
+    let request = msgpack_post("/v1/mset", &token, &Body { items });
+    let reply: Reply = msgpack_call(&app, request).await;
+    assert!(reply.success);
+    let keys: Vec<_> = reply
+        .results
+        .iter()
+        .filter(|status| status.success)
+        .map(|status| status.key.as_str())
+        .collect();
+    assert_eq!(keys, ["a", "b"]);
+    assert_eq!(engine.get("b").await.unwrap().value, [0, 255]);
+}
+
+#[tokio::test]
+async fn test_msgpack_append_round_trip() {
+    #[derive(serde::Deserialize)]
+    struct Reply {
+        success: bool,
+        length: usize,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app, token) = msgpack_app(&temp_dir).await;
+    engine.set("k", vec![0], None).await.unwrap();
+
+    let body = ValueBody {
+        key: "k",
+        value: rust_db::api::content::ValueBytes(vec![255]),
+        expected_version: None,
+    };
+    let reply: Reply = msgpack_call(&app, msgpack_post("/v1/append", &token, &body)).await;
+    assert!(reply.success);
+    assert_eq!(reply.length, 2);
+    assert_eq!(engine.get("k").await.unwrap().value, [0, 255]);
+}
+
+#[tokio::test]
+async fn test_msgpack_getrange_round_trip() {
+    #[derive(serde::Deserialize)]
+    struct Reply {
+        value: rust_db::api::content::ValueBytes,
+        start: usize,
+        total_len: usize,
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app, token) = msgpack_app(&temp_dir).await;
+    engine.set("blob", vec![9, 0, 255, 7], None).await.unwrap();
+
+    let request = axum::http::Request::get("/v1/getrange?key=blob&start=1&end=2")
+        .header("Authorization", format!("Bearer {}", token))
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let reply: Reply = msgpack_call(&app, request).await;
+    assert_eq!(reply.value.0, [0, 255]);
+    assert_eq!(reply.start, 1);
+    assert_eq!(reply.total_len, 4);
+}
+
+// Opens a WebSocket to watch `prefix` with `header` standing in for
+// credentials, and returns the response status and resume token. The
+// socket is closed on return.
+async fn ws_handshake(addr: std::net::SocketAddr, header: &str) -> (u16, Option<String>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "GET /v1/watch/ws?prefix=k HTTP/1.1\r\nHost: localhost\r\n\
+         Connection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}\r\n\r\n",
+        header
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed mid-response");
+        response.extend_from_slice(&buf[..n]);
+    }
+    let response = String::from_utf8_lossy(&response).to_string();
+    let status = response[9..12].parse().unwrap();
+    let token = response.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case(rust_db::api::connections::RESUME_TOKEN_HEADER)
+            .then(|| value.trim().to_string())
+    });
+    (status, token)
+}
+
+#[tokio::test]
+async fn test_websocket_session_resumes_with_its_token_until_disabled() {
+    let temp_dir = TempDir::new().unwrap();
+    let (engine, app) = rest_app_with(
+        &temp_dir,
+        "resume-secret",
+        rust_db::connection::config::ConnectionConfig {
+            resume_window_sec: 60,
+            ..Default::default()
+        },
+    )
+    .await;
+    let mut user = rust_db::catalog::User::new(1, "watcher".to_string(), String::new());
+    let catalog = rust_db::catalog::CatalogManager::new(engine.clone());
+    catalog.set_user(&user).await.unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let jwt = rust_db::auth::jwt::JwtManager::new("resume-secret".to_string())
+        .generate("watcher", vec!["GET".to_string()], 60)
+        .unwrap();
+    let (status, token) = ws_handshake(addr, &format!("Authorization: Bearer {}", jwt)).await;
+    assert_eq!(status, 101);
+    let token = token.expect("no resume token handed out");
+
+    // Detached once the server sees the socket go, which takes a moment
+    let resume = format!("X-KV-Resume-Token: {}", token);
+    let mut resumed = None;
+    for _ in 0..50 {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        match ws_handshake(addr, &resume).await {
+            (101, token) => {
+                resumed = token;
+                break;
+            }
+            (status, _) => assert_eq!(status, 401),
+        }
+    }
+    assert_eq!(resumed.as_deref(), Some(token.as_str()));
+
+    // A session whose account was disabled meanwhile is refused, and stays
+    // refused once the account is back
+    user.is_active = false;
+    catalog.set_user(&user).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(ws_handshake(addr, &resume).await.0, 401);
+    user.is_active = true;
+    catalog.set_user(&user).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(ws_handshake(addr, &resume).await.0, 401);
+}